@@ -0,0 +1,19 @@
+//! A DLL to inject into a d3d9 game, printing the device it grabbed to a console of its own.
+//!
+//! Build it for the game's architecture, for example with
+//! `cargo build --example entry --target i686-pc-windows-msvc`, and inject `entry.dll`.
+//! On other platforms it builds to an empty library.
+
+#![cfg(windows)]
+
+use d3d9_device_grabber::entry::EntryOptions;
+use d3d9_device_grabber::{D3D9GrabError, DummyDevice};
+
+fn init(device: Result<DummyDevice, D3D9GrabError>) {
+    match device {
+        Ok(device) => println!("grabbed {:?}", device.as_ptr()),
+        Err(err) => println!("error getting d3d9 device: {}", err),
+    }
+}
+
+d3d9_device_grabber::d3d9_entry!(init, EntryOptions::new().console(true));
@@ -0,0 +1,330 @@
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use super::Backend;
+use crate::com;
+use crate::sys::*;
+use crate::window::{HiddenWindow, WindowInfo};
+
+type CreateDeviceFn = unsafe extern "system" fn(
+    *mut IDirect3D9,
+    UINT,
+    D3DDEVTYPE,
+    HWND,
+    DWORD,
+    *mut D3DPRESENT_PARAMETERS,
+    *mut *mut IDirect3DDevice9,
+) -> HRESULT;
+
+type CreateDeviceExFn = unsafe extern "system" fn(
+    *mut IDirect3D9Ex,
+    UINT,
+    D3DDEVTYPE,
+    HWND,
+    DWORD,
+    *mut D3DPRESENT_PARAMETERS,
+    *mut c_void,
+    *mut *mut IDirect3DDevice9Ex,
+) -> HRESULT;
+
+/// Slot of `CreateDevice` in the `IDirect3D9` vtable.
+const CREATE_DEVICE_SLOT: usize = 16;
+/// Slot of `CreateDeviceEx` in the `IDirect3D9Ex` vtable.
+const CREATE_DEVICE_EX_SLOT: usize = 20;
+
+/// Build a distinct, non-null window handle for use with [`FakeBackend`].
+pub fn fake_hwnd(id: usize) -> HWND {
+    id as HWND
+}
+
+/// A top-level window known to a [`FakeBackend`].
+#[derive(Debug, Clone)]
+pub struct FakeWindow {
+    pub process_id: DWORD,
+    pub info: WindowInfo,
+}
+
+impl FakeWindow {
+    /// A visible, unowned, zero-sized window with an empty class name and title.
+    pub fn new(hwnd: HWND, process_id: DWORD) -> Self {
+        FakeWindow {
+            process_id,
+            info: WindowInfo {
+                hwnd,
+                visible: true,
+                client_width: 0,
+                client_height: 0,
+                owner: ptr::null_mut(),
+                class_name: String::new(),
+                title: String::new(),
+            },
+        }
+    }
+
+    pub fn visible(mut self, visible: bool) -> Self {
+        self.info.visible = visible;
+        self
+    }
+
+    pub fn client_size(mut self, width: u32, height: u32) -> Self {
+        self.info.client_width = width;
+        self.info.client_height = height;
+        self
+    }
+
+    pub fn owner(mut self, owner: HWND) -> Self {
+        self.info.owner = owner;
+        self
+    }
+
+    pub fn class_name(mut self, class_name: &str) -> Self {
+        self.info.class_name = class_name.to_owned();
+        self
+    }
+
+    pub fn title(mut self, title: &str) -> Self {
+        self.info.title = title.to_owned();
+        self
+    }
+}
+
+/// An in-memory [`Backend`] with a scripted window list.
+///
+/// `Direct3DCreate9` returns the object the backend was configured with, and `CreateDevice`
+/// is called through that object's vtable, so it is usually a
+/// [`MockDirect3D9`](crate::testing::MockDirect3D9).
+pub struct FakeBackend {
+    process_id: DWORD,
+    windows: Vec<FakeWindow>,
+    direct3d9: *mut IDirect3D9,
+    direct3d9_ex: Option<*mut IDirect3D9Ex>,
+    hidden_window: Option<HWND>,
+    hidden_windows_destroyed: Arc<AtomicUsize>,
+    queried_windows: RefCell<Vec<HWND>>,
+    read_only_memory: bool,
+    unprotected_ranges: RefCell<Vec<(usize, usize)>>,
+    executable_memory: RefCell<Vec<Box<[u8]>>>,
+    flushed_ranges: RefCell<Vec<(usize, usize)>>,
+}
+
+impl FakeBackend {
+    /// A backend for process `process_id` with no windows, whose `Direct3DCreate9` returns null.
+    pub fn new(process_id: DWORD) -> Self {
+        FakeBackend {
+            process_id,
+            windows: Vec::new(),
+            direct3d9: ptr::null_mut(),
+            direct3d9_ex: None,
+            hidden_window: None,
+            hidden_windows_destroyed: Arc::new(AtomicUsize::new(0)),
+            queried_windows: RefCell::new(Vec::new()),
+            read_only_memory: false,
+            unprotected_ranges: RefCell::new(Vec::new()),
+            executable_memory: RefCell::new(Vec::new()),
+            flushed_ranges: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Append a default [`FakeWindow`] owned by `process_id` to the enumeration order.
+    pub fn with_window(self, hwnd: HWND, process_id: DWORD) -> Self {
+        self.with_fake_window(FakeWindow::new(hwnd, process_id))
+    }
+
+    /// Append `window` to the enumeration order.
+    pub fn with_fake_window(mut self, window: FakeWindow) -> Self {
+        self.windows.push(window);
+        self
+    }
+
+    /// The object returned from `Direct3DCreate9`.
+    pub fn with_direct3d9(mut self, direct3d9: *mut IDirect3D9) -> Self {
+        self.direct3d9 = direct3d9;
+        self
+    }
+
+    /// The object returned from `Direct3DCreate9Ex`. Without one, the backend behaves as if
+    /// `d3d9.dll` did not export `Direct3DCreate9Ex`.
+    pub fn with_direct3d9_ex(mut self, direct3d9_ex: *mut IDirect3D9Ex) -> Self {
+        self.direct3d9_ex = Some(direct3d9_ex);
+        self
+    }
+
+    /// The handle given out by every successful `create_hidden_window`. Without one,
+    /// creating a hidden window fails.
+    pub fn with_hidden_window(mut self, hwnd: HWND) -> Self {
+        self.hidden_window = Some(hwnd);
+        self
+    }
+
+    /// Number of hidden windows created by this backend that have since been destroyed.
+    pub fn hidden_windows_destroyed(&self) -> usize {
+        self.hidden_windows_destroyed.load(Ordering::SeqCst)
+    }
+
+    /// Every window whose process id or info has been looked up, oldest first.
+    pub fn queried_windows(&self) -> Vec<HWND> {
+        self.queried_windows.borrow().clone()
+    }
+
+    /// Make every `make_writable` call fail, as if the memory could not be unprotected.
+    pub fn with_read_only_memory(mut self) -> Self {
+        self.read_only_memory = true;
+        self
+    }
+
+    /// The `(address, len)` ranges currently made writable and not yet restored.
+    pub fn unprotected_ranges(&self) -> Vec<(usize, usize)> {
+        self.unprotected_ranges.borrow().clone()
+    }
+
+    /// Number of `alloc_executable` blocks not yet freed.
+    pub fn live_allocations(&self) -> usize {
+        self.executable_memory.borrow().len()
+    }
+
+    /// Every `(address, len)` range passed to `flush_instruction_cache`, oldest first.
+    pub fn flushed_ranges(&self) -> Vec<(usize, usize)> {
+        self.flushed_ranges.borrow().clone()
+    }
+
+    fn find_window(&self, hwnd: HWND) -> Option<&FakeWindow> {
+        self.queried_windows.borrow_mut().push(hwnd);
+        self.windows.iter().find(|window| window.info.hwnd == hwnd)
+    }
+}
+
+impl Backend for FakeBackend {
+    fn current_process_id(&self) -> DWORD {
+        self.process_id
+    }
+
+    fn enum_windows(&self) -> Vec<HWND> {
+        self.windows.iter().map(|window| window.info.hwnd).collect()
+    }
+
+    fn window_process_id(&self, hwnd: HWND) -> DWORD {
+        self.find_window(hwnd).map_or(0, |window| window.process_id)
+    }
+
+    fn window_info(&self, hwnd: HWND) -> WindowInfo {
+        match self.find_window(hwnd) {
+            Some(window) => window.info.clone(),
+            None => FakeWindow::new(hwnd, 0).visible(false).info,
+        }
+    }
+
+    fn create_hidden_window(&self) -> Option<HiddenWindow> {
+        let destroyed = Arc::clone(&self.hidden_windows_destroyed);
+        self.hidden_window.map(|hwnd| {
+            HiddenWindow::new(hwnd, move |_| {
+                destroyed.fetch_add(1, Ordering::SeqCst);
+            })
+        })
+    }
+
+    unsafe fn direct3d_create9(&self, _sdk_version: UINT) -> *mut IDirect3D9 {
+        self.direct3d9
+    }
+
+    unsafe fn create_device(
+        &self,
+        d3d9: *mut IDirect3D9,
+        adapter: UINT,
+        device_type: D3DDEVTYPE,
+        focus_window: HWND,
+        behavior_flags: DWORD,
+        present_params: &mut D3DPRESENT_PARAMETERS,
+        device: &mut *mut IDirect3DDevice9,
+    ) -> HRESULT {
+        let create_device: CreateDeviceFn = mem::transmute(com::method(d3d9, CREATE_DEVICE_SLOT));
+        create_device(
+            d3d9,
+            adapter,
+            device_type,
+            focus_window,
+            behavior_flags,
+            present_params,
+            device,
+        )
+    }
+
+    unsafe fn direct3d_create9_ex(
+        &self,
+        _sdk_version: UINT,
+        d3d9ex: &mut *mut IDirect3D9Ex,
+    ) -> Option<HRESULT> {
+        self.direct3d9_ex.map(|direct3d9_ex| {
+            *d3d9ex = direct3d9_ex;
+            0
+        })
+    }
+
+    unsafe fn create_device_ex(
+        &self,
+        d3d9ex: *mut IDirect3D9Ex,
+        adapter: UINT,
+        device_type: D3DDEVTYPE,
+        focus_window: HWND,
+        behavior_flags: DWORD,
+        present_params: &mut D3DPRESENT_PARAMETERS,
+        device: &mut *mut IDirect3DDevice9Ex,
+    ) -> HRESULT {
+        let create_device_ex: CreateDeviceExFn =
+            mem::transmute(com::method(d3d9ex, CREATE_DEVICE_EX_SLOT));
+        create_device_ex(
+            d3d9ex,
+            adapter,
+            device_type,
+            focus_window,
+            behavior_flags,
+            present_params,
+            ptr::null_mut(),
+            device,
+        )
+    }
+
+    /// Heap memory is always writable, so this only checks and records the range.
+    unsafe fn make_writable(&self, address: *mut c_void, len: usize) -> Option<DWORD> {
+        if self.read_only_memory {
+            return None;
+        }
+        self.unprotected_ranges
+            .borrow_mut()
+            .push((address as usize, len));
+        Some(0)
+    }
+
+    unsafe fn restore_protection(&self, address: *mut c_void, len: usize, _protection: DWORD) {
+        let mut ranges = self.unprotected_ranges.borrow_mut();
+        if let Some(index) = ranges
+            .iter()
+            .position(|&range| range == (address as usize, len))
+        {
+            ranges.remove(index);
+        }
+    }
+
+    /// Hands out ordinary heap memory, so code written there can be inspected but not run.
+    unsafe fn alloc_executable(&self, _near: *const c_void, len: usize) -> *mut c_void {
+        let mut memory = vec![0u8; len].into_boxed_slice();
+        let address = memory.as_mut_ptr() as *mut c_void;
+        self.executable_memory.borrow_mut().push(memory);
+        address
+    }
+
+    unsafe fn free_executable(&self, address: *mut c_void, _len: usize) {
+        self.executable_memory
+            .borrow_mut()
+            .retain(|memory| memory.as_ptr() as *mut c_void != address);
+    }
+
+    unsafe fn flush_instruction_cache(&self, address: *const c_void, len: usize) {
+        self.flushed_ranges
+            .borrow_mut()
+            .push((address as usize, len));
+    }
+}
@@ -0,0 +1,249 @@
+//! The operating system and Direct3D entry points the grabber depends on.
+//!
+//! Everything the grabber needs from the outside world goes through [`Backend`], so the
+//! window search and `CreateDevice` fallback logic can run against [`FakeBackend`] on hosts
+//! without Direct3D.
+
+#[cfg(feature = "testing")]
+mod fake;
+#[cfg(windows)]
+mod win32;
+
+#[cfg(feature = "testing")]
+pub use self::fake::{fake_hwnd, FakeBackend, FakeWindow};
+#[cfg(windows)]
+pub use self::win32::WinApiBackend;
+
+use std::ffi::c_void;
+use std::sync::Arc;
+
+use crate::sys::*;
+use crate::window::{HiddenWindow, WindowInfo};
+
+pub trait Backend {
+    /// Id of the process the grabber is running in, like `GetCurrentProcessId`.
+    fn current_process_id(&self) -> DWORD;
+
+    /// Every top-level window on the desktop in z-order, like `EnumWindows`.
+    fn enum_windows(&self) -> Vec<HWND>;
+
+    /// Id of the process that created `hwnd`, like `GetWindowThreadProcessId`.
+    fn window_process_id(&self, hwnd: HWND) -> DWORD;
+
+    /// Visibility, client size, owner, class name and title of `hwnd`.
+    fn window_info(&self, hwnd: HWND) -> WindowInfo;
+
+    /// Register a private window class and create a hidden 1x1 window of it, or `None` on
+    /// failure. The window must never be shown.
+    fn create_hidden_window(&self) -> Option<HiddenWindow>;
+
+    /// Create the `IDirect3D9` object, like `Direct3DCreate9`. Returns null on failure.
+    ///
+    /// # Safety
+    ///
+    /// The returned object is owned by the caller and must be released through its vtable.
+    unsafe fn direct3d_create9(&self, sdk_version: UINT) -> *mut IDirect3D9;
+
+    /// Call `IDirect3D9::CreateDevice` on `d3d9`, writing the new device to `device` on success.
+    ///
+    /// # Safety
+    ///
+    /// `d3d9` must be a live object returned by [`Backend::direct3d_create9`] on this backend.
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn create_device(
+        &self,
+        d3d9: *mut IDirect3D9,
+        adapter: UINT,
+        device_type: D3DDEVTYPE,
+        focus_window: HWND,
+        behavior_flags: DWORD,
+        present_params: &mut D3DPRESENT_PARAMETERS,
+        device: &mut *mut IDirect3DDevice9,
+    ) -> HRESULT;
+
+    /// Create the `IDirect3D9Ex` object, like `Direct3DCreate9Ex`, writing it to `d3d9ex`.
+    ///
+    /// Returns `None` if `d3d9.dll` does not export `Direct3DCreate9Ex`, as before Vista.
+    ///
+    /// # Safety
+    ///
+    /// The object written out is owned by the caller and must be released through its vtable.
+    unsafe fn direct3d_create9_ex(
+        &self,
+        sdk_version: UINT,
+        d3d9ex: &mut *mut IDirect3D9Ex,
+    ) -> Option<HRESULT>;
+
+    /// Call `IDirect3D9Ex::CreateDeviceEx` on `d3d9ex`, writing the new device to `device` on
+    /// success. Fullscreen devices switch to the display mode `present_params` describe.
+    ///
+    /// # Safety
+    ///
+    /// `d3d9ex` must be a live object returned by [`Backend::direct3d_create9_ex`] on this backend.
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn create_device_ex(
+        &self,
+        d3d9ex: *mut IDirect3D9Ex,
+        adapter: UINT,
+        device_type: D3DDEVTYPE,
+        focus_window: HWND,
+        behavior_flags: DWORD,
+        present_params: &mut D3DPRESENT_PARAMETERS,
+        device: &mut *mut IDirect3DDevice9Ex,
+    ) -> HRESULT;
+
+    /// Make `len` bytes at `address` writable and executable, like `VirtualProtect`.
+    ///
+    /// Returns the previous protection to hand back to [`Backend::restore_protection`], or
+    /// `None` if the protection could not be changed.
+    ///
+    /// # Safety
+    ///
+    /// The range must be mapped memory of this process.
+    unsafe fn make_writable(&self, address: *mut c_void, len: usize) -> Option<DWORD>;
+
+    /// Put back the `protection` that [`Backend::make_writable`] returned for the same range.
+    ///
+    /// # Safety
+    ///
+    /// See [`Backend::make_writable`].
+    unsafe fn restore_protection(&self, address: *mut c_void, len: usize, protection: DWORD);
+
+    /// Allocate `len` bytes of readable, writable and executable memory, like `VirtualAlloc`.
+    ///
+    /// On 64-bit targets the memory should lie within 2GB of `near` when possible, so `near`
+    /// and the new memory can reach each other with 32-bit relative jumps. Returns null on
+    /// failure.
+    ///
+    /// # Safety
+    ///
+    /// The memory must be freed with [`Backend::free_executable`] on this backend.
+    unsafe fn alloc_executable(&self, near: *const c_void, len: usize) -> *mut c_void;
+
+    /// Free memory returned by [`Backend::alloc_executable`].
+    ///
+    /// # Safety
+    ///
+    /// Nothing may be executing in, or still refer to, the memory.
+    unsafe fn free_executable(&self, address: *mut c_void, len: usize);
+
+    /// Make the processor see code just written to `len` bytes at `address`, like
+    /// `FlushInstructionCache`.
+    ///
+    /// # Safety
+    ///
+    /// The range must be mapped memory of this process.
+    unsafe fn flush_instruction_cache(&self, address: *const c_void, len: usize);
+}
+
+/// Forward every method to the backend behind a pointer.
+macro_rules! forward_backend {
+    ($(#[$attr:meta])* $pointer:ty) => {
+        $(#[$attr])*
+        impl<B: Backend + ?Sized> Backend for $pointer {
+            fn current_process_id(&self) -> DWORD {
+                (**self).current_process_id()
+            }
+
+            fn enum_windows(&self) -> Vec<HWND> {
+                (**self).enum_windows()
+            }
+
+            fn window_process_id(&self, hwnd: HWND) -> DWORD {
+                (**self).window_process_id(hwnd)
+            }
+
+            fn window_info(&self, hwnd: HWND) -> WindowInfo {
+                (**self).window_info(hwnd)
+            }
+
+            fn create_hidden_window(&self) -> Option<HiddenWindow> {
+                (**self).create_hidden_window()
+            }
+
+            unsafe fn direct3d_create9(&self, sdk_version: UINT) -> *mut IDirect3D9 {
+                (**self).direct3d_create9(sdk_version)
+            }
+
+            unsafe fn create_device(
+                &self,
+                d3d9: *mut IDirect3D9,
+                adapter: UINT,
+                device_type: D3DDEVTYPE,
+                focus_window: HWND,
+                behavior_flags: DWORD,
+                present_params: &mut D3DPRESENT_PARAMETERS,
+                device: &mut *mut IDirect3DDevice9,
+            ) -> HRESULT {
+                (**self).create_device(
+                    d3d9,
+                    adapter,
+                    device_type,
+                    focus_window,
+                    behavior_flags,
+                    present_params,
+                    device,
+                )
+            }
+
+            unsafe fn direct3d_create9_ex(
+                &self,
+                sdk_version: UINT,
+                d3d9ex: &mut *mut IDirect3D9Ex,
+            ) -> Option<HRESULT> {
+                (**self).direct3d_create9_ex(sdk_version, d3d9ex)
+            }
+
+            unsafe fn create_device_ex(
+                &self,
+                d3d9ex: *mut IDirect3D9Ex,
+                adapter: UINT,
+                device_type: D3DDEVTYPE,
+                focus_window: HWND,
+                behavior_flags: DWORD,
+                present_params: &mut D3DPRESENT_PARAMETERS,
+                device: &mut *mut IDirect3DDevice9Ex,
+            ) -> HRESULT {
+                (**self).create_device_ex(
+                    d3d9ex,
+                    adapter,
+                    device_type,
+                    focus_window,
+                    behavior_flags,
+                    present_params,
+                    device,
+                )
+            }
+
+            unsafe fn make_writable(&self, address: *mut c_void, len: usize) -> Option<DWORD> {
+                (**self).make_writable(address, len)
+            }
+
+            unsafe fn restore_protection(&self, address: *mut c_void, len: usize, protection: DWORD) {
+                (**self).restore_protection(address, len, protection)
+            }
+
+            unsafe fn alloc_executable(&self, near: *const c_void, len: usize) -> *mut c_void {
+                (**self).alloc_executable(near, len)
+            }
+
+            unsafe fn free_executable(&self, address: *mut c_void, len: usize) {
+                (**self).free_executable(address, len)
+            }
+
+            unsafe fn flush_instruction_cache(&self, address: *const c_void, len: usize) {
+                (**self).flush_instruction_cache(address, len)
+            }
+        }
+    };
+}
+
+forward_backend!(
+    /// A backend borrowed for as long as a hook lives, so a test can inspect it afterwards.
+    &B
+);
+forward_backend!(
+    /// A backend shared between several hooks, such as the device and swap chain hooks of one
+    /// [`D3D9Hooks`](crate::hook::D3D9Hooks).
+    Arc<B>
+);
@@ -0,0 +1,306 @@
+use std::ffi::c_void;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use winapi::shared::{d3d9::*, d3d9types::*, minwindef::*, windef::*, winerror::HRESULT};
+use winapi::um::{
+    libloaderapi::{GetModuleHandleW, GetProcAddress},
+    memoryapi::{VirtualAlloc, VirtualFree, VirtualProtect, VirtualQuery},
+    processthreadsapi::{FlushInstructionCache, GetCurrentProcess, GetCurrentProcessId},
+    winnt::{
+        MEMORY_BASIC_INFORMATION, MEM_COMMIT, MEM_FREE, MEM_RELEASE, MEM_RESERVE,
+        PAGE_EXECUTE_READWRITE,
+    },
+    winuser::*,
+};
+
+use super::Backend;
+use crate::window::{HiddenWindow, WindowInfo};
+
+/// The real Win32 and `d3d9.dll` implementation.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WinApiBackend;
+
+impl Backend for WinApiBackend {
+    fn current_process_id(&self) -> DWORD {
+        unsafe { GetCurrentProcessId() }
+    }
+
+    fn enum_windows(&self) -> Vec<HWND> {
+        extern "system" fn enum_windows_callback(hwnd: HWND, l_param: LPARAM) -> BOOL {
+            unsafe {
+                (*(l_param as *mut Vec<HWND>)).push(hwnd);
+            }
+            TRUE
+        }
+
+        let mut windows: Vec<HWND> = Vec::new();
+        unsafe {
+            EnumWindows(
+                Some(enum_windows_callback),
+                &mut windows as *mut Vec<HWND> as LPARAM,
+            );
+        }
+        windows
+    }
+
+    // Window handles are checked by Win32, never dereferenced.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn window_process_id(&self, hwnd: HWND) -> DWORD {
+        let mut wnd_proc_id: DWORD = 0;
+        unsafe {
+            GetWindowThreadProcessId(hwnd, &mut wnd_proc_id as *mut DWORD);
+        }
+        wnd_proc_id
+    }
+
+    // Window handles are checked by Win32, never dereferenced.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn window_info(&self, hwnd: HWND) -> WindowInfo {
+        unsafe {
+            let mut client_rect: RECT = mem::zeroed();
+            GetClientRect(hwnd, &mut client_rect);
+
+            let mut class_name = [0u16; 256];
+            let class_name_len =
+                GetClassNameW(hwnd, class_name.as_mut_ptr(), class_name.len() as i32);
+
+            let mut title = vec![0u16; GetWindowTextLengthW(hwnd) as usize + 1];
+            let title_len = GetWindowTextW(hwnd, title.as_mut_ptr(), title.len() as i32);
+
+            WindowInfo {
+                hwnd,
+                visible: IsWindowVisible(hwnd) != FALSE,
+                client_width: (client_rect.right - client_rect.left).max(0) as u32,
+                client_height: (client_rect.bottom - client_rect.top).max(0) as u32,
+                owner: GetWindow(hwnd, GW_OWNER),
+                class_name: String::from_utf16_lossy(&class_name[..class_name_len.max(0) as usize]),
+                title: String::from_utf16_lossy(&title[..title_len.max(0) as usize]),
+            }
+        }
+    }
+
+    fn create_hidden_window(&self) -> Option<HiddenWindow> {
+        static CLASS_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        // Unique per call, so grabbing from several threads never collides on the class.
+        let class_name: Vec<u16> = format!(
+            "d3d9_device_grabber_{}_{}",
+            self.current_process_id(),
+            CLASS_COUNTER.fetch_add(1, Ordering::Relaxed)
+        )
+        .encode_utf16()
+        .chain(Some(0))
+        .collect();
+
+        unsafe {
+            let instance = GetModuleHandleW(ptr::null());
+            let class = WNDCLASSEXW {
+                cbSize: mem::size_of::<WNDCLASSEXW>() as UINT,
+                style: 0,
+                lpfnWndProc: Some(DefWindowProcW),
+                cbClsExtra: 0,
+                cbWndExtra: 0,
+                hInstance: instance,
+                hIcon: ptr::null_mut(),
+                hCursor: ptr::null_mut(),
+                hbrBackground: ptr::null_mut(),
+                lpszMenuName: ptr::null(),
+                lpszClassName: class_name.as_ptr(),
+                hIconSm: ptr::null_mut(),
+            };
+            if RegisterClassExW(&class) == 0 {
+                return None;
+            }
+
+            // No WS_VISIBLE and no ShowWindow: the window is never displayed or activated.
+            let hwnd = CreateWindowExW(
+                0,
+                class_name.as_ptr(),
+                class_name.as_ptr(),
+                WS_POPUP,
+                0,
+                0,
+                1,
+                1,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                instance,
+                ptr::null_mut(),
+            );
+            if hwnd.is_null() {
+                UnregisterClassW(class_name.as_ptr(), instance);
+                return None;
+            }
+
+            // The module handle is only ever passed back to Win32.
+            let instance = instance as usize;
+            Some(HiddenWindow::new(hwnd, move |hwnd| {
+                DestroyWindow(hwnd);
+                UnregisterClassW(class_name.as_ptr(), instance as HINSTANCE);
+            }))
+        }
+    }
+
+    unsafe fn direct3d_create9(&self, sdk_version: UINT) -> *mut IDirect3D9 {
+        Direct3DCreate9(sdk_version)
+    }
+
+    unsafe fn create_device(
+        &self,
+        d3d9: *mut IDirect3D9,
+        adapter: UINT,
+        device_type: D3DDEVTYPE,
+        focus_window: HWND,
+        behavior_flags: DWORD,
+        present_params: &mut D3DPRESENT_PARAMETERS,
+        device: &mut *mut IDirect3DDevice9,
+    ) -> HRESULT {
+        (*d3d9).CreateDevice(
+            adapter,
+            device_type,
+            focus_window,
+            behavior_flags,
+            present_params,
+            device,
+        )
+    }
+
+    unsafe fn direct3d_create9_ex(
+        &self,
+        sdk_version: UINT,
+        d3d9ex: &mut *mut IDirect3D9Ex,
+    ) -> Option<HRESULT> {
+        type Direct3DCreate9ExFn =
+            unsafe extern "system" fn(UINT, *mut *mut IDirect3D9Ex) -> HRESULT;
+
+        // Looked up at runtime: importing it would stop the DLL loading at all before Vista.
+        let module_name: Vec<u16> = "d3d9.dll".encode_utf16().chain(Some(0)).collect();
+        let module = GetModuleHandleW(module_name.as_ptr());
+        if module.is_null() {
+            return None;
+        }
+        let proc = GetProcAddress(module, b"Direct3DCreate9Ex\0".as_ptr() as *const i8);
+        if proc.is_null() {
+            return None;
+        }
+
+        let direct3d_create9_ex: Direct3DCreate9ExFn = mem::transmute(proc);
+        Some(direct3d_create9_ex(sdk_version, d3d9ex))
+    }
+
+    unsafe fn create_device_ex(
+        &self,
+        d3d9ex: *mut IDirect3D9Ex,
+        adapter: UINT,
+        device_type: D3DDEVTYPE,
+        focus_window: HWND,
+        behavior_flags: DWORD,
+        present_params: &mut D3DPRESENT_PARAMETERS,
+        device: &mut *mut IDirect3DDevice9Ex,
+    ) -> HRESULT {
+        // A fullscreen Ex device needs the display mode to switch to, which is the one the
+        // back buffer is sized for. Windowed devices must be given none.
+        let mut display_mode = D3DDISPLAYMODEEX {
+            Size: mem::size_of::<D3DDISPLAYMODEEX>() as UINT,
+            Width: present_params.BackBufferWidth,
+            Height: present_params.BackBufferHeight,
+            RefreshRate: present_params.FullScreen_RefreshRateInHz,
+            Format: present_params.BackBufferFormat,
+            ScanLineOrdering: D3DSCANLINEORDERING_PROGRESSIVE,
+        };
+        let display_mode = if present_params.Windowed == FALSE {
+            &mut display_mode as *mut D3DDISPLAYMODEEX
+        } else {
+            ptr::null_mut()
+        };
+        (*d3d9ex).CreateDeviceEx(
+            adapter,
+            device_type,
+            focus_window,
+            behavior_flags,
+            present_params,
+            display_mode,
+            device,
+        )
+    }
+
+    unsafe fn make_writable(&self, address: *mut c_void, len: usize) -> Option<DWORD> {
+        let mut old_protection: DWORD = 0;
+        if VirtualProtect(address, len, PAGE_EXECUTE_READWRITE, &mut old_protection) == FALSE {
+            return None;
+        }
+        Some(old_protection)
+    }
+
+    unsafe fn restore_protection(&self, address: *mut c_void, len: usize, protection: DWORD) {
+        let mut old_protection: DWORD = 0;
+        VirtualProtect(address, len, protection, &mut old_protection);
+    }
+
+    unsafe fn alloc_executable(&self, near: *const c_void, len: usize) -> *mut c_void {
+        if cfg!(target_pointer_width = "64") {
+            let near_memory = alloc_executable_near(near as usize, len);
+            if !near_memory.is_null() {
+                return near_memory;
+            }
+        }
+        VirtualAlloc(
+            ptr::null_mut(),
+            len,
+            MEM_COMMIT | MEM_RESERVE,
+            PAGE_EXECUTE_READWRITE,
+        )
+    }
+
+    unsafe fn free_executable(&self, address: *mut c_void, _len: usize) {
+        VirtualFree(address, 0, MEM_RELEASE);
+    }
+
+    unsafe fn flush_instruction_cache(&self, address: *const c_void, len: usize) {
+        FlushInstructionCache(GetCurrentProcess(), address, len);
+    }
+}
+
+/// Granularity of `VirtualAlloc` placement on every Windows version.
+const ALLOCATION_GRANULARITY: usize = 0x1_0000;
+/// How far a 32-bit relative jump reaches, less some slack for the jump itself.
+const NEAR_RANGE: usize = 0x7FF0_0000;
+
+/// Walk the free regions within [`NEAR_RANGE`] of `near` and allocate in the first one that fits.
+unsafe fn alloc_executable_near(near: usize, len: usize) -> *mut c_void {
+    let end = near.saturating_add(NEAR_RANGE);
+    let mut address = near.saturating_sub(NEAR_RANGE).max(ALLOCATION_GRANULARITY);
+    while address < end {
+        let mut info: MEMORY_BASIC_INFORMATION = mem::zeroed();
+        if VirtualQuery(
+            address as *const c_void,
+            &mut info,
+            mem::size_of::<MEMORY_BASIC_INFORMATION>(),
+        ) == 0
+        {
+            break;
+        }
+
+        let region_start = info.BaseAddress as usize;
+        let region_end = region_start + info.RegionSize;
+        if info.State == MEM_FREE {
+            let candidate =
+                (region_start + ALLOCATION_GRANULARITY - 1) & !(ALLOCATION_GRANULARITY - 1);
+            if candidate + len <= region_end && candidate + len <= end {
+                let memory = VirtualAlloc(
+                    candidate as *mut c_void,
+                    len,
+                    MEM_COMMIT | MEM_RESERVE,
+                    PAGE_EXECUTE_READWRITE,
+                );
+                if !memory.is_null() {
+                    return memory;
+                }
+            }
+        }
+        address = region_end;
+    }
+    ptr::null_mut()
+}
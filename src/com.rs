@@ -0,0 +1,124 @@
+//! Calls through raw COM vtables, the same way on every platform.
+
+use std::ffi::c_void;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::sys::{GUID, HRESULT};
+
+/// Number of slots in the `IDirect3D9` vtable, including `IUnknown`.
+pub const DIRECT3D9_VTABLE_LEN: usize = 17;
+/// Number of slots in the `IDirect3DDevice9` vtable, including `IUnknown`.
+pub const DEVICE_VTABLE_LEN: usize = 119;
+/// Number of slots in the `IDirect3D9Ex` vtable, including `IDirect3D9`.
+pub const DIRECT3D9EX_VTABLE_LEN: usize = 22;
+/// Number of slots in the `IDirect3DDevice9Ex` vtable, including `IDirect3DDevice9`.
+pub const DEVICE_EX_VTABLE_LEN: usize = 134;
+/// Number of slots in the `IDirect3DSwapChain9` vtable, including `IUnknown`.
+pub const SWAP_CHAIN_VTABLE_LEN: usize = 10;
+/// Number of slots in the `IDirect3DStateBlock9` vtable, including `IUnknown`.
+pub const STATE_BLOCK_VTABLE_LEN: usize = 6;
+
+/// Slot of `IUnknown::AddRef` in every COM vtable.
+pub const ADD_REF_SLOT: usize = 1;
+/// Slot of `IUnknown::Release` in every COM vtable.
+pub const RELEASE_SLOT: usize = 2;
+
+/// `IID_IDirect3DDevice9Ex`, answered by `QueryInterface` on devices created through
+/// `IDirect3D9Ex`.
+pub const IID_IDIRECT3DDEVICE9EX: GUID = GUID {
+    Data1: 0xB18B_10CE,
+    Data2: 0x2649,
+    Data3: 0x405A,
+    Data4: [0x87, 0x0F, 0x95, 0xF7, 0x77, 0xD4, 0x31, 0x3A],
+};
+
+/// Slot of `IUnknown::QueryInterface` in every COM vtable.
+pub const QUERY_INTERFACE_SLOT: usize = 0;
+
+/// References held by the crate's dummy objects and captured devices, oldest first, so
+/// [`shutdown`](crate::shutdown_in) can release the ones still alive.
+static OWNED: Mutex<Vec<(OwnedRef, usize)>> = Mutex::new(Vec::new());
+
+/// Source of the [`OwnedRef`] tokens.
+static NEXT_OWNED: AtomicUsize = AtomicUsize::new(1);
+
+/// One reference recorded by [`track_owned`].
+///
+/// Entries are found by this token rather than by address: once a shutdown has released an
+/// object, a new one may be allocated at the same address and tracked by another owner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct OwnedRef(usize);
+
+type QueryInterfaceFn =
+    unsafe extern "system" fn(*mut c_void, *const GUID, *mut *mut c_void) -> HRESULT;
+type RefCountFn = unsafe extern "system" fn(*mut c_void) -> u32;
+
+/// The vtable of a COM object: an array of method pointers.
+pub(crate) unsafe fn vtable<T>(object: *mut T) -> *const *const c_void {
+    *(object as *const *const *const c_void)
+}
+
+/// The method pointer in `slot` of `object`'s vtable.
+pub(crate) unsafe fn method<T>(object: *mut T, slot: usize) -> *const c_void {
+    *vtable(object).add(slot)
+}
+
+pub(crate) unsafe fn add_ref<T>(object: *mut T) -> u32 {
+    let add_ref: RefCountFn = mem::transmute(method(object, ADD_REF_SLOT));
+    add_ref(object as *mut c_void)
+}
+
+pub(crate) unsafe fn release<T>(object: *mut T) -> u32 {
+    let release: RefCountFn = mem::transmute(method(object, RELEASE_SLOT));
+    release(object as *mut c_void)
+}
+
+/// Record that the crate owns one reference to `object`, to be given up with
+/// [`release_owned`] or [`release_every_owned`].
+pub(crate) fn track_owned<T>(object: *mut T) -> OwnedRef {
+    let token = OwnedRef(NEXT_OWNED.fetch_add(1, Ordering::Relaxed));
+    OWNED
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .push((token, object as usize));
+    token
+}
+
+/// Release the reference recorded as `token` by [`track_owned`], unless a shutdown already
+/// did.
+pub(crate) unsafe fn release_owned(token: OwnedRef) {
+    let mut owned = OWNED.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some(index) = owned.iter().position(|&(owned, _)| owned == token) {
+        let (_, object) = owned.remove(index);
+        drop(owned);
+        release(object as *mut c_void);
+    }
+}
+
+/// Release every reference recorded by [`track_owned`], newest first, returning how many.
+pub(crate) unsafe fn release_every_owned() -> usize {
+    let owned = mem::take(&mut *OWNED.lock().unwrap_or_else(|err| err.into_inner()));
+    for &(_, object) in owned.iter().rev() {
+        release(object as *mut c_void);
+    }
+    owned.len()
+}
+
+/// Whether `object` implements the interface `iid`, asked through `QueryInterface`.
+pub(crate) unsafe fn supports<T>(object: *mut T, iid: &GUID) -> bool {
+    let query_interface: QueryInterfaceFn = mem::transmute(method(object, QUERY_INTERFACE_SLOT));
+    let mut interface = ptr::null_mut();
+    if query_interface(object as *mut c_void, iid, &mut interface) < 0 || interface.is_null() {
+        return false;
+    }
+    release(interface);
+    true
+}
+
+/// Whether two GUIDs are the same, as `winapi`'s `GUID` does not implement `PartialEq`.
+pub fn guid_eq(a: &GUID, b: &GUID) -> bool {
+    (a.Data1, a.Data2, a.Data3, a.Data4) == (b.Data1, b.Data2, b.Data3, b.Data4)
+}
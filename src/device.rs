@@ -0,0 +1,224 @@
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr::{self, NonNull};
+
+use crate::com::{self, OwnedRef};
+use crate::sys::*;
+use crate::vtable::{DeviceExVTable, DeviceMethod, DeviceVTable, SwapChainVTable};
+use crate::window::HiddenWindow;
+use crate::{D3D9GrabError, D3dResult};
+
+type GetSwapChainFn = unsafe extern "system" fn(
+    *mut IDirect3DDevice9,
+    UINT,
+    *mut *mut IDirect3DSwapChain9,
+) -> HRESULT;
+
+/// A device created by the grabber, together with the `IDirect3D9` it came from.
+///
+/// Both interfaces are released when this value is dropped, the device first, unless a
+/// [`shutdown`](crate::shutdown_in) released them already. The device is only reachable
+/// through this owner, so it cannot be handed out mutably to two places. If the device was
+/// created on a [`HiddenWindow`], that window is destroyed last.
+pub struct DummyDevice {
+    device: NonNull<IDirect3DDevice9>,
+    direct3d9: NonNull<IDirect3D9>,
+    /// The references to `device` and `direct3d9`, released in that order.
+    owned: [OwnedRef; 2],
+    // Dropped after `Drop::drop` has released the device living on it.
+    hidden_window: Option<HiddenWindow>,
+}
+
+impl DummyDevice {
+    /// Take ownership of one reference to each of `device` and `direct3d9`.
+    ///
+    /// # Safety
+    ///
+    /// Both must be live COM objects and the caller must own the references being passed.
+    pub unsafe fn from_raw(
+        device: NonNull<IDirect3DDevice9>,
+        direct3d9: NonNull<IDirect3D9>,
+    ) -> Self {
+        let direct3d9_ref = com::track_owned(direct3d9.as_ptr());
+        let device_ref = com::track_owned(device.as_ptr());
+        DummyDevice {
+            device,
+            direct3d9,
+            owned: [device_ref, direct3d9_ref],
+            hidden_window: None,
+        }
+    }
+
+    pub(crate) fn with_hidden_window(mut self, hidden_window: Option<HiddenWindow>) -> Self {
+        self.hidden_window = hidden_window;
+        self
+    }
+
+    /// The device's COM pointer. It stays valid for as long as this value is alive.
+    pub fn as_ptr(&self) -> *mut IDirect3DDevice9 {
+        self.device.as_ptr()
+    }
+
+    /// The `IDirect3D9` the device was created from.
+    pub fn direct3d9(&self) -> *mut IDirect3D9 {
+        self.direct3d9.as_ptr()
+    }
+
+    /// The device's vtable, one method pointer per `IDirect3DDevice9` slot.
+    pub fn vtable(&self) -> &DeviceVTable {
+        unsafe { &*(com::vtable(self.as_ptr()) as *const DeviceVTable) }
+    }
+
+    /// The device's swap chain number `index`, through `IDirect3DDevice9::GetSwapChain`.
+    ///
+    /// Index 0 is the implicit swap chain that the device's own `Present` goes through.
+    pub fn swap_chain(&self, index: UINT) -> Result<DummySwapChain<'_>, D3D9GrabError> {
+        let mut swap_chain: *mut IDirect3DSwapChain9 = ptr::null_mut();
+        let result = unsafe {
+            let get_swap_chain: GetSwapChainFn = mem::transmute(com::method(
+                self.as_ptr(),
+                DeviceMethod::GetSwapChain.index(),
+            ));
+            get_swap_chain(self.as_ptr(), index, &mut swap_chain)
+        };
+
+        if result != 0 {
+            return Err(D3D9GrabError::GetSwapChainFailed(D3dResult::from(result)));
+        }
+        match NonNull::new(swap_chain) {
+            Some(swap_chain) => {
+                let owned = com::track_owned(swap_chain.as_ptr());
+                Ok(DummySwapChain {
+                    swap_chain,
+                    owned,
+                    device: PhantomData,
+                })
+            }
+            None => Err(D3D9GrabError::NullSwapChain),
+        }
+    }
+}
+
+impl Drop for DummyDevice {
+    fn drop(&mut self) {
+        for &owned in &self.owned {
+            unsafe { com::release_owned(owned) }
+        }
+    }
+}
+
+/// The game's own device, found by [`capture_live_device`](crate::hook::capture_live_device).
+///
+/// Holds one reference to the device, released on drop unless a
+/// [`shutdown`](crate::shutdown_in) released it already.
+pub struct LiveDevice {
+    device: NonNull<IDirect3DDevice9>,
+    owned: OwnedRef,
+}
+
+impl LiveDevice {
+    /// Take ownership of one reference to `device`.
+    ///
+    /// # Safety
+    ///
+    /// `device` must be a live COM object and the caller must own the reference being passed.
+    pub unsafe fn from_raw(device: NonNull<IDirect3DDevice9>) -> Self {
+        let owned = com::track_owned(device.as_ptr());
+        LiveDevice { device, owned }
+    }
+
+    /// The device's COM pointer. It stays valid for as long as this value is alive.
+    pub fn as_ptr(&self) -> *mut IDirect3DDevice9 {
+        self.device.as_ptr()
+    }
+
+    /// The device's vtable, which is the one shared with the dummy devices.
+    pub fn vtable(&self) -> &DeviceVTable {
+        unsafe { &*(com::vtable(self.as_ptr()) as *const DeviceVTable) }
+    }
+}
+
+impl Drop for LiveDevice {
+    fn drop(&mut self) {
+        unsafe { com::release_owned(self.owned) }
+    }
+}
+
+/// An `IDirect3DDevice9Ex` created by the grabber, together with the `IDirect3D9Ex` it came from.
+///
+/// Releases both on drop, exactly like [`DummyDevice`].
+pub struct DummyDeviceEx {
+    device: DummyDevice,
+}
+
+impl DummyDeviceEx {
+    /// Take ownership of one reference to each of `device` and `direct3d9_ex`.
+    ///
+    /// # Safety
+    ///
+    /// Both must be live COM objects and the caller must own the references being passed.
+    pub unsafe fn from_raw(
+        device: NonNull<IDirect3DDevice9Ex>,
+        direct3d9_ex: NonNull<IDirect3D9Ex>,
+    ) -> Self {
+        // The Ex interfaces extend the plain ones, so the same pointers are valid as both.
+        DummyDeviceEx {
+            device: DummyDevice::from_raw(device.cast(), direct3d9_ex.cast()),
+        }
+    }
+
+    pub(crate) fn with_hidden_window(self, hidden_window: Option<HiddenWindow>) -> Self {
+        DummyDeviceEx {
+            device: self.device.with_hidden_window(hidden_window),
+        }
+    }
+
+    /// The device's COM pointer. It stays valid for as long as this value is alive.
+    pub fn as_ptr(&self) -> *mut IDirect3DDevice9Ex {
+        self.device.as_ptr() as *mut IDirect3DDevice9Ex
+    }
+
+    /// The `IDirect3D9Ex` the device was created from.
+    pub fn direct3d9_ex(&self) -> *mut IDirect3D9Ex {
+        self.device.direct3d9() as *mut IDirect3D9Ex
+    }
+
+    /// The same device seen through its `IDirect3DDevice9` interface.
+    pub fn device(&self) -> &DummyDevice {
+        &self.device
+    }
+
+    /// The device's vtable, including the `IDirect3DDevice9Ex` slots.
+    pub fn vtable(&self) -> &DeviceExVTable {
+        unsafe { &*(com::vtable(self.as_ptr()) as *const DeviceExVTable) }
+    }
+}
+
+/// A swap chain of a [`DummyDevice`], released on drop.
+///
+/// It borrows the device, so it can never outlive the objects backing it.
+pub struct DummySwapChain<'a> {
+    swap_chain: NonNull<IDirect3DSwapChain9>,
+    owned: OwnedRef,
+    device: PhantomData<&'a DummyDevice>,
+}
+
+impl DummySwapChain<'_> {
+    /// The swap chain's COM pointer. It stays valid for as long as this value is alive.
+    pub fn as_ptr(&self) -> *mut IDirect3DSwapChain9 {
+        self.swap_chain.as_ptr()
+    }
+
+    /// The swap chain's vtable, one method pointer per `IDirect3DSwapChain9` slot.
+    pub fn vtable(&self) -> &SwapChainVTable {
+        unsafe { &*(com::vtable(self.as_ptr()) as *const SwapChainVTable) }
+    }
+}
+
+impl Drop for DummySwapChain<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            com::release_owned(self.owned);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! The `DllMain` of an injected `cdylib`, generated by [`d3d9_entry!`](crate::d3d9_entry).
+//!
+//! Almost nothing is safe to do inside `DllMain` while the loader lock is held, grabbing a
+//! device least of all. The generated `DllMain` only starts a thread, which waits for the
+//! loader lock to be released before doing the real work.
+
+use crate::{D3D9GrabError, DummyDevice, GrabOptions, WaitPolicy};
+
+/// The function [`d3d9_entry!`](crate::d3d9_entry) calls once a device is grabbed, or grabbing
+/// has failed for good.
+pub type InitFn = fn(Result<DummyDevice, D3D9GrabError>);
+
+/// What the `DllMain` generated by [`d3d9_entry!`](crate::d3d9_entry) does before calling the
+/// init function.
+#[derive(Debug)]
+pub struct EntryOptions {
+    /// Allocate a console for the process, so `println!` output can be seen.
+    pub console: bool,
+    /// How to grab the device. By default this waits for the process's window indefinitely,
+    /// as the DLL may be injected before the game opened it.
+    pub grab: GrabOptions,
+}
+
+impl EntryOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn console(mut self, console: bool) -> Self {
+        self.console = console;
+        self
+    }
+
+    pub fn grab(mut self, grab: GrabOptions) -> Self {
+        self.grab = grab;
+        self
+    }
+}
+
+impl Default for EntryOptions {
+    fn default() -> Self {
+        EntryOptions {
+            console: false,
+            grab: GrabOptions::new().wait(WaitPolicy::new()),
+        }
+    }
+}
+
+#[cfg(windows)]
+mod dll_main {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::ptr;
+
+    use winapi::shared::minwindef::{BOOL, DWORD, FALSE, HINSTANCE, LPVOID, TRUE};
+    use winapi::um::consoleapi::AllocConsole;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::libloaderapi::DisableThreadLibraryCalls;
+    use winapi::um::processthreadsapi::CreateThread;
+    use winapi::um::winnt::DLL_PROCESS_ATTACH;
+
+    use super::{EntryOptions, InitFn};
+    use crate::backend::WinApiBackend;
+    use crate::DeviceGrabber;
+
+    struct Start {
+        options: fn() -> EntryOptions,
+        init: InitFn,
+    }
+
+    /// The body of the `DllMain` generated by [`d3d9_entry!`](crate::d3d9_entry).
+    ///
+    /// `options` is only called on the new thread, so building the options never happens
+    /// under the loader lock.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called from `DllMain`, with its arguments.
+    #[doc(hidden)]
+    pub unsafe fn dll_main(
+        module: HINSTANCE,
+        reason: DWORD,
+        options: fn() -> EntryOptions,
+        init: InitFn,
+    ) -> BOOL {
+        if reason != DLL_PROCESS_ATTACH {
+            return TRUE;
+        }
+        DisableThreadLibraryCalls(module);
+
+        // The thread cannot start running before the loader lock is released, which happens
+        // once this returns.
+        let start = Box::into_raw(Box::new(Start { options, init }));
+        let thread = CreateThread(
+            ptr::null_mut(),
+            0,
+            Some(init_thread),
+            start as LPVOID,
+            0,
+            ptr::null_mut(),
+        );
+        if thread.is_null() {
+            drop(Box::from_raw(start));
+            return FALSE;
+        }
+        CloseHandle(thread);
+        TRUE
+    }
+
+    unsafe extern "system" fn init_thread(start: LPVOID) -> DWORD {
+        let start = Box::from_raw(start as *mut Start);
+        // Unwinding out of the thread's start routine would be undefined behaviour.
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+            let options = (start.options)();
+            if options.console {
+                AllocConsole();
+            }
+            (start.init)(DeviceGrabber::from(options.grab).device_in(&WinApiBackend));
+        }));
+        0
+    }
+}
+
+#[cfg(windows)]
+#[doc(hidden)]
+pub use self::dll_main::dll_main;
+
+/// Generate the `DllMain` of an injected `cdylib`, which calls `init` on a new thread with
+/// the grabbed device.
+///
+/// The thread waits for the process to have a window before grabbing, following the
+/// [`WaitPolicy`](crate::WaitPolicy) in the [`EntryOptions`], which can be passed as a second
+/// argument. `init` must be a
+/// `fn(Result<DummyDevice, D3D9GrabError>)`.
+///
+/// `examples/entry.rs` builds a DLL with it:
+///
+/// ```ignore
+/// fn init(device: Result<DummyDevice, D3D9GrabError>) {
+///     match device {
+///         Ok(device) => println!("grabbed {:?}", device.as_ptr()),
+///         Err(err) => println!("error getting d3d9 device: {}", err),
+///     }
+/// }
+///
+/// d3d9_device_grabber::d3d9_entry!(init, EntryOptions::new().console(true));
+/// ```
+#[macro_export]
+macro_rules! d3d9_entry {
+    ($init:path) => {
+        $crate::d3d9_entry!($init, $crate::entry::EntryOptions::new());
+    };
+    ($init:path, $options:expr) => {
+        #[no_mangle]
+        #[allow(non_snake_case)]
+        pub unsafe extern "system" fn DllMain(
+            module: $crate::sys::HINSTANCE,
+            reason: $crate::sys::DWORD,
+            _reserved: *mut ::std::ffi::c_void,
+        ) -> $crate::sys::BOOL {
+            $crate::entry::dll_main(module, reason, || $options, $init)
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_waits_for_the_window_without_a_console() {
+        let options = EntryOptions::default();
+
+        assert!(!options.console);
+        assert!(!options.grab.hidden_window);
+        let wait = options.grab.wait.expect("the default waits for the window");
+        assert_eq!(wait.timeout, None);
+    }
+
+    #[test]
+    fn builder_sets_the_fields() {
+        let options = EntryOptions::new()
+            .console(true)
+            .grab(GrabOptions::new().hidden_window(true));
+
+        assert!(options.console);
+        assert!(options.grab.hidden_window);
+        assert!(options.grab.wait.is_none());
+    }
+}
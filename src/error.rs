@@ -0,0 +1,189 @@
+use std::fmt;
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::fallback::device_type_name;
+use crate::sys::*;
+use crate::{D3dResult, DeviceMethod};
+
+#[derive(Debug, Error)]
+pub enum D3D9GrabError {
+    #[error("d3d9.CreateDevice returned a null device despite succeeding")]
+    NullDevice,
+    #[error("d3d9.CreateDevice call failed: {}", describe_attempts(.attempts))]
+    CreateDeviceError { attempts: Vec<CreateDeviceAttempt> },
+    #[error("D3DCreate9 call returned null")]
+    D3DCreate9Null,
+    #[error("Could not get current process window handle ({enumerated} windows enumerated, {owned} owned by this process)")]
+    GetProcessWindowFailed { enumerated: usize, owned: usize },
+    #[error("d3d9.dll does not export Direct3DCreate9Ex, which needs Windows Vista or later")]
+    Direct3DCreate9ExUnavailable,
+    #[error("Direct3DCreate9Ex call failed with {0}")]
+    Direct3DCreate9ExFailed(D3dResult),
+    #[error("IDirect3DDevice9.GetSwapChain call failed with {0}")]
+    GetSwapChainFailed(D3dResult),
+    #[error("IDirect3DDevice9.GetSwapChain returned a null swap chain despite succeeding")]
+    NullSwapChain,
+    #[error("Could not create a hidden window to host the device")]
+    CreateHiddenWindowFailed,
+    #[error("Invalid window title pattern `{pattern}`: {reason}")]
+    InvalidTitlePattern { pattern: String, reason: String },
+    #[error("Setup {index} of the fallback chain has invalid present parameters: {source}")]
+    InvalidPresentParams {
+        index: usize,
+        source: PresentParamsError,
+    },
+}
+
+/// Why a [`PresentParamsBuilder`](crate::PresentParamsBuilder) was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum PresentParamsError {
+    #[error("Fullscreen mode needs a back buffer size and format, not {width}x{height} in format {format}")]
+    FullscreenNeedsSizeAndFormat {
+        width: UINT,
+        height: UINT,
+        format: D3DFORMAT,
+    },
+    #[error("{count} back buffers were asked for, but d3d9 allows at most 3")]
+    TooManyBackBuffers { count: UINT },
+    #[error("D3DSWAPEFFECT_COPY allows a single back buffer, but {count} were asked for")]
+    CopyWithSeveralBackBuffers { count: UINT },
+    #[error("Multisampling (type {multisample_type}) needs D3DSWAPEFFECT_DISCARD, not swap effect {swap_effect}")]
+    MultisampleWithoutDiscard {
+        multisample_type: D3DMULTISAMPLE_TYPE,
+        swap_effect: D3DSWAPEFFECT,
+    },
+    #[error("A multisample quality of {quality} was given without a multisample type")]
+    QualityWithoutMultisample { quality: DWORD },
+    #[error("A multisampled back buffer cannot be lockable")]
+    LockableMultisampledBackBuffer,
+    #[error("A refresh rate ({refresh_rate}Hz) can only be set in fullscreen mode")]
+    RefreshRateWhenWindowed { refresh_rate: UINT },
+    #[error("Presentation interval {interval:#x} is only available in fullscreen mode")]
+    IntervalWhenWindowed { interval: UINT },
+    #[error(
+        "A depth stencil format ({format}) was given without enabling the automatic depth stencil"
+    )]
+    DepthFormatWithoutAutoDepthStencil { format: D3DFORMAT },
+    #[error("The automatic depth stencil is enabled without a depth stencil format")]
+    AutoDepthStencilWithoutFormat,
+    #[error("D3DPRESENTFLAG_DISCARD_DEPTHSTENCIL needs the automatic depth stencil to be enabled")]
+    DiscardDepthStencilWithoutAutoDepthStencil,
+}
+
+/// Why a [`Batch`](crate::overlay::Batch) could not be drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum OverlayError {
+    #[error("IDirect3DDevice9.{} call failed with {result}", .method.name())]
+    DeviceCallFailed {
+        method: DeviceMethod,
+        result: D3dResult,
+    },
+    #[error("IDirect3DDevice9.CreateStateBlock returned a null state block despite succeeding")]
+    NullStateBlock,
+    #[error("IDirect3DStateBlock9.Apply call failed with {0}, leaving the overlay's render state on the device")]
+    RestoreStateFailed(D3dResult),
+}
+
+#[derive(Debug, Error)]
+pub enum HookError {
+    #[error("Slot {slot} is past the end of a vtable of {len} slots")]
+    SlotOutOfRange { slot: usize, len: usize },
+    #[error("Slot {slot} is already hooked")]
+    AlreadyHooked { slot: usize },
+    #[error("Slot {slot} is not hooked")]
+    NotHooked { slot: usize },
+    #[error("Could not make {len} bytes at {address:#x} writable")]
+    ProtectFailed { address: usize, len: usize },
+    #[error("The function at {address:#x} is already detoured")]
+    AlreadyDetoured { address: usize },
+    #[error(
+        "Cannot move the instruction at offset {offset} (opcode {opcode:#04x}) into a trampoline"
+    )]
+    UnsupportedInstruction { offset: usize, opcode: u8 },
+    #[error("Cannot move the branch at offset {offset} into a trampoline, as it jumps back into the patched bytes")]
+    BranchIntoPatch { offset: usize },
+    #[error("The instruction at offset {offset} cannot reach its target from the trampoline")]
+    RelocationOutOfRange { offset: usize },
+    #[error("The function ends after {len} bytes, before there is room for the jump")]
+    FunctionTooShort { len: usize },
+    #[error("Could not allocate executable memory for a trampoline")]
+    AllocTrampolineFailed,
+    #[error("Device hooks are already installed")]
+    AlreadyInstalled,
+    #[error("{in_flight} hooked calls were still running when shutdown stopped waiting")]
+    DrainTimedOut { in_flight: usize },
+    #[error("No device called EndScene or Present within {timeout:?}")]
+    CaptureTimedOut { timeout: Duration },
+    #[error("Another capture of the live device is already running")]
+    CaptureInProgress,
+    #[error("Could not grab a device to hook: {0}")]
+    Grab(#[from] D3D9GrabError),
+}
+
+/// One failed `CreateDevice` call: what it returned and the arguments it was given.
+#[derive(Clone, Copy)]
+pub struct CreateDeviceAttempt {
+    pub result: D3dResult,
+    pub device_type: D3DDEVTYPE,
+    pub behavior_flags: DWORD,
+    pub present_params: D3DPRESENT_PARAMETERS,
+}
+
+impl CreateDeviceAttempt {
+    pub fn windowed(&self) -> bool {
+        self.present_params.Windowed != FALSE
+    }
+}
+
+fn describe_attempts(attempts: &[CreateDeviceAttempt]) -> String {
+    if attempts.is_empty() {
+        return "the fallback chain is empty".to_string();
+    }
+    let described: Vec<String> = attempts
+        .iter()
+        .map(|attempt| {
+            let mode = if attempt.windowed() {
+                "windowed"
+            } else {
+                "fullscreen"
+            };
+            format!(
+                "{} {} mode returned {}",
+                device_type_name(attempt.device_type),
+                mode,
+                attempt.result
+            )
+        })
+        .collect();
+    described.join(", then ")
+}
+
+impl fmt::Debug for CreateDeviceAttempt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let params = &self.present_params;
+        f.debug_struct("CreateDeviceAttempt")
+            .field("result", &self.result)
+            .field("device_type", &self.device_type)
+            .field("behavior_flags", &self.behavior_flags)
+            .field("BackBufferWidth", &params.BackBufferWidth)
+            .field("BackBufferHeight", &params.BackBufferHeight)
+            .field("BackBufferFormat", &params.BackBufferFormat)
+            .field("BackBufferCount", &params.BackBufferCount)
+            .field("MultiSampleType", &params.MultiSampleType)
+            .field("MultiSampleQuality", &params.MultiSampleQuality)
+            .field("SwapEffect", &params.SwapEffect)
+            .field("hDeviceWindow", &params.hDeviceWindow)
+            .field("Windowed", &params.Windowed)
+            .field("EnableAutoDepthStencil", &params.EnableAutoDepthStencil)
+            .field("AutoDepthStencilFormat", &params.AutoDepthStencilFormat)
+            .field("Flags", &params.Flags)
+            .field(
+                "FullScreen_RefreshRateInHz",
+                &params.FullScreen_RefreshRateInHz,
+            )
+            .field("PresentationInterval", &params.PresentationInterval)
+            .finish()
+    }
+}
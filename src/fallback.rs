@@ -0,0 +1,138 @@
+//! The `CreateDevice` calls tried, in order, until one of them gives a device.
+
+use std::borrow::Cow;
+use std::iter::FromIterator;
+
+use crate::sys::*;
+use crate::PresentParamsBuilder;
+
+/// The arguments of one `CreateDevice` call in a [`FallbackChain`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceSetup {
+    /// `D3DDEVTYPE_HAL`, `D3DDEVTYPE_REF` or `D3DDEVTYPE_NULLREF`.
+    pub device_type: D3DDEVTYPE,
+    /// The `D3DCREATE_*` flags, which must include one of the vertex processing ones.
+    pub behavior_flags: DWORD,
+    /// Everything but the window, which is filled in when the device is created.
+    pub present_params: PresentParamsBuilder,
+}
+
+impl DeviceSetup {
+    /// A hardware device with software vertex processing, on
+    /// [`PresentParamsBuilder::dummy_windowed`].
+    pub fn new() -> Self {
+        DeviceSetup {
+            device_type: D3DDEVTYPE_HAL,
+            behavior_flags: D3DCREATE_SOFTWARE_VERTEXPROCESSING,
+            present_params: PresentParamsBuilder::dummy_windowed(),
+        }
+    }
+
+    pub fn device_type(mut self, device_type: D3DDEVTYPE) -> Self {
+        self.device_type = device_type;
+        self
+    }
+
+    pub fn behavior_flags(mut self, behavior_flags: DWORD) -> Self {
+        self.behavior_flags = behavior_flags;
+        self
+    }
+
+    pub fn present_params(mut self, present_params: PresentParamsBuilder) -> Self {
+        self.present_params = present_params;
+        self
+    }
+
+    pub fn windowed(mut self, windowed: bool) -> Self {
+        self.present_params.windowed = windowed;
+        self
+    }
+
+    pub fn back_buffer_size(mut self, width: UINT, height: UINT) -> Self {
+        self.present_params.back_buffer_width = width;
+        self.present_params.back_buffer_height = height;
+        self
+    }
+
+    pub fn back_buffer_format(mut self, format: D3DFORMAT) -> Self {
+        self.present_params.back_buffer_format = format;
+        self
+    }
+
+    pub fn swap_effect(mut self, swap_effect: D3DSWAPEFFECT) -> Self {
+        self.present_params.swap_effect = swap_effect;
+        self
+    }
+}
+
+impl Default for DeviceSetup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The [`DeviceSetup`]s to try one after the other, stopping at the first that creates a
+/// device.
+///
+/// ```ignore
+/// let options = GrabOptions::new().fallback(
+///     FallbackChain::new()
+///         .then(DeviceSetup::new().behavior_flags(D3DCREATE_HARDWARE_VERTEXPROCESSING))
+///         .then(DeviceSetup::new())
+///         .then(DeviceSetup::new().device_type(D3DDEVTYPE_NULLREF)),
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FallbackChain {
+    setups: Vec<DeviceSetup>,
+}
+
+impl FallbackChain {
+    /// A chain without any setups, which fails without calling `CreateDevice`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// What a process window is tried with by default: a hardware device in fullscreen mode,
+    /// then in windowed mode.
+    pub fn for_process_window() -> Self {
+        FallbackChain::new()
+            .then(DeviceSetup::new().present_params(PresentParamsBuilder::dummy_fullscreen()))
+            .then(DeviceSetup::new())
+    }
+
+    /// What a hidden window is tried with by default: a windowed hardware device only, as
+    /// going fullscreen would take focus and the display mode away from the game.
+    pub fn for_hidden_window() -> Self {
+        FallbackChain::new().then(DeviceSetup::new())
+    }
+
+    /// Try `setup` after the setups already in the chain.
+    pub fn then(mut self, setup: DeviceSetup) -> Self {
+        self.setups.push(setup);
+        self
+    }
+
+    pub fn setups(&self) -> &[DeviceSetup] {
+        &self.setups
+    }
+}
+
+impl FromIterator<DeviceSetup> for FallbackChain {
+    fn from_iter<I: IntoIterator<Item = DeviceSetup>>(iter: I) -> Self {
+        FallbackChain {
+            setups: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// The `D3DDEVTYPE_` name of `device_type` without the prefix, for error messages.
+pub(crate) fn device_type_name(device_type: D3DDEVTYPE) -> Cow<'static, str> {
+    match device_type {
+        D3DDEVTYPE_HAL => "HAL".into(),
+        D3DDEVTYPE_REF => "REF".into(),
+        D3DDEVTYPE_SW => "SW".into(),
+        D3DDEVTYPE_NULLREF => "NULLREF".into(),
+        other => format!("device type {}", other).into(),
+    }
+}
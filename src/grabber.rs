@@ -0,0 +1,430 @@
+//! The one entry point for creating a dummy device, configured like [`GrabOptions`].
+
+use std::borrow::Cow;
+use std::ptr::{self, NonNull};
+
+use crate::backend::Backend;
+#[cfg(windows)]
+use crate::backend::WinApiBackend;
+use crate::sys::*;
+use crate::window::{HiddenWindow, WindowInfo, WindowSelector};
+use crate::{
+    com, CreateDeviceAttempt, D3D9GrabError, D3D9VTables, D3dResult, DeviceExVTable, DeviceSetup,
+    DeviceVTable, DummyDevice, DummyDeviceEx, FallbackChain, GrabOptions, PresentParamsBuilder,
+    WaitPolicy,
+};
+
+/// Finds a window, creates a dummy device on it and hands back what the caller asked for.
+///
+/// Every setting starts out as in [`GrabOptions::default`]: the first window of the process,
+/// no waiting, and the default [`FallbackChain`].
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use d3d9_device_grabber::backend::Backend;
+/// # use d3d9_device_grabber::window::WindowSelector;
+/// # use d3d9_device_grabber::{D3D9GrabError, DeviceGrabber, WaitPolicy};
+/// # unsafe fn grab<B: Backend>(backend: &B) -> Result<(), D3D9GrabError> {
+/// // `device_with_hwnd` and `vtables` do the same through the Win32 backend.
+/// let (device, hwnd) = DeviceGrabber::new()
+///     .window(WindowSelector::LargestClientArea)
+///     .wait(WaitPolicy::new().timeout(Some(Duration::from_secs(10))))
+///     .device_with_hwnd_in(backend)?;
+///
+/// let vtables = DeviceGrabber::new().hidden_window(true).vtables_in(backend)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct DeviceGrabber {
+    options: GrabOptions,
+}
+
+impl DeviceGrabber {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn window(mut self, window: WindowSelector) -> Self {
+        self.options.window = window;
+        self
+    }
+
+    pub fn hidden_window(mut self, hidden_window: bool) -> Self {
+        self.options.hidden_window = hidden_window;
+        self
+    }
+
+    pub fn wait(mut self, wait: WaitPolicy) -> Self {
+        self.options.wait = Some(wait);
+        self
+    }
+
+    pub fn fallback(mut self, fallback: FallbackChain) -> Self {
+        self.options.fallback = Some(fallback);
+        self
+    }
+
+    /// Make a single attempt, a hardware device with software vertex processing on
+    /// `present_params`. This replaces any fallback chain set before.
+    pub fn present_params(self, present_params: PresentParamsBuilder) -> Self {
+        self.fallback(FallbackChain::new().then(DeviceSetup::new().present_params(present_params)))
+    }
+
+    pub fn options(&self) -> &GrabOptions {
+        &self.options
+    }
+
+    /// Grab the device.
+    ///
+    /// # Safety
+    ///
+    /// Creates a device through `d3d9.dll`. Must not be called from `DllMain`; see
+    /// [`d3d9_entry!`](crate::d3d9_entry).
+    #[cfg(windows)]
+    pub unsafe fn device(&self) -> Result<DummyDevice, D3D9GrabError> {
+        self.device_in(&WinApiBackend)
+    }
+
+    /// Grab the device and the window it was created on.
+    ///
+    /// # Safety
+    ///
+    /// See [`DeviceGrabber::device`].
+    #[cfg(windows)]
+    pub unsafe fn device_with_hwnd(&self) -> Result<(DummyDevice, HWND), D3D9GrabError> {
+        self.device_with_hwnd_in(&WinApiBackend)
+    }
+
+    /// Copy the device's vtable, releasing the device before this returns.
+    ///
+    /// # Safety
+    ///
+    /// See [`DeviceGrabber::device`].
+    #[cfg(windows)]
+    pub unsafe fn vtable(&self) -> Result<DeviceVTable, D3D9GrabError> {
+        self.vtable_in(&WinApiBackend)
+    }
+
+    /// Copy the vtables of the device and of its implicit swap chain, releasing both before
+    /// this returns.
+    ///
+    /// # Safety
+    ///
+    /// See [`DeviceGrabber::device`].
+    #[cfg(windows)]
+    pub unsafe fn vtables(&self) -> Result<D3D9VTables, D3D9GrabError> {
+        self.vtables_in(&WinApiBackend)
+    }
+
+    /// Grab an `IDirect3DDevice9Ex`, for games that render through `Direct3DCreate9Ex`.
+    ///
+    /// Fails with [`D3D9GrabError::Direct3DCreate9ExUnavailable`] before Windows Vista.
+    ///
+    /// # Safety
+    ///
+    /// See [`DeviceGrabber::device`].
+    #[cfg(windows)]
+    pub unsafe fn device_ex(&self) -> Result<DummyDeviceEx, D3D9GrabError> {
+        self.device_ex_in(&WinApiBackend)
+    }
+
+    /// Grab an `IDirect3DDevice9Ex` and the window it was created on.
+    ///
+    /// # Safety
+    ///
+    /// See [`DeviceGrabber::device`].
+    #[cfg(windows)]
+    pub unsafe fn device_ex_with_hwnd(&self) -> Result<(DummyDeviceEx, HWND), D3D9GrabError> {
+        self.device_ex_with_hwnd_in(&WinApiBackend)
+    }
+
+    /// Copy the `IDirect3DDevice9Ex` vtable, releasing the device before this returns.
+    ///
+    /// # Safety
+    ///
+    /// See [`DeviceGrabber::device`].
+    #[cfg(windows)]
+    pub unsafe fn vtable_ex(&self) -> Result<DeviceExVTable, D3D9GrabError> {
+        self.vtable_ex_in(&WinApiBackend)
+    }
+
+    /// Grab the device using `backend` for all window and Direct3D calls.
+    ///
+    /// # Safety
+    ///
+    /// The objects returned by `backend` must be live COM objects.
+    pub unsafe fn device_in<B: Backend>(&self, backend: &B) -> Result<DummyDevice, D3D9GrabError> {
+        grab(backend, &self.options).map(|(device, _)| device)
+    }
+
+    /// # Safety
+    ///
+    /// See [`DeviceGrabber::device_in`].
+    pub unsafe fn device_with_hwnd_in<B: Backend>(
+        &self,
+        backend: &B,
+    ) -> Result<(DummyDevice, HWND), D3D9GrabError> {
+        grab(backend, &self.options)
+    }
+
+    /// # Safety
+    ///
+    /// See [`DeviceGrabber::device_in`].
+    pub unsafe fn vtable_in<B: Backend>(&self, backend: &B) -> Result<DeviceVTable, D3D9GrabError> {
+        let device = self.device_in(backend)?;
+        Ok(*device.vtable())
+    }
+
+    /// # Safety
+    ///
+    /// See [`DeviceGrabber::device_in`].
+    pub unsafe fn vtables_in<B: Backend>(&self, backend: &B) -> Result<D3D9VTables, D3D9GrabError> {
+        let device = self.device_in(backend)?;
+        let swap_chain = device.swap_chain(0)?;
+        Ok(D3D9VTables {
+            device: *device.vtable(),
+            swap_chain: *swap_chain.vtable(),
+        })
+    }
+
+    /// # Safety
+    ///
+    /// See [`DeviceGrabber::device_in`].
+    pub unsafe fn device_ex_in<B: Backend>(
+        &self,
+        backend: &B,
+    ) -> Result<DummyDeviceEx, D3D9GrabError> {
+        grab_ex(backend, &self.options).map(|(device, _)| device)
+    }
+
+    /// # Safety
+    ///
+    /// See [`DeviceGrabber::device_in`].
+    pub unsafe fn device_ex_with_hwnd_in<B: Backend>(
+        &self,
+        backend: &B,
+    ) -> Result<(DummyDeviceEx, HWND), D3D9GrabError> {
+        grab_ex(backend, &self.options)
+    }
+
+    /// # Safety
+    ///
+    /// See [`DeviceGrabber::device_in`].
+    pub unsafe fn vtable_ex_in<B: Backend>(
+        &self,
+        backend: &B,
+    ) -> Result<DeviceExVTable, D3D9GrabError> {
+        let (device, _) = grab_ex(backend, &self.options)?;
+        Ok(*device.vtable())
+    }
+}
+
+impl From<GrabOptions> for DeviceGrabber {
+    fn from(options: GrabOptions) -> Self {
+        DeviceGrabber { options }
+    }
+}
+
+pub(crate) unsafe fn grab<B: Backend>(
+    backend: &B,
+    options: &GrabOptions,
+) -> Result<(DummyDevice, HWND), D3D9GrabError> {
+    let (window, hidden_window) = device_window(backend, options)?;
+
+    let d3d9 = backend.direct3d_create9(D3D_SDK_VERSION);
+
+    let d3d9 = match NonNull::new(d3d9) {
+        Some(d3d9) => d3d9,
+        None => return Err(D3D9GrabError::D3DCreate9Null),
+    };
+
+    let chain = default_chain(options, hidden_window.is_some());
+    let device = create_or_release(
+        window,
+        &chain,
+        d3d9,
+        |setup, present_params, device| {
+            backend.create_device(
+                d3d9.as_ptr(),
+                D3DADAPTER_DEFAULT,
+                setup.device_type,
+                present_params.hDeviceWindow,
+                setup.behavior_flags,
+                present_params,
+                device,
+            )
+        },
+        |device, d3d9| DummyDevice::from_raw(device, d3d9).with_hidden_window(hidden_window),
+    )?;
+    Ok((device, window))
+}
+
+unsafe fn grab_ex<B: Backend>(
+    backend: &B,
+    options: &GrabOptions,
+) -> Result<(DummyDeviceEx, HWND), D3D9GrabError> {
+    let (window, hidden_window) = device_window(backend, options)?;
+
+    let mut d3d9ex: *mut IDirect3D9Ex = ptr::null_mut();
+    match backend.direct3d_create9_ex(D3D_SDK_VERSION, &mut d3d9ex) {
+        None => return Err(D3D9GrabError::Direct3DCreate9ExUnavailable),
+        Some(result) if result != 0 => {
+            return Err(D3D9GrabError::Direct3DCreate9ExFailed(D3dResult::from(
+                result,
+            )))
+        }
+        Some(_) => {}
+    }
+
+    let d3d9ex = match NonNull::new(d3d9ex) {
+        Some(d3d9ex) => d3d9ex,
+        None => return Err(D3D9GrabError::D3DCreate9Null),
+    };
+
+    let chain = default_chain(options, hidden_window.is_some());
+    let device = create_or_release(
+        window,
+        &chain,
+        d3d9ex,
+        |setup, present_params, device| {
+            backend.create_device_ex(
+                d3d9ex.as_ptr(),
+                D3DADAPTER_DEFAULT,
+                setup.device_type,
+                present_params.hDeviceWindow,
+                setup.behavior_flags,
+                present_params,
+                device,
+            )
+        },
+        |device, d3d9ex| DummyDeviceEx::from_raw(device, d3d9ex).with_hidden_window(hidden_window),
+    )?;
+    Ok((device, window))
+}
+
+/// The chain `options` asks for, or the default one for a hidden or a process window.
+fn default_chain(options: &GrabOptions, hidden: bool) -> Cow<'_, FallbackChain> {
+    match &options.fallback {
+        Some(chain) => Cow::Borrowed(chain),
+        None if hidden => Cow::Owned(FallbackChain::for_hidden_window()),
+        None => Cow::Owned(FallbackChain::for_process_window()),
+    }
+}
+
+/// Create a device from `direct3d9` through `create` and take ownership of both with `wrap`,
+/// or give the reference to `direct3d9` back if every setup of `chain` fails.
+///
+/// `D` is `IDirect3D9` or `IDirect3D9Ex`, and `T` the device interface it creates.
+unsafe fn create_or_release<D, T, W, F>(
+    window: HWND,
+    chain: &FallbackChain,
+    direct3d9: NonNull<D>,
+    create: F,
+    wrap: impl FnOnce(NonNull<T>, NonNull<D>) -> W,
+) -> Result<W, D3D9GrabError>
+where
+    F: FnMut(&DeviceSetup, &mut D3DPRESENT_PARAMETERS, &mut *mut T) -> HRESULT,
+{
+    match create_device_with_fallback(window, chain, create) {
+        Ok(device) => Ok(wrap(device, direct3d9)),
+        Err(err) => {
+            com::release(direct3d9.as_ptr());
+            Err(err)
+        }
+    }
+}
+
+/// The window to create the device on, plus the hidden window backing it if we made one.
+fn device_window<B: Backend>(
+    backend: &B,
+    options: &GrabOptions,
+) -> Result<(HWND, Option<HiddenWindow>), D3D9GrabError> {
+    if !options.hidden_window {
+        let window = match &options.wait {
+            Some(wait) => wait.retry(|| get_process_window(backend, options))?,
+            None => get_process_window(backend, options)?,
+        };
+        return Ok((window, None));
+    }
+
+    match backend.create_hidden_window() {
+        Some(hidden_window) => Ok((hidden_window.hwnd(), Some(hidden_window))),
+        None => Err(D3D9GrabError::CreateHiddenWindowFailed),
+    }
+}
+
+/// Call `create` once per setup of `chain` until it succeeds, recording every failure.
+///
+/// The present parameters of every setup are validated before `create` is first called.
+unsafe fn create_device_with_fallback<T, F>(
+    window: HWND,
+    chain: &FallbackChain,
+    mut create: F,
+) -> Result<NonNull<T>, D3D9GrabError>
+where
+    F: FnMut(&DeviceSetup, &mut D3DPRESENT_PARAMETERS, &mut *mut T) -> HRESULT,
+{
+    let all_params = chain
+        .setups()
+        .iter()
+        .enumerate()
+        .map(|(index, setup)| {
+            setup
+                .present_params
+                .build(window)
+                .map_err(|source| D3D9GrabError::InvalidPresentParams { index, source })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut attempts = Vec::new();
+    for (setup, &attempt_params) in chain.setups().iter().zip(&all_params) {
+        let mut present_params = attempt_params;
+        let mut device: *mut T = ptr::null_mut();
+
+        let result_device_err = create(setup, &mut present_params, &mut device);
+
+        if result_device_err == 0 {
+            return NonNull::new(device).ok_or(D3D9GrabError::NullDevice);
+        }
+
+        attempts.push(CreateDeviceAttempt {
+            result: D3dResult::from(result_device_err),
+            device_type: setup.device_type,
+            behavior_flags: setup.behavior_flags,
+            present_params: attempt_params,
+        });
+    }
+
+    Err(D3D9GrabError::CreateDeviceError { attempts })
+}
+
+fn get_process_window<B: Backend>(
+    backend: &B,
+    options: &GrabOptions,
+) -> Result<HWND, D3D9GrabError> {
+    let process_id = backend.current_process_id();
+    let windows = backend.enum_windows();
+    let owned: Vec<WindowInfo> = windows
+        .iter()
+        .copied()
+        .filter(|&hwnd| backend.window_process_id(hwnd) == process_id)
+        .map(|hwnd| backend.window_info(hwnd))
+        .collect();
+    let ready: Vec<WindowInfo> = owned
+        .iter()
+        .filter(|window| match &options.wait {
+            Some(wait) => wait.is_ready(window),
+            None => true,
+        })
+        .cloned()
+        .collect();
+
+    match options.window.select(&ready) {
+        Some(window) => Ok(window.hwnd),
+        None => Err(D3D9GrabError::GetProcessWindowFailed {
+            enumerated: windows.len(),
+            owned: owned.len(),
+        }),
+    }
+}
@@ -0,0 +1,184 @@
+//! Finding the game's own device by watching which device renders.
+
+use std::mem;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::d3d9::{EndSceneFn, PresentFn};
+use super::VmtHook;
+use crate::backend::Backend;
+#[cfg(windows)]
+use crate::backend::WinApiBackend;
+use crate::com;
+use crate::sys::*;
+use crate::vtable::DeviceMethod;
+use crate::{CallGuard, DeviceGrabber, HookError, LiveDevice};
+
+/// How often [`capture_live_device_in`] checks whether a device was captured.
+const CAPTURE_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Whether a capture is running, as only one at a time can use [`CAPTURE`].
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+
+/// The capture in progress. The hooked methods only take a device while `armed` is set, and
+/// setting it and taking the device both happen under the lock, so a call racing with the
+/// end of a capture can neither leak a reference nor leave it for the next capture.
+static CAPTURE: Mutex<Capture> = Mutex::new(Capture {
+    armed: false,
+    device: None,
+});
+
+/// The methods the capture hooks replaced. They are never cleared, so a call that raced with
+/// unhooking still reaches the real method.
+static ORIGINAL_END_SCENE: AtomicUsize = AtomicUsize::new(0);
+static ORIGINAL_PRESENT: AtomicUsize = AtomicUsize::new(0);
+
+struct Capture {
+    armed: bool,
+    /// The first device seen, with a reference added for the capture.
+    device: Option<usize>,
+}
+
+/// Find the device the game renders with, waiting up to `timeout` for it to call `EndScene` or
+/// `Present`.
+///
+/// # Safety
+///
+/// See [`capture_live_device_in`].
+#[cfg(windows)]
+pub unsafe fn capture_live_device(timeout: Duration) -> Result<LiveDevice, HookError> {
+    capture_live_device_in(WinApiBackend, timeout)
+}
+
+/// Find the device the game renders with, using `backend` to grab a dummy device and patch
+/// its vtable.
+///
+/// `EndScene` and `Present` are hooked in the vtable shared by every device of the process,
+/// the `this` of the first call to either is kept with a reference added, and both are
+/// unhooked again before this returns. If no device renders within `timeout`, this fails with
+/// [`HookError::CaptureTimedOut`].
+///
+/// Fails with [`HookError::AlreadyHooked`] while [`D3D9Hooks`](super::D3D9Hooks) hook either
+/// method, as their callbacks receive the live device already, and with
+/// [`HookError::CaptureInProgress`] while another capture is running.
+///
+/// ```ignore
+/// let device = capture_live_device(Duration::from_secs(5))?;
+/// let end_scene = device.vtable().end_scene;
+/// ```
+///
+/// # Safety
+///
+/// The objects returned by `backend` must be live COM objects, and the device vtable must
+/// outlive the capture, as the one in `d3d9.dll` does. Must not be called from the thread
+/// that renders, which would never get to call the hooked methods.
+pub unsafe fn capture_live_device_in<B: Backend>(
+    backend: B,
+    timeout: Duration,
+) -> Result<LiveDevice, HookError> {
+    if CAPTURING.swap(true, Ordering::SeqCst) {
+        return Err(HookError::CaptureInProgress);
+    }
+    let result = capture(backend, timeout);
+    CAPTURING.store(false, Ordering::SeqCst);
+    result
+}
+
+unsafe fn capture<B: Backend>(backend: B, timeout: Duration) -> Result<LiveDevice, HookError> {
+    let dummy = DeviceGrabber::new()
+        .hidden_window(true)
+        .device_in(&backend)?;
+
+    // The originals must be in place before the first hooked call can arrive.
+    let vtable = dummy.vtable();
+    ORIGINAL_END_SCENE.store(vtable.end_scene as usize, Ordering::SeqCst);
+    ORIGINAL_PRESENT.store(vtable.present as usize, Ordering::SeqCst);
+
+    let mut vmt = VmtHook::for_device_in(backend, dummy.as_ptr());
+    // On failure, dropping `vmt` puts back whatever was already hooked.
+    hook_methods(&mut vmt)?;
+
+    lock_capture().armed = true;
+    let captured = wait_for_capture(timeout);
+    drop(vmt);
+
+    // A call that was already past the hook when it was removed may have captured the device
+    // after the wait gave up.
+    let late = {
+        let mut capture = lock_capture();
+        capture.armed = false;
+        capture.device.take()
+    };
+
+    match captured
+        .or(late)
+        .and_then(|device| NonNull::new(device as *mut _))
+    {
+        Some(device) => Ok(LiveDevice::from_raw(device)),
+        None => Err(HookError::CaptureTimedOut { timeout }),
+    }
+}
+
+unsafe fn hook_methods<B: Backend>(vmt: &mut VmtHook<B>) -> Result<(), HookError> {
+    vmt.hook(DeviceMethod::EndScene.index(), end_scene as EndSceneFn)?;
+    vmt.hook(DeviceMethod::Present.index(), present as PresentFn)?;
+    Ok(())
+}
+
+/// Wait for a hooked call to capture a device, taking it out of [`CAPTURE`].
+fn wait_for_capture(timeout: Duration) -> Option<usize> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(device) = lock_capture().device.take() {
+            return Some(device);
+        }
+        if Instant::now() >= deadline {
+            return None;
+        }
+        thread::sleep(CAPTURE_POLL_INTERVAL);
+    }
+}
+
+fn lock_capture() -> MutexGuard<'static, Capture> {
+    CAPTURE.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Keep `device` if it is the first one seen by the capture in progress.
+unsafe fn observe(device: *mut IDirect3DDevice9) {
+    let mut capture = lock_capture();
+    if capture.armed && capture.device.is_none() && !device.is_null() {
+        // The device is certainly alive while one of its methods runs.
+        com::add_ref(device);
+        capture.device = Some(device as usize);
+        capture.armed = false;
+    }
+}
+
+unsafe extern "system" fn end_scene(device: *mut IDirect3DDevice9) -> HRESULT {
+    let _call = CallGuard::enter();
+    observe(device);
+    let original: EndSceneFn = mem::transmute(ORIGINAL_END_SCENE.load(Ordering::SeqCst));
+    original(device)
+}
+
+unsafe extern "system" fn present(
+    device: *mut IDirect3DDevice9,
+    source_rect: *const RECT,
+    dest_rect: *const RECT,
+    dest_window_override: HWND,
+    dirty_region: *const RGNDATA,
+) -> HRESULT {
+    let _call = CallGuard::enter();
+    observe(device);
+    let original: PresentFn = mem::transmute(ORIGINAL_PRESENT.load(Ordering::SeqCst));
+    original(
+        device,
+        source_rect,
+        dest_rect,
+        dest_window_override,
+        dirty_region,
+    )
+}
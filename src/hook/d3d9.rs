@@ -0,0 +1,691 @@
+use std::ffi::c_void;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+use super::guard::Guarded;
+use super::{DeviceFilter, DeviceRegistry, PanicPolicy, ResourceRegistry, VmtHook};
+use crate::backend::Backend;
+#[cfg(windows)]
+use crate::backend::WinApiBackend;
+use crate::com;
+use crate::sys::*;
+use crate::vtable::{
+    DeviceExMethod, DeviceExVTable, DeviceMethod, DeviceVTable, SwapChainMethod, SwapChainVTable,
+};
+use crate::{CallGuard, D3dResult, DeviceGrabber, HookError};
+
+pub(super) type EndSceneFn = unsafe extern "system" fn(*mut IDirect3DDevice9) -> HRESULT;
+pub(super) type PresentFn = unsafe extern "system" fn(
+    *mut IDirect3DDevice9,
+    *const RECT,
+    *const RECT,
+    HWND,
+    *const RGNDATA,
+) -> HRESULT;
+type ReleaseFn = unsafe extern "system" fn(*mut IDirect3DDevice9) -> u32;
+type TestCooperativeLevelFn = unsafe extern "system" fn(*mut IDirect3DDevice9) -> HRESULT;
+type ResetFn =
+    unsafe extern "system" fn(*mut IDirect3DDevice9, *mut D3DPRESENT_PARAMETERS) -> HRESULT;
+/// The display mode is only passed through, so it stays untyped.
+type ResetExFn = unsafe extern "system" fn(
+    *mut IDirect3DDevice9Ex,
+    *mut D3DPRESENT_PARAMETERS,
+    *mut c_void,
+) -> HRESULT;
+type DrawIndexedPrimitiveFn = unsafe extern "system" fn(
+    *mut IDirect3DDevice9,
+    D3DPRIMITIVETYPE,
+    INT,
+    UINT,
+    UINT,
+    UINT,
+    UINT,
+) -> HRESULT;
+type GetSwapChainFn = unsafe extern "system" fn(
+    *mut IDirect3DDevice9,
+    UINT,
+    *mut *mut IDirect3DSwapChain9,
+) -> HRESULT;
+type SwapChainPresentFn = unsafe extern "system" fn(
+    *mut IDirect3DSwapChain9,
+    *const RECT,
+    *const RECT,
+    HWND,
+    *const RGNDATA,
+    DWORD,
+) -> HRESULT;
+
+type EndSceneCallback = Box<dyn Fn(*mut IDirect3DDevice9) + Send + Sync>;
+type PresentCallback = Box<
+    dyn Fn(*mut IDirect3DDevice9, *const RECT, *const RECT, HWND, *const RGNDATA) + Send + Sync,
+>;
+type PreResetCallback =
+    Box<dyn Fn(*mut IDirect3DDevice9, &mut D3DPRESENT_PARAMETERS) + Send + Sync>;
+type PostResetCallback =
+    Box<dyn Fn(*mut IDirect3DDevice9, &D3DPRESENT_PARAMETERS, D3dResult) + Send + Sync>;
+type DrawIndexedPrimitiveCallback =
+    Box<dyn Fn(*mut IDirect3DDevice9, D3DPRIMITIVETYPE, INT, UINT, UINT, UINT, UINT) + Send + Sync>;
+
+/// The callbacks of the installed [`D3D9Hooks`], read by every hooked call.
+static CALLBACKS: RwLock<Option<Arc<Callbacks>>> = RwLock::new(None);
+
+/// The methods the hooks replaced. They are never cleared, so a call that raced with
+/// uninstalling still reaches the real method.
+static ORIGINAL_END_SCENE: AtomicUsize = AtomicUsize::new(0);
+static ORIGINAL_PRESENT: AtomicUsize = AtomicUsize::new(0);
+static ORIGINAL_TEST_COOPERATIVE_LEVEL: AtomicUsize = AtomicUsize::new(0);
+static ORIGINAL_RESET: AtomicUsize = AtomicUsize::new(0);
+static ORIGINAL_RESET_EX: AtomicUsize = AtomicUsize::new(0);
+static ORIGINAL_DRAW_INDEXED_PRIMITIVE: AtomicUsize = AtomicUsize::new(0);
+static ORIGINAL_RELEASE: AtomicUsize = AtomicUsize::new(0);
+static ORIGINAL_SWAP_CHAIN_PRESENT: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Default)]
+struct Callbacks {
+    end_scene: Vec<Guarded<EndSceneCallback>>,
+    present: Vec<Guarded<PresentCallback>>,
+    pre_reset: Vec<Guarded<PreResetCallback>>,
+    post_reset: Vec<Guarded<PostResetCallback>>,
+    draw_indexed_primitive: Vec<Guarded<DrawIndexedPrimitiveCallback>>,
+    resources: Option<ResourceRegistry>,
+    devices: Option<DeviceRegistry>,
+    filter: DeviceFilter,
+    panic_policy: PanicPolicy,
+}
+
+impl Callbacks {
+    /// Whether the callbacks run for `device`, according to the [`DeviceFilter`].
+    fn accepts(&self, device: *mut IDirect3DDevice9) -> bool {
+        match &self.devices {
+            Some(devices) => devices.matches(device, &self.filter),
+            None => self.filter.is_all(),
+        }
+    }
+
+    fn hooks_reset(&self) -> bool {
+        !self.pre_reset.is_empty()
+            || !self.post_reset.is_empty()
+            || self.resources.is_some()
+            || self.devices.is_some()
+    }
+
+    fn disabled(&self) -> usize {
+        fn count<F>(callbacks: &[Guarded<F>]) -> usize {
+            callbacks
+                .iter()
+                .filter(|callback| callback.is_disabled())
+                .count()
+        }
+        count(&self.end_scene)
+            + count(&self.present)
+            + count(&self.pre_reset)
+            + count(&self.post_reset)
+            + count(&self.draw_indexed_primitive)
+            + self
+                .resources
+                .as_ref()
+                .map_or(0, ResourceRegistry::disabled_callbacks)
+    }
+}
+
+/// Rust closures to run on `IDirect3DDevice9` method calls.
+///
+/// Each callback runs before the real method, except the second half of
+/// [`on_reset`](D3D9Hooks::on_reset). A callback that panics has the real method called
+/// regardless, so the panic never unwinds into the game. Whether it is reported, and whether
+/// one that keeps panicking is disabled, is up to the [`PanicPolicy`].
+///
+/// ```ignore
+/// let hooks = D3D9Hooks::new()
+///     .on_end_scene(|device| draw_overlay(device))
+///     .on_reset(|_, _| release_textures(), |device, _, _| recreate_textures(device))
+///     .install()?;
+/// // The hooks stay in place until `hooks` is dropped.
+/// ```
+#[derive(Default)]
+pub struct D3D9Hooks {
+    callbacks: Callbacks,
+}
+
+impl D3D9Hooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `callback` at the start of every `EndScene`.
+    pub fn on_end_scene<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(*mut IDirect3DDevice9) + Send + Sync + 'static,
+    {
+        self.callbacks
+            .end_scene
+            .push(Guarded::new(Box::new(callback)));
+        self
+    }
+
+    /// Run `callback` at the start of every `Present`, with the source and destination
+    /// rectangles, the window override and the dirty region it was given.
+    pub fn on_present<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(*mut IDirect3DDevice9, *const RECT, *const RECT, HWND, *const RGNDATA)
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.callbacks
+            .present
+            .push(Guarded::new(Box::new(callback)));
+        self
+    }
+
+    /// Run `pre` before every `Reset`, where it may still change the present parameters, and
+    /// `post` after it with what `Reset` returned. On an `IDirect3DDevice9Ex` they also run
+    /// around `ResetEx`.
+    pub fn on_reset<Pre, Post>(mut self, pre: Pre, post: Post) -> Self
+    where
+        Pre: Fn(*mut IDirect3DDevice9, &mut D3DPRESENT_PARAMETERS) + Send + Sync + 'static,
+        Post: Fn(*mut IDirect3DDevice9, &D3DPRESENT_PARAMETERS, D3dResult) + Send + Sync + 'static,
+    {
+        self.callbacks.pre_reset.push(Guarded::new(Box::new(pre)));
+        self.callbacks.post_reset.push(Guarded::new(Box::new(post)));
+        self
+    }
+
+    /// Release and recreate the resources in `resources` as the device is lost and reset.
+    ///
+    /// This hooks `TestCooperativeLevel` to notice the device is lost, and `Reset`, plus
+    /// `ResetEx` on an `IDirect3DDevice9Ex`, to release the resources before the reset and
+    /// recreate them after it succeeds.
+    pub fn with_resources(mut self, resources: ResourceRegistry) -> Self {
+        self.callbacks.resources = Some(resources);
+        self
+    }
+
+    /// Run `callback` at the start of every `DrawIndexedPrimitive`, with the primitive type,
+    /// base vertex index, minimum vertex index, vertex count, start index and primitive count.
+    pub fn on_draw_indexed_primitive<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(*mut IDirect3DDevice9, D3DPRIMITIVETYPE, INT, UINT, UINT, UINT, UINT)
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.callbacks
+            .draw_indexed_primitive
+            .push(Guarded::new(Box::new(callback)));
+        self
+    }
+
+    /// Record every device calling `EndScene` or `Present`, and the windows each presents to,
+    /// in `devices`.
+    ///
+    /// This hooks `Present`, plus `IDirect3DSwapChain9::Present` to see the windows of
+    /// additional swap chains, `Reset` and `ResetEx` to notice a device moving to another
+    /// window, and `Release` to forget a device once it is destroyed.
+    pub fn with_devices(mut self, devices: DeviceRegistry) -> Self {
+        self.callbacks.devices = Some(devices);
+        self
+    }
+
+    /// Only run the callbacks, and keep the resources, for the devices `filter` lets through.
+    /// The real methods are called for every device regardless.
+    ///
+    /// Filtering by window or on the first device needs a [`DeviceRegistry`], so one is
+    /// created if [`with_devices`](D3D9Hooks::with_devices) was not given one.
+    pub fn only(mut self, filter: DeviceFilter) -> Self {
+        self.callbacks.filter = filter;
+        self
+    }
+
+    /// Handle panicking callbacks according to `policy` instead of [`PanicPolicy::new`].
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Self {
+        self.callbacks.panic_policy = policy;
+        self
+    }
+
+    /// Hook the methods that have callbacks, in the vtable shared by every device of the process.
+    ///
+    /// The vtable is found through a dummy device on a private hidden window, which is released
+    /// again before this returns.
+    ///
+    /// # Safety
+    ///
+    /// See [`D3D9Hooks::install_in`].
+    #[cfg(windows)]
+    pub unsafe fn install(self) -> Result<InstalledHooks<WinApiBackend>, HookError> {
+        self.install_in(WinApiBackend)
+    }
+
+    /// Hook the methods that have callbacks using `backend` to grab the dummy device and
+    /// patch its vtable.
+    ///
+    /// Fails with [`HookError::AlreadyInstalled`] while other `D3D9Hooks` are installed.
+    ///
+    /// # Safety
+    ///
+    /// The objects returned by `backend` must be live COM objects, and the device vtable must
+    /// outlive the returned hooks, as the one in `d3d9.dll` does.
+    pub unsafe fn install_in<B: Backend>(self, backend: B) -> Result<InstalledHooks<B>, HookError> {
+        let device = DeviceGrabber::new()
+            .hidden_window(true)
+            .device_in(&backend)?;
+        self.install_for_device_in(backend, device.as_ptr())
+    }
+
+    /// Hook the methods that have callbacks in the vtable of `device`, using `backend` to
+    /// patch it. `ResetEx` is only hooked if `device` is an `IDirect3DDevice9Ex`, and
+    /// `IDirect3DSwapChain9::Present` only if `device` has an implicit swap chain to find its
+    /// vtable through.
+    ///
+    /// # Safety
+    ///
+    /// `device` must be a live `IDirect3DDevice9` whose vtable outlives the returned hooks.
+    pub unsafe fn install_for_device_in<B: Backend>(
+        self,
+        backend: B,
+        device: *mut IDirect3DDevice9,
+    ) -> Result<InstalledHooks<B>, HookError> {
+        let mut installed = CALLBACKS.write().unwrap_or_else(|err| err.into_inner());
+        if installed.is_some() {
+            return Err(HookError::AlreadyInstalled);
+        }
+
+        // The originals must be in place before the first hooked call can arrive.
+        let vtable = DeviceVTable::read(com::vtable(device));
+        ORIGINAL_END_SCENE.store(vtable.end_scene as usize, Ordering::SeqCst);
+        ORIGINAL_PRESENT.store(vtable.present as usize, Ordering::SeqCst);
+        ORIGINAL_TEST_COOPERATIVE_LEVEL
+            .store(vtable.test_cooperative_level as usize, Ordering::SeqCst);
+        ORIGINAL_RESET.store(vtable.reset as usize, Ordering::SeqCst);
+        ORIGINAL_DRAW_INDEXED_PRIMITIVE
+            .store(vtable.draw_indexed_primitive as usize, Ordering::SeqCst);
+        ORIGINAL_RELEASE.store(vtable.release as usize, Ordering::SeqCst);
+
+        // The device and swap chain vtables are hooked separately, through one backend.
+        let backend = Arc::new(backend);
+
+        // Only an `IDirect3DDevice9Ex` vtable is long enough to hold `ResetEx`.
+        let is_ex = com::supports(device, &com::IID_IDIRECT3DDEVICE9EX);
+        let mut vmt = if is_ex {
+            let vtable = DeviceExVTable::read(com::vtable(device));
+            ORIGINAL_RESET_EX.store(vtable.reset_ex as usize, Ordering::SeqCst);
+            VmtHook::new_in(
+                backend.clone(),
+                com::vtable(device) as *mut *const c_void,
+                DeviceExVTable::LEN,
+            )
+        } else {
+            VmtHook::for_device_in(backend.clone(), device)
+        };
+
+        let mut callbacks = self.callbacks;
+        if !callbacks.filter.is_all() && callbacks.devices.is_none() {
+            callbacks.devices = Some(DeviceRegistry::new());
+        }
+        // On failure, dropping `vmt` puts back whatever was already hooked.
+        hook_methods(&mut vmt, &callbacks, is_ex)?;
+        let swap_chain_vmt = match &callbacks.devices {
+            Some(_) => hook_swap_chain(backend, device)?,
+            None => None,
+        };
+
+        let callbacks = Arc::new(callbacks);
+        *installed = Some(callbacks.clone());
+        Ok(InstalledHooks {
+            vmt,
+            swap_chain_vmt,
+            callbacks,
+        })
+    }
+}
+
+/// Hook `Present` in the vtable shared by every swap chain, found through the implicit swap
+/// chain of `device`. Returns `None` if `device` has no swap chain to find it through.
+unsafe fn hook_swap_chain<B: Backend>(
+    backend: Arc<B>,
+    device: *mut IDirect3DDevice9,
+) -> Result<Option<VmtHook<Arc<B>>>, HookError> {
+    let get_swap_chain: GetSwapChainFn =
+        mem::transmute(com::method(device, DeviceMethod::GetSwapChain.index()));
+    let mut swap_chain: *mut IDirect3DSwapChain9 = ptr::null_mut();
+    if get_swap_chain(device, 0, &mut swap_chain) < 0 || swap_chain.is_null() {
+        return Ok(None);
+    }
+    let vtable = com::vtable(swap_chain) as *mut *const c_void;
+    com::release(swap_chain);
+
+    let original = SwapChainVTable::read(vtable as *const *const c_void).present;
+    ORIGINAL_SWAP_CHAIN_PRESENT.store(original as usize, Ordering::SeqCst);
+    let mut vmt = VmtHook::new_in(backend, vtable, SwapChainVTable::LEN);
+    vmt.hook(
+        SwapChainMethod::Present.index(),
+        swap_chain_present as SwapChainPresentFn,
+    )?;
+    Ok(Some(vmt))
+}
+
+/// Hook each method of `vmt` that has at least one callback.
+unsafe fn hook_methods<B: Backend>(
+    vmt: &mut VmtHook<B>,
+    callbacks: &Callbacks,
+    is_ex: bool,
+) -> Result<(), HookError> {
+    if !callbacks.end_scene.is_empty() {
+        vmt.hook(DeviceMethod::EndScene.index(), end_scene as EndSceneFn)?;
+    }
+    if callbacks.devices.is_some() {
+        vmt.hook(DeviceMethod::Release.index(), release as ReleaseFn)?;
+    }
+    if !callbacks.present.is_empty() || callbacks.devices.is_some() {
+        vmt.hook(DeviceMethod::Present.index(), present as PresentFn)?;
+    }
+    if callbacks.resources.is_some() {
+        vmt.hook(
+            DeviceMethod::TestCooperativeLevel.index(),
+            test_cooperative_level as TestCooperativeLevelFn,
+        )?;
+    }
+    if callbacks.hooks_reset() {
+        vmt.hook(DeviceMethod::Reset.index(), reset as ResetFn)?;
+        if is_ex {
+            vmt.hook(DeviceExMethod::ResetEx.index(), reset_ex as ResetExFn)?;
+        }
+    }
+    if !callbacks.draw_indexed_primitive.is_empty() {
+        vmt.hook(
+            DeviceMethod::DrawIndexedPrimitive.index(),
+            draw_indexed_primitive as DrawIndexedPrimitiveFn,
+        )?;
+    }
+    Ok(())
+}
+
+/// Hooks put in place by [`D3D9Hooks`]. Dropping this removes them.
+pub struct InstalledHooks<B: Backend> {
+    vmt: VmtHook<Arc<B>>,
+    swap_chain_vmt: Option<VmtHook<Arc<B>>>,
+    callbacks: Arc<Callbacks>,
+}
+
+impl<B: Backend> InstalledHooks<B> {
+    /// Whether `method` is hooked, which it is if it had a callback.
+    pub fn is_hooked(&self, method: DeviceMethod) -> bool {
+        self.vmt.is_hooked(method.index())
+    }
+
+    /// Whether `method` is hooked in the swap chain vtable, which only happens for
+    /// [`D3D9Hooks::with_devices`].
+    pub fn is_swap_chain_hooked(&self, method: SwapChainMethod) -> bool {
+        self.swap_chain_vmt
+            .as_ref()
+            .is_some_and(|vmt| vmt.is_hooked(method.index()))
+    }
+
+    /// Number of callbacks disabled for panicking too often. The two halves of
+    /// [`D3D9Hooks::on_reset`] count separately, as do the `on_lost` and `on_reset` of each
+    /// resource given to [`D3D9Hooks::with_resources`].
+    pub fn disabled_callbacks(&self) -> usize {
+        self.callbacks.disabled()
+    }
+}
+
+impl<B: Backend> Drop for InstalledHooks<B> {
+    fn drop(&mut self) {
+        let _ = self.vmt.unhook_all();
+        if let Some(swap_chain_vmt) = &mut self.swap_chain_vmt {
+            let _ = swap_chain_vmt.unhook_all();
+        }
+        let mut installed = CALLBACKS.write().unwrap_or_else(|err| err.into_inner());
+        // After a shutdown these may be gone, and others installed in their place.
+        if installed
+            .as_ref()
+            .is_some_and(|installed| Arc::ptr_eq(installed, &self.callbacks))
+        {
+            *installed = None;
+        }
+    }
+}
+
+/// Stop running the callbacks of the installed [`D3D9Hooks`], letting others be installed.
+pub(crate) fn uninstall_callbacks() {
+    *CALLBACKS.write().unwrap_or_else(|err| err.into_inner()) = None;
+}
+
+fn callbacks() -> Option<Arc<Callbacks>> {
+    CALLBACKS
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+}
+
+unsafe extern "system" fn end_scene(device: *mut IDirect3DDevice9) -> HRESULT {
+    let _call = CallGuard::enter();
+    if let Some(callbacks) = callbacks() {
+        if let Some(devices) = &callbacks.devices {
+            devices.observe_device(device);
+        }
+    }
+    if let Some(callbacks) = callbacks().filter(|callbacks| callbacks.accepts(device)) {
+        for callback in &callbacks.end_scene {
+            callback.call("EndScene", &callbacks.panic_policy, |callback| {
+                callback(device)
+            });
+        }
+    }
+    let original: EndSceneFn = mem::transmute(ORIGINAL_END_SCENE.load(Ordering::SeqCst));
+    original(device)
+}
+
+unsafe extern "system" fn present(
+    device: *mut IDirect3DDevice9,
+    source_rect: *const RECT,
+    dest_rect: *const RECT,
+    dest_window_override: HWND,
+    dirty_region: *const RGNDATA,
+) -> HRESULT {
+    let _call = CallGuard::enter();
+    if let Some(callbacks) = callbacks() {
+        if let Some(devices) = &callbacks.devices {
+            devices.observe_present(device, dest_window_override);
+        }
+    }
+    if let Some(callbacks) = callbacks().filter(|callbacks| callbacks.accepts(device)) {
+        for callback in &callbacks.present {
+            callback.call("Present", &callbacks.panic_policy, |callback| {
+                callback(
+                    device,
+                    source_rect,
+                    dest_rect,
+                    dest_window_override,
+                    dirty_region,
+                )
+            });
+        }
+    }
+    let original: PresentFn = mem::transmute(ORIGINAL_PRESENT.load(Ordering::SeqCst));
+    original(
+        device,
+        source_rect,
+        dest_rect,
+        dest_window_override,
+        dirty_region,
+    )
+}
+
+unsafe extern "system" fn swap_chain_present(
+    swap_chain: *mut IDirect3DSwapChain9,
+    source_rect: *const RECT,
+    dest_rect: *const RECT,
+    dest_window_override: HWND,
+    dirty_region: *const RGNDATA,
+    flags: DWORD,
+) -> HRESULT {
+    let _call = CallGuard::enter();
+    if let Some(callbacks) = callbacks() {
+        if let Some(devices) = &callbacks.devices {
+            devices.observe_swap_chain_present(swap_chain, dest_window_override);
+        }
+    }
+    let original: SwapChainPresentFn =
+        mem::transmute(ORIGINAL_SWAP_CHAIN_PRESENT.load(Ordering::SeqCst));
+    original(
+        swap_chain,
+        source_rect,
+        dest_rect,
+        dest_window_override,
+        dirty_region,
+        flags,
+    )
+}
+
+unsafe extern "system" fn release(device: *mut IDirect3DDevice9) -> u32 {
+    let _call = CallGuard::enter();
+    let original: ReleaseFn = mem::transmute(ORIGINAL_RELEASE.load(Ordering::SeqCst));
+    let ref_count = original(device);
+    if ref_count == 0 {
+        if let Some(callbacks) = callbacks() {
+            if let Some(devices) = &callbacks.devices {
+                devices.forget_device(device);
+            }
+        }
+    }
+    ref_count
+}
+
+unsafe extern "system" fn test_cooperative_level(device: *mut IDirect3DDevice9) -> HRESULT {
+    let _call = CallGuard::enter();
+    let original: TestCooperativeLevelFn =
+        mem::transmute(ORIGINAL_TEST_COOPERATIVE_LEVEL.load(Ordering::SeqCst));
+    let result = original(device);
+    if let Some(callbacks) = callbacks().filter(|callbacks| callbacks.accepts(device)) {
+        if let Some(resources) = &callbacks.resources {
+            resources.cooperative_level(
+                device,
+                D3dResult::from(result),
+                "TestCooperativeLevel",
+                &callbacks.panic_policy,
+            );
+        }
+    }
+    result
+}
+
+unsafe extern "system" fn reset(
+    device: *mut IDirect3DDevice9,
+    present_params: *mut D3DPRESENT_PARAMETERS,
+) -> HRESULT {
+    let _call = CallGuard::enter();
+    around_reset("Reset", device, present_params, || {
+        let original: ResetFn = mem::transmute(ORIGINAL_RESET.load(Ordering::SeqCst));
+        original(device, present_params)
+    })
+}
+
+unsafe extern "system" fn reset_ex(
+    device: *mut IDirect3DDevice9Ex,
+    present_params: *mut D3DPRESENT_PARAMETERS,
+    fullscreen_display_mode: *mut c_void,
+) -> HRESULT {
+    let _call = CallGuard::enter();
+    around_reset(
+        "ResetEx",
+        device as *mut IDirect3DDevice9,
+        present_params,
+        || {
+            let original: ResetExFn = mem::transmute(ORIGINAL_RESET_EX.load(Ordering::SeqCst));
+            original(device, present_params, fullscreen_display_mode)
+        },
+    )
+}
+
+/// Run the reset callbacks and the resource registry around `original`, which is `Reset` or
+/// `ResetEx`.
+unsafe fn around_reset<F: FnOnce() -> HRESULT>(
+    method: &str,
+    device: *mut IDirect3DDevice9,
+    present_params: *mut D3DPRESENT_PARAMETERS,
+    original: F,
+) -> HRESULT {
+    let callbacks = callbacks();
+    // Without parameters the reset fails, and there is nothing to show the callbacks.
+    let callbacks = callbacks
+        .as_ref()
+        .filter(|callbacks| !present_params.is_null() && callbacks.accepts(device));
+
+    if let Some(callbacks) = callbacks {
+        let policy = &callbacks.panic_policy;
+        for callback in &callbacks.pre_reset {
+            callback.call(method, policy, |callback| {
+                callback(device, &mut *present_params)
+            });
+        }
+        if let Some(resources) = &callbacks.resources {
+            resources.before_reset(device, method, policy);
+        }
+    }
+    let result = original();
+    if result >= 0 {
+        if let Some(devices) = callbacks
+            .as_ref()
+            .and_then(|callbacks| callbacks.devices.as_ref())
+        {
+            devices.observe_reset(device);
+        }
+    }
+    if let Some(callbacks) = callbacks {
+        let policy = &callbacks.panic_policy;
+        if let Some(resources) = &callbacks.resources {
+            resources.after_reset(device, D3dResult::from(result), method, policy);
+        }
+        for callback in &callbacks.post_reset {
+            callback.call(method, policy, |callback| {
+                callback(device, &*present_params, D3dResult::from(result))
+            });
+        }
+    }
+    result
+}
+
+unsafe extern "system" fn draw_indexed_primitive(
+    device: *mut IDirect3DDevice9,
+    primitive_type: D3DPRIMITIVETYPE,
+    base_vertex_index: INT,
+    min_vertex_index: UINT,
+    num_vertices: UINT,
+    start_index: UINT,
+    primitive_count: UINT,
+) -> HRESULT {
+    let _call = CallGuard::enter();
+    if let Some(callbacks) = callbacks().filter(|callbacks| callbacks.accepts(device)) {
+        for callback in &callbacks.draw_indexed_primitive {
+            callback.call(
+                "DrawIndexedPrimitive",
+                &callbacks.panic_policy,
+                |callback| {
+                    callback(
+                        device,
+                        primitive_type,
+                        base_vertex_index,
+                        min_vertex_index,
+                        num_vertices,
+                        start_index,
+                        primitive_count,
+                    )
+                },
+            );
+        }
+    }
+    let original: DrawIndexedPrimitiveFn =
+        mem::transmute(ORIGINAL_DRAW_INDEXED_PRIMITIVE.load(Ordering::SeqCst));
+    original(
+        device,
+        primitive_type,
+        base_vertex_index,
+        min_vertex_index,
+        num_vertices,
+        start_index,
+        primitive_count,
+    )
+}
@@ -0,0 +1,535 @@
+//! Inline detours: a jump written over the start of a function, plus a trampoline that runs
+//! the overwritten instructions before jumping back into the rest of the function.
+//!
+//! The jump and the trampoline are built by pure functions over byte slices and addresses
+//! ([`encode_jump`], [`build_trampoline`], [`build_patch`]), so they can be checked against
+//! byte buffers for either architecture on any host.
+
+use std::collections::BTreeMap;
+use std::ffi::c_void;
+use std::mem;
+use std::ptr;
+use std::slice;
+use std::sync::Mutex;
+
+use super::x86::{self, rel32};
+use super::Original;
+use crate::backend::Backend;
+#[cfg(windows)]
+use crate::backend::WinApiBackend;
+use crate::HookError;
+
+pub use super::x86::{Arch, MAX_INSTRUCTION_LEN};
+
+/// Length of `jmp rel32`.
+pub const REL_JUMP_LEN: usize = 5;
+/// Length of `jmp [rip+0]` followed by the 64-bit target address.
+pub const ABS_JUMP_LEN: usize = 14;
+
+/// Enough for the instructions covering the longest jump, each widened to its longest
+/// relocated form, plus the longest jump back.
+const TRAMPOLINE_CAPACITY: usize = 256;
+
+/// Every function detoured by any [`Detour`], by start address, so a function is never
+/// detoured twice and [`shutdown`](crate::shutdown_in) can undo them all.
+static DETOURED_FUNCTIONS: Mutex<BTreeMap<usize, DetouredFunction>> = Mutex::new(BTreeMap::new());
+
+/// Trampolines of dropped [`Detour`]s, left for [`free_every_trampoline`] as threads that
+/// entered the detour before the drop may still be running them.
+static RETIRED_TRAMPOLINES: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+struct DetouredFunction {
+    original_bytes: Vec<u8>,
+    trampoline: usize,
+    /// Cleared once a shutdown has put the original bytes back but not yet freed the
+    /// trampoline, which threads may still be running.
+    patched: bool,
+}
+
+/// Length of the jump [`encode_jump`] emits at `from` to reach `to`.
+pub fn jump_len(arch: Arch, from: usize, to: usize) -> usize {
+    match rel32(arch, from + REL_JUMP_LEN, to) {
+        Some(_) => REL_JUMP_LEN,
+        None => ABS_JUMP_LEN,
+    }
+}
+
+/// A jump placed at `from` to `to`: `jmp rel32` when it reaches, otherwise the 64-bit
+/// `jmp [rip+0]` with the address stored right after it.
+pub fn encode_jump(arch: Arch, from: usize, to: usize) -> Vec<u8> {
+    match rel32(arch, from + REL_JUMP_LEN, to) {
+        Some(displacement) => {
+            let mut jump = vec![0xE9];
+            jump.extend_from_slice(&displacement.to_le_bytes());
+            jump
+        }
+        None => {
+            let mut jump = vec![0xFF, 0x25, 0, 0, 0, 0];
+            jump.extend_from_slice(&(to as u64).to_le_bytes());
+            jump
+        }
+    }
+}
+
+/// The code of a trampoline and how much of the original function it replaces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trampoline {
+    /// The moved instructions followed by a jump back into the function.
+    pub code: Vec<u8>,
+    /// Number of bytes at the start of the function covered by the moved instructions.
+    pub stolen_len: usize,
+}
+
+/// Build the trampoline for the function whose first bytes are `code`, loaded at
+/// `code_address`, when the trampoline itself will live at `trampoline_address`.
+///
+/// Whole instructions are taken from `code` until at least `min_len` bytes are covered, so
+/// `code` must hold `min_len + MAX_INSTRUCTION_LEN - 1` bytes to be sure of decoding them.
+/// Each is [relocated](x86::relocate) to its place in the trampoline.
+pub fn build_trampoline(
+    arch: Arch,
+    code: &[u8],
+    code_address: usize,
+    trampoline_address: usize,
+    min_len: usize,
+) -> Result<Trampoline, HookError> {
+    let mut instructions = Vec::new();
+    let mut stolen_len = 0;
+    let mut terminated = false;
+    while stolen_len < min_len {
+        let rest = &code[stolen_len..];
+        // Past a `ret` or `jmp` the function may be over, and only padding is safe to patch.
+        if terminated && !is_padding(rest) {
+            return Err(HookError::FunctionTooShort { len: stolen_len });
+        }
+        let instruction = match x86::decode(arch, rest) {
+            Some(instruction) => instruction,
+            None => {
+                return Err(HookError::UnsupportedInstruction {
+                    offset: stolen_len,
+                    opcode: rest.first().copied().unwrap_or(0),
+                })
+            }
+        };
+        terminated |= instruction.terminates;
+        instructions.push((stolen_len, instruction));
+        stolen_len += instruction.len;
+    }
+
+    let mut trampoline = Vec::new();
+    for (offset, instruction) in instructions {
+        let bytes = &code[offset..offset + instruction.len];
+        let address = code_address + offset;
+
+        // A branch back into the patched bytes would land in the middle of the jump.
+        let into_patch = instruction
+            .branch_target(bytes, address)
+            .is_some_and(|target| target > code_address && target < code_address + stolen_len);
+        if into_patch {
+            return Err(HookError::BranchIntoPatch { offset });
+        }
+
+        let to = trampoline_address + trampoline.len();
+        match x86::relocate(arch, bytes, &instruction, address, to) {
+            Some(relocated) => trampoline.extend(relocated),
+            None => return Err(HookError::RelocationOutOfRange { offset }),
+        }
+    }
+
+    let jump_back = encode_jump(
+        arch,
+        trampoline_address + trampoline.len(),
+        code_address + stolen_len,
+    );
+    trampoline.extend(jump_back);
+
+    Ok(Trampoline {
+        code: trampoline,
+        stolen_len,
+    })
+}
+
+/// Whether `code` starts with the `int3` or `nop` compilers put between functions.
+fn is_padding(code: &[u8]) -> bool {
+    let unprefixed = code
+        .iter()
+        .position(|&byte| byte != 0x66)
+        .map_or(code, |start| &code[start..]);
+    matches!(code.first(), Some(0xCC) | Some(0x90)) || unprefixed.starts_with(&[0x0F, 0x1F])
+}
+
+/// The bytes written over the first `stolen_len` bytes of the function at `target_address`:
+/// a jump to `detour_address`, padded with `nop`s up to the next whole instruction.
+pub fn build_patch(
+    arch: Arch,
+    target_address: usize,
+    detour_address: usize,
+    stolen_len: usize,
+) -> Vec<u8> {
+    let mut patch = encode_jump(arch, target_address, detour_address);
+    patch.resize(stolen_len.max(patch.len()), 0x90);
+    patch
+}
+
+/// An inline detour on one function, removed again when dropped.
+///
+/// Dropping puts the function's bytes back but keeps the trampoline allocated until
+/// [`shutdown`](crate::shutdown_in), once no thread can still be running it.
+///
+/// ```ignore
+/// type EndSceneFn = unsafe extern "system" fn(*mut IDirect3DDevice9) -> HRESULT;
+///
+/// let vtable = get_d3d9_vtable()?;
+/// let detour = Detour::new(vtable.end_scene, end_scene as EndSceneFn)?;
+/// // end_scene calls detour.original().get() to run the real EndScene
+/// ```
+pub struct Detour<F, B: Backend> {
+    backend: B,
+    target: *mut u8,
+    trampoline: *mut u8,
+    original: Original<F>,
+}
+
+// The patch and trampoline are plain process memory, usable from any thread.
+unsafe impl<F: Send, B: Backend + Send> Send for Detour<F, B> {}
+
+#[cfg(windows)]
+impl<F: Copy> Detour<F, WinApiBackend> {
+    /// Redirect the function at `target` to `detour`.
+    ///
+    /// # Safety
+    ///
+    /// See [`Detour::new_in`].
+    pub unsafe fn new(target: *const c_void, detour: F) -> Result<Self, HookError> {
+        Self::new_in(WinApiBackend, target, detour)
+    }
+}
+
+impl<F: Copy, B: Backend> Detour<F, B> {
+    /// Redirect the function at `target` to `detour`, allocating and patching memory through
+    /// `backend`.
+    ///
+    /// Fails if `target` is already detoured, or if its first instructions cannot be moved
+    /// into a trampoline.
+    ///
+    /// # Panics
+    ///
+    /// If `F` is not pointer-sized, which means it cannot be a function pointer.
+    ///
+    /// # Safety
+    ///
+    /// `target` must be a function with exactly the signature `F`, with at least
+    /// `ABS_JUMP_LEN + MAX_INSTRUCTION_LEN - 1` readable bytes, and no thread may be running
+    /// its first instructions while they are patched.
+    pub unsafe fn new_in(backend: B, target: *const c_void, detour: F) -> Result<Self, HookError> {
+        super::assert_function_pointer::<F>();
+        let arch = Arch::HOST;
+        let target_address = target as usize;
+        let detour_address: usize = mem::transmute_copy(&detour);
+
+        let mut detoured = DETOURED_FUNCTIONS
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        if detoured.contains_key(&target_address) {
+            return Err(HookError::AlreadyDetoured {
+                address: target_address,
+            });
+        }
+
+        let trampoline = backend.alloc_executable(target, TRAMPOLINE_CAPACITY) as *mut u8;
+        if trampoline.is_null() {
+            return Err(HookError::AllocTrampolineFailed);
+        }
+
+        let patch_len = jump_len(arch, target_address, detour_address);
+        let code = slice::from_raw_parts(target as *const u8, patch_len + MAX_INSTRUCTION_LEN - 1);
+        let original_bytes =
+            build_trampoline(arch, code, target_address, trampoline as usize, patch_len).and_then(
+                |built| {
+                    ptr::copy_nonoverlapping(built.code.as_ptr(), trampoline, built.code.len());
+                    backend.flush_instruction_cache(trampoline as *const c_void, built.code.len());
+
+                    // Copied before patching, as `code` is the live function.
+                    let original_bytes = code[..built.stolen_len].to_vec();
+                    let patch = build_patch(arch, target_address, detour_address, built.stolen_len);
+                    write_code(&backend, target as *mut u8, &patch)?;
+                    Ok(original_bytes)
+                },
+            );
+        let original_bytes = match original_bytes {
+            Ok(original_bytes) => original_bytes,
+            Err(err) => {
+                backend.free_executable(trampoline as *mut c_void, TRAMPOLINE_CAPACITY);
+                return Err(err);
+            }
+        };
+
+        detoured.insert(
+            target_address,
+            DetouredFunction {
+                original_bytes,
+                trampoline: trampoline as usize,
+                patched: true,
+            },
+        );
+        Ok(Detour {
+            backend,
+            target: target as *mut u8,
+            trampoline,
+            original: Original::from_address(trampoline as usize),
+        })
+    }
+
+    /// Calls the function as it was before the detour, through the trampoline.
+    pub fn original(&self) -> Original<F> {
+        self.original
+    }
+
+    /// The detoured function.
+    pub fn target(&self) -> *const c_void {
+        self.target as *const c_void
+    }
+
+    /// The trampoline holding the function's moved first instructions.
+    pub fn trampoline(&self) -> *const c_void {
+        self.trampoline as *const c_void
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+}
+
+impl<F, B: Backend> Drop for Detour<F, B> {
+    fn drop(&mut self) {
+        let mut detoured = DETOURED_FUNCTIONS
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        // After a shutdown the function is restored already, and the trampoline is the
+        // shutdown's to free. The function may even have been detoured again since, by
+        // another `Detour` with its own trampoline.
+        let original_bytes = match detoured.get(&(self.target as usize)) {
+            Some(function)
+                if function.patched && function.trampoline == self.trampoline as usize =>
+            {
+                &function.original_bytes
+            }
+            _ => return,
+        };
+        // If the patch cannot be undone the function still jumps to the detour, which may
+        // call the trampoline, so both must be left in place.
+        if unsafe { write_code(&self.backend, self.target, original_bytes) }.is_err() {
+            return;
+        }
+        detoured.remove(&(self.target as usize));
+        RETIRED_TRAMPOLINES
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .push(self.trampoline as usize);
+    }
+}
+
+/// Put back the original bytes of every function detoured by any [`Detour`], changing
+/// protection through `backend`. The trampolines stay allocated until
+/// [`free_every_trampoline`].
+///
+/// Functions that cannot be written stay detoured, and the first such failure is returned.
+pub(crate) unsafe fn unpatch_every_detour<B: Backend>(backend: &B) -> Result<(), HookError> {
+    let mut detoured = DETOURED_FUNCTIONS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    let mut result = Ok(());
+    for (&target, function) in detoured.iter_mut().filter(|(_, function)| function.patched) {
+        match write_code(backend, target as *mut u8, &function.original_bytes) {
+            Ok(()) => function.patched = false,
+            Err(err) => {
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Free the trampolines of the functions [`unpatch_every_detour`] restored, and of every
+/// dropped [`Detour`].
+///
+/// # Safety
+///
+/// No thread may still be running a trampoline.
+pub(crate) unsafe fn free_every_trampoline<B: Backend>(backend: &B) {
+    let mut detoured = DETOURED_FUNCTIONS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    detoured.retain(|_, function| {
+        if !function.patched {
+            backend.free_executable(function.trampoline as *mut c_void, TRAMPOLINE_CAPACITY);
+        }
+        function.patched
+    });
+    let mut retired = RETIRED_TRAMPOLINES
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    for trampoline in retired.drain(..) {
+        backend.free_executable(trampoline as *mut c_void, TRAMPOLINE_CAPACITY);
+    }
+}
+
+/// Copy `bytes` over the code at `address`.
+unsafe fn write_code<B: Backend>(
+    backend: &B,
+    address: *mut u8,
+    bytes: &[u8],
+) -> Result<(), HookError> {
+    let protection = match backend.make_writable(address as *mut c_void, bytes.len()) {
+        Some(protection) => protection,
+        None => {
+            return Err(HookError::ProtectFailed {
+                address: address as usize,
+                len: bytes.len(),
+            })
+        }
+    };
+    ptr::copy_nonoverlapping(bytes.as_ptr(), address, bytes.len());
+    backend.restore_protection(address as *mut c_void, bytes.len(), protection);
+    backend.flush_instruction_cache(address as *const c_void, bytes.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `mov edi, edi; push ebp; mov ebp, esp; sub esp, 0x10; ret`, the hot-patchable 32-bit
+    /// prologue, followed by padding.
+    const X86_PROLOGUE: [u8; 20] = [
+        0x8B, 0xFF, 0x55, 0x8B, 0xEC, 0x83, 0xEC, 0x10, 0xC3, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC,
+        0xCC, 0xCC, 0xCC, 0xCC, 0xCC,
+    ];
+
+    /// `sub rsp, 0x28; mov [rsp+8], rbx; mov [rsp+0x10], rbp; push rsi`, then padding.
+    const X64_PROLOGUE: [u8; 30] = [
+        0x48, 0x83, 0xEC, 0x28, 0x48, 0x89, 0x5C, 0x24, 0x08, 0x48, 0x89, 0x6C, 0x24, 0x10, 0x56,
+        0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC,
+    ];
+
+    #[test]
+    fn near_jumps_are_relative() {
+        assert_eq!(jump_len(Arch::X86, 0x1000, 0x2000), REL_JUMP_LEN);
+        assert_eq!(
+            encode_jump(Arch::X86, 0x1000, 0x2000),
+            vec![0xE9, 0xFB, 0x0F, 0x00, 0x00]
+        );
+        assert_eq!(
+            encode_jump(Arch::X64, 0x7FF0_0000_2000, 0x7FF0_0000_1000),
+            vec![0xE9, 0xFB, 0xEF, 0xFF, 0xFF]
+        );
+    }
+
+    #[test]
+    fn far_jumps_on_x64_are_absolute() {
+        let from = 0x7FF0_0000_0000;
+        assert_eq!(jump_len(Arch::X64, from, 0x1000), ABS_JUMP_LEN);
+        assert_eq!(
+            encode_jump(Arch::X64, from, 0x1122_3344_5566),
+            vec![
+                0xFF, 0x25, 0x00, 0x00, 0x00, 0x00, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0x00, 0x00,
+            ]
+        );
+    }
+
+    #[test]
+    fn x86_trampoline_moves_whole_instructions_and_jumps_back() {
+        let trampoline = build_trampoline(Arch::X86, &X86_PROLOGUE, 0x1000, 0x5000, 5).unwrap();
+
+        // `mov edi, edi; push ebp; mov ebp, esp` cover exactly five bytes.
+        assert_eq!(trampoline.stolen_len, 5);
+        assert_eq!(
+            trampoline.code,
+            vec![
+                0x8B, 0xFF, 0x55, 0x8B, 0xEC, // the moved instructions
+                0xE9, 0xFB, 0xBF, 0xFF, 0xFF, // jmp 0x1005
+            ]
+        );
+    }
+
+    #[test]
+    fn x86_trampoline_stops_at_the_first_instruction_boundary_past_the_jump() {
+        let trampoline = build_trampoline(Arch::X86, &X86_PROLOGUE, 0x1000, 0x5000, 6).unwrap();
+
+        assert_eq!(trampoline.stolen_len, 8);
+        assert_eq!(&trampoline.code[..8], &X86_PROLOGUE[..8]);
+        assert_eq!(trampoline.code.len(), 8 + REL_JUMP_LEN);
+    }
+
+    #[test]
+    fn x64_trampoline_far_from_the_function_jumps_back_absolutely() {
+        let function = 0x7FF0_0000_0000;
+        let trampoline =
+            build_trampoline(Arch::X64, &X64_PROLOGUE, function, 0x1000, ABS_JUMP_LEN).unwrap();
+
+        assert_eq!(trampoline.stolen_len, 14);
+        let mut expected = X64_PROLOGUE[..14].to_vec();
+        expected.extend(&[0xFF, 0x25, 0x00, 0x00, 0x00, 0x00]);
+        expected.extend(&(function as u64 + 14).to_le_bytes());
+        assert_eq!(trampoline.code, expected);
+    }
+
+    #[test]
+    fn patch_is_padded_to_the_stolen_instructions() {
+        assert_eq!(
+            build_patch(Arch::X86, 0x1000, 0x2000, 6),
+            vec![0xE9, 0xFB, 0x0F, 0x00, 0x00, 0x90]
+        );
+        assert_eq!(build_patch(Arch::X86, 0x1000, 0x2000, 5).len(), 5);
+        assert_eq!(
+            build_patch(Arch::X64, 0x7FF0_0000_0000, 0x1000, 15).len(),
+            15
+        );
+    }
+
+    #[test]
+    fn functions_shorter_than_the_jump_are_rejected() {
+        // xor eax, eax; ret; push ebp: the next function starts inside the patch.
+        let code = [
+            0x33, 0xC0, 0xC3, 0x55, 0x8B, 0xEC, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        assert!(matches!(
+            build_trampoline(Arch::X86, &code, 0x1000, 0x5000, 5),
+            Err(HookError::FunctionTooShort { len: 3 })
+        ));
+    }
+
+    #[test]
+    fn padding_after_a_short_function_can_be_patched() {
+        let code = [
+            0x33, 0xC0, 0xC3, 0xCC, 0xCC, 0xCC, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let trampoline = build_trampoline(Arch::X86, &code, 0x1000, 0x5000, 5).unwrap();
+        assert_eq!(trampoline.stolen_len, 5);
+    }
+
+    #[test]
+    fn branches_back_into_the_patch_are_rejected() {
+        // je +1 lands on the `push ebp`, which the jump overwrites.
+        let code = [
+            0x74, 0x01, 0x90, 0x55, 0x8B, 0xEC, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        assert!(matches!(
+            build_trampoline(Arch::X86, &code, 0x1000, 0x5000, 5),
+            Err(HookError::BranchIntoPatch { offset: 0 })
+        ));
+    }
+
+    #[test]
+    fn undecodable_instructions_are_rejected() {
+        assert!(matches!(
+            build_trampoline(Arch::X64, &[0x48, 0x8B], 0x1000, 0x5000, 5),
+            Err(HookError::UnsupportedInstruction {
+                offset: 0,
+                opcode: 0x48
+            })
+        ));
+    }
+}
@@ -0,0 +1,354 @@
+//! Telling apart the devices of a process that renders with more than one, such as an editor
+//! with tool windows or a game spanning several monitors.
+
+use std::mem;
+use std::ptr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::com;
+use crate::sys::*;
+use crate::vtable::{DeviceMethod, SwapChainMethod};
+
+type GetSwapChainFn = unsafe extern "system" fn(
+    *mut IDirect3DDevice9,
+    UINT,
+    *mut *mut IDirect3DSwapChain9,
+) -> HRESULT;
+type GetPresentParametersFn =
+    unsafe extern "system" fn(*mut IDirect3DSwapChain9, *mut D3DPRESENT_PARAMETERS) -> HRESULT;
+type GetDeviceFn =
+    unsafe extern "system" fn(*mut IDirect3DSwapChain9, *mut *mut IDirect3DDevice9) -> HRESULT;
+
+/// What a [`DeviceRegistry`] knows about one device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub device: *mut IDirect3DDevice9,
+    /// The window of the device's implicit swap chain, or null if it could not be asked for.
+    pub window: HWND,
+    /// Every window `Present` was called for, in the order they were first seen, through the
+    /// device or any of its swap chains. Calls without a window override count as presenting
+    /// to the window of the swap chain presented.
+    pub presented_to: Vec<HWND>,
+    /// Number of `Present` calls seen.
+    pub presents: u64,
+}
+
+/// Which devices the callbacks of [`D3D9Hooks`](super::D3D9Hooks) run for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeviceFilter(Filter);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Filter {
+    #[default]
+    All,
+    Device(usize),
+    Window(usize),
+    First,
+}
+
+impl DeviceFilter {
+    /// Every device.
+    pub fn all() -> Self {
+        DeviceFilter(Filter::All)
+    }
+
+    /// Only `device`, such as one found by [`capture_live_device_in`](super::capture_live_device_in).
+    pub fn device(device: *mut IDirect3DDevice9) -> Self {
+        DeviceFilter(Filter::Device(device as usize))
+    }
+
+    /// Only devices whose swap chain window is `window`, or that presented to it.
+    pub fn window(window: HWND) -> Self {
+        DeviceFilter(Filter::Window(window as usize))
+    }
+
+    /// Only the first device seen calling `EndScene` or `Present`.
+    pub fn first() -> Self {
+        DeviceFilter(Filter::First)
+    }
+
+    pub fn is_all(&self) -> bool {
+        self.0 == Filter::All
+    }
+}
+
+struct Entry {
+    device: usize,
+    window: usize,
+    presented_to: Vec<usize>,
+    presents: u64,
+}
+
+/// Every device seen by the hooks, and the windows each presented to.
+///
+/// A device is forgotten when its last reference is released, and the windows it presented
+/// to are forgotten when it is reset, as a reset may move it to another window.
+///
+/// Cloning gives another handle to the same registry, so one clone can be given to
+/// [`D3D9Hooks::with_devices`](super::D3D9Hooks::with_devices) and the other read from
+/// callbacks.
+///
+/// ```ignore
+/// let devices = DeviceRegistry::new();
+/// let seen = devices.clone();
+/// let hooks = D3D9Hooks::new()
+///     .with_devices(devices)
+///     .on_end_scene(move |device| {
+///         if seen.matches(device, &DeviceFilter::window(game_window)) {
+///             draw_overlay(device);
+///         }
+///     })
+///     .install()?;
+/// ```
+#[derive(Clone, Default)]
+pub struct DeviceRegistry {
+    entries: Arc<Mutex<Vec<Entry>>>,
+}
+
+impl DeviceRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of devices seen.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Every device seen, in the order they were first seen.
+    pub fn devices(&self) -> Vec<DeviceInfo> {
+        self.lock().iter().map(Entry::info).collect()
+    }
+
+    /// What is known about `device`, if it was seen.
+    pub fn device(&self, device: *mut IDirect3DDevice9) -> Option<DeviceInfo> {
+        self.lock()
+            .iter()
+            .find(|entry| entry.device == device as usize)
+            .map(Entry::info)
+    }
+
+    /// Every window a device was created on or presented to, without duplicates.
+    pub fn windows(&self) -> Vec<HWND> {
+        let mut windows: Vec<usize> = Vec::new();
+        for entry in self.lock().iter() {
+            for &window in Some(&entry.window).into_iter().chain(&entry.presented_to) {
+                if window != 0 && !windows.contains(&window) {
+                    windows.push(window);
+                }
+            }
+        }
+        windows.into_iter().map(|window| window as HWND).collect()
+    }
+
+    /// The first device seen that is on or presented to `window`.
+    pub fn device_for_window(&self, window: HWND) -> Option<*mut IDirect3DDevice9> {
+        self.lock()
+            .iter()
+            .find(|entry| entry.is_on(window as usize))
+            .map(|entry| entry.device as *mut IDirect3DDevice9)
+    }
+
+    /// Whether `device` passes `filter`. A device that was never seen only passes
+    /// [`DeviceFilter::all`] and [`DeviceFilter::device`].
+    pub fn matches(&self, device: *mut IDirect3DDevice9, filter: &DeviceFilter) -> bool {
+        let device = device as usize;
+        match filter.0 {
+            Filter::All => true,
+            Filter::Device(wanted) => device == wanted,
+            Filter::Window(window) => self
+                .lock()
+                .iter()
+                .any(|entry| entry.device == device && entry.is_on(window)),
+            Filter::First => self
+                .lock()
+                .first()
+                .is_some_and(|entry| entry.device == device),
+        }
+    }
+
+    /// Forget every device, for instance after the game recreated its devices.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Record that `device` is in use, asking it for its window the first time it is seen.
+    ///
+    /// # Safety
+    ///
+    /// `device` must be a live `IDirect3DDevice9`.
+    pub unsafe fn observe_device(&self, device: *mut IDirect3DDevice9) {
+        if device.is_null()
+            || self
+                .lock()
+                .iter()
+                .any(|entry| entry.device == device as usize)
+        {
+            return;
+        }
+        // Asked without the lock, as the call goes back into d3d9.
+        let window = swap_chain_window(device) as usize;
+        let mut entries = self.lock();
+        if !entries.iter().any(|entry| entry.device == device as usize) {
+            entries.push(Entry {
+                device: device as usize,
+                window,
+                presented_to: Vec::new(),
+                presents: 0,
+            });
+        }
+    }
+
+    /// Record a `Present` call of `device` with its window override, which may be null.
+    ///
+    /// # Safety
+    ///
+    /// `device` must be a live `IDirect3DDevice9`.
+    pub unsafe fn observe_present(&self, device: *mut IDirect3DDevice9, window_override: HWND) {
+        self.observe_device(device);
+        self.record_present(device, None, window_override);
+    }
+
+    /// Record an `IDirect3DSwapChain9::Present` call of `swap_chain`, which may be one of the
+    /// additional swap chains a device presents to other windows with, with its window
+    /// override, which may be null.
+    ///
+    /// # Safety
+    ///
+    /// `swap_chain` must be a live `IDirect3DSwapChain9`.
+    pub unsafe fn observe_swap_chain_present(
+        &self,
+        swap_chain: *mut IDirect3DSwapChain9,
+        window_override: HWND,
+    ) {
+        if swap_chain.is_null() {
+            return;
+        }
+        // Asked on every call rather than remembered, as a released swap chain's address may
+        // be reused by the next one.
+        let device = swap_chain_device(swap_chain);
+        if device.is_null() {
+            return;
+        }
+        self.observe_device(device);
+        let window = present_parameters_window(swap_chain);
+        self.record_present(device, Some(window), window_override);
+    }
+
+    /// Forget the windows `device` presented to and ask for its window again, after a
+    /// successful `Reset` that may have moved it to another window.
+    ///
+    /// # Safety
+    ///
+    /// `device` must be a live `IDirect3DDevice9`.
+    pub unsafe fn observe_reset(&self, device: *mut IDirect3DDevice9) {
+        if self.device(device).is_none() {
+            return;
+        }
+        let window = swap_chain_window(device) as usize;
+        if let Some(entry) = self
+            .lock()
+            .iter_mut()
+            .find(|entry| entry.device == device as usize)
+        {
+            entry.window = window;
+            entry.presented_to.clear();
+        }
+    }
+
+    /// Forget `device`, once its last reference is released and its address may be reused.
+    pub fn forget_device(&self, device: *mut IDirect3DDevice9) {
+        self.lock().retain(|entry| entry.device != device as usize);
+    }
+
+    /// Count a present of `device` to `window_override`, or else to `window`, or else to the
+    /// window of the device's implicit swap chain.
+    fn record_present(
+        &self,
+        device: *mut IDirect3DDevice9,
+        window: Option<HWND>,
+        window_override: HWND,
+    ) {
+        let mut entries = self.lock();
+        if let Some(entry) = entries
+            .iter_mut()
+            .find(|entry| entry.device == device as usize)
+        {
+            entry.presents += 1;
+            let window = if !window_override.is_null() {
+                window_override as usize
+            } else {
+                window.map_or(entry.window, |window| window as usize)
+            };
+            if window != 0 && !entry.presented_to.contains(&window) {
+                entry.presented_to.push(window);
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Entry>> {
+        self.entries.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Entry {
+    fn is_on(&self, window: usize) -> bool {
+        window != 0 && (self.window == window || self.presented_to.contains(&window))
+    }
+
+    fn info(&self) -> DeviceInfo {
+        DeviceInfo {
+            device: self.device as *mut IDirect3DDevice9,
+            window: self.window as HWND,
+            presented_to: self
+                .presented_to
+                .iter()
+                .map(|&window| window as HWND)
+                .collect(),
+            presents: self.presents,
+        }
+    }
+}
+
+/// The `hDeviceWindow` of `device`'s implicit swap chain, or null if it cannot be had.
+unsafe fn swap_chain_window(device: *mut IDirect3DDevice9) -> HWND {
+    let get_swap_chain: GetSwapChainFn =
+        mem::transmute(com::method(device, DeviceMethod::GetSwapChain.index()));
+    let mut swap_chain: *mut IDirect3DSwapChain9 = ptr::null_mut();
+    if get_swap_chain(device, 0, &mut swap_chain) < 0 || swap_chain.is_null() {
+        return ptr::null_mut();
+    }
+    let window = present_parameters_window(swap_chain);
+    com::release(swap_chain);
+    window
+}
+
+/// The `hDeviceWindow` of `swap_chain`, or null if it cannot be had.
+unsafe fn present_parameters_window(swap_chain: *mut IDirect3DSwapChain9) -> HWND {
+    let get_present_parameters: GetPresentParametersFn = mem::transmute(com::method(
+        swap_chain,
+        SwapChainMethod::GetPresentParameters.index(),
+    ));
+    let mut present_params: D3DPRESENT_PARAMETERS = mem::zeroed();
+    if get_present_parameters(swap_chain, &mut present_params) < 0 {
+        return ptr::null_mut();
+    }
+    present_params.hDeviceWindow
+}
+
+/// The device `swap_chain` belongs to, or null if it cannot be had. The reference
+/// `GetDevice` adds is given back straight away, as the swap chain keeps the device alive.
+unsafe fn swap_chain_device(swap_chain: *mut IDirect3DSwapChain9) -> *mut IDirect3DDevice9 {
+    let get_device: GetDeviceFn =
+        mem::transmute(com::method(swap_chain, SwapChainMethod::GetDevice.index()));
+    let mut device: *mut IDirect3DDevice9 = ptr::null_mut();
+    if get_device(swap_chain, &mut device) < 0 || device.is_null() {
+        return ptr::null_mut();
+    }
+    com::release(device);
+    device
+}
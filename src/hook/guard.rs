@@ -0,0 +1,269 @@
+//! Keeping panics in hook callbacks from unwinding into the game.
+//!
+//! Unwinding out of an `extern "system"` function is undefined behaviour, so every callback
+//! runs inside `catch_unwind`. A caught panic goes to the sink of the [`PanicPolicy`], if it
+//! has one, and a callback that keeps panicking is switched off as the policy says.
+
+use std::any::Any;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+
+type PanicSink = Arc<dyn Fn(&PanicReport) + Send + Sync>;
+
+/// What to do about callbacks that panic.
+///
+/// The real method is always called after a panicking callback. By default a callback is
+/// disabled for good after panicking [`PanicPolicy::DEFAULT_DISABLE_AFTER`] times in a row,
+/// and nothing is reported beyond what the process's panic hook prints.
+#[derive(Clone)]
+pub struct PanicPolicy {
+    disable_after: Option<u32>,
+    sink: Option<PanicSink>,
+}
+
+impl PanicPolicy {
+    /// Number of panics in a row after which a callback is disabled by default.
+    pub const DEFAULT_DISABLE_AFTER: u32 = 3;
+
+    pub fn new() -> Self {
+        PanicPolicy {
+            disable_after: Some(Self::DEFAULT_DISABLE_AFTER),
+            sink: None,
+        }
+    }
+
+    /// Disable a callback once it has panicked `panics` times in a row. A call that returns
+    /// normally starts the count again. Zero is taken as one.
+    pub fn disable_after(mut self, panics: u32) -> Self {
+        self.disable_after = Some(panics.max(1));
+        self
+    }
+
+    /// Keep calling callbacks however often they panic.
+    pub fn never_disable(mut self) -> Self {
+        self.disable_after = None;
+        self
+    }
+
+    /// Hand every caught panic to `sink`, on the thread that made the hooked call. This is
+    /// the place to route them to the application's log.
+    pub fn report_to<F>(mut self, sink: F) -> Self
+    where
+        F: Fn(&PanicReport) + Send + Sync + 'static,
+    {
+        self.sink = Some(Arc::new(sink));
+        self
+    }
+
+    /// Number of panics in a row that disable a callback, or `None` if they never do.
+    pub fn disables_after(&self) -> Option<u32> {
+        self.disable_after
+    }
+}
+
+impl Default for PanicPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for PanicPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PanicPolicy")
+            .field("disable_after", &self.disable_after)
+            .field("sink", &self.sink.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+/// A panic caught in a hook callback.
+#[derive(Debug, Clone, Copy)]
+pub struct PanicReport<'a> {
+    /// The hooked method the callback ran for, such as `"EndScene"`.
+    pub method: &'a str,
+    /// The message the callback panicked with.
+    pub message: &'a str,
+    /// How many times in a row the callback has panicked, this time included.
+    pub consecutive_panics: u32,
+    /// Whether this panic got the callback disabled.
+    pub disabled: bool,
+}
+
+/// A callback with the count of its panics in a row.
+pub(super) struct Guarded<F> {
+    callback: F,
+    consecutive_panics: AtomicU32,
+    disabled: AtomicBool,
+}
+
+impl<F> Guarded<F> {
+    pub(super) fn new(callback: F) -> Self {
+        Guarded {
+            callback,
+            consecutive_panics: AtomicU32::new(0),
+            disabled: AtomicBool::new(false),
+        }
+    }
+
+    /// Run `call` on the callback unless it was disabled, catching a panic and reporting it
+    /// to the policy's sink.
+    ///
+    /// `method` names the hooked method in the report.
+    pub(super) fn call<C: FnOnce(&F)>(&self, method: &str, policy: &PanicPolicy, call: C) {
+        if self.is_disabled() {
+            return;
+        }
+        let payload = match panic::catch_unwind(AssertUnwindSafe(|| call(&self.callback))) {
+            Ok(()) => {
+                self.consecutive_panics.store(0, Ordering::Relaxed);
+                return;
+            }
+            Err(payload) => payload,
+        };
+
+        let panics = self.consecutive_panics.fetch_add(1, Ordering::Relaxed) + 1;
+        let disabled = policy.disable_after.is_some_and(|limit| panics >= limit)
+            && !self.disabled.swap(true, Ordering::Relaxed);
+        if let Some(sink) = &policy.sink {
+            let report = PanicReport {
+                method,
+                message: panic_message(&*payload),
+                consecutive_panics: panics,
+                disabled,
+            };
+            // A sink that panics in turn must not unwind into the game either.
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| sink(&report)));
+        }
+    }
+
+    /// Whether the callback panicked often enough to be switched off.
+    pub(super) fn is_disabled(&self) -> bool {
+        self.disabled.load(Ordering::Relaxed)
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "non-string panic payload"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    type Reports = Arc<Mutex<Vec<(String, String, u32, bool)>>>;
+
+    /// A policy whose sink records every report.
+    fn recorded(policy: PanicPolicy) -> (PanicPolicy, Reports) {
+        let reports = Reports::default();
+        let sink = reports.clone();
+        let policy = policy.report_to(move |report| {
+            sink.lock().unwrap().push((
+                report.method.to_owned(),
+                report.message.to_owned(),
+                report.consecutive_panics,
+                report.disabled,
+            ))
+        });
+        (policy, reports)
+    }
+
+    /// Call `guarded` once, panicking if `panic` is set, and return whether it ran.
+    fn call(guarded: &Guarded<()>, policy: &PanicPolicy, panic: bool) -> bool {
+        let mut ran = false;
+        guarded.call("EndScene", policy, |_| {
+            ran = true;
+            if panic {
+                panic!("frame {}", 7);
+            }
+        });
+        ran
+    }
+
+    #[test]
+    fn policy_defaults_and_limits() {
+        assert_eq!(PanicPolicy::new().disables_after(), Some(3));
+        assert_eq!(PanicPolicy::default().disables_after(), Some(3));
+        assert_eq!(
+            PanicPolicy::new().disable_after(0).disables_after(),
+            Some(1)
+        );
+        assert_eq!(PanicPolicy::new().never_disable().disables_after(), None);
+    }
+
+    #[test]
+    fn disabled_after_panics_in_a_row() {
+        let (policy, reports) = recorded(PanicPolicy::new().disable_after(2));
+        let guarded = Guarded::new(());
+
+        assert!(call(&guarded, &policy, true));
+        assert!(!guarded.is_disabled());
+        assert!(call(&guarded, &policy, true));
+        assert!(guarded.is_disabled());
+        assert!(!call(&guarded, &policy, false));
+
+        assert_eq!(
+            *reports.lock().unwrap(),
+            vec![
+                ("EndScene".to_owned(), "frame 7".to_owned(), 1, false),
+                ("EndScene".to_owned(), "frame 7".to_owned(), 2, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_normal_return_starts_the_count_again() {
+        let policy = PanicPolicy::new().disable_after(2);
+        let guarded = Guarded::new(());
+
+        for _ in 0..5 {
+            call(&guarded, &policy, true);
+            call(&guarded, &policy, false);
+        }
+
+        assert!(!guarded.is_disabled());
+    }
+
+    #[test]
+    fn never_disabled_when_asked() {
+        let (policy, reports) = recorded(PanicPolicy::new().never_disable());
+        let guarded = Guarded::new(());
+
+        for _ in 0..10 {
+            assert!(call(&guarded, &policy, true));
+        }
+
+        assert!(!guarded.is_disabled());
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 10);
+        assert_eq!(reports[9].2, 10);
+        assert!(reports.iter().all(|report| !report.3));
+    }
+
+    #[test]
+    fn a_panicking_sink_is_caught_too() {
+        let policy = PanicPolicy::new().report_to(|_| panic!("sink"));
+        let guarded = Guarded::new(());
+
+        assert!(call(&guarded, &policy, true));
+    }
+
+    #[test]
+    fn non_string_payloads_are_named() {
+        let (policy, reports) = recorded(PanicPolicy::new());
+        let guarded = Guarded::new(());
+
+        guarded.call("Present", &policy, |_| panic::panic_any(42));
+
+        assert_eq!(reports.lock().unwrap()[0].1, "non-string panic payload");
+    }
+}
@@ -0,0 +1,500 @@
+//! Releasing and recreating `D3DPOOL_DEFAULT` resources around a device `Reset`.
+//!
+//! A device can only be reset once every resource in `D3DPOOL_DEFAULT` is released, and the
+//! game never knows about the ones an overlay created. A [`ResourceRegistry`] holds a pair of
+//! callbacks per resource and runs them as the device is lost and reset. It is driven by
+//! [`D3D9Hooks::with_resources`](super::D3D9Hooks::with_resources) from the hooked
+//! `TestCooperativeLevel`, `Reset` and `ResetEx`, or by calling its `observe_*` methods
+//! directly with the codes they returned.
+
+use std::collections::HashMap;
+use std::iter;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::guard::Guarded;
+use super::PanicPolicy;
+use crate::sys::*;
+use crate::D3dResult;
+
+/// Locked on its own, so it runs without the registry locked, and guarded on its own, so one
+/// that panics is counted and disabled apart from the others.
+type ResourceCallback = Arc<Guarded<Mutex<Box<dyn FnMut(*mut IDirect3DDevice9) + Send>>>>;
+
+/// Where a device is in the lost / reset cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceState {
+    /// Rendering normally.
+    Operational,
+    /// Lost, as after alt-tab from fullscreen, and not yet ready to be reset.
+    Lost,
+    /// Ready to be reset, and unusable until it is.
+    NotReset,
+}
+
+/// Identifies a resource registered with a [`ResourceRegistry`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ResourceId(u64);
+
+struct Resource {
+    id: ResourceId,
+    device: usize,
+    on_lost: ResourceCallback,
+    on_reset: ResourceCallback,
+}
+
+/// What the registry knows about one device.
+#[derive(Clone, Copy)]
+struct Device {
+    state: DeviceState,
+    /// Whether `on_lost` ran without an `on_reset` after it.
+    released: bool,
+}
+
+impl Default for Device {
+    fn default() -> Self {
+        Device {
+            state: DeviceState::Operational,
+            released: false,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    devices: HashMap<usize, Device>,
+    next_id: u64,
+    resources: Vec<Resource>,
+}
+
+/// Callbacks that release resources before their device is reset and recreate them after.
+///
+/// Each resource belongs to the device it was created on. Its `on_lost` runs once when that
+/// device is first seen lost or about to be reset, and its `on_reset` once after a `Reset` of
+/// the device succeeds. Devices are tracked separately, so one registry can serve every device
+/// of the process. Clones share the same registry, so one can be handed to the hooks while
+/// another registers resources as they are created.
+///
+/// The callbacks run without the registry locked, so they may register and unregister
+/// resources. A callback that panics does not keep the others from running, and its resource
+/// counts as released or recreated all the same. Each callback is handled on its own as the
+/// [`PanicPolicy`] of the hooks says, or the default policy when the `observe_*` methods are
+/// called directly.
+///
+/// ```ignore
+/// let resources = ResourceRegistry::new();
+/// let texture = Arc::new(Mutex::new(create_texture(device)));
+/// resources.register(
+///     device,
+///     { let texture = texture.clone(); move |_| texture.lock().unwrap().release() },
+///     move |device| *texture.lock().unwrap() = create_texture(device),
+/// );
+/// let hooks = D3D9Hooks::new().with_resources(resources.clone()).install()?;
+/// ```
+#[derive(Clone)]
+pub struct ResourceRegistry {
+    inner: Arc<Mutex<Registry>>,
+}
+
+impl ResourceRegistry {
+    pub fn new() -> Self {
+        ResourceRegistry {
+            inner: Arc::new(Mutex::new(Registry::default())),
+        }
+    }
+
+    /// Add a resource that already exists on `device`, with `on_lost` to release it and
+    /// `on_reset` to create it again once `device` is reset.
+    pub fn register<L, R>(
+        &self,
+        device: *mut IDirect3DDevice9,
+        on_lost: L,
+        on_reset: R,
+    ) -> ResourceId
+    where
+        L: FnMut(*mut IDirect3DDevice9) + Send + 'static,
+        R: FnMut(*mut IDirect3DDevice9) + Send + 'static,
+    {
+        let mut registry = self.lock();
+        let id = ResourceId(registry.next_id);
+        registry.next_id += 1;
+        registry.resources.push(Resource {
+            id,
+            device: device as usize,
+            on_lost: Arc::new(Guarded::new(Mutex::new(Box::new(on_lost)))),
+            on_reset: Arc::new(Guarded::new(Mutex::new(Box::new(on_reset)))),
+        });
+        id
+    }
+
+    /// Remove a resource without running its callbacks. Returns whether it was registered.
+    pub fn unregister(&self, id: ResourceId) -> bool {
+        let mut registry = self.lock();
+        let len = registry.resources.len();
+        registry.resources.retain(|resource| resource.id != id);
+        registry.resources.len() != len
+    }
+
+    /// Number of registered resources, across every device.
+    pub fn len(&self) -> usize {
+        self.lock().resources.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The state of `device` as last observed. Devices never observed are operational.
+    pub fn state(&self, device: *mut IDirect3DDevice9) -> DeviceState {
+        self.device(device).state
+    }
+
+    /// Whether the resources of `device` are released and waiting for a successful `Reset`.
+    pub fn is_released(&self, device: *mut IDirect3DDevice9) -> bool {
+        self.device(device).released
+    }
+
+    /// Take in what `TestCooperativeLevel` returned on `device`.
+    ///
+    /// `D3DERR_DEVICELOST` and `D3DERR_DEVICENOTRESET` release the resources. `D3D_OK` only
+    /// marks the device operational again if nothing is waiting on a `Reset`.
+    pub fn observe_cooperative_level(&self, device: *mut IDirect3DDevice9, result: D3dResult) {
+        self.cooperative_level(
+            device,
+            result,
+            "TestCooperativeLevel",
+            &PanicPolicy::default(),
+        );
+    }
+
+    /// Release the resources of `device` ahead of `Reset` or `ResetEx`, if they are not
+    /// already.
+    pub fn observe_before_reset(&self, device: *mut IDirect3DDevice9) {
+        self.before_reset(device, "Reset", &PanicPolicy::default());
+    }
+
+    /// Take in what `Reset` or `ResetEx` returned on `device`, recreating its resources if it
+    /// succeeded.
+    pub fn observe_after_reset(&self, device: *mut IDirect3DDevice9, result: D3dResult) {
+        self.after_reset(device, result, "Reset", &PanicPolicy::default());
+    }
+
+    /// Number of callbacks disabled for panicking too often, `on_lost` and `on_reset`
+    /// counting separately.
+    pub(super) fn disabled_callbacks(&self) -> usize {
+        self.lock()
+            .resources
+            .iter()
+            .flat_map(|resource| {
+                iter::once(&resource.on_lost).chain(iter::once(&resource.on_reset))
+            })
+            .filter(|callback| callback.is_disabled())
+            .count()
+    }
+
+    /// [`observe_cooperative_level`](Self::observe_cooperative_level) from the hooked
+    /// `method`, handling panics as `policy` says.
+    pub(super) fn cooperative_level(
+        &self,
+        device: *mut IDirect3DDevice9,
+        result: D3dResult,
+        method: &str,
+        policy: &PanicPolicy,
+    ) {
+        let state = match result {
+            D3dResult::DeviceLost => DeviceState::Lost,
+            D3dResult::DeviceNotReset => DeviceState::NotReset,
+            D3dResult::Ok => {
+                let mut registry = self.lock();
+                let entry = registry.devices.entry(device as usize).or_default();
+                if !entry.released {
+                    entry.state = DeviceState::Operational;
+                }
+                return;
+            }
+            _ => return,
+        };
+        let released = {
+            let mut registry = self.lock();
+            registry.devices.entry(device as usize).or_default().state = state;
+            registry.release(device)
+        };
+        run(released, device, method, policy);
+    }
+
+    /// [`observe_before_reset`](Self::observe_before_reset) from the hooked `method`.
+    pub(super) fn before_reset(
+        &self,
+        device: *mut IDirect3DDevice9,
+        method: &str,
+        policy: &PanicPolicy,
+    ) {
+        let released = self.lock().release(device);
+        run(released, device, method, policy);
+    }
+
+    /// [`observe_after_reset`](Self::observe_after_reset) from the hooked `method`.
+    pub(super) fn after_reset(
+        &self,
+        device: *mut IDirect3DDevice9,
+        result: D3dResult,
+        method: &str,
+        policy: &PanicPolicy,
+    ) {
+        let recreated = {
+            let mut registry = self.lock();
+            let entry = registry.devices.entry(device as usize).or_default();
+            if result.is_success() {
+                entry.state = DeviceState::Operational;
+                if entry.released {
+                    entry.released = false;
+                    registry.callbacks_of(device, |resource| &resource.on_reset)
+                } else {
+                    Vec::new()
+                }
+            } else {
+                entry.state = if result == D3dResult::DeviceLost {
+                    DeviceState::Lost
+                } else {
+                    DeviceState::NotReset
+                };
+                Vec::new()
+            }
+        };
+        run(recreated, device, method, policy);
+    }
+
+    fn device(&self, device: *mut IDirect3DDevice9) -> Device {
+        self.lock()
+            .devices
+            .get(&(device as usize))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Registry> {
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Default for ResourceRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Registry {
+    /// Mark the resources of `device` released, returning the `on_lost` callbacks to run now.
+    /// None if they already are.
+    fn release(&mut self, device: *mut IDirect3DDevice9) -> Vec<ResourceCallback> {
+        let entry = self.devices.entry(device as usize).or_default();
+        if entry.released {
+            return Vec::new();
+        }
+        entry.released = true;
+        self.callbacks_of(device, |resource| &resource.on_lost)
+    }
+
+    /// The callback `pick` chooses of every resource of `device`.
+    fn callbacks_of<F>(&self, device: *mut IDirect3DDevice9, pick: F) -> Vec<ResourceCallback>
+    where
+        F: Fn(&Resource) -> &ResourceCallback,
+    {
+        self.resources
+            .iter()
+            .filter(|resource| resource.device == device as usize)
+            .map(|resource| pick(resource).clone())
+            .collect()
+    }
+}
+
+/// Run `callbacks` with the registry unlocked, each in its own guard, so one that panics
+/// cannot leave the rest unreleased.
+fn run(
+    callbacks: Vec<ResourceCallback>,
+    device: *mut IDirect3DDevice9,
+    method: &str,
+    policy: &PanicPolicy,
+) {
+    for callback in callbacks {
+        callback.call(method, policy, |callback| {
+            (*callback.lock().unwrap_or_else(|err| err.into_inner()))(device)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ptr;
+
+    fn device(n: usize) -> *mut IDirect3DDevice9 {
+        n as *mut IDirect3DDevice9
+    }
+
+    /// A registry with one resource on `device(1)`, logging its callbacks.
+    fn logged() -> (ResourceRegistry, Arc<Mutex<Vec<&'static str>>>) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let resources = ResourceRegistry::new();
+        let (lost, reset) = (log.clone(), log.clone());
+        resources.register(
+            device(1),
+            move |_| lost.lock().unwrap().push("lost"),
+            move |_| reset.lock().unwrap().push("reset"),
+        );
+        (resources, log)
+    }
+
+    #[test]
+    fn cooperative_level_codes_drive_the_state() {
+        let (resources, log) = logged();
+        let script = [
+            (D3dResult::Ok, DeviceState::Operational, false),
+            (D3dResult::DeviceLost, DeviceState::Lost, true),
+            (D3dResult::DeviceLost, DeviceState::Lost, true),
+            (D3dResult::DeviceNotReset, DeviceState::NotReset, true),
+            // Still waiting for a reset, whatever TestCooperativeLevel says.
+            (D3dResult::Ok, DeviceState::NotReset, true),
+            (D3dResult::InvalidCall, DeviceState::NotReset, true),
+        ];
+        for &(result, state, released) in &script {
+            resources.observe_cooperative_level(device(1), result);
+            assert_eq!(resources.state(device(1)), state, "after {:?}", result);
+            assert_eq!(resources.is_released(device(1)), released);
+        }
+        assert_eq!(*log.lock().unwrap(), vec!["lost"]);
+    }
+
+    #[test]
+    fn reset_results_drive_the_state() {
+        let (resources, log) = logged();
+        resources.observe_cooperative_level(device(1), D3dResult::DeviceNotReset);
+
+        resources.observe_before_reset(device(1));
+        resources.observe_after_reset(device(1), D3dResult::DeviceLost);
+        assert_eq!(resources.state(device(1)), DeviceState::Lost);
+        resources.observe_after_reset(device(1), D3dResult::InvalidCall);
+        assert_eq!(resources.state(device(1)), DeviceState::NotReset);
+        assert!(resources.is_released(device(1)));
+
+        resources.observe_after_reset(device(1), D3dResult::Ok);
+        assert_eq!(resources.state(device(1)), DeviceState::Operational);
+        assert!(!resources.is_released(device(1)));
+        assert_eq!(*log.lock().unwrap(), vec!["lost", "reset"]);
+    }
+
+    #[test]
+    fn a_reset_without_losing_the_device_releases_and_recreates() {
+        let (resources, log) = logged();
+
+        resources.observe_before_reset(device(1));
+        resources.observe_before_reset(device(1));
+        resources.observe_after_reset(device(1), D3dResult::Ok);
+        resources.observe_after_reset(device(1), D3dResult::Ok);
+
+        assert_eq!(*log.lock().unwrap(), vec!["lost", "reset"]);
+    }
+
+    #[test]
+    fn devices_are_tracked_separately() {
+        let (resources, log) = logged();
+
+        resources.observe_cooperative_level(device(2), D3dResult::DeviceLost);
+        assert_eq!(resources.state(device(2)), DeviceState::Lost);
+        assert_eq!(resources.state(device(1)), DeviceState::Operational);
+        assert!(!resources.is_released(device(1)));
+        assert!(log.lock().unwrap().is_empty());
+
+        resources.observe_cooperative_level(device(1), D3dResult::DeviceLost);
+        resources.observe_after_reset(device(2), D3dResult::Ok);
+        assert_eq!(resources.state(device(1)), DeviceState::Lost);
+        assert_eq!(*log.lock().unwrap(), vec!["lost"]);
+    }
+
+    #[test]
+    fn callbacks_get_the_device() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let resources = ResourceRegistry::new();
+        let (lost, reset) = (seen.clone(), seen.clone());
+        resources.register(
+            device(3),
+            move |device| lost.lock().unwrap().push(device as usize),
+            move |device| reset.lock().unwrap().push(device as usize),
+        );
+
+        resources.observe_before_reset(device(3));
+        resources.observe_after_reset(device(3), D3dResult::Ok);
+
+        assert_eq!(*seen.lock().unwrap(), vec![3, 3]);
+    }
+
+    #[test]
+    fn callbacks_may_register_and_unregister_resources() {
+        let resources = ResourceRegistry::new();
+        let registry = resources.clone();
+        let id = Arc::new(Mutex::new(None));
+        let own_id = id.clone();
+        *id.lock().unwrap() = Some(resources.register(
+            device(1),
+            move |device| {
+                registry.unregister(own_id.lock().unwrap().unwrap());
+                registry.register(device, |_| (), |_| ());
+            },
+            |_| (),
+        ));
+
+        resources.observe_before_reset(device(1));
+
+        assert_eq!(resources.len(), 1);
+        assert!(!resources.unregister(id.lock().unwrap().unwrap()));
+    }
+
+    #[test]
+    fn unregistered_resources_are_left_alone() {
+        let (resources, log) = logged();
+        let id = resources.register(device(1), |_| panic!("released"), |_| ());
+        assert_eq!(resources.len(), 2);
+
+        assert!(resources.unregister(id));
+        resources.observe_before_reset(device(1));
+
+        assert_eq!(resources.len(), 1);
+        assert_eq!(*log.lock().unwrap(), vec!["lost"]);
+    }
+
+    #[test]
+    fn a_panicking_resource_does_not_skip_the_others() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let resources = ResourceRegistry::new();
+        let reset = log.clone();
+        resources.register(
+            device(1),
+            |_| panic!("release failed"),
+            move |_| reset.lock().unwrap().push("first reset"),
+        );
+        let (lost, reset) = (log.clone(), log.clone());
+        resources.register(
+            device(1),
+            move |_| lost.lock().unwrap().push("second lost"),
+            move |_| reset.lock().unwrap().push("second reset"),
+        );
+
+        resources.observe_before_reset(device(1));
+        assert!(resources.is_released(device(1)));
+        resources.observe_after_reset(device(1), D3dResult::Ok);
+
+        assert!(!resources.is_released(device(1)));
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["second lost", "first reset", "second reset"]
+        );
+    }
+
+    #[test]
+    fn unknown_devices_are_operational() {
+        let resources = ResourceRegistry::new();
+        assert_eq!(resources.state(ptr::null_mut()), DeviceState::Operational);
+        assert!(!resources.is_released(ptr::null_mut()));
+        assert!(resources.is_empty());
+    }
+}
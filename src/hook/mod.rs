@@ -0,0 +1,58 @@
+//! Redirecting Direct3D methods to Rust functions.
+//!
+//! [`D3D9Hooks`] routes device methods such as `EndScene` and `Present` into Rust closures,
+//! and keeps the resources in a [`ResourceRegistry`] alive across device resets.
+//! [`capture_live_device_in`] hooks them just long enough to find the game's own device, and a
+//! [`DeviceRegistry`] tells the devices apart when the process renders with several.
+//! Underneath, [`VmtHook`] swaps method pointers in a vtable, such as the one shared by every
+//! `IDirect3DDevice9` of the process. [`Detour`] patches the start of the method itself, which
+//! also catches callers that cached the method pointer. Memory protection and allocation go
+//! through a [`Backend`](crate::backend::Backend), so both can be installed on vtables and
+//! byte buffers in ordinary heap memory under [`FakeBackend`](crate::backend::FakeBackend).
+
+mod capture;
+mod d3d9;
+pub mod detour;
+mod devices;
+mod guard;
+mod lifecycle;
+mod vmt;
+pub mod x86;
+
+#[cfg(windows)]
+pub use self::capture::capture_live_device;
+pub use self::capture::capture_live_device_in;
+pub use self::d3d9::{D3D9Hooks, InstalledHooks};
+pub use self::detour::Detour;
+pub use self::devices::{DeviceFilter, DeviceInfo, DeviceRegistry};
+pub use self::guard::{PanicPolicy, PanicReport};
+pub use self::lifecycle::{DeviceState, ResourceId, ResourceRegistry};
+pub use self::vmt::{Original, VmtHook};
+
+use std::mem;
+
+use crate::backend::Backend;
+use crate::HookError;
+
+pub(crate) use self::detour::free_every_trampoline;
+
+/// Hooks take their replacement as a generic `F`, which can only be a function pointer if it
+/// is pointer-sized.
+fn assert_function_pointer<F>() {
+    assert_eq!(
+        mem::size_of::<F>(),
+        mem::size_of::<usize>(),
+        "a hook replacement must be a function pointer"
+    );
+}
+
+/// Put back every vtable slot and function the crate hooked, then stop running the callbacks
+/// of [`D3D9Hooks`]. Trampolines stay allocated, as threads may still be running them.
+///
+/// Everything that can be restored is, and the first failure is returned.
+pub(crate) unsafe fn remove_every_hook<B: Backend>(backend: &B) -> Result<(), HookError> {
+    let slots = vmt::unhook_every_slot(backend);
+    let detours = detour::unpatch_every_detour(backend);
+    d3d9::uninstall_callbacks();
+    slots.and(detours)
+}
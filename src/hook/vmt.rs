@@ -0,0 +1,284 @@
+use std::collections::BTreeMap;
+use std::ffi::c_void;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::backend::Backend;
+#[cfg(windows)]
+use crate::backend::WinApiBackend;
+use crate::com;
+use crate::sys::*;
+use crate::vtable::DeviceVTable;
+use crate::HookError;
+
+/// Addresses of every vtable slot hooked by any [`VmtHook`], with the method each held
+/// before, so two hooks never fight over the same slot and [`shutdown`](crate::shutdown_in)
+/// can put them all back.
+static HOOKED_SLOTS: Mutex<BTreeMap<usize, HookedAddress>> = Mutex::new(BTreeMap::new());
+
+/// Source of the [`VmtHook::owner`] tokens.
+static NEXT_OWNER: AtomicUsize = AtomicUsize::new(1);
+
+struct HookedAddress {
+    original: usize,
+    /// The hook that wrote the slot. Once a shutdown has put a slot back, another hook may
+    /// take it, and the first one must then leave it alone.
+    owner: usize,
+}
+
+/// The method a slot held before it was hooked, typed like the replacement.
+#[derive(Debug, Clone, Copy)]
+pub struct Original<F> {
+    function: F,
+}
+
+impl<F: Copy> Original<F> {
+    /// `F` must be pointer-sized; the callers check this before hooking anything.
+    pub(super) unsafe fn from_address(address: usize) -> Self {
+        Original {
+            function: mem::transmute_copy(&address),
+        }
+    }
+
+    /// The original method, for the replacement to call through.
+    pub fn get(&self) -> F {
+        self.function
+    }
+}
+
+struct HookedSlot {
+    slot: usize,
+    original: usize,
+}
+
+/// Replaces methods in a COM vtable and puts them back when dropped.
+///
+/// Every object sharing the vtable is affected: hooking the vtable of a dummy device hooks
+/// `EndScene` of the game's device as well.
+///
+/// ```ignore
+/// type EndSceneFn = unsafe extern "system" fn(*mut IDirect3DDevice9) -> HRESULT;
+///
+/// static mut END_SCENE: Option<Original<EndSceneFn>> = None;
+///
+/// unsafe extern "system" fn end_scene(device: *mut IDirect3DDevice9) -> HRESULT {
+///     // draw something
+///     END_SCENE.unwrap().get()(device)
+/// }
+///
+/// let dummy = DeviceGrabber::new().device()?;
+/// let mut hook = VmtHook::for_device(dummy.as_ptr());
+/// END_SCENE = Some(hook.hook(DeviceMethod::EndScene.index(), end_scene as EndSceneFn)?);
+/// ```
+pub struct VmtHook<B: Backend> {
+    backend: B,
+    vtable: *mut *const c_void,
+    len: usize,
+    owner: usize,
+    hooked: Vec<HookedSlot>,
+}
+
+// The vtable is shared process-wide and every slot is written atomically.
+unsafe impl<B: Backend + Send> Send for VmtHook<B> {}
+
+#[cfg(windows)]
+impl VmtHook<WinApiBackend> {
+    /// Prepare to hook the `len` slots of the vtable at `vtable`.
+    ///
+    /// # Safety
+    ///
+    /// See [`VmtHook::new_in`].
+    pub unsafe fn new(vtable: *mut *const c_void, len: usize) -> Self {
+        Self::new_in(WinApiBackend, vtable, len)
+    }
+
+    /// Prepare to hook the vtable of `device`, shared by every `IDirect3DDevice9` in the process.
+    ///
+    /// # Safety
+    ///
+    /// See [`VmtHook::for_device_in`].
+    pub unsafe fn for_device(device: *mut IDirect3DDevice9) -> Self {
+        Self::for_device_in(WinApiBackend, device)
+    }
+}
+
+impl<B: Backend> VmtHook<B> {
+    /// Prepare to hook the `len` slots of the vtable at `vtable`, changing memory protection
+    /// through `backend`.
+    ///
+    /// # Safety
+    ///
+    /// `vtable` must point to `len` method pointers that stay mapped for the life of the hook.
+    pub unsafe fn new_in(backend: B, vtable: *mut *const c_void, len: usize) -> Self {
+        VmtHook {
+            backend,
+            vtable,
+            len,
+            owner: NEXT_OWNER.fetch_add(1, Ordering::Relaxed),
+            hooked: Vec::new(),
+        }
+    }
+
+    /// Prepare to hook the vtable of `device` using `backend`.
+    ///
+    /// # Safety
+    ///
+    /// `device` must be a live `IDirect3DDevice9`.
+    pub unsafe fn for_device_in(backend: B, device: *mut IDirect3DDevice9) -> Self {
+        Self::new_in(
+            backend,
+            com::vtable(device) as *mut *const c_void,
+            DeviceVTable::LEN,
+        )
+    }
+
+    /// Point `slot` at `replacement`, returning the method it held before.
+    ///
+    /// `F` must be the `unsafe extern "system" fn` type of the method, with `this` as its
+    /// first parameter. Fails if the slot is already hooked, by this or any other `VmtHook`.
+    ///
+    /// # Panics
+    ///
+    /// If `F` is not pointer-sized, which means it cannot be a function pointer.
+    ///
+    /// # Safety
+    ///
+    /// `replacement` must have exactly the signature of the method in `slot`.
+    pub unsafe fn hook<F: Copy>(
+        &mut self,
+        slot: usize,
+        replacement: F,
+    ) -> Result<Original<F>, HookError> {
+        super::assert_function_pointer::<F>();
+        if slot >= self.len {
+            return Err(HookError::SlotOutOfRange {
+                slot,
+                len: self.len,
+            });
+        }
+
+        let address = self.vtable.add(slot) as usize;
+        let mut hooked_slots = HOOKED_SLOTS.lock().unwrap_or_else(|err| err.into_inner());
+        if hooked_slots.contains_key(&address) {
+            return Err(HookError::AlreadyHooked { slot });
+        }
+
+        let original = write_slot(
+            &self.backend,
+            address as *mut c_void,
+            mem::transmute_copy(&replacement),
+        )?;
+        hooked_slots.insert(
+            address,
+            HookedAddress {
+                original,
+                owner: self.owner,
+            },
+        );
+        self.hooked.push(HookedSlot { slot, original });
+
+        Ok(Original::from_address(original))
+    }
+
+    /// Put back the method `slot` held before it was hooked.
+    ///
+    /// After a [`shutdown`](crate::shutdown_in) the slot is already restored and is left alone,
+    /// even if another hook has replaced it since.
+    pub fn unhook(&mut self, slot: usize) -> Result<(), HookError> {
+        let index = match self.hooked.iter().position(|hooked| hooked.slot == slot) {
+            Some(index) => index,
+            None => return Err(HookError::NotHooked { slot }),
+        };
+
+        let address = unsafe { self.vtable.add(slot) } as usize;
+        let mut hooked_slots = HOOKED_SLOTS.lock().unwrap_or_else(|err| err.into_inner());
+        let owned =
+            matches!(hooked_slots.get(&address), Some(hooked) if hooked.owner == self.owner);
+        if owned {
+            unsafe {
+                write_slot(
+                    &self.backend,
+                    address as *mut c_void,
+                    self.hooked[index].original,
+                )?;
+            }
+            hooked_slots.remove(&address);
+        }
+        self.hooked.remove(index);
+        Ok(())
+    }
+
+    /// Put back every hooked slot, most recently hooked first.
+    pub fn unhook_all(&mut self) -> Result<(), HookError> {
+        while let Some(hooked) = self.hooked.last() {
+            self.unhook(hooked.slot)?;
+        }
+        Ok(())
+    }
+
+    /// Whether this hook has replaced `slot`.
+    pub fn is_hooked(&self, slot: usize) -> bool {
+        self.hooked.iter().any(|hooked| hooked.slot == slot)
+    }
+
+    /// The vtable being hooked.
+    pub fn vtable(&self) -> *mut *const c_void {
+        self.vtable
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+}
+
+/// Put back every slot hooked by any [`VmtHook`], changing protection through `backend`.
+///
+/// Slots that cannot be written stay hooked, and the first such failure is returned.
+pub(crate) unsafe fn unhook_every_slot<B: Backend>(backend: &B) -> Result<(), HookError> {
+    let mut hooked_slots = HOOKED_SLOTS.lock().unwrap_or_else(|err| err.into_inner());
+    let mut result = Ok(());
+    hooked_slots.retain(|&address, hooked| {
+        match write_slot(backend, address as *mut c_void, hooked.original) {
+            Ok(_) => false,
+            Err(err) => {
+                if result.is_ok() {
+                    result = Err(err);
+                }
+                true
+            }
+        }
+    });
+    result
+}
+
+/// Store `value` in the vtable slot at `address`, returning the value it replaced.
+unsafe fn write_slot<B: Backend>(
+    backend: &B,
+    address: *mut c_void,
+    value: usize,
+) -> Result<usize, HookError> {
+    let len = mem::size_of::<usize>();
+    let protection = match backend.make_writable(address, len) {
+        Some(protection) => protection,
+        None => {
+            return Err(HookError::ProtectFailed {
+                address: address as usize,
+                len,
+            })
+        }
+    };
+
+    // Other threads may be calling through the slot, so it must never be seen half-written.
+    let previous = (*(address as *const AtomicUsize)).swap(value, Ordering::SeqCst);
+    backend.restore_protection(address, len, protection);
+    Ok(previous)
+}
+
+impl<B: Backend> Drop for VmtHook<B> {
+    fn drop(&mut self) {
+        // A slot that cannot be unprotected now could not be hooked either, so this only
+        // fails if something else changed the protection in between.
+        let _ = self.unhook_all();
+    }
+}
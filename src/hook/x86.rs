@@ -0,0 +1,885 @@
+//! Instruction length decoding and relocation for x86 and x86_64.
+//!
+//! This is not a full disassembler: [`decode`] only works out how long an instruction is and
+//! where its address-dependent operand sits, which is all a trampoline needs. [`relocate`]
+//! then rewrites that operand so the instruction does the same thing at another address,
+//! widening `rel8` branches that no longer reach.
+
+use std::convert::TryFrom;
+
+use super::detour::encode_jump;
+
+/// No x86 instruction is longer than this.
+pub const MAX_INSTRUCTION_LEN: usize = 15;
+
+/// Segment overrides, operand and address size, `lock`, `repne`/`bnd` and `rep`.
+const LEGACY_PREFIXES: [u8; 11] = [
+    0x26, 0x2E, 0x36, 0x3E, 0x64, 0x65, 0x66, 0x67, 0xF0, 0xF2, 0xF3,
+];
+
+/// The instruction set being decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+    X86,
+    X64,
+}
+
+impl Arch {
+    /// The architecture this crate was compiled for.
+    #[cfg(target_arch = "x86")]
+    pub const HOST: Arch = Arch::X86;
+    /// The architecture this crate was compiled for.
+    #[cfg(not(target_arch = "x86"))]
+    pub const HOST: Arch = Arch::X64;
+}
+
+/// One decoded instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    /// Length in bytes, prefixes included.
+    pub len: usize,
+    /// The operand that depends on where the instruction is, if any.
+    pub operand: RelativeOperand,
+    /// Execution never continues with the next instruction: `ret`, `jmp` and the like.
+    pub terminates: bool,
+}
+
+/// An operand encoded relative to the end of its instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelativeOperand {
+    None,
+    /// A branch whose signed displacement of `size` bytes starts at `offset`.
+    Branch {
+        kind: Branch,
+        offset: usize,
+        size: usize,
+    },
+    /// A RIP-relative memory operand whose 32-bit displacement starts at `offset`.
+    RipRelative {
+        offset: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Branch {
+    /// `jmp`.
+    Jump,
+    /// `call`.
+    Call,
+    /// `jcc`, with the condition code from the low nibble of the opcode.
+    Conditional(u8),
+    /// `loopne`, `loope`, `loop` or `jecxz`, with their one-byte opcode. These only exist
+    /// with a `rel8` displacement.
+    Loop(u8),
+}
+
+impl Instruction {
+    /// Where the branch or memory operand of `code`, located at `address`, points to.
+    pub fn target(&self, code: &[u8], address: usize) -> Option<usize> {
+        let (offset, size) = match self.operand {
+            RelativeOperand::None => return None,
+            RelativeOperand::Branch { offset, size, .. } => (offset, size),
+            RelativeOperand::RipRelative { offset } => (offset, 4),
+        };
+        let displacement = read_signed(&code[offset..offset + size]);
+        Some((address + self.len).wrapping_add(displacement as usize))
+    }
+
+    /// The destination of a branch located at `address`. `None` for anything but a branch.
+    pub fn branch_target(&self, code: &[u8], address: usize) -> Option<usize> {
+        match self.operand {
+            RelativeOperand::Branch { .. } => self.target(code, address),
+            _ => None,
+        }
+    }
+}
+
+fn read_signed(bytes: &[u8]) -> i64 {
+    match *bytes {
+        [byte] => byte as i8 as i64,
+        [low, high] => i16::from_le_bytes([low, high]) as i64,
+        [a, b, c, d] => i32::from_le_bytes([a, b, c, d]) as i64,
+        _ => unreachable!(),
+    }
+}
+
+/// The displacement of a 32-bit relative operand ending at `from_end` to `to`, if it reaches.
+/// On x86 every address does.
+pub(super) fn rel32(arch: Arch, from_end: usize, to: usize) -> Option<i32> {
+    match arch {
+        Arch::X86 => Some((to as u32).wrapping_sub(from_end as u32) as i32),
+        Arch::X64 => i32::try_from(to as i128 - from_end as i128).ok(),
+    }
+}
+
+/// Decode the instruction at the start of `code`.
+///
+/// Returns `None` if `code` ends before the instruction does, or if it is not a valid
+/// instruction for `arch`.
+pub fn decode(arch: Arch, code: &[u8]) -> Option<Instruction> {
+    let x64 = arch == Arch::X64;
+    let mut at = 0;
+    let mut operand_size_16 = false;
+    let mut address_size_override = false;
+    loop {
+        match *code.get(at)? {
+            0x66 => operand_size_16 = true,
+            0x67 => address_size_override = true,
+            byte if LEGACY_PREFIXES.contains(&byte) => {}
+            _ => break,
+        }
+        at += 1;
+        if at >= MAX_INSTRUCTION_LEN {
+            return None;
+        }
+    }
+    let mut rex_w = false;
+    if x64 && code.get(at)? & 0xF0 == 0x40 {
+        rex_w = code[at] & 0x08 != 0;
+        at += 1;
+    }
+
+    let opcode = *code.get(at)?;
+    at += 1;
+    let immediate_z = if operand_size_16 { 2 } else { 4 };
+    let modrm = ModRmDecoder {
+        x64,
+        address_16: !x64 && address_size_override,
+    };
+
+    let mut form = Form::default();
+    match opcode {
+        0x0F => return decode_0f(arch, code, at, operand_size_16, modrm),
+        // VEX and EVEX, which on x86 are only told apart from les, lds and bound by their
+        // second byte looking like a register operand.
+        0xC4 | 0xC5 | 0x62 if x64 || *code.get(at)? >= 0xC0 => {
+            return decode_vex(code, at, opcode, modrm)
+        }
+        _ if x64 && invalid_in_64_bit_mode(opcode) => return None,
+
+        0x00..=0x3F => match opcode & 0x07 {
+            0..=3 => form.modrm = true,
+            4 => form.immediate = 1,
+            5 => form.immediate = immediate_z,
+            // push/pop segment, daa and friends; the segment prefixes were consumed above
+            _ => {}
+        },
+        0x40..=0x4F if x64 => return None,
+        0x40..=0x61 => {}
+        0x62 | 0x63 => form.modrm = true,
+        0x68 => form.immediate = immediate_z,
+        0x69 => {
+            form.modrm = true;
+            form.immediate = immediate_z;
+        }
+        0x6A => form.immediate = 1,
+        0x6B => {
+            form.modrm = true;
+            form.immediate = 1;
+        }
+        0x6C..=0x6F => {}
+        0x70..=0x7F => form.branch = Some((Branch::Conditional(opcode & 0x0F), 1)),
+        0x80 | 0x82 | 0x83 => {
+            form.modrm = true;
+            form.immediate = 1;
+        }
+        0x81 => {
+            form.modrm = true;
+            form.immediate = immediate_z;
+        }
+        0x84..=0x8F => form.modrm = true,
+        0x90..=0x99 | 0x9B..=0x9F => {}
+        0x9A | 0xEA => {
+            form.immediate = immediate_z + 2;
+            form.terminates = opcode == 0xEA;
+        }
+        0xA0..=0xA3 => {
+            form.immediate = match (x64, address_size_override) {
+                (true, false) => 8,
+                (true, true) | (false, false) => 4,
+                (false, true) => 2,
+            }
+        }
+        0xA4..=0xA7 | 0xAA..=0xAF => {}
+        0xA8 => form.immediate = 1,
+        0xA9 => form.immediate = immediate_z,
+        0xB0..=0xB7 => form.immediate = 1,
+        0xB8..=0xBF => form.immediate = if rex_w { 8 } else { immediate_z },
+        0xC0 | 0xC1 | 0xC6 => {
+            form.modrm = true;
+            form.immediate = 1;
+        }
+        0xC2 | 0xCA => {
+            form.immediate = 2;
+            form.terminates = true;
+        }
+        0xC3 | 0xCB | 0xCF => form.terminates = true,
+        0xC4 | 0xC5 => form.modrm = true,
+        0xC7 => {
+            form.modrm = true;
+            form.immediate = immediate_z;
+        }
+        0xC8 => form.immediate = 3,
+        0xC9 | 0xCC | 0xCE => {}
+        0xCD | 0xD4 | 0xD5 => form.immediate = 1,
+        0xD0..=0xD3 | 0xD8..=0xDF => form.modrm = true,
+        0xD6 | 0xD7 => {}
+        0xE0..=0xE3 => form.branch = Some((Branch::Loop(opcode), 1)),
+        0xE4..=0xE7 => form.immediate = 1,
+        0xE8 | 0xE9 => {
+            let size = if !x64 && operand_size_16 { 2 } else { 4 };
+            let kind = if opcode == 0xE8 {
+                Branch::Call
+            } else {
+                Branch::Jump
+            };
+            form.branch = Some((kind, size));
+            form.terminates = opcode == 0xE9;
+        }
+        0xEB => {
+            form.branch = Some((Branch::Jump, 1));
+            form.terminates = true;
+        }
+        0xEC..=0xEF | 0xF1 | 0xF4 | 0xF5 | 0xF8..=0xFD => {}
+        0xF6 | 0xF7 => {
+            // Only test, /0 and /1, takes an immediate.
+            form.modrm = true;
+            if (code.get(at)? >> 3) & 0x07 < 2 {
+                form.immediate = if opcode == 0xF6 { 1 } else { immediate_z };
+            }
+        }
+        0xFE => form.modrm = true,
+        0xFF => {
+            form.modrm = true;
+            // jmp near and jmp far through memory
+            form.terminates = matches!((code.get(at)? >> 3) & 0x07, 4 | 5);
+        }
+        // The prefixes, already consumed
+        0x64..=0x67 | 0xF0 | 0xF2 | 0xF3 => unreachable!(),
+    }
+
+    form.finish(code, at, modrm)
+}
+
+/// Opcodes that were removed from the one-byte map in 64-bit mode.
+fn invalid_in_64_bit_mode(opcode: u8) -> bool {
+    matches!(
+        opcode,
+        0x06 | 0x07
+            | 0x0E
+            | 0x16
+            | 0x17
+            | 0x1E
+            | 0x1F
+            | 0x27
+            | 0x2F
+            | 0x37
+            | 0x3F
+            | 0x60
+            | 0x61
+            | 0x82
+            | 0x9A
+            | 0xCE
+            | 0xD4
+            | 0xD5
+            | 0xD6
+            | 0xEA
+    )
+}
+
+/// Instructions following the `0F` escape byte, including the `0F 38` and `0F 3A` maps.
+fn decode_0f(
+    arch: Arch,
+    code: &[u8],
+    mut at: usize,
+    operand_size_16: bool,
+    modrm: ModRmDecoder,
+) -> Option<Instruction> {
+    let opcode = *code.get(at)?;
+    at += 1;
+
+    let mut form = Form::default();
+    match opcode {
+        0x38 => {
+            at += 1;
+            form.modrm = true;
+        }
+        0x3A => {
+            at += 1;
+            form.modrm = true;
+            form.immediate = 1;
+        }
+        // 3DNow!, whose real opcode is a trailing byte
+        0x0F => {
+            form.modrm = true;
+            form.immediate = 1;
+        }
+        0x80..=0x8F => {
+            let size = if arch == Arch::X86 && operand_size_16 {
+                2
+            } else {
+                4
+            };
+            form.branch = Some((Branch::Conditional(opcode & 0x0F), size));
+        }
+        0x0B => form.terminates = true,
+        0x05..=0x09 | 0x0E | 0x30..=0x37 | 0x77 | 0xA0..=0xA2 | 0xA8..=0xAA | 0xC8..=0xCF => {}
+        0x70..=0x73 | 0xA4 | 0xAC | 0xBA | 0xC2 | 0xC4..=0xC6 => {
+            form.modrm = true;
+            form.immediate = 1;
+        }
+        _ => form.modrm = true,
+    }
+
+    form.finish(code, at, modrm)
+}
+
+/// Instructions behind a `C5`, `C4` or `62` VEX or EVEX prefix, starting with the prefix.
+fn decode_vex(
+    code: &[u8],
+    prefix_at: usize,
+    prefix: u8,
+    modrm: ModRmDecoder,
+) -> Option<Instruction> {
+    let (map, payload_len) = match prefix {
+        0xC5 => (1, 1),
+        0xC4 => (*code.get(prefix_at)? & 0x1F, 2),
+        _ => (*code.get(prefix_at)? & 0x07, 3),
+    };
+    let opcode_at = prefix_at + payload_len;
+    let opcode = *code.get(opcode_at)?;
+
+    let form = Form {
+        // vzeroupper and vzeroall are the only ones without operands
+        modrm: (map, opcode) != (1, 0x77),
+        immediate: match (map, opcode) {
+            (3, _) | (1, 0x70..=0x73) | (1, 0xC2) | (1, 0xC4..=0xC6) => 1,
+            _ => 0,
+        },
+        ..Form::default()
+    };
+    form.finish(code, opcode_at + 1, modrm)
+}
+
+/// What follows an opcode.
+#[derive(Default)]
+struct Form {
+    modrm: bool,
+    immediate: usize,
+    branch: Option<(Branch, usize)>,
+    terminates: bool,
+}
+
+impl Form {
+    /// Put the instruction together, with its operands starting at `at`.
+    fn finish(self, code: &[u8], at: usize, modrm: ModRmDecoder) -> Option<Instruction> {
+        let mut len = at;
+        let mut operand = RelativeOperand::None;
+        if self.modrm {
+            let (modrm_len, rip_relative) = modrm.decode(code.get(at..)?)?;
+            if rip_relative {
+                operand = RelativeOperand::RipRelative { offset: at + 1 };
+            }
+            len += modrm_len;
+        }
+        len += self.immediate;
+        if let Some((kind, size)) = self.branch {
+            operand = RelativeOperand::Branch {
+                kind,
+                offset: len,
+                size,
+            };
+            len += size;
+        }
+
+        if len > code.len() || len > MAX_INSTRUCTION_LEN {
+            return None;
+        }
+        Some(Instruction {
+            len,
+            operand,
+            terminates: self.terminates,
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ModRmDecoder {
+    x64: bool,
+    /// 16-bit addressing, from an address-size prefix in 32-bit code.
+    address_16: bool,
+}
+
+impl ModRmDecoder {
+    /// Length of the ModRM byte at the start of `code` with its SIB byte and displacement,
+    /// and whether it is RIP-relative.
+    fn decode(self, code: &[u8]) -> Option<(usize, bool)> {
+        let modrm = *code.first()?;
+        let (mode, rm) = (modrm >> 6, modrm & 0x07);
+        if mode == 3 {
+            return Some((1, false));
+        }
+
+        if self.address_16 {
+            let displacement = match mode {
+                0 if rm == 6 => 2,
+                0 => 0,
+                1 => 1,
+                _ => 2,
+            };
+            return Some((1 + displacement, false));
+        }
+
+        let mut len = 1;
+        let mut base = rm;
+        if rm == 4 {
+            base = *code.get(1)? & 0x07;
+            len += 1;
+        }
+        let rip_relative = self.x64 && mode == 0 && rm == 5;
+        len += match mode {
+            0 if rm == 5 || base == 5 => 4,
+            0 => 0,
+            1 => 1,
+            _ => 4,
+        };
+        Some((len, rip_relative))
+    }
+}
+
+/// Rewrite the instruction `code`, decoded as `instruction`, so it behaves at `to` as it did
+/// at `from`.
+///
+/// Branches that no longer reach are widened: a `rel8` to `rel32`, and on x86_64 anything out
+/// of 32-bit range to an absolute jump. A widened branch keeps its prefixes, such as `bnd` or
+/// the address-size prefix that makes `jecxz` test `cx`. Returns `None` for what cannot be
+/// rewritten: a RIP-relative operand more than 2GB from its target, 16-bit branches, and
+/// widened branches whose prefixes would change what they do, see [`encode_branch`].
+pub fn relocate(
+    arch: Arch,
+    code: &[u8],
+    instruction: &Instruction,
+    from: usize,
+    to: usize,
+) -> Option<Vec<u8>> {
+    let mut relocated = code[..instruction.len].to_vec();
+    match instruction.operand {
+        RelativeOperand::None => Some(relocated),
+        RelativeOperand::RipRelative { offset } => {
+            let target = instruction.target(code, from)?;
+            let displacement = rel32(arch, to + instruction.len, target)?;
+            relocated[offset..offset + 4].copy_from_slice(&displacement.to_le_bytes());
+            Some(relocated)
+        }
+        RelativeOperand::Branch { kind, offset, size } => {
+            let target = instruction.target(code, from)?;
+            match size {
+                4 => match rel32(arch, to + instruction.len, target) {
+                    Some(displacement) => {
+                        relocated[offset..offset + 4].copy_from_slice(&displacement.to_le_bytes());
+                        Some(relocated)
+                    }
+                    None => encode_branch(arch, kind, prefixes(arch, code), to, target),
+                },
+                1 => encode_branch(arch, kind, prefixes(arch, code), to, target),
+                _ => None,
+            }
+        }
+    }
+}
+
+/// The legacy prefixes, and on x86_64 the REX prefix, that `code` starts with.
+fn prefixes(arch: Arch, code: &[u8]) -> &[u8] {
+    let mut len = code
+        .iter()
+        .take_while(|byte| LEGACY_PREFIXES.contains(byte))
+        .count();
+    if arch == Arch::X64 && code.get(len).is_some_and(|byte| byte & 0xF0 == 0x40) {
+        len += 1;
+    }
+    &code[..len]
+}
+
+/// The shortest branch of `kind` at `at` to `target`, without any `rel8` form, with
+/// `prefixes` in front of the instruction that branches.
+///
+/// Returns `None` if the prefixes include an operand-size override, which would make the
+/// rewritten branch a 16-bit one, or if a prefixed conditional branch has to be split into
+/// two instructions.
+fn encode_branch(
+    arch: Arch,
+    kind: Branch,
+    prefixes: &[u8],
+    at: usize,
+    target: usize,
+) -> Option<Vec<u8>> {
+    if prefixes.contains(&0x66) {
+        return None;
+    }
+    let mut branch = prefixes.to_vec();
+    let at = at + prefixes.len();
+    match kind {
+        Branch::Jump => branch.extend(encode_jump(arch, at, target)),
+        Branch::Call => match rel32(arch, at + 5, target) {
+            Some(displacement) => {
+                branch.push(0xE8);
+                branch.extend_from_slice(&displacement.to_le_bytes());
+            }
+            None => {
+                // call [rip+2]; jmp +8; the 64-bit target
+                branch.extend_from_slice(&[0xFF, 0x15, 0x02, 0, 0, 0, 0xEB, 0x08]);
+                branch.extend_from_slice(&(target as u64).to_le_bytes());
+            }
+        },
+        Branch::Conditional(condition) => match rel32(arch, at + 6, target) {
+            Some(displacement) => {
+                branch.extend_from_slice(&[0x0F, 0x80 | condition]);
+                branch.extend_from_slice(&displacement.to_le_bytes());
+            }
+            None if !prefixes.is_empty() => return None,
+            None => {
+                // The opposite condition skips over an absolute jump.
+                let jump = encode_jump(arch, at + 2, target);
+                branch.extend_from_slice(&[0x70 | (condition ^ 1), jump.len() as u8]);
+                branch.extend(jump);
+            }
+        },
+        Branch::Loop(opcode) => {
+            // loop +2 lands on the jump to the target; falling through skips over it.
+            let jump = encode_jump(arch, at + 4, target);
+            branch.extend_from_slice(&[opcode, 0x02, 0xEB, jump.len() as u8]);
+            branch.extend(jump);
+        }
+    }
+    Some(branch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A function prologue and the lengths of the instructions it starts with.
+    struct Prologue {
+        shape: &'static str,
+        arch: Arch,
+        code: &'static [u8],
+        lengths: &'static [usize],
+    }
+
+    /// Hand-assembled prologues in the shapes compilers give COM methods on both
+    /// architectures: hot-patchable frames, SEH and security cookie setup, register saves,
+    /// VEX code and RIP-relative operands.
+    const PROLOGUES: &[Prologue] = &[
+        Prologue {
+            // mov edi, edi; push ebp; mov ebp, esp; push -1; push imm32; mov eax, fs:[0]
+            shape: "x86, hot-patchable with an SEH frame",
+            arch: Arch::X86,
+            code: &[
+                0x8B, 0xFF, 0x55, 0x8B, 0xEC, 0x6A, 0xFF, 0x68, 0x10, 0x32, 0x54, 0x76, 0x64, 0xA1,
+                0x00, 0x00, 0x00, 0x00,
+            ],
+            lengths: &[2, 1, 2, 2, 5, 6],
+        },
+        Prologue {
+            // mov edi, edi; push ebp; mov ebp, esp; sub esp, 0x10; push ebx; push esi; push edi
+            shape: "x86, hot-patchable",
+            arch: Arch::X86,
+            code: &[
+                0x8B, 0xFF, 0x55, 0x8B, 0xEC, 0x83, 0xEC, 0x10, 0x53, 0x56, 0x57,
+            ],
+            lengths: &[2, 1, 2, 3, 1, 1, 1],
+        },
+        Prologue {
+            // push ebp; push edi; push esi; push ebx; sub esp, 0x2C; mov eax, [esp+0x40]
+            shape: "x86, saving every callee-saved register",
+            arch: Arch::X86,
+            code: &[
+                0x55, 0x57, 0x56, 0x53, 0x83, 0xEC, 0x2C, 0x8B, 0x44, 0x24, 0x40,
+            ],
+            lengths: &[1, 1, 1, 1, 3, 4],
+        },
+        Prologue {
+            // mov rax, rsp; mov [rax+8], rbx; mov [rax+0x10], rbp; mov [rax+0x18], rsi;
+            // push rdi; push r12
+            shape: "x64, saving registers through rax",
+            arch: Arch::X64,
+            code: &[
+                0x48, 0x8B, 0xC4, 0x48, 0x89, 0x58, 0x08, 0x48, 0x89, 0x68, 0x10, 0x48, 0x89, 0x70,
+                0x18, 0x57, 0x41, 0x54,
+            ],
+            lengths: &[3, 4, 4, 4, 1, 2],
+        },
+        Prologue {
+            // mov [rsp+8], rbx; push rdi; sub rsp, 0x20; mov rax, [rip+disp32]; xor rax, rsp
+            shape: "x64, loading the security cookie",
+            arch: Arch::X64,
+            code: &[
+                0x48, 0x89, 0x5C, 0x24, 0x08, 0x57, 0x48, 0x83, 0xEC, 0x20, 0x48, 0x8B, 0x05, 0x00,
+                0x10, 0x00, 0x00, 0x48, 0x33, 0xC4,
+            ],
+            lengths: &[5, 1, 4, 7, 3],
+        },
+        Prologue {
+            // push rbx with a REX prefix; sub rsp, 0x20; mov rbx, rcx; call rel32
+            shape: "x64, REX-prefixed push and a call",
+            arch: Arch::X64,
+            code: &[
+                0x40, 0x53, 0x48, 0x83, 0xEC, 0x20, 0x48, 0x8B, 0xD9, 0xE8, 0x01, 0x02, 0x03, 0x04,
+            ],
+            lengths: &[2, 4, 3, 5],
+        },
+        Prologue {
+            // sub rsp, 0x28; cmp byte [rip+disp32], 0; je +10
+            shape: "x64, checking a global flag",
+            arch: Arch::X64,
+            code: &[
+                0x48, 0x83, 0xEC, 0x28, 0x80, 0x3D, 0xF0, 0xFF, 0x00, 0x00, 0x00, 0x74, 0x0A,
+            ],
+            lengths: &[4, 7, 2],
+        },
+        Prologue {
+            // push r15; push r14; push rbp; push rdi; push rsi; push rbx; sub rsp, 0x88
+            shape: "x64, saving every callee-saved register",
+            arch: Arch::X64,
+            code: &[
+                0x41, 0x57, 0x41, 0x56, 0x55, 0x57, 0x56, 0x53, 0x48, 0x81, 0xEC, 0x88, 0x00, 0x00,
+                0x00,
+            ],
+            lengths: &[2, 2, 1, 1, 1, 1, 7],
+        },
+        Prologue {
+            // vzeroupper; vpermilps xmm0, xmm0, 0; nop dword [rax+rax]; nop word [rax+rax]
+            shape: "x64, VEX and long nops",
+            arch: Arch::X64,
+            code: &[
+                0xC5, 0xF8, 0x77, 0xC4, 0xE3, 0x79, 0x04, 0xC0, 0x00, 0x0F, 0x1F, 0x44, 0x00, 0x00,
+                0x66, 0x0F, 0x1F, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00,
+            ],
+            lengths: &[3, 6, 5, 9],
+        },
+        Prologue {
+            // mov rax, imm64; test ecx, imm32; not ecx
+            shape: "x64, 64-bit immediates",
+            arch: Arch::X64,
+            code: &[
+                0x48, 0xB8, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0xF7, 0xC1, 0x01, 0x00,
+                0x00, 0x00, 0xF7, 0xD1,
+            ],
+            lengths: &[10, 6, 2],
+        },
+        Prologue {
+            // mov dword [rip+disp32], imm32; cmp qword [rip+disp32], 0
+            shape: "x64, RIP-relative stores",
+            arch: Arch::X64,
+            code: &[
+                0xC7, 0x05, 0x00, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x48, 0x83, 0x3D, 0x00,
+                0x02, 0x00, 0x00, 0x00,
+            ],
+            lengths: &[10, 8],
+        },
+    ];
+
+    fn lengths(arch: Arch, code: &[u8]) -> Vec<usize> {
+        let mut lengths = Vec::new();
+        let mut at = 0;
+        while at < code.len() {
+            match decode(arch, &code[at..]) {
+                Some(instruction) => lengths.push(instruction.len),
+                None => panic!("no instruction at offset {} of {:02X?}", at, code),
+            }
+            at += lengths.last().unwrap();
+        }
+        lengths
+    }
+
+    #[test]
+    fn prologues_decode_into_whole_instructions() {
+        for prologue in PROLOGUES {
+            assert_eq!(
+                lengths(prologue.arch, prologue.code),
+                prologue.lengths,
+                "{}",
+                prologue.shape
+            );
+        }
+    }
+
+    #[test]
+    fn rex_prefixes_are_only_prefixes_on_x64() {
+        // push r15 on x64, inc ecx; push edi on x86
+        assert_eq!(lengths(Arch::X64, &[0x41, 0x57]), vec![2]);
+        assert_eq!(lengths(Arch::X86, &[0x41, 0x57]), vec![1, 1]);
+    }
+
+    #[test]
+    fn truncated_and_invalid_instructions_are_not_decoded() {
+        assert_eq!(decode(Arch::X86, &[0x68, 0x01, 0x02]), None);
+        assert_eq!(decode(Arch::X64, &[0x48]), None);
+        // push es does not exist in 64-bit mode
+        assert_eq!(decode(Arch::X64, &[0x06]), None);
+        assert_eq!(decode(Arch::X86, &[0x06]).map(|ins| ins.len), Some(1));
+    }
+
+    #[test]
+    fn rip_relative_operands_are_found_before_immediates() {
+        // cmp byte [rip+0xFFF0], 0
+        let code = [0x80, 0x3D, 0xF0, 0xFF, 0x00, 0x00, 0x00];
+        let instruction = decode(Arch::X64, &code).unwrap();
+        assert_eq!(
+            instruction.operand,
+            RelativeOperand::RipRelative { offset: 2 }
+        );
+        // Relative to the end of the instruction, after the immediate.
+        assert_eq!(instruction.target(&code, 0x1000), Some(0x1000 + 7 + 0xFFF0));
+
+        // mov dword [rip+0x100], 1
+        let code = [0xC7, 0x05, 0x00, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00];
+        let instruction = decode(Arch::X64, &code).unwrap();
+        assert_eq!(
+            instruction.operand,
+            RelativeOperand::RipRelative { offset: 2 }
+        );
+        assert_eq!(instruction.target(&code, 0x1000), Some(0x1000 + 10 + 0x100));
+    }
+
+    #[test]
+    fn disp32_is_absolute_on_x86() {
+        // mov eax, [0x12345678]
+        let instruction = decode(Arch::X86, &[0x8B, 0x05, 0x78, 0x56, 0x34, 0x12]).unwrap();
+        assert_eq!(instruction.len, 6);
+        assert_eq!(instruction.operand, RelativeOperand::None);
+    }
+
+    #[test]
+    fn rip_relative_operands_keep_their_target_when_moved() {
+        // mov dword [rip+0x100], 1
+        let code = [0xC7, 0x05, 0x00, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00];
+        let instruction = decode(Arch::X64, &code).unwrap();
+
+        let relocated = relocate(Arch::X64, &code, &instruction, 0x10_0000, 0x20_0000).unwrap();
+
+        assert_eq!(relocated.len(), 10);
+        assert_eq!(&relocated[..2], &code[..2]);
+        // The immediate is left alone.
+        assert_eq!(&relocated[6..], &code[6..]);
+        assert_eq!(
+            instruction.target(&relocated, 0x20_0000),
+            instruction.target(&code, 0x10_0000)
+        );
+    }
+
+    #[test]
+    fn rip_relative_operands_out_of_reach_cannot_be_moved() {
+        let code = [0x48, 0x8B, 0x05, 0x00, 0x10, 0x00, 0x00];
+        let instruction = decode(Arch::X64, &code).unwrap();
+        assert_eq!(
+            relocate(Arch::X64, &code, &instruction, 0x10_0000, 0x7000_0000_0000),
+            None
+        );
+    }
+
+    #[test]
+    fn short_branches_are_widened() {
+        // je +0x10 at 0x1002
+        let code = [0x74, 0x10];
+        let instruction = decode(Arch::X86, &code).unwrap();
+        let relocated = relocate(Arch::X86, &code, &instruction, 0x1002, 0x9000).unwrap();
+        let mut expected = vec![0x0F, 0x84];
+        expected.extend(&(0x1014_i32 - 0x9006).to_le_bytes());
+        assert_eq!(relocated, expected);
+
+        // jmp -2 at 0x1000, back onto itself
+        let code = [0xEB, 0xFE];
+        let instruction = decode(Arch::X86, &code).unwrap();
+        assert!(instruction.terminates);
+        let relocated = relocate(Arch::X86, &code, &instruction, 0x1000, 0x9000).unwrap();
+        assert_eq!(relocated, encode_jump(Arch::X86, 0x9000, 0x1000));
+    }
+
+    #[test]
+    fn far_conditional_branches_skip_over_an_absolute_jump() {
+        // je +0x10 at 0x1000, moved 0x7000_0000_0000 away
+        let code = [0x74, 0x10];
+        let instruction = decode(Arch::X64, &code).unwrap();
+        let relocated = relocate(Arch::X64, &code, &instruction, 0x1000, 0x7000_0000_0000).unwrap();
+
+        assert_eq!(&relocated[..2], &[0x75, 14]);
+        assert_eq!(
+            &relocated[2..],
+            &encode_jump(Arch::X64, 0x7000_0000_0002, 0x1012)[..]
+        );
+    }
+
+    #[test]
+    fn far_calls_go_through_memory() {
+        // call +0x100 at 0x1000
+        let code = [0xE8, 0x00, 0x01, 0x00, 0x00];
+        let instruction = decode(Arch::X64, &code).unwrap();
+        let relocated = relocate(Arch::X64, &code, &instruction, 0x1000, 0x7000_0000_0000).unwrap();
+
+        let mut expected = vec![0xFF, 0x15, 0x02, 0x00, 0x00, 0x00, 0xEB, 0x08];
+        expected.extend(&0x1105_u64.to_le_bytes());
+        assert_eq!(relocated, expected);
+    }
+
+    #[test]
+    fn loops_branch_through_a_jump() {
+        // loop +0x10 at 0x1000
+        let code = [0xE2, 0x10];
+        let instruction = decode(Arch::X86, &code).unwrap();
+        let relocated = relocate(Arch::X86, &code, &instruction, 0x1000, 0x9000).unwrap();
+
+        let mut expected = vec![0xE2, 0x02, 0xEB, 0x05];
+        expected.extend(encode_jump(Arch::X86, 0x9004, 0x1012));
+        assert_eq!(relocated, expected);
+    }
+
+    #[test]
+    fn widened_branches_keep_their_prefixes() {
+        // bnd jmp +0x10 at 0x1000
+        let code = [0xF2, 0xEB, 0x10];
+        let instruction = decode(Arch::X86, &code).unwrap();
+        let relocated = relocate(Arch::X86, &code, &instruction, 0x1000, 0x9000).unwrap();
+        let mut expected = vec![0xF2, 0xE9];
+        expected.extend(&(0x1013_i32 - 0x9006).to_le_bytes());
+        assert_eq!(relocated, expected);
+
+        // jcxz +0x10 at 0x1000, which tests cx rather than ecx
+        let code = [0x67, 0xE3, 0x10];
+        let instruction = decode(Arch::X86, &code).unwrap();
+        let relocated = relocate(Arch::X86, &code, &instruction, 0x1000, 0x9000).unwrap();
+        let mut expected = vec![0x67, 0xE3, 0x02, 0xEB, 0x05];
+        expected.extend(encode_jump(Arch::X86, 0x9005, 0x1013));
+        assert_eq!(relocated, expected);
+
+        // bnd call +0x100 at 0x1000, moved out of 32-bit range
+        let code = [0xF2, 0xE8, 0x00, 0x01, 0x00, 0x00];
+        let instruction = decode(Arch::X64, &code).unwrap();
+        let relocated = relocate(Arch::X64, &code, &instruction, 0x1000, 0x7000_0000_0000).unwrap();
+        let mut expected = vec![0xF2, 0xFF, 0x15, 0x02, 0x00, 0x00, 0x00, 0xEB, 0x08];
+        expected.extend(&0x1106_u64.to_le_bytes());
+        assert_eq!(relocated, expected);
+    }
+
+    #[test]
+    fn prefixes_that_change_a_widened_branch_are_rejected() {
+        // jmp +0x10 with an operand-size prefix, which would make the rel32 form a rel16
+        let code = [0x66, 0xEB, 0x10];
+        let instruction = decode(Arch::X86, &code).unwrap();
+        assert_eq!(
+            relocate(Arch::X86, &code, &instruction, 0x1000, 0x9000),
+            None
+        );
+
+        // bnd je +0x10, which has to be split around an absolute jump
+        let code = [0xF2, 0x74, 0x10];
+        let instruction = decode(Arch::X64, &code).unwrap();
+        assert_eq!(
+            relocate(Arch::X64, &code, &instruction, 0x1000, 0x7000_0000_0000),
+            None
+        );
+        // but is widened in place when rel32 still reaches
+        let relocated = relocate(Arch::X64, &code, &instruction, 0x1000, 0x9000).unwrap();
+        assert_eq!(&relocated[..3], &[0xF2, 0x0F, 0x84]);
+    }
+}
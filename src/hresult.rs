@@ -0,0 +1,243 @@
+use std::fmt;
+
+use crate::sys::HRESULT;
+
+macro_rules! d3d_results {
+    ($($(#[$meta:meta])* $variant:ident = $code:literal, $name:literal, $description:literal;)*) => {
+        /// An HRESULT as returned by Direct3D 9, decoded into its named constant.
+        ///
+        /// Converting any `i32` into a `D3dResult` and back yields the original value. Codes
+        /// that d3d9 does not document end up in [`D3dResult::Unknown`].
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum D3dResult {
+            $($(#[$meta])* $variant,)*
+            /// Any other HRESULT.
+            Unknown(HRESULT),
+        }
+
+        impl D3dResult {
+            /// The raw HRESULT.
+            pub const fn code(self) -> HRESULT {
+                match self {
+                    $(D3dResult::$variant => {
+                        let code: u32 = $code;
+                        code as HRESULT
+                    })*
+                    D3dResult::Unknown(code) => code,
+                }
+            }
+
+            /// The name of the constant in the Windows SDK headers, e.g. `D3DERR_INVALIDCALL`.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(D3dResult::$variant => $name,)*
+                    D3dResult::Unknown(_) => "unknown HRESULT",
+                }
+            }
+
+            /// What the code usually means when it comes back from d3d9.
+            pub fn description(self) -> &'static str {
+                match self {
+                    $(D3dResult::$variant => $description,)*
+                    D3dResult::Unknown(_) => "not a code Direct3D 9 is documented to return",
+                }
+            }
+        }
+
+        impl From<HRESULT> for D3dResult {
+            fn from(code: HRESULT) -> Self {
+                match code as u32 {
+                    $($code => D3dResult::$variant,)*
+                    _ => D3dResult::Unknown(code),
+                }
+            }
+        }
+    };
+}
+
+d3d_results! {
+    /// `D3D_OK`, also known as `S_OK`.
+    Ok = 0x0000_0000, "D3D_OK", "the call succeeded";
+    False = 0x0000_0001, "S_FALSE", "the call succeeded but did nothing";
+    NoAutoGen = 0x0876_086F, "D3DOK_NOAUTOGEN",
+        "the format is supported but mipmaps will not be generated automatically";
+    NotResident = 0x0876_0875, "S_NOT_RESIDENT",
+        "at least one resource is evicted from video memory";
+    ResidentInSharedMemory = 0x0876_0876, "S_RESIDENT_IN_SHARED_MEMORY",
+        "no resources are evicted but at least one lives in shared system memory";
+    PresentModeChanged = 0x0876_0877, "S_PRESENT_MODE_CHANGED",
+        "the desktop display mode changed; the device should be reset";
+    PresentOccluded = 0x0876_0878, "S_PRESENT_OCCLUDED",
+        "the window is occluded or minimized, so nothing was presented";
+    WrongTextureFormat = 0x8876_0818, "D3DERR_WRONGTEXTUREFORMAT",
+        "the pixel format of the texture surface is not valid";
+    UnsupportedColorOperation = 0x8876_0819, "D3DERR_UNSUPPORTEDCOLOROPERATION",
+        "the device does not support a specified texture-blending color operation";
+    UnsupportedColorArg = 0x8876_081A, "D3DERR_UNSUPPORTEDCOLORARG",
+        "the device does not support a specified texture-blending color argument";
+    UnsupportedAlphaOperation = 0x8876_081B, "D3DERR_UNSUPPORTEDALPHAOPERATION",
+        "the device does not support a specified texture-blending alpha operation";
+    UnsupportedAlphaArg = 0x8876_081C, "D3DERR_UNSUPPORTEDALPHAARG",
+        "the device does not support a specified texture-blending alpha argument";
+    TooManyOperations = 0x8876_081D, "D3DERR_TOOMANYOPERATIONS",
+        "the application requested more texture-filtering operations than the device supports";
+    ConflictingTextureFilter = 0x8876_081E, "D3DERR_CONFLICTINGTEXTUREFILTER",
+        "the current texture filters cannot be used together";
+    UnsupportedFactorValue = 0x8876_081F, "D3DERR_UNSUPPORTEDFACTORVALUE",
+        "the device does not support the specified texture factor value";
+    ConflictingRenderState = 0x8876_0821, "D3DERR_CONFLICTINGRENDERSTATE",
+        "the currently set render states cannot be used together";
+    UnsupportedTextureFilter = 0x8876_0822, "D3DERR_UNSUPPORTEDTEXTUREFILTER",
+        "the device does not support the specified texture filter";
+    ConflictingTexturePalette = 0x8876_0826, "D3DERR_CONFLICTINGTEXTUREPALETTE",
+        "the current textures cannot be used simultaneously";
+    DriverInternalError = 0x8876_0827, "D3DERR_DRIVERINTERNALERROR",
+        "the display driver hit an internal error; the device is usually unusable afterwards";
+    NotFound = 0x8876_0866, "D3DERR_NOTFOUND", "the requested item was not found";
+    MoreData = 0x8876_0867, "D3DERR_MOREDATA",
+        "there is more data available than the output buffer can hold";
+    DeviceLost = 0x8876_0868, "D3DERR_DEVICELOST",
+        "the device is lost, usually because a fullscreen application lost focus, and cannot be reset yet";
+    DeviceNotReset = 0x8876_0869, "D3DERR_DEVICENOTRESET",
+        "the device was lost and can now be restored by calling Reset";
+    NotAvailable = 0x8876_086A, "D3DERR_NOTAVAILABLE",
+        "the adapter does not support the requested device type, format or behavior flags";
+    OutOfVideoMemory = 0x8876_017C, "D3DERR_OUTOFVIDEOMEMORY",
+        "not enough display memory, often from an oversized back buffer or too many devices";
+    InvalidDevice = 0x8876_086B, "D3DERR_INVALIDDEVICE", "the requested device type is not valid";
+    InvalidCall = 0x8876_086C, "D3DERR_INVALIDCALL",
+        "a parameter is invalid, e.g. bad present parameters or a window that cannot host the device";
+    DriverInvalidCall = 0x8876_086D, "D3DERR_DRIVERINVALIDCALL",
+        "the driver rejected the call; not used by current runtimes";
+    WasStillDrawing = 0x8876_021C, "D3DERR_WASSTILLDRAWING",
+        "the surface is still in use by a previous blit";
+    DeviceRemoved = 0x8876_0870, "D3DERR_DEVICEREMOVED",
+        "the adapter was removed or the driver was upgraded; a new device must be created";
+    DeviceHung = 0x8876_0874, "D3DERR_DEVICEHUNG",
+        "the device stopped responding and the runtime reset it";
+    UnsupportedOverlay = 0x8876_087B, "D3DERR_UNSUPPORTEDOVERLAY",
+        "the device does not support overlay for the specified size or display mode";
+    UnsupportedOverlayFormat = 0x8876_087C, "D3DERR_UNSUPPORTEDOVERLAYFORMAT",
+        "the device does not support overlay for the specified surface format";
+    CannotProtectContent = 0x8876_087D, "D3DERR_CANNOTPROTECTCONTENT",
+        "the specified content cannot be protected";
+    UnsupportedCrypto = 0x8876_087E, "D3DERR_UNSUPPORTEDCRYPTO",
+        "the specified cryptographic algorithm is not supported";
+    PresentStatisticsDisjoint = 0x8876_0884, "D3DERR_PRESENT_STATISTICS_DISJOINT",
+        "the present statistics have no orderly sequence";
+    NotImpl = 0x8000_4001, "E_NOTIMPL", "the method is not implemented";
+    NoInterface = 0x8000_4002, "E_NOINTERFACE", "the object does not support the requested interface";
+    Pointer = 0x8000_4003, "E_POINTER", "a required pointer argument was null";
+    Fail = 0x8000_4005, "E_FAIL", "an undetermined error occurred inside the Direct3D subsystem";
+    OutOfMemory = 0x8007_000E, "E_OUTOFMEMORY", "Direct3D could not allocate enough system memory";
+    InvalidArg = 0x8007_0057, "E_INVALIDARG", "an invalid parameter was passed to the method";
+}
+
+impl D3dResult {
+    /// Whether the code is a success code, i.e. not negative.
+    pub fn is_success(self) -> bool {
+        self.code() >= 0
+    }
+}
+
+impl From<D3dResult> for HRESULT {
+    fn from(result: D3dResult) -> Self {
+        result.code()
+    }
+}
+
+impl fmt::Display for D3dResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ({:#X}): {}",
+            self.name(),
+            self.code(),
+            self.description()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every code around the ones d3d9 documents, so both the named codes and their unnamed
+    /// neighbours are covered.
+    fn codes() -> impl Iterator<Item = HRESULT> {
+        const RANGES: [(u32, u32); 6] = [
+            (0x0000_0000, 0x0000_0010),
+            (0x0876_0800, 0x0876_0900),
+            (0x8876_0100, 0x8876_0300),
+            (0x8876_0800, 0x8876_0900),
+            (0x8000_4000, 0x8000_4010),
+            (0x8007_0000, 0x8007_0060),
+        ];
+        RANGES
+            .iter()
+            .flat_map(|&(start, end)| start..end)
+            .map(|code| code as HRESULT)
+            .chain(vec![HRESULT::MIN, HRESULT::MAX, -1])
+    }
+
+    #[test]
+    fn every_code_round_trips() {
+        for code in codes() {
+            assert_eq!(HRESULT::from(D3dResult::from(code)), code, "{:#X}", code);
+        }
+    }
+
+    #[test]
+    fn documented_codes_are_named() {
+        let cases: [(u32, D3dResult, &str); 8] = [
+            (0x0000_0000, D3dResult::Ok, "D3D_OK"),
+            (
+                0x0876_0878,
+                D3dResult::PresentOccluded,
+                "S_PRESENT_OCCLUDED",
+            ),
+            (0x8876_086C, D3dResult::InvalidCall, "D3DERR_INVALIDCALL"),
+            (0x8876_086A, D3dResult::NotAvailable, "D3DERR_NOTAVAILABLE"),
+            (
+                0x8876_017C,
+                D3dResult::OutOfVideoMemory,
+                "D3DERR_OUTOFVIDEOMEMORY",
+            ),
+            (0x8876_0868, D3dResult::DeviceLost, "D3DERR_DEVICELOST"),
+            (
+                0x8876_0869,
+                D3dResult::DeviceNotReset,
+                "D3DERR_DEVICENOTRESET",
+            ),
+            (0x8007_000E, D3dResult::OutOfMemory, "E_OUTOFMEMORY"),
+        ];
+        for &(code, result, name) in &cases {
+            assert_eq!(D3dResult::from(code as HRESULT), result);
+            assert_eq!(result.name(), name);
+        }
+    }
+
+    #[test]
+    fn unknown_codes_keep_their_value() {
+        let result = D3dResult::from(0x8876_0999_u32 as HRESULT);
+        assert_eq!(result, D3dResult::Unknown(0x8876_0999_u32 as HRESULT));
+        assert_eq!(result.name(), "unknown HRESULT");
+        assert!(!result.is_success());
+    }
+
+    #[test]
+    fn success_follows_the_sign_bit() {
+        assert!(D3dResult::Ok.is_success());
+        assert!(D3dResult::PresentOccluded.is_success());
+        assert!(!D3dResult::DeviceLost.is_success());
+    }
+
+    #[test]
+    fn display_names_the_code_and_its_cause() {
+        assert_eq!(
+            D3dResult::InvalidCall.to_string(),
+            "D3DERR_INVALIDCALL (0x8876086C): a parameter is invalid, e.g. bad present \
+             parameters or a window that cannot host the device"
+        );
+    }
+}
@@ -1,188 +1,215 @@
-use std::error;
-use std::mem;
-use std::ptr;
+pub mod backend;
+pub mod com;
+mod device;
+pub mod entry;
+mod error;
+mod fallback;
+mod grabber;
+pub mod hook;
+mod hresult;
+mod options;
+pub mod overlay;
+mod present;
+mod shutdown;
+pub mod sys;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod vtable;
+mod wait;
+pub mod window;
+
+use backend::Backend;
+#[cfg(windows)]
+use backend::WinApiBackend;
+pub use device::{DummyDevice, DummyDeviceEx, DummySwapChain, LiveDevice};
+pub use error::{CreateDeviceAttempt, D3D9GrabError, HookError, OverlayError, PresentParamsError};
+pub use fallback::{DeviceSetup, FallbackChain};
+pub use grabber::DeviceGrabber;
+pub use hresult::D3dResult;
+pub use options::GrabOptions;
+pub use present::PresentParamsBuilder;
+#[cfg(windows)]
+pub use shutdown::shutdown;
+pub use shutdown::{shutdown_in, CallGuard};
+use sys::*;
+pub use vtable::{
+    D3D9VTables, DeviceExMethod, DeviceExVTable, DeviceMethod, DeviceVTable, SwapChainMethod,
+    SwapChainVTable,
+};
+pub use wait::{Clock, SystemClock, WaitPolicy};
 
-use winapi::shared::{d3d9::*, d3d9types::*, minwindef::*, windef::*};
-use winapi::um::{processthreadsapi::GetCurrentProcessId, winuser::*};
+/// Get the D3D9 device pointer
+///
+/// # Safety
+///
+/// See [`DeviceGrabber::device`].
+#[cfg(windows)]
+#[deprecated(note = "use `DeviceGrabber::new().device()`")]
+pub unsafe fn get_d3d9_device() -> Result<DummyDevice, D3D9GrabError> {
+    DeviceGrabber::new().device()
+}
 
-use thiserror::Error;
+/// Get the D3D9 device pointer and the window it was created on
+///
+/// # Safety
+///
+/// See [`DeviceGrabber::device`].
+#[cfg(windows)]
+#[deprecated(note = "use `DeviceGrabber::new().device_with_hwnd()`")]
+pub unsafe fn get_d3d9_device_with_hwnd() -> Result<(DummyDevice, HWND), D3D9GrabError> {
+    DeviceGrabber::new().device_with_hwnd()
+}
 
-/// Get the D3D9 device pointer
+/// Get the D3D9 device pointer, choosing the window and device setup from `options`
+///
+/// # Safety
+///
+/// See [`DeviceGrabber::device`].
+#[cfg(windows)]
+#[deprecated(note = "use `DeviceGrabber::from(options).device()`")]
+pub unsafe fn get_d3d9_device_with(options: &GrabOptions) -> Result<DummyDevice, D3D9GrabError> {
+    grabber::grab(&WinApiBackend, options).map(|(device, _)| device)
+}
+
+/// Copy the `IDirect3DDevice9` vtable, for hooking methods such as `EndScene` or `Present`
 ///
-/// Example usage:
+/// The device is created on a private hidden window, so the game's windows are never touched,
+/// and both are released again before this returns.
 ///
+/// ```ignore
+/// let vtable = get_d3d9_vtable()?;
+/// let end_scene = vtable.get(DeviceMethod::EndScene);
+/// assert_eq!(end_scene, vtable.end_scene);
 /// ```
-/// // This would be part of the lib.rs inside of a library that is of the type `cdylib`
-/// // init() would be called from a new thread spawned in DllMain
 ///
-/// unsafe fn init() {
-///    AllocConsole();
-///    let device_result = get_d3d9_device();
+/// # Safety
+///
+/// See [`DeviceGrabber::device`].
+#[cfg(windows)]
+pub unsafe fn get_d3d9_vtable() -> Result<DeviceVTable, D3D9GrabError> {
+    get_d3d9_vtable_in(&WinApiBackend)
+}
+
+/// Copy the `IDirect3DDevice9` vtable and the vtable of the device's implicit swap chain
 ///
-///    if let Err(e) = device_result {
-///        println!("error getting d3d9 device: {}", e);
-///        return
-///    }
+/// Some engines present through `IDirect3DSwapChain9::Present` instead of the device's
+/// `Present`; hooking both catches every frame. Like [`get_d3d9_vtable`], everything is
+/// created on a private hidden window and released before this returns.
 ///
-///    let device = device_result.unwrap();
-///    // do whatever you want with device
-/// }
+/// ```ignore
+/// let vtables = get_d3d9_vtables()?;
+/// let device_present = vtables.device.get(DeviceMethod::Present);
+/// let swap_chain_present = vtables.swap_chain.get(SwapChainMethod::Present);
 /// ```
-pub unsafe fn get_d3d9_device() -> Result<&'static mut IDirect3DDevice9, Box<dyn error::Error>> {
-    let window = match get_process_window() {
-        Some(hwnd) => hwnd,
-        None => return Err(Box::new(D3D9GrabError::GetProcessWindowFailed)),
-    };
-
-    let d3d9 = Direct3DCreate9(D3D_SDK_VERSION);
-
-    if d3d9.is_null() {
-        return Err(Box::new(D3D9GrabError::D3DCreate9Null));
-    }
-
-    let mut present_params = D3DPRESENT_PARAMETERS {
-        BackBufferWidth: 0,
-        BackBufferHeight: 0,
-        BackBufferFormat: 0,
-        BackBufferCount: 0,
-        MultiSampleType: 0,
-        MultiSampleQuality: 0,
-        SwapEffect: D3DSWAPEFFECT_DISCARD,
-        hDeviceWindow: window,
-        Windowed: FALSE,
-        EnableAutoDepthStencil: 0,
-        AutoDepthStencilFormat: 0,
-        Flags: 0,
-        FullScreen_RefreshRateInHz: 0,
-        PresentationInterval: 0,
-    };
-
-    let d3d9_device: *mut IDirect3DDevice9 = ptr::null_mut();
-
-    let result_device_err = (*d3d9).CreateDevice(
-        D3DADAPTER_DEFAULT,
-        D3DDEVTYPE_HAL,
-        present_params.hDeviceWindow,
-        D3DCREATE_SOFTWARE_VERTEXPROCESSING,
-        &mut present_params,
-        mem::transmute(&d3d9_device),
-    );
-
-    if result_device_err != 0 {
-        present_params.Windowed = !present_params.Windowed;
-        let result_device_err = (*d3d9).CreateDevice(
-            D3DADAPTER_DEFAULT,
-            D3DDEVTYPE_HAL,
-            present_params.hDeviceWindow,
-            D3DCREATE_SOFTWARE_VERTEXPROCESSING,
-            mem::transmute(&present_params),
-            mem::transmute(&d3d9_device),
-        );
-        if result_device_err != 0 {
-            return Err(Box::new(D3D9GrabError::CreateDeviceError(
-                result_device_err,
-            )));
-        }
-    }
-
-    match d3d9_device.as_mut() {
-        None => return Err(Box::new(D3D9GrabError::AsMutError)),
-        Some(device_ref) => return Ok(device_ref),
-    }
+///
+/// # Safety
+///
+/// See [`DeviceGrabber::device`].
+#[cfg(windows)]
+pub unsafe fn get_d3d9_vtables() -> Result<D3D9VTables, D3D9GrabError> {
+    get_d3d9_vtables_in(&WinApiBackend)
 }
 
-pub unsafe fn get_d3d9_device_with_hwnd() -> Result<(&'static mut IDirect3DDevice9, HWND), Box<dyn error::Error>> {
-    let window = match get_process_window() {
-        Some(hwnd) => hwnd,
-        None => return Err(Box::new(D3D9GrabError::GetProcessWindowFailed)),
-    };
-
-    let d3d9 = Direct3DCreate9(D3D_SDK_VERSION);
-
-    if d3d9.is_null() {
-        return Err(Box::new(D3D9GrabError::D3DCreate9Null));
-    }
+/// Get an `IDirect3DDevice9Ex`, for games that render through `Direct3DCreate9Ex` and `PresentEx`
+///
+/// Fails with [`D3D9GrabError::Direct3DCreate9ExUnavailable`] before Windows Vista.
+///
+/// # Safety
+///
+/// See [`DeviceGrabber::device`].
+#[cfg(windows)]
+#[deprecated(note = "use `DeviceGrabber::new().device_ex()`")]
+pub unsafe fn get_d3d9ex_device() -> Result<DummyDeviceEx, D3D9GrabError> {
+    DeviceGrabber::new().device_ex()
+}
 
-    let mut present_params = D3DPRESENT_PARAMETERS {
-        BackBufferWidth: 0,
-        BackBufferHeight: 0,
-        BackBufferFormat: 0,
-        BackBufferCount: 0,
-        MultiSampleType: 0,
-        MultiSampleQuality: 0,
-        SwapEffect: D3DSWAPEFFECT_DISCARD,
-        hDeviceWindow: window,
-        Windowed: FALSE,
-        EnableAutoDepthStencil: 0,
-        AutoDepthStencilFormat: 0,
-        Flags: 0,
-        FullScreen_RefreshRateInHz: 0,
-        PresentationInterval: 0,
-    };
+/// Copy the `IDirect3DDevice9Ex` vtable, for hooking methods such as `PresentEx` or `ResetEx`
+///
+/// Like [`get_d3d9_vtable`], the device lives on a private hidden window and is released
+/// before this returns.
+///
+/// # Safety
+///
+/// See [`DeviceGrabber::device`].
+#[cfg(windows)]
+pub unsafe fn get_d3d9ex_vtable() -> Result<DeviceExVTable, D3D9GrabError> {
+    get_d3d9ex_vtable_in(&WinApiBackend)
+}
 
-    let d3d9_device: *mut IDirect3DDevice9 = ptr::null_mut();
+/// Get the D3D9 device pointer using `backend` for all window and Direct3D calls
+///
+/// # Safety
+///
+/// See [`DeviceGrabber::device_in`].
+#[deprecated(note = "use `DeviceGrabber::new().device_in(backend)`")]
+pub unsafe fn get_d3d9_device_in<B: Backend>(backend: &B) -> Result<DummyDevice, D3D9GrabError> {
+    DeviceGrabber::new().device_in(backend)
+}
 
-    let result_device_err = (*d3d9).CreateDevice(
-        D3DADAPTER_DEFAULT,
-        D3DDEVTYPE_HAL,
-        present_params.hDeviceWindow,
-        D3DCREATE_SOFTWARE_VERTEXPROCESSING,
-        &mut present_params,
-        mem::transmute(&d3d9_device),
-    );
+/// Get the D3D9 device pointer and its window using `backend` for all window and Direct3D calls
+///
+/// # Safety
+///
+/// See [`DeviceGrabber::device_in`].
+#[deprecated(note = "use `DeviceGrabber::new().device_with_hwnd_in(backend)`")]
+pub unsafe fn get_d3d9_device_with_hwnd_in<B: Backend>(
+    backend: &B,
+) -> Result<(DummyDevice, HWND), D3D9GrabError> {
+    DeviceGrabber::new().device_with_hwnd_in(backend)
+}
 
-    if result_device_err != 0 {
-        present_params.Windowed = !present_params.Windowed;
-        let result_device_err = (*d3d9).CreateDevice(
-            D3DADAPTER_DEFAULT,
-            D3DDEVTYPE_HAL,
-            present_params.hDeviceWindow,
-            D3DCREATE_SOFTWARE_VERTEXPROCESSING,
-            mem::transmute(&present_params),
-            mem::transmute(&d3d9_device),
-        );
-        if result_device_err != 0 {
-            return Err(Box::new(D3D9GrabError::CreateDeviceError(
-                result_device_err,
-            )));
-        }
-    }
+/// Get the D3D9 device pointer using `backend`, choosing the window and device setup from `options`
+///
+/// # Safety
+///
+/// See [`DeviceGrabber::device_in`].
+#[deprecated(note = "use `DeviceGrabber::from(options).device_in(backend)`")]
+pub unsafe fn get_d3d9_device_with_in<B: Backend>(
+    backend: &B,
+    options: &GrabOptions,
+) -> Result<DummyDevice, D3D9GrabError> {
+    grabber::grab(backend, options).map(|(device, _)| device)
+}
 
-    match d3d9_device.as_mut() {
-        None => return Err(Box::new(D3D9GrabError::AsMutError)),
-        Some(device_ref) => return Ok((device_ref, window)),
-    }
+/// Copy the `IDirect3DDevice9` vtable using `backend` for all window and Direct3D calls
+///
+/// # Safety
+///
+/// See [`DeviceGrabber::device_in`].
+pub unsafe fn get_d3d9_vtable_in<B: Backend>(backend: &B) -> Result<DeviceVTable, D3D9GrabError> {
+    DeviceGrabber::new().hidden_window(true).vtable_in(backend)
 }
 
-unsafe fn get_process_window() -> Option<HWND> {
-    extern "system" fn enum_windows_callback(hwnd: HWND, l_param: LPARAM) -> BOOL {
-        let mut wnd_proc_id: DWORD = 0;
-        unsafe {
-            GetWindowThreadProcessId(hwnd, &mut wnd_proc_id as *mut DWORD);
-            if GetCurrentProcessId() != wnd_proc_id {
-                return TRUE;
-            }
-            *(l_param as *mut HWND) = hwnd;
-        }
-        return FALSE;
-    }
+/// Copy the device and implicit swap chain vtables using `backend` for all window and Direct3D calls
+///
+/// # Safety
+///
+/// See [`DeviceGrabber::device_in`].
+pub unsafe fn get_d3d9_vtables_in<B: Backend>(backend: &B) -> Result<D3D9VTables, D3D9GrabError> {
+    DeviceGrabber::new().hidden_window(true).vtables_in(backend)
+}
 
-    let mut hwnd: HWND = ptr::null_mut();
-    EnumWindows(
-        Some(enum_windows_callback),
-        &mut hwnd as *mut HWND as LPARAM,
-    );
-    return if hwnd.is_null() { None } else { Some(hwnd) };
+/// Get an `IDirect3DDevice9Ex` using `backend` for all window and Direct3D calls
+///
+/// # Safety
+///
+/// See [`DeviceGrabber::device_in`].
+#[deprecated(note = "use `DeviceGrabber::new().device_ex_in(backend)`")]
+pub unsafe fn get_d3d9ex_device_in<B: Backend>(
+    backend: &B,
+) -> Result<DummyDeviceEx, D3D9GrabError> {
+    DeviceGrabber::new().device_ex_in(backend)
 }
 
-#[derive(Debug, Error)]
-pub enum D3D9GrabError {
-    #[error("d3d9_device.as_mut() failed to return an instance of &mut IDirect3D9Device")]
-    AsMutError,
-    #[error("d3d9.CreateDevice call returned with an error code `{0:#X}`")]
-    CreateDeviceError(i32),
-    #[error("D3DCreate9 call returned null")]
-    D3DCreate9Null,
-    #[error("Could not get current process window handle")]
-    GetProcessWindowFailed,
+/// Copy the `IDirect3DDevice9Ex` vtable using `backend` for all window and Direct3D calls
+///
+/// # Safety
+///
+/// See [`DeviceGrabber::device_in`].
+pub unsafe fn get_d3d9ex_vtable_in<B: Backend>(
+    backend: &B,
+) -> Result<DeviceExVTable, D3D9GrabError> {
+    DeviceGrabber::new()
+        .hidden_window(true)
+        .vtable_ex_in(backend)
 }
@@ -0,0 +1,52 @@
+use crate::window::WindowSelector;
+use crate::{FallbackChain, WaitPolicy};
+
+/// How a [`DeviceGrabber`](crate::DeviceGrabber) finds a window and creates the device.
+///
+/// The default is what [`DeviceGrabber::new`](crate::DeviceGrabber::new) starts out with.
+#[derive(Debug, Default)]
+pub struct GrabOptions {
+    /// Which of the process's top-level windows to create the device on.
+    pub window: WindowSelector,
+    /// Create the device on a private hidden window instead of one of the process's windows.
+    ///
+    /// The process's windows are then never looked at, the device is created windowed only,
+    /// and the hidden window lives until the returned device is dropped.
+    pub hidden_window: bool,
+    /// Keep looking for the process's window until it exists, instead of failing at once.
+    ///
+    /// Unused with a hidden window.
+    pub wait: Option<WaitPolicy>,
+    /// The `CreateDevice` calls to try, in order.
+    ///
+    /// `None` picks [`FallbackChain::for_process_window`], or
+    /// [`FallbackChain::for_hidden_window`] with a hidden window, for both kinds of device. A
+    /// chain given here is used as it is in all cases.
+    pub fallback: Option<FallbackChain>,
+}
+
+impl GrabOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn window(mut self, window: WindowSelector) -> Self {
+        self.window = window;
+        self
+    }
+
+    pub fn hidden_window(mut self, hidden_window: bool) -> Self {
+        self.hidden_window = hidden_window;
+        self
+    }
+
+    pub fn wait(mut self, wait: WaitPolicy) -> Self {
+        self.wait = Some(wait);
+        self
+    }
+
+    pub fn fallback(mut self, fallback: FallbackChain) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+}
@@ -0,0 +1,543 @@
+//! Turning shapes into triangle lists, in plain Rust.
+
+use std::f32::consts::TAU;
+
+use crate::sys::*;
+
+/// Direct3D 9 samples pixels at their top-left corner rather than their centre, so vertices
+/// are moved up and left by half a pixel to land shapes on the pixels they cover.
+const HALF_PIXEL: f32 = 0.5;
+
+/// Circles are split into segments of about this many pixels of circumference.
+const CIRCLE_SEGMENT_LENGTH: f32 = 4.0;
+const MIN_CIRCLE_SEGMENTS: usize = 12;
+const MAX_CIRCLE_SEGMENTS: usize = 128;
+
+/// A `D3DCOLOR`: alpha, red, green and blue packed from the high byte down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Color(pub D3DCOLOR);
+
+impl Color {
+    pub const WHITE: Color = Color::rgb(255, 255, 255);
+    pub const BLACK: Color = Color::rgb(0, 0, 0);
+    pub const TRANSPARENT: Color = Color::rgba(0, 0, 0, 0);
+
+    /// An opaque colour.
+    pub const fn rgb(red: u8, green: u8, blue: u8) -> Self {
+        Color::rgba(red, green, blue, 255)
+    }
+
+    pub const fn rgba(red: u8, green: u8, blue: u8, alpha: u8) -> Self {
+        Color((alpha as u32) << 24 | (red as u32) << 16 | (green as u32) << 8 | blue as u32)
+    }
+
+    pub const fn alpha(self) -> u8 {
+        (self.0 >> 24) as u8
+    }
+
+    pub const fn with_alpha(self, alpha: u8) -> Self {
+        Color(self.0 & 0x00FF_FFFF | (alpha as u32) << 24)
+    }
+}
+
+/// An axis-aligned rectangle, in pixels of the render target from its top-left corner, or in
+/// texture coordinates for [`Batch::textured_quad`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    /// The whole of a texture, as texture coordinates.
+    pub const FULL_TEXTURE: Rect = Rect::new(0.0, 0.0, 1.0, 1.0);
+
+    pub const fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        !positive(self.width) || !positive(self.height)
+    }
+
+    /// The corners clockwise from the top-left one.
+    fn corners(&self) -> [(f32, f32); 4] {
+        let (right, bottom) = (self.x + self.width, self.y + self.height);
+        [
+            (self.x, self.y),
+            (right, self.y),
+            (right, bottom),
+            (self.x, bottom),
+        ]
+    }
+}
+
+/// One vertex as handed to `DrawPrimitiveUP`, in the [`Vertex::FVF`] layout.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vertex {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    /// Always one, as the position is already in screen space.
+    pub rhw: f32,
+    pub color: D3DCOLOR,
+    pub u: f32,
+    pub v: f32,
+}
+
+impl Vertex {
+    /// `D3DFVF_XYZRHW | D3DFVF_DIFFUSE | D3DFVF_TEX1`.
+    pub const FVF: DWORD = D3DFVF_XYZRHW | D3DFVF_DIFFUSE | D3DFVF_TEX1;
+
+    fn new((x, y): (f32, f32), color: Color, (u, v): (f32, f32)) -> Self {
+        Vertex {
+            x: x - HALF_PIXEL,
+            y: y - HALF_PIXEL,
+            z: 0.0,
+            rhw: 1.0,
+            color: color.0,
+            u,
+            v,
+        }
+    }
+}
+
+/// A run of vertices drawn with one texture, or none, by a single `DrawPrimitiveUP` call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DrawCall {
+    /// Null for untextured shapes.
+    pub texture: *mut IDirect3DTexture9,
+    pub first_vertex: usize,
+    pub vertex_count: usize,
+}
+
+impl DrawCall {
+    /// Number of triangles drawn.
+    pub fn primitive_count(&self) -> usize {
+        self.vertex_count / 3
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Run {
+    texture: usize,
+    first_vertex: usize,
+    vertex_count: usize,
+}
+
+/// Shapes to draw over the frame, kept as a triangle list in the order they were added.
+///
+/// Consecutive shapes with the same texture share a [`DrawCall`], so a batch without textured
+/// quads is drawn with a single call. Shapes with an empty size or a thickness that is not
+/// positive add nothing.
+#[derive(Debug, Clone, Default)]
+pub struct Batch {
+    vertices: Vec<Vertex>,
+    runs: Vec<Run>,
+}
+
+impl Batch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    /// Drop every shape, keeping the memory for the next frame.
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.runs.clear();
+    }
+
+    /// Every vertex, three to a triangle.
+    pub fn vertices(&self) -> &[Vertex] {
+        &self.vertices
+    }
+
+    /// The calls [`flush`](Batch::flush) will make, in order.
+    pub fn draw_calls(&self) -> impl Iterator<Item = DrawCall> + '_ {
+        self.runs.iter().map(|run| DrawCall {
+            texture: run.texture as *mut IDirect3DTexture9,
+            first_vertex: run.first_vertex,
+            vertex_count: run.vertex_count,
+        })
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, color: Color) {
+        if !rect.is_empty() {
+            self.push_quad(0, rect.corners(), color, [(0.0, 0.0); 4]);
+        }
+    }
+
+    /// The outline of `rect`, `thickness` pixels wide on the inside of its edges.
+    pub fn rect(&mut self, rect: Rect, thickness: f32, color: Color) {
+        if rect.is_empty() || !positive(thickness) {
+            return;
+        }
+        if thickness * 2.0 >= rect.width || thickness * 2.0 >= rect.height {
+            self.fill_rect(rect, color);
+            return;
+        }
+        let Rect {
+            x,
+            y,
+            width,
+            height,
+        } = rect;
+        let side_height = height - thickness * 2.0;
+        self.fill_rect(Rect::new(x, y, width, thickness), color);
+        self.fill_rect(
+            Rect::new(x, y + height - thickness, width, thickness),
+            color,
+        );
+        self.fill_rect(Rect::new(x, y + thickness, thickness, side_height), color);
+        self.fill_rect(
+            Rect::new(x + width - thickness, y + thickness, thickness, side_height),
+            color,
+        );
+    }
+
+    /// A line from `from` to `to`, `thickness` pixels wide and centred on the two points.
+    pub fn line(&mut self, from: (f32, f32), to: (f32, f32), thickness: f32, color: Color) {
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let length = (dx * dx + dy * dy).sqrt();
+        if !positive(length) || !positive(thickness) {
+            return;
+        }
+        let scale = thickness / 2.0 / length;
+        let (nx, ny) = (-dy * scale, dx * scale);
+        self.push_quad(
+            0,
+            [
+                (from.0 + nx, from.1 + ny),
+                (to.0 + nx, to.1 + ny),
+                (to.0 - nx, to.1 - ny),
+                (from.0 - nx, from.1 - ny),
+            ],
+            color,
+            [(0.0, 0.0); 4],
+        );
+    }
+
+    pub fn fill_circle(&mut self, center: (f32, f32), radius: f32, color: Color) {
+        if !positive(radius) {
+            return;
+        }
+        let segments = circle_segments(radius);
+        self.begin_run(0);
+        for i in 0..segments {
+            let start = circle_point(center, radius, i, segments);
+            let end = circle_point(center, radius, i + 1, segments);
+            self.push_vertices(&[
+                Vertex::new(center, color, (0.0, 0.0)),
+                Vertex::new(start, color, (0.0, 0.0)),
+                Vertex::new(end, color, (0.0, 0.0)),
+            ]);
+        }
+    }
+
+    /// The outline of a circle, `thickness` pixels wide on the inside of `radius`.
+    pub fn circle(&mut self, center: (f32, f32), radius: f32, thickness: f32, color: Color) {
+        if !positive(radius) || !positive(thickness) {
+            return;
+        }
+        if thickness >= radius {
+            self.fill_circle(center, radius, color);
+            return;
+        }
+        let segments = circle_segments(radius);
+        let inner = radius - thickness;
+        for i in 0..segments {
+            self.push_quad(
+                0,
+                [
+                    circle_point(center, radius, i, segments),
+                    circle_point(center, radius, i + 1, segments),
+                    circle_point(center, inner, i + 1, segments),
+                    circle_point(center, inner, i, segments),
+                ],
+                color,
+                [(0.0, 0.0); 4],
+            );
+        }
+    }
+
+    /// The part `uv` of `texture` stretched over `dest`, multiplied by `color`, so
+    /// [`Color::WHITE`] draws the texture as it is.
+    ///
+    /// The texture is only drawn by [`flush`](Batch::flush), and must stay alive until then.
+    pub fn textured_quad(
+        &mut self,
+        texture: *mut IDirect3DTexture9,
+        dest: Rect,
+        uv: Rect,
+        color: Color,
+    ) {
+        if !dest.is_empty() {
+            self.push_quad(texture as usize, dest.corners(), color, uv.corners());
+        }
+    }
+
+    /// Two triangles covering `corners`, which go round the quad in either direction.
+    fn push_quad(
+        &mut self,
+        texture: usize,
+        corners: [(f32, f32); 4],
+        color: Color,
+        uvs: [(f32, f32); 4],
+    ) {
+        let [a, b, c, d] = corners;
+        let [ta, tb, tc, td] = uvs;
+        self.begin_run(texture);
+        self.push_vertices(&[
+            Vertex::new(a, color, ta),
+            Vertex::new(b, color, tb),
+            Vertex::new(c, color, tc),
+            Vertex::new(a, color, ta),
+            Vertex::new(c, color, tc),
+            Vertex::new(d, color, td),
+        ]);
+    }
+
+    /// Make the last run the one for `texture`, starting a new one if the texture changes.
+    fn begin_run(&mut self, texture: usize) {
+        match self.runs.last() {
+            Some(run) if run.texture == texture => {}
+            _ => self.runs.push(Run {
+                texture,
+                first_vertex: self.vertices.len(),
+                vertex_count: 0,
+            }),
+        }
+    }
+
+    fn push_vertices(&mut self, vertices: &[Vertex]) {
+        self.vertices.extend_from_slice(vertices);
+        if let Some(run) = self.runs.last_mut() {
+            run.vertex_count += vertices.len();
+        }
+    }
+}
+
+/// Whether `value` is above zero, which NaN is not.
+fn positive(value: f32) -> bool {
+    value > 0.0
+}
+
+/// How many segments make a circle of `radius` look round.
+fn circle_segments(radius: f32) -> usize {
+    let segments = (TAU * radius / CIRCLE_SEGMENT_LENGTH).ceil() as usize;
+    segments.clamp(MIN_CIRCLE_SEGMENTS, MAX_CIRCLE_SEGMENTS)
+}
+
+/// Point `index` of `segments` around a circle, starting from the right and going clockwise
+/// on screen. Point `segments` is the same as point zero.
+fn circle_point(center: (f32, f32), radius: f32, index: usize, segments: usize) -> (f32, f32) {
+    if index % segments == 0 {
+        // Exactly the same point, so the last segment closes the circle without a crack.
+        return (center.0 + radius, center.1);
+    }
+    let angle = TAU * index as f32 / segments as f32;
+    (
+        center.0 + radius * angle.cos(),
+        center.1 + radius * angle.sin(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{mem, ptr};
+
+    use super::*;
+
+    fn positions(batch: &Batch) -> Vec<(f32, f32)> {
+        batch
+            .vertices()
+            .iter()
+            .map(|vertex| (vertex.x + HALF_PIXEL, vertex.y + HALF_PIXEL))
+            .collect()
+    }
+
+    #[test]
+    fn colors_pack_alpha_in_the_high_byte() {
+        assert_eq!(Color::rgba(0x11, 0x22, 0x33, 0x44).0, 0x4411_2233);
+        assert_eq!(Color::rgb(1, 2, 3).alpha(), 255);
+        assert_eq!(Color::WHITE.with_alpha(0x80).0, 0x80FF_FFFF);
+    }
+
+    #[test]
+    fn vertices_match_their_fvf() {
+        assert_eq!(mem::size_of::<Vertex>(), 28);
+        assert_eq!(Vertex::FVF, 0x144);
+    }
+
+    #[test]
+    fn filled_rect_is_two_triangles_moved_half_a_pixel() {
+        let mut batch = Batch::new();
+        batch.fill_rect(Rect::new(10.0, 20.0, 30.0, 40.0), Color::WHITE);
+
+        let corners: Vec<(f32, f32)> = batch.vertices().iter().map(|v| (v.x, v.y)).collect();
+        assert_eq!(
+            corners,
+            vec![
+                (9.5, 19.5),
+                (39.5, 19.5),
+                (39.5, 59.5),
+                (9.5, 19.5),
+                (39.5, 59.5),
+                (9.5, 59.5),
+            ]
+        );
+        assert!(batch
+            .vertices()
+            .iter()
+            .all(|v| v.z == 0.0 && v.rhw == 1.0 && v.color == Color::WHITE.0));
+        let calls: Vec<DrawCall> = batch.draw_calls().collect();
+        assert_eq!(calls.len(), 1);
+        assert!(calls[0].texture.is_null());
+        assert_eq!(calls[0].primitive_count(), 2);
+    }
+
+    #[test]
+    fn empty_shapes_add_nothing() {
+        let mut batch = Batch::new();
+        batch.fill_rect(Rect::new(0.0, 0.0, 0.0, 5.0), Color::WHITE);
+        batch.fill_rect(Rect::new(0.0, 0.0, f32::NAN, 5.0), Color::WHITE);
+        batch.rect(Rect::new(0.0, 0.0, 5.0, 5.0), 0.0, Color::WHITE);
+        batch.line((1.0, 1.0), (1.0, 1.0), 2.0, Color::WHITE);
+        batch.line((1.0, 1.0), (5.0, 1.0), -1.0, Color::WHITE);
+        batch.fill_circle((0.0, 0.0), 0.0, Color::WHITE);
+        batch.circle((0.0, 0.0), 5.0, 0.0, Color::WHITE);
+        batch.textured_quad(
+            ptr::null_mut(),
+            Rect::new(0.0, 0.0, 5.0, -1.0),
+            Rect::FULL_TEXTURE,
+            Color::WHITE,
+        );
+
+        assert!(batch.is_empty());
+        assert_eq!(batch.draw_calls().count(), 0);
+    }
+
+    #[test]
+    fn thick_outlines_become_filled_rects() {
+        let mut batch = Batch::new();
+        batch.rect(Rect::new(0.0, 0.0, 10.0, 10.0), 1.0, Color::WHITE);
+        assert_eq!(batch.vertices().len(), 4 * 6);
+
+        batch.clear();
+        batch.rect(Rect::new(0.0, 0.0, 10.0, 10.0), 6.0, Color::WHITE);
+        assert_eq!(positions(&batch).len(), 6);
+        assert_eq!(positions(&batch)[2], (10.0, 10.0));
+    }
+
+    #[test]
+    fn lines_are_centred_on_their_points() {
+        let mut batch = Batch::new();
+        batch.line((0.0, 0.0), (10.0, 0.0), 2.0, Color::WHITE);
+
+        assert_eq!(
+            positions(&batch),
+            vec![
+                (0.0, 1.0),
+                (10.0, 1.0),
+                (10.0, -1.0),
+                (0.0, 1.0),
+                (10.0, -1.0),
+                (0.0, -1.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn circles_close_on_their_first_point() {
+        let mut batch = Batch::new();
+        batch.fill_circle((50.0, 50.0), 10.0, Color::WHITE);
+
+        // 2 * pi * 10 pixels of circumference in segments of 4.
+        assert_eq!(batch.vertices().len(), 16 * 3);
+        for (x, y) in positions(&batch) {
+            let distance = ((x - 50.0).powi(2) + (y - 50.0).powi(2)).sqrt();
+            assert!(distance < 1e-3 || (distance - 10.0).abs() < 1e-3);
+        }
+        let points = positions(&batch);
+        assert_eq!(points[1], points[points.len() - 1]);
+    }
+
+    #[test]
+    fn circle_segments_are_clamped() {
+        let mut batch = Batch::new();
+        batch.circle((0.0, 0.0), 1000.0, 2.0, Color::WHITE);
+        assert_eq!(batch.vertices().len(), MAX_CIRCLE_SEGMENTS * 6);
+
+        // As thick as it is wide, so filled.
+        batch.clear();
+        batch.circle((0.0, 0.0), 1.0, 2.0, Color::WHITE);
+        assert_eq!(batch.vertices().len(), MIN_CIRCLE_SEGMENTS * 3);
+    }
+
+    #[test]
+    fn consecutive_shapes_with_one_texture_share_a_draw_call() {
+        let first = 0x1000 as *mut IDirect3DTexture9;
+        let second = 0x2000 as *mut IDirect3DTexture9;
+        let mut batch = Batch::new();
+        batch.fill_rect(Rect::new(0.0, 0.0, 1.0, 1.0), Color::WHITE);
+        batch.line((0.0, 0.0), (3.0, 3.0), 1.0, Color::WHITE);
+        batch.textured_quad(
+            first,
+            Rect::new(0.0, 0.0, 4.0, 4.0),
+            Rect::FULL_TEXTURE,
+            Color::WHITE,
+        );
+        batch.textured_quad(
+            first,
+            Rect::new(4.0, 0.0, 4.0, 4.0),
+            Rect::new(0.5, 0.5, 0.5, 0.5),
+            Color::WHITE,
+        );
+        batch.textured_quad(
+            second,
+            Rect::new(0.0, 0.0, 4.0, 4.0),
+            Rect::FULL_TEXTURE,
+            Color::WHITE,
+        );
+        batch.fill_rect(Rect::new(0.0, 0.0, 1.0, 1.0), Color::WHITE);
+
+        let calls: Vec<(usize, usize, usize)> = batch
+            .draw_calls()
+            .map(|call| (call.texture as usize, call.first_vertex, call.vertex_count))
+            .collect();
+        assert_eq!(
+            calls,
+            vec![(0, 0, 12), (0x1000, 12, 12), (0x2000, 24, 6), (0, 30, 6)]
+        );
+        let uvs: Vec<(f32, f32)> = batch.vertices()[18..24]
+            .iter()
+            .map(|v| (v.u, v.v))
+            .collect();
+        assert_eq!(uvs[0], (0.5, 0.5));
+        assert_eq!(uvs[2], (1.0, 1.0));
+    }
+
+    #[test]
+    fn clear_drops_every_shape() {
+        let mut batch = Batch::new();
+        batch.fill_circle((0.0, 0.0), 5.0, Color::BLACK);
+        batch.clear();
+
+        assert!(batch.is_empty());
+        assert_eq!(batch.draw_calls().count(), 0);
+        batch.fill_rect(Rect::new(0.0, 0.0, 1.0, 1.0), Color::WHITE);
+        assert_eq!(batch.draw_calls().next().unwrap().first_vertex, 0);
+    }
+}
@@ -0,0 +1,27 @@
+//! Drawing 2D shapes over the game's frame.
+//!
+//! A [`Batch`] collects rectangles, lines, circles and textured quads as pre-transformed
+//! vertices, without touching d3d9. [`Batch::flush`] then draws them on the device from an
+//! `EndScene` or `Present` callback, with one `DrawPrimitiveUP` call per texture, and puts the
+//! device's state back the way the game left it. From `Present`, which runs outside any scene,
+//! the shapes are drawn in a scene of their own.
+//!
+//! ```ignore
+//! let batch = Mutex::new(Batch::new());
+//! let hooks = D3D9Hooks::new()
+//!     .on_end_scene(move |device| {
+//!         let mut batch = batch.lock().unwrap();
+//!         batch.fill_rect(Rect::new(10.0, 10.0, 200.0, 40.0), Color::rgba(0, 0, 0, 160));
+//!         batch.line((10.0, 50.0), (210.0, 50.0), 2.0, Color::WHITE);
+//!         batch.circle((110.0, 120.0), 50.0, 3.0, Color::rgb(255, 64, 64));
+//!         if let Err(err) = unsafe { batch.flush(device) } {
+//!             log::warn!("overlay not drawn: {}", err);
+//!         }
+//!     })
+//!     .install()?;
+//! ```
+
+mod batch;
+mod render;
+
+pub use self::batch::{Batch, Color, DrawCall, Rect, Vertex};
@@ -0,0 +1,252 @@
+//! Drawing a [`Batch`] on a device without disturbing the game's render state.
+
+use std::ffi::c_void;
+use std::mem;
+use std::ptr;
+
+use super::batch::{Batch, Vertex};
+use crate::com;
+use crate::sys::*;
+use crate::vtable::DeviceMethod;
+use crate::{D3dResult, OverlayError};
+
+/// Slot of `IDirect3DStateBlock9::Apply`, the last one.
+const STATE_BLOCK_APPLY_SLOT: usize = com::STATE_BLOCK_VTABLE_LEN - 1;
+
+/// Many drivers cap a single draw call at this many triangles.
+const MAX_PRIMITIVES_PER_CALL: usize = 0xFFFF;
+
+/// `D3DCOLORWRITEENABLE_RED | GREEN | BLUE | ALPHA`.
+const COLOR_WRITE_ALL: DWORD = 0xF;
+
+/// Solid, alpha blended into the back buffer as it is, and drawn over everything regardless
+/// of depth, stencil, scissor or fog.
+const RENDER_STATES: &[(D3DRENDERSTATETYPE, DWORD)] = &[
+    (D3DRS_FILLMODE, D3DFILL_SOLID),
+    (D3DRS_ZENABLE, FALSE as DWORD),
+    (D3DRS_ZWRITEENABLE, FALSE as DWORD),
+    (D3DRS_ALPHATESTENABLE, FALSE as DWORD),
+    (D3DRS_STENCILENABLE, FALSE as DWORD),
+    (D3DRS_SCISSORTESTENABLE, FALSE as DWORD),
+    (D3DRS_FOGENABLE, FALSE as DWORD),
+    (D3DRS_CULLMODE, D3DCULL_NONE),
+    (D3DRS_COLORWRITEENABLE, COLOR_WRITE_ALL),
+    (D3DRS_ALPHABLENDENABLE, TRUE as DWORD),
+    (D3DRS_SRCBLEND, D3DBLEND_SRCALPHA),
+    (D3DRS_DESTBLEND, D3DBLEND_INVSRCALPHA),
+    (D3DRS_BLENDOP, D3DBLENDOP_ADD),
+    (D3DRS_SEPARATEALPHABLENDENABLE, FALSE as DWORD),
+    (D3DRS_SRGBWRITEENABLE, FALSE as DWORD),
+];
+
+/// Bilinear filtering, with texture coordinates clamped to the edge rather than wrapped.
+const SAMPLER_STATES: &[(D3DSAMPLERSTATETYPE, DWORD)] = &[
+    (D3DSAMP_MINFILTER, D3DTEXF_LINEAR),
+    (D3DSAMP_MAGFILTER, D3DTEXF_LINEAR),
+    (D3DSAMP_ADDRESSU, D3DTADDRESS_CLAMP),
+    (D3DSAMP_ADDRESSV, D3DTADDRESS_CLAMP),
+];
+
+type CreateStateBlockFn = unsafe extern "system" fn(
+    *mut IDirect3DDevice9,
+    D3DSTATEBLOCKTYPE,
+    *mut *mut IDirect3DStateBlock9,
+) -> HRESULT;
+type ApplyFn = unsafe extern "system" fn(*mut IDirect3DStateBlock9) -> HRESULT;
+/// `BeginScene` and `EndScene`.
+type SceneFn = unsafe extern "system" fn(*mut IDirect3DDevice9) -> HRESULT;
+type SetRenderStateFn =
+    unsafe extern "system" fn(*mut IDirect3DDevice9, D3DRENDERSTATETYPE, DWORD) -> HRESULT;
+type SetTextureStageStateFn = unsafe extern "system" fn(
+    *mut IDirect3DDevice9,
+    DWORD,
+    D3DTEXTURESTAGESTATETYPE,
+    DWORD,
+) -> HRESULT;
+type SetSamplerStateFn =
+    unsafe extern "system" fn(*mut IDirect3DDevice9, DWORD, D3DSAMPLERSTATETYPE, DWORD) -> HRESULT;
+type SetTextureFn =
+    unsafe extern "system" fn(*mut IDirect3DDevice9, DWORD, *mut IDirect3DBaseTexture9) -> HRESULT;
+type SetFVFFn = unsafe extern "system" fn(*mut IDirect3DDevice9, DWORD) -> HRESULT;
+/// `SetVertexShader` and `SetPixelShader`, only ever called to unbind the shader.
+type SetShaderFn = unsafe extern "system" fn(*mut IDirect3DDevice9, *mut c_void) -> HRESULT;
+type DrawPrimitiveUPFn = unsafe extern "system" fn(
+    *mut IDirect3DDevice9,
+    D3DPRIMITIVETYPE,
+    UINT,
+    *const c_void,
+    UINT,
+) -> HRESULT;
+
+impl Batch {
+    /// Draw every shape on `device` and empty the batch, even if drawing fails.
+    ///
+    /// The device's whole state is captured in a state block first and applied again
+    /// afterwards, so the game renders on as if nothing happened. The state block is created
+    /// and released on every flush, so nothing is left to release before a `Reset`. Devices
+    /// created with `D3DCREATE_PUREDEVICE` cannot capture their state, and fail with
+    /// `D3DERR_INVALIDCALL`.
+    ///
+    /// Drawing needs a scene. In an `EndScene` callback the game's scene is still open, and
+    /// `BeginScene` failing with `D3DERR_INVALIDCALL` says so. In a `Present` callback, after
+    /// the last `EndScene`, the shapes are drawn in a scene of their own instead, and the
+    /// `EndScene` closing it runs any `EndScene` callbacks like the game's own calls do.
+    ///
+    /// # Safety
+    ///
+    /// `device` must be a live `IDirect3DDevice9` on the thread rendering with it, as it is in
+    /// an `EndScene` or `Present` callback, and every texture in the batch must still be alive.
+    pub unsafe fn flush(&mut self, device: *mut IDirect3DDevice9) -> Result<(), OverlayError> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let result = draw_in_scene(device, self);
+        self.clear();
+        result
+    }
+}
+
+/// Draw `batch` in the scene already open on `device`, or in one opened for it.
+unsafe fn draw_in_scene(device: *mut IDirect3DDevice9, batch: &Batch) -> Result<(), OverlayError> {
+    let begin_scene: SceneFn =
+        mem::transmute(com::method(device, DeviceMethod::BeginScene.index()));
+    let result = begin_scene(device);
+    if result == D3dResult::InvalidCall.code() {
+        return draw_saving_state(device, batch);
+    }
+    check(DeviceMethod::BeginScene, result)?;
+
+    let drawn = draw_saving_state(device, batch);
+    let end_scene: SceneFn = mem::transmute(com::method(device, DeviceMethod::EndScene.index()));
+    let result = end_scene(device);
+    drawn?;
+    check(DeviceMethod::EndScene, result)
+}
+
+unsafe fn draw_saving_state(
+    device: *mut IDirect3DDevice9,
+    batch: &Batch,
+) -> Result<(), OverlayError> {
+    let create_state_block: CreateStateBlockFn =
+        mem::transmute(com::method(device, DeviceMethod::CreateStateBlock.index()));
+    let mut state_block: *mut IDirect3DStateBlock9 = ptr::null_mut();
+    check(
+        DeviceMethod::CreateStateBlock,
+        create_state_block(device, D3DSBT_ALL, &mut state_block),
+    )?;
+    if state_block.is_null() {
+        return Err(OverlayError::NullStateBlock);
+    }
+
+    let drawn = draw(device, batch);
+
+    let apply: ApplyFn = mem::transmute(com::method(state_block, STATE_BLOCK_APPLY_SLOT));
+    let result = apply(state_block);
+    com::release(state_block);
+    drawn?;
+    if result < 0 {
+        return Err(OverlayError::RestoreStateFailed(result.into()));
+    }
+    Ok(())
+}
+
+unsafe fn draw(device: *mut IDirect3DDevice9, batch: &Batch) -> Result<(), OverlayError> {
+    set_up(device)?;
+
+    let set_texture: SetTextureFn =
+        mem::transmute(com::method(device, DeviceMethod::SetTexture.index()));
+    let draw_primitive_up: DrawPrimitiveUPFn =
+        mem::transmute(com::method(device, DeviceMethod::DrawPrimitiveUP.index()));
+    let vertices = batch.vertices();
+    for call in batch.draw_calls() {
+        check(
+            DeviceMethod::SetTexture,
+            set_texture(device, 0, call.texture as *mut IDirect3DBaseTexture9),
+        )?;
+        // Untextured shapes take their colour from the vertices alone.
+        let op = if call.texture.is_null() {
+            D3DTOP_SELECTARG2
+        } else {
+            D3DTOP_MODULATE
+        };
+        set_texture_stage_state(device, 0, D3DTSS_COLOROP, op)?;
+        set_texture_stage_state(device, 0, D3DTSS_ALPHAOP, op)?;
+
+        let run = &vertices[call.first_vertex..call.first_vertex + call.vertex_count];
+        for chunk in run.chunks(MAX_PRIMITIVES_PER_CALL * 3) {
+            check(
+                DeviceMethod::DrawPrimitiveUP,
+                draw_primitive_up(
+                    device,
+                    D3DPT_TRIANGLELIST,
+                    (chunk.len() / 3) as UINT,
+                    chunk.as_ptr() as *const c_void,
+                    mem::size_of::<Vertex>() as UINT,
+                ),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Put the fixed function pipeline in the state the overlay is drawn with.
+unsafe fn set_up(device: *mut IDirect3DDevice9) -> Result<(), OverlayError> {
+    let set_render_state: SetRenderStateFn =
+        mem::transmute(com::method(device, DeviceMethod::SetRenderState.index()));
+    for &(state, value) in RENDER_STATES {
+        check(
+            DeviceMethod::SetRenderState,
+            set_render_state(device, state, value),
+        )?;
+    }
+
+    for &method in &[DeviceMethod::SetVertexShader, DeviceMethod::SetPixelShader] {
+        let set_shader: SetShaderFn = mem::transmute(com::method(device, method.index()));
+        check(method, set_shader(device, ptr::null_mut()))?;
+    }
+    let set_fvf: SetFVFFn = mem::transmute(com::method(device, DeviceMethod::SetFVF.index()));
+    check(DeviceMethod::SetFVF, set_fvf(device, Vertex::FVF))?;
+
+    set_texture_stage_state(device, 0, D3DTSS_COLORARG1, D3DTA_TEXTURE)?;
+    set_texture_stage_state(device, 0, D3DTSS_COLORARG2, D3DTA_DIFFUSE)?;
+    set_texture_stage_state(device, 0, D3DTSS_ALPHAARG1, D3DTA_TEXTURE)?;
+    set_texture_stage_state(device, 0, D3DTSS_ALPHAARG2, D3DTA_DIFFUSE)?;
+    set_texture_stage_state(device, 1, D3DTSS_COLOROP, D3DTOP_DISABLE)?;
+    set_texture_stage_state(device, 1, D3DTSS_ALPHAOP, D3DTOP_DISABLE)?;
+
+    let set_sampler_state: SetSamplerStateFn =
+        mem::transmute(com::method(device, DeviceMethod::SetSamplerState.index()));
+    for &(state, value) in SAMPLER_STATES {
+        check(
+            DeviceMethod::SetSamplerState,
+            set_sampler_state(device, 0, state, value),
+        )?;
+    }
+    Ok(())
+}
+
+unsafe fn set_texture_stage_state(
+    device: *mut IDirect3DDevice9,
+    stage: DWORD,
+    state: D3DTEXTURESTAGESTATETYPE,
+    value: DWORD,
+) -> Result<(), OverlayError> {
+    let set_texture_stage_state: SetTextureStageStateFn = mem::transmute(com::method(
+        device,
+        DeviceMethod::SetTextureStageState.index(),
+    ));
+    check(
+        DeviceMethod::SetTextureStageState,
+        set_texture_stage_state(device, stage, state, value),
+    )
+}
+
+fn check(method: DeviceMethod, result: HRESULT) -> Result<(), OverlayError> {
+    if result < 0 {
+        return Err(OverlayError::DeviceCallFailed {
+            method,
+            result: result.into(),
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,416 @@
+//! Building `D3DPRESENT_PARAMETERS` that d3d9 will accept.
+
+use crate::error::PresentParamsError;
+use crate::sys::*;
+
+/// `D3DPRESENT_BACK_BUFFERS_MAX`, which winapi does not define.
+const MAX_BACK_BUFFERS: UINT = 3;
+
+/// The fields of `D3DPRESENT_PARAMETERS` other than the window, checked against each other
+/// before they reach d3d9.
+///
+/// d3d9 answers most bad combinations with a bare `D3DERR_INVALIDCALL`. [`build`] rejects the
+/// ones it can recognise up front, with a [`PresentParamsError`] saying what is wrong.
+///
+/// ```ignore
+/// let params = PresentParamsBuilder::dummy_windowed()
+///     .auto_depth_stencil(D3DFMT_D24S8)
+///     .build(hwnd)?;
+/// ```
+///
+/// [`build`]: PresentParamsBuilder::build
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PresentParamsBuilder {
+    /// Zero takes the width of the window's client area, in windowed mode only.
+    pub back_buffer_width: UINT,
+    /// Zero takes the height of the window's client area, in windowed mode only.
+    pub back_buffer_height: UINT,
+    /// `D3DFMT_UNKNOWN` takes the display format, in windowed mode only.
+    pub back_buffer_format: D3DFORMAT,
+    /// Zero is taken as one.
+    pub back_buffer_count: UINT,
+    pub multisample_type: D3DMULTISAMPLE_TYPE,
+    pub multisample_quality: DWORD,
+    pub swap_effect: D3DSWAPEFFECT,
+    pub windowed: bool,
+    pub enable_auto_depth_stencil: bool,
+    pub auto_depth_stencil_format: D3DFORMAT,
+    /// The `D3DPRESENTFLAG_*` flags.
+    pub flags: DWORD,
+    /// Zero uses the adapter's default rate.
+    pub refresh_rate: UINT,
+    /// One of the `D3DPRESENT_INTERVAL_*` values.
+    pub presentation_interval: UINT,
+}
+
+impl PresentParamsBuilder {
+    /// Everything zeroed apart from the swap effect, which is `D3DSWAPEFFECT_DISCARD`, in
+    /// windowed mode.
+    pub fn new() -> Self {
+        PresentParamsBuilder {
+            back_buffer_width: 0,
+            back_buffer_height: 0,
+            back_buffer_format: D3DFMT_UNKNOWN,
+            back_buffer_count: 0,
+            multisample_type: D3DMULTISAMPLE_NONE,
+            multisample_quality: 0,
+            swap_effect: D3DSWAPEFFECT_DISCARD,
+            windowed: true,
+            enable_auto_depth_stencil: false,
+            auto_depth_stencil_format: D3DFMT_UNKNOWN,
+            flags: 0,
+            refresh_rate: 0,
+            presentation_interval: D3DPRESENT_INTERVAL_DEFAULT,
+        }
+    }
+
+    /// The cheapest device that still has a real swap chain: windowed, with a back buffer
+    /// sized to the window in the display format, and no depth buffer or multisampling.
+    pub fn dummy_windowed() -> Self {
+        Self::new()
+    }
+
+    /// Like [`dummy_windowed`](PresentParamsBuilder::dummy_windowed), but fullscreen at
+    /// 640x480 in `D3DFMT_X8R8G8B8`, a display mode every d3d9 adapter lists. Fullscreen has
+    /// no window or display to take a size or format from, so both must be given.
+    pub fn dummy_fullscreen() -> Self {
+        Self::new()
+            .windowed(false)
+            .back_buffer_size(640, 480)
+            .back_buffer_format(D3DFMT_X8R8G8B8)
+    }
+
+    /// A windowed device with a 1x1 back buffer, for windows whose client area is empty, such
+    /// as minimised ones.
+    pub fn dummy_1x1() -> Self {
+        Self::new().back_buffer_size(1, 1)
+    }
+
+    pub fn back_buffer_size(mut self, width: UINT, height: UINT) -> Self {
+        self.back_buffer_width = width;
+        self.back_buffer_height = height;
+        self
+    }
+
+    pub fn back_buffer_format(mut self, format: D3DFORMAT) -> Self {
+        self.back_buffer_format = format;
+        self
+    }
+
+    pub fn back_buffer_count(mut self, count: UINT) -> Self {
+        self.back_buffer_count = count;
+        self
+    }
+
+    pub fn multisample(mut self, multisample_type: D3DMULTISAMPLE_TYPE, quality: DWORD) -> Self {
+        self.multisample_type = multisample_type;
+        self.multisample_quality = quality;
+        self
+    }
+
+    pub fn swap_effect(mut self, swap_effect: D3DSWAPEFFECT) -> Self {
+        self.swap_effect = swap_effect;
+        self
+    }
+
+    pub fn windowed(mut self, windowed: bool) -> Self {
+        self.windowed = windowed;
+        self
+    }
+
+    /// Have d3d9 create a depth stencil buffer of `format` along with the back buffer.
+    pub fn auto_depth_stencil(mut self, format: D3DFORMAT) -> Self {
+        self.enable_auto_depth_stencil = true;
+        self.auto_depth_stencil_format = format;
+        self
+    }
+
+    pub fn flags(mut self, flags: DWORD) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn refresh_rate(mut self, refresh_rate: UINT) -> Self {
+        self.refresh_rate = refresh_rate;
+        self
+    }
+
+    pub fn presentation_interval(mut self, presentation_interval: UINT) -> Self {
+        self.presentation_interval = presentation_interval;
+        self
+    }
+
+    /// Check the combination of fields, returning the first problem found.
+    pub fn validate(&self) -> Result<(), PresentParamsError> {
+        let multisampled = self.multisample_type != D3DMULTISAMPLE_NONE;
+
+        if !self.windowed
+            && (self.back_buffer_width == 0
+                || self.back_buffer_height == 0
+                || self.back_buffer_format == D3DFMT_UNKNOWN)
+        {
+            return Err(PresentParamsError::FullscreenNeedsSizeAndFormat {
+                width: self.back_buffer_width,
+                height: self.back_buffer_height,
+                format: self.back_buffer_format,
+            });
+        }
+        if self.back_buffer_count > MAX_BACK_BUFFERS {
+            return Err(PresentParamsError::TooManyBackBuffers {
+                count: self.back_buffer_count,
+            });
+        }
+        if self.swap_effect == D3DSWAPEFFECT_COPY && self.back_buffer_count > 1 {
+            return Err(PresentParamsError::CopyWithSeveralBackBuffers {
+                count: self.back_buffer_count,
+            });
+        }
+        if multisampled && self.swap_effect != D3DSWAPEFFECT_DISCARD {
+            return Err(PresentParamsError::MultisampleWithoutDiscard {
+                multisample_type: self.multisample_type,
+                swap_effect: self.swap_effect,
+            });
+        }
+        if !multisampled && self.multisample_quality != 0 {
+            return Err(PresentParamsError::QualityWithoutMultisample {
+                quality: self.multisample_quality,
+            });
+        }
+        if multisampled && self.flags & D3DPRESENTFLAG_LOCKABLE_BACKBUFFER != 0 {
+            return Err(PresentParamsError::LockableMultisampledBackBuffer);
+        }
+        if self.windowed && self.refresh_rate != 0 {
+            return Err(PresentParamsError::RefreshRateWhenWindowed {
+                refresh_rate: self.refresh_rate,
+            });
+        }
+        if self.windowed
+            && ![
+                D3DPRESENT_INTERVAL_DEFAULT,
+                D3DPRESENT_INTERVAL_ONE,
+                D3DPRESENT_INTERVAL_IMMEDIATE,
+            ]
+            .contains(&self.presentation_interval)
+        {
+            return Err(PresentParamsError::IntervalWhenWindowed {
+                interval: self.presentation_interval,
+            });
+        }
+        if !self.enable_auto_depth_stencil && self.auto_depth_stencil_format != D3DFMT_UNKNOWN {
+            return Err(PresentParamsError::DepthFormatWithoutAutoDepthStencil {
+                format: self.auto_depth_stencil_format,
+            });
+        }
+        if self.enable_auto_depth_stencil && self.auto_depth_stencil_format == D3DFMT_UNKNOWN {
+            return Err(PresentParamsError::AutoDepthStencilWithoutFormat);
+        }
+        if !self.enable_auto_depth_stencil && self.flags & D3DPRESENTFLAG_DISCARD_DEPTHSTENCIL != 0
+        {
+            return Err(PresentParamsError::DiscardDepthStencilWithoutAutoDepthStencil);
+        }
+        Ok(())
+    }
+
+    /// Validate the fields and turn them into present parameters for `window`.
+    pub fn build(&self, window: HWND) -> Result<D3DPRESENT_PARAMETERS, PresentParamsError> {
+        self.validate()?;
+        Ok(D3DPRESENT_PARAMETERS {
+            BackBufferWidth: self.back_buffer_width,
+            BackBufferHeight: self.back_buffer_height,
+            BackBufferFormat: self.back_buffer_format,
+            BackBufferCount: self.back_buffer_count,
+            MultiSampleType: self.multisample_type,
+            MultiSampleQuality: self.multisample_quality,
+            SwapEffect: self.swap_effect,
+            hDeviceWindow: window,
+            Windowed: if self.windowed { TRUE } else { FALSE },
+            EnableAutoDepthStencil: if self.enable_auto_depth_stencil {
+                TRUE
+            } else {
+                FALSE
+            },
+            AutoDepthStencilFormat: self.auto_depth_stencil_format,
+            Flags: self.flags,
+            FullScreen_RefreshRateInHz: self.refresh_rate,
+            PresentationInterval: self.presentation_interval,
+        })
+    }
+}
+
+impl Default for PresentParamsBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::PresentParamsError::*;
+
+    fn window() -> HWND {
+        0x30 as HWND
+    }
+
+    #[test]
+    fn dummies_are_valid() {
+        for builder in &[
+            PresentParamsBuilder::dummy_windowed(),
+            PresentParamsBuilder::dummy_fullscreen(),
+            PresentParamsBuilder::dummy_1x1(),
+        ] {
+            assert_eq!(builder.validate(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn build_fills_in_the_window() {
+        let params = PresentParamsBuilder::dummy_1x1()
+            .auto_depth_stencil(D3DFMT_D24S8)
+            .build(window())
+            .unwrap();
+
+        assert_eq!(params.hDeviceWindow, window());
+        assert_eq!(params.Windowed, TRUE);
+        assert_eq!(params.SwapEffect, D3DSWAPEFFECT_DISCARD);
+        assert_eq!((params.BackBufferWidth, params.BackBufferHeight), (1, 1));
+        assert_eq!(params.EnableAutoDepthStencil, TRUE);
+        assert_eq!(params.AutoDepthStencilFormat, D3DFMT_D24S8);
+        assert_eq!(
+            PresentParamsBuilder::dummy_fullscreen()
+                .build(window())
+                .unwrap()
+                .Windowed,
+            FALSE
+        );
+    }
+
+    #[test]
+    fn fullscreen_needs_a_size_and_a_format() {
+        let fullscreen = PresentParamsBuilder::dummy_fullscreen();
+        for builder in &[
+            fullscreen.back_buffer_size(0, 0),
+            fullscreen.back_buffer_size(640, 0),
+            fullscreen.back_buffer_format(D3DFMT_UNKNOWN),
+            PresentParamsBuilder::new().windowed(false),
+        ] {
+            assert_eq!(
+                builder.validate(),
+                Err(FullscreenNeedsSizeAndFormat {
+                    width: builder.back_buffer_width,
+                    height: builder.back_buffer_height,
+                    format: builder.back_buffer_format,
+                })
+            );
+        }
+
+        let params = fullscreen.build(window()).unwrap();
+        assert_eq!(
+            (params.BackBufferWidth, params.BackBufferHeight),
+            (640, 480)
+        );
+        assert_eq!(params.BackBufferFormat, D3DFMT_X8R8G8B8);
+    }
+
+    #[test]
+    fn rejects_conflicting_fields() {
+        let windowed = PresentParamsBuilder::dummy_windowed();
+        let cases = vec![
+            (
+                windowed.back_buffer_count(4),
+                TooManyBackBuffers { count: 4 },
+            ),
+            (
+                windowed
+                    .swap_effect(D3DSWAPEFFECT_COPY)
+                    .back_buffer_count(2),
+                CopyWithSeveralBackBuffers { count: 2 },
+            ),
+            (
+                windowed
+                    .multisample(D3DMULTISAMPLE_4_SAMPLES, 0)
+                    .swap_effect(D3DSWAPEFFECT_FLIP),
+                MultisampleWithoutDiscard {
+                    multisample_type: D3DMULTISAMPLE_4_SAMPLES,
+                    swap_effect: D3DSWAPEFFECT_FLIP,
+                },
+            ),
+            (
+                windowed.multisample(D3DMULTISAMPLE_NONE, 2),
+                QualityWithoutMultisample { quality: 2 },
+            ),
+            (
+                windowed
+                    .multisample(D3DMULTISAMPLE_NONMASKABLE, 0)
+                    .flags(D3DPRESENTFLAG_LOCKABLE_BACKBUFFER),
+                LockableMultisampledBackBuffer,
+            ),
+            (
+                windowed.refresh_rate(60),
+                RefreshRateWhenWindowed { refresh_rate: 60 },
+            ),
+            (
+                windowed.presentation_interval(D3DPRESENT_INTERVAL_TWO),
+                IntervalWhenWindowed {
+                    interval: D3DPRESENT_INTERVAL_TWO,
+                },
+            ),
+            (
+                PresentParamsBuilder {
+                    auto_depth_stencil_format: D3DFMT_D16,
+                    ..windowed
+                },
+                DepthFormatWithoutAutoDepthStencil { format: D3DFMT_D16 },
+            ),
+            (
+                PresentParamsBuilder {
+                    enable_auto_depth_stencil: true,
+                    ..windowed
+                },
+                AutoDepthStencilWithoutFormat,
+            ),
+            (
+                windowed.flags(D3DPRESENTFLAG_DISCARD_DEPTHSTENCIL),
+                DiscardDepthStencilWithoutAutoDepthStencil,
+            ),
+        ];
+
+        for (builder, expected) in cases {
+            assert_eq!(builder.validate(), Err(expected));
+            assert_eq!(builder.build(window()).err(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn accepts_the_valid_neighbours() {
+        let windowed = PresentParamsBuilder::dummy_windowed();
+        let valid = [
+            windowed.back_buffer_count(3),
+            windowed.multisample(D3DMULTISAMPLE_4_SAMPLES, 1),
+            windowed.multisample(D3DMULTISAMPLE_NONMASKABLE, 2),
+            windowed
+                .swap_effect(D3DSWAPEFFECT_COPY)
+                .back_buffer_count(1),
+            windowed.presentation_interval(D3DPRESENT_INTERVAL_IMMEDIATE),
+            windowed.presentation_interval(D3DPRESENT_INTERVAL_ONE),
+            windowed
+                .auto_depth_stencil(D3DFMT_D24S8)
+                .flags(D3DPRESENTFLAG_DISCARD_DEPTHSTENCIL),
+            PresentParamsBuilder::dummy_fullscreen()
+                .refresh_rate(60)
+                .presentation_interval(D3DPRESENT_INTERVAL_TWO),
+        ];
+
+        for builder in &valid {
+            assert_eq!(builder.validate(), Ok(()), "{:?}", builder);
+        }
+    }
+
+    #[test]
+    fn reports_the_first_problem() {
+        let builder = PresentParamsBuilder::dummy_windowed()
+            .back_buffer_count(5)
+            .refresh_rate(60);
+        assert_eq!(builder.validate(), Err(TooManyBackBuffers { count: 5 }));
+    }
+}
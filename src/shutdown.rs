@@ -0,0 +1,118 @@
+//! Undoing everything the crate did to the process, so the module it lives in can be unloaded.
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::backend::Backend;
+#[cfg(windows)]
+use crate::backend::WinApiBackend;
+use crate::{com, hook, window, HookError};
+
+/// How often [`shutdown_in`] checks whether the hooked calls have returned.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Calls running in hook code, across all threads.
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Calls running in hook code on this thread, which a shutdown started from inside a
+    /// callback must not wait for.
+    static IN_FLIGHT_HERE: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Marks a call as running in hook code for as long as it is alive, so a shutdown knows when
+/// the code is no longer in use.
+///
+/// The hooks of [`D3D9Hooks`](crate::hook::D3D9Hooks) hold one for the whole call. A
+/// replacement given to [`VmtHook`](crate::hook::VmtHook) or [`Detour`](crate::hook::Detour)
+/// should create one as the first thing it does, so shutting down waits for it as well.
+#[must_use = "the call only counts while the guard is alive"]
+pub struct CallGuard {
+    _private: (),
+}
+
+impl CallGuard {
+    pub fn enter() -> Self {
+        IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+        IN_FLIGHT_HERE.with(|here| here.set(here.get() + 1));
+        CallGuard { _private: () }
+    }
+
+    /// Number of calls running in hook code, on all threads.
+    pub fn in_flight() -> usize {
+        IN_FLIGHT.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for CallGuard {
+    fn drop(&mut self) {
+        IN_FLIGHT_HERE.with(|here| here.set(here.get() - 1));
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Remove every hook, wait up to `timeout` for hooked calls to return, and release the COM
+/// objects and hidden windows of every dummy device and swap chain still alive.
+///
+/// # Safety
+///
+/// See [`shutdown_in`].
+#[cfg(windows)]
+pub unsafe fn shutdown(timeout: Duration) -> Result<(), HookError> {
+    shutdown_in(&WinApiBackend, timeout)
+}
+
+/// Undo what the crate did to the process, changing memory protection through `backend`.
+///
+/// Every vtable slot hooked by a [`VmtHook`](crate::hook::VmtHook) and every function patched
+/// by a [`Detour`](crate::hook::Detour) gets its original back, and the callbacks of
+/// [`D3D9Hooks`](crate::hook::D3D9Hooks) stop running. Then this waits for the calls counted
+/// by [`CallGuard`]s on other threads to return, which may still be using the dummies. Once
+/// they have, the references held by live [`DummyDevice`](crate::DummyDevice)s and
+/// [`DummySwapChain`](crate::DummySwapChain)s are released, the hidden windows the devices
+/// were created on are destroyed, and the detour trampolines are freed.
+///
+/// Hooks and dummies dropped afterwards leave the process alone. Calls made from inside a
+/// callback on this thread are not waited for, so shutting down from a callback works, but
+/// the module may only be unloaded once that callback has returned.
+///
+/// Win32 only lets a window be destroyed by the thread that created it, so a hidden window
+/// created on another thread lives on until that thread exits.
+///
+/// If calls are still running after `timeout`, this fails with
+/// [`HookError::DrainTimedOut`] and leaves the dummies, their windows and the trampolines
+/// alone, and the module must stay loaded. Otherwise it returns the first hook that could not
+/// be removed, if any.
+///
+/// # Safety
+///
+/// No dummy device or swap chain, nor any pointer obtained from one, may be used afterwards
+/// other than to drop it. Nothing may call an [`Original`](crate::hook::Original) taken from
+/// a hook. A thread that read a hooked slot just before it was restored, but has not reached
+/// its [`CallGuard`] yet, is not waited for.
+pub unsafe fn shutdown_in<B: Backend>(backend: &B, timeout: Duration) -> Result<(), HookError> {
+    let removed = hook::remove_every_hook(backend);
+    drain(timeout)?;
+    com::release_every_owned();
+    window::destroy_every_hidden_window();
+    hook::free_every_trampoline(backend);
+    removed
+}
+
+/// Wait until the only calls running in hook code are those of this thread.
+fn drain(timeout: Duration) -> Result<(), HookError> {
+    let deadline = Instant::now() + timeout;
+    let here = IN_FLIGHT_HERE.with(Cell::get);
+    loop {
+        let in_flight = CallGuard::in_flight() - here;
+        if in_flight == 0 {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(HookError::DrainTimedOut { in_flight });
+        }
+        thread::sleep(DRAIN_POLL_INTERVAL);
+    }
+}
@@ -0,0 +1,246 @@
+//! Win32 and Direct3D 9 types used by the grabber.
+//!
+//! On Windows everything is re-exported from `winapi`. Everywhere else the same names are
+//! defined as ABI-compatible stand-ins, so the grabbing logic builds and runs against a
+//! non-Windows [`Backend`](crate::backend::Backend).
+
+#[cfg(windows)]
+pub use winapi::shared::d3d9::{
+    IDirect3D9, IDirect3D9Ex, IDirect3DBaseTexture9, IDirect3DDevice9, IDirect3DDevice9Ex,
+    IDirect3DStateBlock9, IDirect3DSwapChain9, IDirect3DTexture9, D3DADAPTER_DEFAULT,
+    D3DCREATE_FPU_PRESERVE, D3DCREATE_HARDWARE_VERTEXPROCESSING, D3DCREATE_MIXED_VERTEXPROCESSING,
+    D3DCREATE_MULTITHREADED, D3DCREATE_SOFTWARE_VERTEXPROCESSING, D3D_SDK_VERSION,
+};
+#[cfg(windows)]
+pub use winapi::shared::d3d9caps::{
+    D3DPRESENT_INTERVAL_DEFAULT, D3DPRESENT_INTERVAL_FOUR, D3DPRESENT_INTERVAL_IMMEDIATE,
+    D3DPRESENT_INTERVAL_ONE, D3DPRESENT_INTERVAL_THREE, D3DPRESENT_INTERVAL_TWO,
+};
+#[cfg(windows)]
+pub use winapi::shared::d3d9types::{
+    D3DBLEND, D3DBLENDOP, D3DBLENDOP_ADD, D3DBLEND_INVSRCALPHA, D3DBLEND_SRCALPHA, D3DCOLOR,
+    D3DCULL, D3DCULL_NONE, D3DDEVTYPE, D3DDEVTYPE_HAL, D3DDEVTYPE_NULLREF, D3DDEVTYPE_REF,
+    D3DDEVTYPE_SW, D3DFILLMODE, D3DFILL_SOLID, D3DFMT_A8R8G8B8, D3DFMT_D16, D3DFMT_D24S8,
+    D3DFMT_D24X8, D3DFMT_R5G6B5, D3DFMT_UNKNOWN, D3DFMT_X8R8G8B8, D3DFORMAT, D3DFVF_DIFFUSE,
+    D3DFVF_TEX1, D3DFVF_XYZRHW, D3DMULTISAMPLE_4_SAMPLES, D3DMULTISAMPLE_NONE,
+    D3DMULTISAMPLE_NONMASKABLE, D3DMULTISAMPLE_TYPE, D3DPRESENTFLAG_DISCARD_DEPTHSTENCIL,
+    D3DPRESENTFLAG_LOCKABLE_BACKBUFFER, D3DPRESENT_PARAMETERS, D3DPRIMITIVETYPE,
+    D3DPT_TRIANGLELIST, D3DRENDERSTATETYPE, D3DRS_ALPHABLENDENABLE, D3DRS_ALPHATESTENABLE,
+    D3DRS_BLENDOP, D3DRS_COLORWRITEENABLE, D3DRS_CULLMODE, D3DRS_DESTBLEND, D3DRS_FILLMODE,
+    D3DRS_FOGENABLE, D3DRS_SCISSORTESTENABLE, D3DRS_SEPARATEALPHABLENDENABLE, D3DRS_SRCBLEND,
+    D3DRS_SRGBWRITEENABLE, D3DRS_STENCILENABLE, D3DRS_ZENABLE, D3DRS_ZWRITEENABLE,
+    D3DSAMPLERSTATETYPE, D3DSAMP_ADDRESSU, D3DSAMP_ADDRESSV, D3DSAMP_MAGFILTER, D3DSAMP_MINFILTER,
+    D3DSBT_ALL, D3DSTATEBLOCKTYPE, D3DSWAPEFFECT, D3DSWAPEFFECT_COPY, D3DSWAPEFFECT_DISCARD,
+    D3DSWAPEFFECT_FLIP, D3DTADDRESS_CLAMP, D3DTA_DIFFUSE, D3DTA_TEXTURE, D3DTEXF_LINEAR,
+    D3DTEXTUREADDRESS, D3DTEXTUREFILTERTYPE, D3DTEXTUREOP, D3DTEXTURESTAGESTATETYPE,
+    D3DTOP_DISABLE, D3DTOP_MODULATE, D3DTOP_SELECTARG2, D3DTSS_ALPHAARG1, D3DTSS_ALPHAARG2,
+    D3DTSS_ALPHAOP, D3DTSS_COLORARG1, D3DTSS_COLORARG2, D3DTSS_COLOROP,
+};
+#[cfg(windows)]
+pub use winapi::shared::guiddef::GUID;
+#[cfg(windows)]
+pub use winapi::shared::minwindef::{BOOL, DWORD, FALSE, HINSTANCE, INT, TRUE, UINT};
+#[cfg(windows)]
+pub use winapi::shared::windef::{HWND, RECT};
+#[cfg(windows)]
+pub use winapi::shared::winerror::HRESULT;
+#[cfg(windows)]
+pub use winapi::um::wingdi::RGNDATA;
+
+#[cfg(not(windows))]
+pub use self::portable::*;
+
+#[cfg(not(windows))]
+#[allow(non_camel_case_types, non_snake_case)]
+mod portable {
+    use std::ffi::c_void;
+
+    pub type BOOL = i32;
+    pub type DWORD = u32;
+    pub type INT = i32;
+    pub type UINT = u32;
+    pub type HRESULT = i32;
+
+    pub const FALSE: BOOL = 0;
+    pub const TRUE: BOOL = 1;
+
+    #[repr(C)]
+    #[derive(Debug, Copy, Clone)]
+    pub struct GUID {
+        pub Data1: u32,
+        pub Data2: u16,
+        pub Data3: u16,
+        pub Data4: [u8; 8],
+    }
+
+    pub enum HWND__ {}
+    pub type HWND = *mut HWND__;
+
+    pub enum HINSTANCE__ {}
+    pub type HINSTANCE = *mut HINSTANCE__;
+
+    #[repr(C)]
+    #[derive(Debug, Copy, Clone)]
+    pub struct RECT {
+        pub left: i32,
+        pub top: i32,
+        pub right: i32,
+        pub bottom: i32,
+    }
+
+    /// Only ever handled by pointer.
+    pub enum RGNDATA {}
+
+    pub type D3DDEVTYPE = u32;
+    pub type D3DFORMAT = u32;
+    pub type D3DMULTISAMPLE_TYPE = u32;
+    pub type D3DPRIMITIVETYPE = u32;
+    pub type D3DSWAPEFFECT = u32;
+    pub type D3DCOLOR = DWORD;
+    pub type D3DBLEND = u32;
+    pub type D3DBLENDOP = u32;
+    pub type D3DCULL = u32;
+    pub type D3DFILLMODE = u32;
+    pub type D3DRENDERSTATETYPE = u32;
+    pub type D3DSAMPLERSTATETYPE = u32;
+    pub type D3DSTATEBLOCKTYPE = u32;
+    pub type D3DTEXTUREFILTERTYPE = u32;
+    pub type D3DTEXTUREADDRESS = u32;
+    pub type D3DTEXTUREOP = u32;
+    pub type D3DTEXTURESTAGESTATETYPE = u32;
+
+    pub const D3D_SDK_VERSION: DWORD = 32;
+    pub const D3DADAPTER_DEFAULT: DWORD = 0;
+    pub const D3DCREATE_FPU_PRESERVE: DWORD = 0x2;
+    pub const D3DCREATE_MULTITHREADED: DWORD = 0x4;
+    pub const D3DCREATE_SOFTWARE_VERTEXPROCESSING: DWORD = 0x20;
+    pub const D3DCREATE_HARDWARE_VERTEXPROCESSING: DWORD = 0x40;
+    pub const D3DCREATE_MIXED_VERTEXPROCESSING: DWORD = 0x80;
+    pub const D3DDEVTYPE_HAL: D3DDEVTYPE = 1;
+    pub const D3DDEVTYPE_REF: D3DDEVTYPE = 2;
+    pub const D3DDEVTYPE_SW: D3DDEVTYPE = 3;
+    pub const D3DDEVTYPE_NULLREF: D3DDEVTYPE = 4;
+    pub const D3DFMT_UNKNOWN: D3DFORMAT = 0;
+    pub const D3DFMT_A8R8G8B8: D3DFORMAT = 21;
+    pub const D3DFMT_X8R8G8B8: D3DFORMAT = 22;
+    pub const D3DFMT_R5G6B5: D3DFORMAT = 23;
+    pub const D3DFMT_D24S8: D3DFORMAT = 75;
+    pub const D3DFMT_D24X8: D3DFORMAT = 77;
+    pub const D3DFMT_D16: D3DFORMAT = 80;
+    pub const D3DMULTISAMPLE_NONE: D3DMULTISAMPLE_TYPE = 0;
+    pub const D3DMULTISAMPLE_NONMASKABLE: D3DMULTISAMPLE_TYPE = 1;
+    pub const D3DMULTISAMPLE_4_SAMPLES: D3DMULTISAMPLE_TYPE = 4;
+    pub const D3DPRESENTFLAG_LOCKABLE_BACKBUFFER: DWORD = 0x1;
+    pub const D3DPRESENTFLAG_DISCARD_DEPTHSTENCIL: DWORD = 0x2;
+    pub const D3DPRESENT_INTERVAL_DEFAULT: DWORD = 0x0;
+    pub const D3DPRESENT_INTERVAL_ONE: DWORD = 0x1;
+    pub const D3DPRESENT_INTERVAL_TWO: DWORD = 0x2;
+    pub const D3DPRESENT_INTERVAL_THREE: DWORD = 0x4;
+    pub const D3DPRESENT_INTERVAL_FOUR: DWORD = 0x8;
+    pub const D3DPRESENT_INTERVAL_IMMEDIATE: DWORD = 0x80000000;
+    pub const D3DSWAPEFFECT_DISCARD: D3DSWAPEFFECT = 1;
+    pub const D3DSWAPEFFECT_FLIP: D3DSWAPEFFECT = 2;
+    pub const D3DSWAPEFFECT_COPY: D3DSWAPEFFECT = 3;
+    pub const D3DPT_TRIANGLELIST: D3DPRIMITIVETYPE = 4;
+    pub const D3DFVF_XYZRHW: DWORD = 0x004;
+    pub const D3DFVF_DIFFUSE: DWORD = 0x040;
+    pub const D3DFVF_TEX1: DWORD = 0x100;
+    pub const D3DSBT_ALL: D3DSTATEBLOCKTYPE = 1;
+    pub const D3DRS_ZENABLE: D3DRENDERSTATETYPE = 7;
+    pub const D3DRS_FILLMODE: D3DRENDERSTATETYPE = 8;
+    pub const D3DRS_ZWRITEENABLE: D3DRENDERSTATETYPE = 14;
+    pub const D3DRS_ALPHATESTENABLE: D3DRENDERSTATETYPE = 15;
+    pub const D3DRS_SRCBLEND: D3DRENDERSTATETYPE = 19;
+    pub const D3DRS_DESTBLEND: D3DRENDERSTATETYPE = 20;
+    pub const D3DRS_CULLMODE: D3DRENDERSTATETYPE = 22;
+    pub const D3DRS_ALPHABLENDENABLE: D3DRENDERSTATETYPE = 27;
+    pub const D3DRS_FOGENABLE: D3DRENDERSTATETYPE = 28;
+    pub const D3DRS_STENCILENABLE: D3DRENDERSTATETYPE = 52;
+    pub const D3DRS_COLORWRITEENABLE: D3DRENDERSTATETYPE = 168;
+    pub const D3DRS_BLENDOP: D3DRENDERSTATETYPE = 171;
+    pub const D3DRS_SCISSORTESTENABLE: D3DRENDERSTATETYPE = 174;
+    pub const D3DRS_SRGBWRITEENABLE: D3DRENDERSTATETYPE = 194;
+    pub const D3DRS_SEPARATEALPHABLENDENABLE: D3DRENDERSTATETYPE = 206;
+    pub const D3DFILL_SOLID: D3DFILLMODE = 3;
+    pub const D3DBLENDOP_ADD: D3DBLENDOP = 1;
+    pub const D3DBLEND_SRCALPHA: D3DBLEND = 5;
+    pub const D3DBLEND_INVSRCALPHA: D3DBLEND = 6;
+    pub const D3DCULL_NONE: D3DCULL = 1;
+    pub const D3DTSS_COLOROP: D3DTEXTURESTAGESTATETYPE = 1;
+    pub const D3DTSS_COLORARG1: D3DTEXTURESTAGESTATETYPE = 2;
+    pub const D3DTSS_COLORARG2: D3DTEXTURESTAGESTATETYPE = 3;
+    pub const D3DTSS_ALPHAOP: D3DTEXTURESTAGESTATETYPE = 4;
+    pub const D3DTSS_ALPHAARG1: D3DTEXTURESTAGESTATETYPE = 5;
+    pub const D3DTSS_ALPHAARG2: D3DTEXTURESTAGESTATETYPE = 6;
+    pub const D3DTOP_DISABLE: D3DTEXTUREOP = 1;
+    pub const D3DTOP_SELECTARG2: D3DTEXTUREOP = 3;
+    pub const D3DTOP_MODULATE: D3DTEXTUREOP = 4;
+    pub const D3DTA_DIFFUSE: DWORD = 0x0;
+    pub const D3DTA_TEXTURE: DWORD = 0x2;
+    pub const D3DSAMP_ADDRESSU: D3DSAMPLERSTATETYPE = 1;
+    pub const D3DSAMP_ADDRESSV: D3DSAMPLERSTATETYPE = 2;
+    pub const D3DSAMP_MAGFILTER: D3DSAMPLERSTATETYPE = 5;
+    pub const D3DSAMP_MINFILTER: D3DSAMPLERSTATETYPE = 6;
+    pub const D3DTEXF_LINEAR: D3DTEXTUREFILTERTYPE = 2;
+    pub const D3DTADDRESS_CLAMP: D3DTEXTUREADDRESS = 3;
+
+    #[repr(C)]
+    #[derive(Copy, Clone)]
+    pub struct D3DPRESENT_PARAMETERS {
+        pub BackBufferWidth: UINT,
+        pub BackBufferHeight: UINT,
+        pub BackBufferFormat: D3DFORMAT,
+        pub BackBufferCount: UINT,
+        pub MultiSampleType: D3DMULTISAMPLE_TYPE,
+        pub MultiSampleQuality: DWORD,
+        pub SwapEffect: D3DSWAPEFFECT,
+        pub hDeviceWindow: HWND,
+        pub Windowed: BOOL,
+        pub EnableAutoDepthStencil: BOOL,
+        pub AutoDepthStencilFormat: D3DFORMAT,
+        pub Flags: DWORD,
+        pub FullScreen_RefreshRateInHz: UINT,
+        pub PresentationInterval: UINT,
+    }
+
+    /// A COM object: a pointer to its vtable followed by implementation data.
+    #[repr(C)]
+    pub struct IDirect3D9 {
+        pub lpVtbl: *const c_void,
+    }
+
+    #[repr(C)]
+    pub struct IDirect3DDevice9 {
+        pub lpVtbl: *const c_void,
+    }
+
+    #[repr(C)]
+    pub struct IDirect3D9Ex {
+        pub lpVtbl: *const c_void,
+    }
+
+    #[repr(C)]
+    pub struct IDirect3DDevice9Ex {
+        pub lpVtbl: *const c_void,
+    }
+
+    #[repr(C)]
+    pub struct IDirect3DSwapChain9 {
+        pub lpVtbl: *const c_void,
+    }
+
+    #[repr(C)]
+    pub struct IDirect3DBaseTexture9 {
+        pub lpVtbl: *const c_void,
+    }
+
+    #[repr(C)]
+    pub struct IDirect3DTexture9 {
+        pub lpVtbl: *const c_void,
+    }
+
+    #[repr(C)]
+    pub struct IDirect3DStateBlock9 {
+        pub lpVtbl: *const c_void,
+    }
+}
@@ -0,0 +1,51 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::Clock;
+
+#[derive(Default)]
+struct FakeClockState {
+    now: Duration,
+    sleeps: Vec<Duration>,
+}
+
+/// A [`Clock`] whose time only moves when something sleeps on it or it is advanced by hand.
+///
+/// Clones share the same time, so one can be given to a
+/// [`WaitPolicy`](crate::WaitPolicy) while the test keeps another.
+#[derive(Clone, Default)]
+pub struct FakeClock {
+    state: Arc<Mutex<FakeClockState>>,
+}
+
+impl FakeClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move time forward by `duration` without recording a sleep.
+    pub fn advance(&self, duration: Duration) {
+        self.state().now += duration;
+    }
+
+    /// Every duration slept so far, oldest first.
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.state().sleeps.clone()
+    }
+
+    fn state(&self) -> MutexGuard<'_, FakeClockState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> Duration {
+        self.state().now
+    }
+
+    fn sleep(&self, duration: Duration) {
+        let mut state = self.state();
+        state.now += duration;
+        state.sleeps.push(duration);
+    }
+}
@@ -0,0 +1,954 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::ffi::c_void;
+use std::mem;
+use std::ops::Deref;
+use std::ptr;
+
+use crate::com::{
+    self, DEVICE_EX_VTABLE_LEN, DEVICE_VTABLE_LEN, DIRECT3D9EX_VTABLE_LEN, DIRECT3D9_VTABLE_LEN,
+    STATE_BLOCK_VTABLE_LEN, SWAP_CHAIN_VTABLE_LEN,
+};
+use crate::sys::*;
+use crate::vtable::{DeviceMethod, SwapChainMethod};
+use crate::D3dResult;
+
+const QUERY_INTERFACE: usize = com::QUERY_INTERFACE_SLOT;
+const ADD_REF: usize = com::ADD_REF_SLOT;
+const RELEASE: usize = com::RELEASE_SLOT;
+const CREATE_DEVICE: usize = 16;
+const CREATE_DEVICE_EX: usize = 20;
+const GET_SWAP_CHAIN: usize = DeviceMethod::GetSwapChain.index();
+const CREATE_STATE_BLOCK: usize = DeviceMethod::CreateStateBlock.index();
+const GET_PRESENT_PARAMETERS: usize = SwapChainMethod::GetPresentParameters.index();
+const GET_DEVICE: usize = SwapChainMethod::GetDevice.index();
+
+const E_NOINTERFACE: HRESULT = D3dResult::NoInterface.code();
+const D3DERR_INVALIDCALL: HRESULT = D3dResult::InvalidCall.code();
+
+/// One call made through a mock's vtable.
+///
+/// `args` holds the arguments after `this`, widened to `usize`. A `float` argument, as taken
+/// by `Clear` or `SetNPatchMode`, is not passed where an integer would be, so what is
+/// recorded for it is meaningless.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockCall {
+    pub slot: usize,
+    pub args: Vec<usize>,
+}
+
+type Handler = Box<dyn FnMut(&MockCall) -> HRESULT>;
+
+struct MockState {
+    ref_count: Cell<u32>,
+    calls: RefCell<Vec<MockCall>>,
+    handlers: RefCell<HashMap<usize, Handler>>,
+}
+
+/// The memory a COM pointer to a mock points at: the vtable pointer must come first.
+#[repr(C)]
+struct RawMock {
+    vtbl: *const usize,
+    state: MockState,
+    vtable: Box<[usize]>,
+    extra: MockExtra,
+}
+
+enum MockExtra {
+    Direct3D9(Direct3D9State),
+    Device(DeviceState),
+    SwapChain(SwapChainState),
+    StateBlock,
+}
+
+struct Direct3D9State {
+    device: Cell<*mut IDirect3DDevice9>,
+    create_device_results: RefCell<VecDeque<HRESULT>>,
+    create_device_calls: RefCell<Vec<CreateDeviceCall>>,
+}
+
+struct DeviceState {
+    swap_chain: Cell<*mut IDirect3DSwapChain9>,
+    state_block: Cell<*mut IDirect3DStateBlock9>,
+}
+
+struct SwapChainState {
+    device_window: Cell<HWND>,
+    device: Cell<*mut IDirect3DDevice9>,
+}
+
+/// A COM object whose vtable slots dispatch to Rust closures and record every call.
+///
+/// The object starts with a reference count of 1. `Release` never frees it; the memory is
+/// owned by this value, so tests can inspect [`MockObject::ref_count`] after the code under
+/// test is done with the pointer.
+pub struct MockObject {
+    raw: Box<RawMock>,
+}
+
+impl MockObject {
+    fn new(mut vtable: Box<[usize]>, extra: MockExtra) -> Self {
+        let mut raw = Box::new(RawMock {
+            vtbl: ptr::null(),
+            state: MockState {
+                ref_count: Cell::new(1),
+                calls: RefCell::new(Vec::new()),
+                handlers: RefCell::new(HashMap::new()),
+            },
+            vtable: Box::new([]),
+            extra,
+        });
+        raw.vtbl = vtable.as_mut_ptr();
+        raw.vtable = vtable;
+        MockObject { raw }
+    }
+
+    /// The COM pointer to hand to code under test.
+    pub fn as_raw(&self) -> *mut c_void {
+        &*self.raw as *const RawMock as *mut c_void
+    }
+
+    /// The object's vtable. It lives in ordinary heap memory and may be written to.
+    pub fn vtable(&self) -> *mut usize {
+        self.raw.vtbl as *mut usize
+    }
+
+    /// Number of slots in the vtable.
+    pub fn slot_count(&self) -> usize {
+        self.raw.vtable.len()
+    }
+
+    /// The current COM reference count.
+    pub fn ref_count(&self) -> u32 {
+        self.raw.state.ref_count.get()
+    }
+
+    /// Every call made through the vtable so far, oldest first.
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.raw.state.calls.borrow().clone()
+    }
+
+    /// Number of calls made through vtable slot `slot`.
+    pub fn call_count(&self, slot: usize) -> usize {
+        self.raw
+            .state
+            .calls
+            .borrow()
+            .iter()
+            .filter(|call| call.slot == slot)
+            .count()
+    }
+
+    /// Run `handler` for every call through `slot`, replacing the slot's default behaviour.
+    ///
+    /// The handler's return value becomes the method's return value. `AddRef` and `Release`
+    /// always maintain the reference count and ignore the handler's result.
+    pub fn on<F>(&self, slot: usize, handler: F)
+    where
+        F: FnMut(&MockCall) -> HRESULT + 'static,
+    {
+        self.raw
+            .state
+            .handlers
+            .borrow_mut()
+            .insert(slot, Box::new(handler));
+    }
+}
+
+/// A mock `IDirect3D9` whose `CreateDevice` returns scripted HRESULTs.
+pub struct MockDirect3D9(MockObject);
+
+impl MockDirect3D9 {
+    pub fn new() -> Self {
+        Self::with_vtable_len(DIRECT3D9_VTABLE_LEN)
+    }
+
+    /// A mock that is also an `IDirect3D9Ex`, whose `CreateDeviceEx` behaves like `CreateDevice`.
+    pub fn new_ex() -> Self {
+        Self::with_vtable_len(DIRECT3D9EX_VTABLE_LEN)
+    }
+
+    fn with_vtable_len(len: usize) -> Self {
+        let mut vtable = untyped_vtable(&direct3d9_slots()[..len]);
+        vtable[CREATE_DEVICE] = create_device as *const () as usize;
+        if len > CREATE_DEVICE_EX {
+            vtable[CREATE_DEVICE_EX] = create_device_ex as *const () as usize;
+        }
+        MockDirect3D9(MockObject::new(
+            vtable,
+            MockExtra::Direct3D9(Direct3D9State {
+                device: Cell::new(ptr::null_mut()),
+                create_device_results: RefCell::new(VecDeque::new()),
+                create_device_calls: RefCell::new(Vec::new()),
+            }),
+        ))
+    }
+
+    pub fn as_ptr(&self) -> *mut IDirect3D9 {
+        self.as_raw() as *mut IDirect3D9
+    }
+
+    pub fn as_ex_ptr(&self) -> *mut IDirect3D9Ex {
+        self.as_raw() as *mut IDirect3D9Ex
+    }
+
+    /// The device written out, and `AddRef`'d, by every successful `CreateDevice` or
+    /// `CreateDeviceEx`.
+    pub fn with_device(self, device: &MockDevice) -> Self {
+        self.state().device.set(device.as_ptr());
+        self
+    }
+
+    /// HRESULTs returned by successive `CreateDevice` or `CreateDeviceEx` calls. Once they run out, calls succeed.
+    pub fn with_create_device_results<I: IntoIterator<Item = HRESULT>>(self, results: I) -> Self {
+        self.state()
+            .create_device_results
+            .borrow_mut()
+            .extend(results);
+        self
+    }
+
+    /// Every `CreateDevice` or `CreateDeviceEx` call made so far, oldest first.
+    pub fn create_device_calls(&self) -> Vec<CreateDeviceCall> {
+        self.state().create_device_calls.borrow().clone()
+    }
+
+    fn state(&self) -> &Direct3D9State {
+        match &self.0.raw.extra {
+            MockExtra::Direct3D9(state) => state,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Default for MockDirect3D9 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for MockDirect3D9 {
+    type Target = MockObject;
+
+    fn deref(&self) -> &MockObject {
+        &self.0
+    }
+}
+
+/// A mock `IDirect3DDevice9`. Every slot succeeds unless given a handler, except
+/// `GetSwapChain` and `CreateStateBlock`, which fail until given a swap chain or state block,
+/// and `QueryInterface`, which only answers `IID_IDirect3DDevice9Ex` on a mock made with
+/// [`MockDevice::new_ex`].
+pub struct MockDevice(MockObject);
+
+impl MockDevice {
+    pub fn new() -> Self {
+        Self::with_vtable_len(DEVICE_VTABLE_LEN)
+    }
+
+    /// A mock that is also an `IDirect3DDevice9Ex`.
+    pub fn new_ex() -> Self {
+        Self::with_vtable_len(DEVICE_EX_VTABLE_LEN)
+    }
+
+    fn with_vtable_len(len: usize) -> Self {
+        let mut vtable = untyped_vtable(&device_slots()[..len]);
+        vtable[GET_SWAP_CHAIN] = get_swap_chain as *const () as usize;
+        vtable[CREATE_STATE_BLOCK] = create_state_block as *const () as usize;
+        MockDevice(MockObject::new(
+            vtable,
+            MockExtra::Device(DeviceState {
+                swap_chain: Cell::new(ptr::null_mut()),
+                state_block: Cell::new(ptr::null_mut()),
+            }),
+        ))
+    }
+
+    /// The swap chain written out, and `AddRef`'d, by every `GetSwapChain` call.
+    pub fn with_swap_chain(self, swap_chain: &MockSwapChain) -> Self {
+        if let MockExtra::Device(state) = &self.0.raw.extra {
+            state.swap_chain.set(swap_chain.as_ptr());
+        }
+        self
+    }
+
+    /// The state block written out, and `AddRef`'d, by every `CreateStateBlock` call.
+    pub fn with_state_block(self, state_block: &MockStateBlock) -> Self {
+        if let MockExtra::Device(state) = &self.0.raw.extra {
+            state.state_block.set(state_block.as_ptr());
+        }
+        self
+    }
+
+    /// Call through the vtable of `other`, as every real device of a process shares one, so
+    /// hooking the vtable of one mock hooks this one too. `other` must outlive this mock.
+    pub fn with_shared_vtable(mut self, other: &MockDevice) -> Self {
+        self.0.raw.vtbl = other.0.raw.vtbl;
+        self
+    }
+
+    pub fn as_ptr(&self) -> *mut IDirect3DDevice9 {
+        self.as_raw() as *mut IDirect3DDevice9
+    }
+
+    pub fn as_ex_ptr(&self) -> *mut IDirect3DDevice9Ex {
+        self.as_raw() as *mut IDirect3DDevice9Ex
+    }
+}
+
+impl Default for MockDevice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for MockDevice {
+    type Target = MockObject;
+
+    fn deref(&self) -> &MockObject {
+        &self.0
+    }
+}
+
+/// A mock `IDirect3DSwapChain9`. Every slot succeeds unless given a handler.
+/// `GetPresentParameters` writes out zeroed parameters apart from the device window, and
+/// `GetDevice` fails until given a device.
+pub struct MockSwapChain(MockObject);
+
+impl MockSwapChain {
+    pub fn new() -> Self {
+        let mut vtable = untyped_vtable(&swap_chain_slots());
+        vtable[GET_PRESENT_PARAMETERS] = get_present_parameters as *const () as usize;
+        vtable[GET_DEVICE] = get_device as *const () as usize;
+        MockSwapChain(MockObject::new(
+            vtable,
+            MockExtra::SwapChain(SwapChainState {
+                device_window: Cell::new(ptr::null_mut()),
+                device: Cell::new(ptr::null_mut()),
+            }),
+        ))
+    }
+
+    /// The `hDeviceWindow` written out by `GetPresentParameters`.
+    pub fn with_device_window(self, window: HWND) -> Self {
+        self.set_device_window(window);
+        self
+    }
+
+    /// Change the `hDeviceWindow` written out by `GetPresentParameters`, as a `Reset` can.
+    pub fn set_device_window(&self, window: HWND) {
+        if let MockExtra::SwapChain(state) = &self.0.raw.extra {
+            state.device_window.set(window);
+        }
+    }
+
+    /// The device written out, and `AddRef`'d, by every `GetDevice` call.
+    pub fn with_device(self, device: &MockDevice) -> Self {
+        if let MockExtra::SwapChain(state) = &self.0.raw.extra {
+            state.device.set(device.as_ptr());
+        }
+        self
+    }
+
+    /// Call through the vtable of `other`, as every real swap chain of a process shares one.
+    /// `other` must outlive this mock.
+    pub fn with_shared_vtable(mut self, other: &MockSwapChain) -> Self {
+        self.0.raw.vtbl = other.0.raw.vtbl;
+        self
+    }
+
+    pub fn as_ptr(&self) -> *mut IDirect3DSwapChain9 {
+        self.as_raw() as *mut IDirect3DSwapChain9
+    }
+}
+
+impl Default for MockSwapChain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for MockSwapChain {
+    type Target = MockObject;
+
+    fn deref(&self) -> &MockObject {
+        &self.0
+    }
+}
+
+/// A mock `IDirect3DStateBlock9`. Every slot succeeds unless given a handler.
+pub struct MockStateBlock(MockObject);
+
+impl MockStateBlock {
+    pub fn new() -> Self {
+        MockStateBlock(MockObject::new(
+            untyped_vtable(&state_block_slots()),
+            MockExtra::StateBlock,
+        ))
+    }
+
+    pub fn as_ptr(&self) -> *mut IDirect3DStateBlock9 {
+        self.as_raw() as *mut IDirect3DStateBlock9
+    }
+}
+
+impl Default for MockStateBlock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for MockStateBlock {
+    type Target = MockObject;
+
+    fn deref(&self) -> &MockObject {
+        &self.0
+    }
+}
+
+/// The arguments of one `IDirect3D9::CreateDevice` call, with the present parameters copied.
+#[derive(Clone, Copy)]
+pub struct CreateDeviceCall {
+    pub adapter: UINT,
+    pub device_type: D3DDEVTYPE,
+    pub focus_window: HWND,
+    pub behavior_flags: DWORD,
+    pub present_params: D3DPRESENT_PARAMETERS,
+}
+
+unsafe fn dispatch(this: *mut c_void, slot: usize, args: Vec<usize>, default: HRESULT) -> HRESULT {
+    let state = &(*(this as *const RawMock)).state;
+    let call = MockCall { slot, args };
+    state.calls.borrow_mut().push(call.clone());
+
+    // The handler is taken out while it runs so it can call back into the same mock.
+    let handler = state.handlers.borrow_mut().remove(&slot);
+    match handler {
+        None => default,
+        Some(mut handler) => {
+            let result = handler(&call);
+            state.handlers.borrow_mut().entry(slot).or_insert(handler);
+            result
+        }
+    }
+}
+
+unsafe extern "system" fn query_interface(
+    this: *mut c_void,
+    riid: *const GUID,
+    object: *mut *mut c_void,
+) -> HRESULT {
+    if !object.is_null() {
+        *object = ptr::null_mut();
+    }
+    let raw = &*(this as *const RawMock);
+    let is_device_ex = matches!(raw.extra, MockExtra::Device(_))
+        && raw.vtable.len() == DEVICE_EX_VTABLE_LEN
+        && !riid.is_null()
+        && com::guid_eq(&*riid, &com::IID_IDIRECT3DDEVICE9EX);
+    let default = if is_device_ex { 0 } else { E_NOINTERFACE };
+    let result = dispatch(
+        this,
+        QUERY_INTERFACE,
+        vec![riid as usize, object as usize],
+        default,
+    );
+
+    if result >= 0 && is_device_ex && !object.is_null() {
+        add_ref(this);
+        *object = this;
+    }
+    result
+}
+
+unsafe extern "system" fn add_ref(this: *mut c_void) -> u32 {
+    dispatch(this, ADD_REF, Vec::new(), 0);
+    let ref_count = &(*(this as *const RawMock)).state.ref_count;
+    ref_count.set(ref_count.get() + 1);
+    ref_count.get()
+}
+
+unsafe extern "system" fn release(this: *mut c_void) -> u32 {
+    dispatch(this, RELEASE, Vec::new(), 0);
+    let ref_count = &(*(this as *const RawMock)).state.ref_count;
+    ref_count.set(ref_count.get().saturating_sub(1));
+    ref_count.get()
+}
+
+unsafe extern "system" fn create_device(
+    this: *mut c_void,
+    adapter: UINT,
+    device_type: D3DDEVTYPE,
+    focus_window: HWND,
+    behavior_flags: DWORD,
+    present_params: *mut D3DPRESENT_PARAMETERS,
+    returned_device: *mut *mut IDirect3DDevice9,
+) -> HRESULT {
+    record_create_device(
+        this,
+        CREATE_DEVICE,
+        adapter,
+        device_type,
+        focus_window,
+        behavior_flags,
+        present_params,
+        ptr::null_mut(),
+        returned_device as *mut *mut c_void,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe extern "system" fn create_device_ex(
+    this: *mut c_void,
+    adapter: UINT,
+    device_type: D3DDEVTYPE,
+    focus_window: HWND,
+    behavior_flags: DWORD,
+    present_params: *mut D3DPRESENT_PARAMETERS,
+    fullscreen_display_mode: *mut c_void,
+    returned_device: *mut *mut IDirect3DDevice9Ex,
+) -> HRESULT {
+    record_create_device(
+        this,
+        CREATE_DEVICE_EX,
+        adapter,
+        device_type,
+        focus_window,
+        behavior_flags,
+        present_params,
+        fullscreen_display_mode,
+        returned_device as *mut *mut c_void,
+    )
+}
+
+/// Shared by `CreateDevice` and `CreateDeviceEx`, which differ only by the display mode.
+#[allow(clippy::too_many_arguments)]
+unsafe fn record_create_device(
+    this: *mut c_void,
+    slot: usize,
+    adapter: UINT,
+    device_type: D3DDEVTYPE,
+    focus_window: HWND,
+    behavior_flags: DWORD,
+    present_params: *mut D3DPRESENT_PARAMETERS,
+    fullscreen_display_mode: *mut c_void,
+    returned_device: *mut *mut c_void,
+) -> HRESULT {
+    let state = match &(*(this as *const RawMock)).extra {
+        MockExtra::Direct3D9(state) => state,
+        _ => unreachable!(),
+    };
+    state
+        .create_device_calls
+        .borrow_mut()
+        .push(CreateDeviceCall {
+            adapter,
+            device_type,
+            focus_window,
+            behavior_flags,
+            present_params: *present_params,
+        });
+
+    let scripted = state
+        .create_device_results
+        .borrow_mut()
+        .pop_front()
+        .unwrap_or(0);
+    let mut args = vec![
+        adapter as usize,
+        device_type as usize,
+        focus_window as usize,
+        behavior_flags as usize,
+        present_params as usize,
+    ];
+    if slot == CREATE_DEVICE_EX {
+        args.push(fullscreen_display_mode as usize);
+    }
+    args.push(returned_device as usize);
+    let result = dispatch(this, slot, args, scripted);
+
+    let device = state.device.get();
+    if result >= 0 && !device.is_null() {
+        add_ref(device as *mut c_void);
+        *returned_device = device as *mut c_void;
+    } else {
+        *returned_device = ptr::null_mut();
+    }
+    result
+}
+
+unsafe extern "system" fn get_swap_chain(
+    this: *mut c_void,
+    index: UINT,
+    returned_swap_chain: *mut *mut IDirect3DSwapChain9,
+) -> HRESULT {
+    let swap_chain = match &(*(this as *const RawMock)).extra {
+        MockExtra::Device(state) => state.swap_chain.get(),
+        _ => unreachable!(),
+    };
+    let default = if swap_chain.is_null() {
+        D3DERR_INVALIDCALL
+    } else {
+        0
+    };
+    let result = dispatch(
+        this,
+        GET_SWAP_CHAIN,
+        vec![index as usize, returned_swap_chain as usize],
+        default,
+    );
+
+    if result >= 0 && !swap_chain.is_null() {
+        add_ref(swap_chain as *mut c_void);
+        *returned_swap_chain = swap_chain;
+    } else {
+        *returned_swap_chain = ptr::null_mut();
+    }
+    result
+}
+
+unsafe extern "system" fn create_state_block(
+    this: *mut c_void,
+    state_block_type: D3DSTATEBLOCKTYPE,
+    returned_state_block: *mut *mut IDirect3DStateBlock9,
+) -> HRESULT {
+    let state_block = match &(*(this as *const RawMock)).extra {
+        MockExtra::Device(state) => state.state_block.get(),
+        _ => unreachable!(),
+    };
+    let default = if state_block.is_null() {
+        D3DERR_INVALIDCALL
+    } else {
+        0
+    };
+    let result = dispatch(
+        this,
+        CREATE_STATE_BLOCK,
+        vec![state_block_type as usize, returned_state_block as usize],
+        default,
+    );
+
+    if result >= 0 && !state_block.is_null() {
+        add_ref(state_block as *mut c_void);
+        *returned_state_block = state_block;
+    } else {
+        *returned_state_block = ptr::null_mut();
+    }
+    result
+}
+
+unsafe extern "system" fn get_present_parameters(
+    this: *mut c_void,
+    present_params: *mut D3DPRESENT_PARAMETERS,
+) -> HRESULT {
+    let device_window = match &(*(this as *const RawMock)).extra {
+        MockExtra::SwapChain(state) => state.device_window.get(),
+        _ => unreachable!(),
+    };
+    let result = dispatch(
+        this,
+        GET_PRESENT_PARAMETERS,
+        vec![present_params as usize],
+        0,
+    );
+
+    if result >= 0 {
+        *present_params = D3DPRESENT_PARAMETERS {
+            hDeviceWindow: device_window,
+            ..mem::zeroed()
+        };
+    }
+    result
+}
+
+unsafe extern "system" fn get_device(
+    this: *mut c_void,
+    returned_device: *mut *mut IDirect3DDevice9,
+) -> HRESULT {
+    let device = match &(*(this as *const RawMock)).extra {
+        MockExtra::SwapChain(state) => state.device.get(),
+        _ => unreachable!(),
+    };
+    let default = if device.is_null() {
+        D3DERR_INVALIDCALL
+    } else {
+        0
+    };
+    let result = dispatch(this, GET_DEVICE, vec![returned_device as usize], default);
+
+    if result >= 0 && !device.is_null() {
+        add_ref(device as *mut c_void);
+        *returned_device = device;
+    } else {
+        *returned_device = ptr::null_mut();
+    }
+    result
+}
+
+macro_rules! untyped_thunks {
+    ($($name:ident($($arg:ident),*);)*) => {$(
+        unsafe extern "system" fn $name<const SLOT: usize>(
+            this: *mut c_void,
+            $($arg: usize),*
+        ) -> HRESULT {
+            dispatch(this, SLOT, vec![$($arg),*], 0)
+        }
+    )*};
+}
+
+// One thunk per number of arguments after `this`. Under 32-bit `stdcall` the callee pops the
+// arguments, so each slot must take exactly as many as its method, or the caller's stack is
+// left unbalanced.
+untyped_thunks! {
+    untyped0();
+    untyped1(a);
+    untyped2(a, b);
+    untyped3(a, b, c);
+    untyped4(a, b, c, d);
+    untyped5(a, b, c, d, e);
+    untyped6(a, b, c, d, e, f);
+    untyped7(a, b, c, d, e, f, g);
+    untyped8(a, b, c, d, e, f, g, h);
+    untyped9(a, b, c, d, e, f, g, h, i);
+}
+
+macro_rules! untyped_slot {
+    (0, $slot:literal) => {
+        untyped0::<$slot>
+    };
+    (1, $slot:literal) => {
+        untyped1::<$slot>
+    };
+    (2, $slot:literal) => {
+        untyped2::<$slot>
+    };
+    (3, $slot:literal) => {
+        untyped3::<$slot>
+    };
+    (4, $slot:literal) => {
+        untyped4::<$slot>
+    };
+    (5, $slot:literal) => {
+        untyped5::<$slot>
+    };
+    (6, $slot:literal) => {
+        untyped6::<$slot>
+    };
+    (7, $slot:literal) => {
+        untyped7::<$slot>
+    };
+    (8, $slot:literal) => {
+        untyped8::<$slot>
+    };
+    (9, $slot:literal) => {
+        untyped9::<$slot>
+    };
+}
+
+/// The untyped thunk of every `slot: arity` pair, in slot order.
+macro_rules! untyped_slots {
+    ($($slot:literal: $arity:tt)*) => {
+        [$(untyped_slot!($arity, $slot) as *const () as usize),*]
+    };
+}
+
+fn direct3d9_slots() -> [usize; DIRECT3D9EX_VTABLE_LEN] {
+    untyped_slots![
+        0: 2 // QueryInterface
+        1: 0 // AddRef
+        2: 0 // Release
+        3: 1 // RegisterSoftwareDevice
+        4: 0 // GetAdapterCount
+        5: 3 // GetAdapterIdentifier
+        6: 2 // GetAdapterModeCount
+        7: 4 // EnumAdapterModes
+        8: 2 // GetAdapterDisplayMode
+        9: 5 // CheckDeviceType
+        10: 6 // CheckDeviceFormat
+        11: 6 // CheckDeviceMultiSampleType
+        12: 5 // CheckDepthStencilMatch
+        13: 4 // CheckDeviceFormatConversion
+        14: 3 // GetDeviceCaps
+        15: 1 // GetAdapterMonitor
+        16: 6 // CreateDevice
+        17: 2 // GetAdapterModeCountEx
+        18: 4 // EnumAdapterModesEx
+        19: 3 // GetAdapterDisplayModeEx
+        20: 7 // CreateDeviceEx
+        21: 2 // GetAdapterLUID
+    ]
+}
+
+fn device_slots() -> [usize; DEVICE_EX_VTABLE_LEN] {
+    untyped_slots![
+        0: 2 // QueryInterface
+        1: 0 // AddRef
+        2: 0 // Release
+        3: 0 // TestCooperativeLevel
+        4: 0 // GetAvailableTextureMem
+        5: 0 // EvictManagedResources
+        6: 1 // GetDirect3D
+        7: 1 // GetDeviceCaps
+        8: 2 // GetDisplayMode
+        9: 1 // GetCreationParameters
+        10: 3 // SetCursorProperties
+        11: 3 // SetCursorPosition
+        12: 1 // ShowCursor
+        13: 2 // CreateAdditionalSwapChain
+        14: 2 // GetSwapChain
+        15: 0 // GetNumberOfSwapChains
+        16: 1 // Reset
+        17: 4 // Present
+        18: 4 // GetBackBuffer
+        19: 2 // GetRasterStatus
+        20: 1 // SetDialogBoxMode
+        21: 3 // SetGammaRamp
+        22: 2 // GetGammaRamp
+        23: 8 // CreateTexture
+        24: 9 // CreateVolumeTexture
+        25: 7 // CreateCubeTexture
+        26: 6 // CreateVertexBuffer
+        27: 6 // CreateIndexBuffer
+        28: 8 // CreateRenderTarget
+        29: 8 // CreateDepthStencilSurface
+        30: 4 // UpdateSurface
+        31: 2 // UpdateTexture
+        32: 2 // GetRenderTargetData
+        33: 2 // GetFrontBufferData
+        34: 5 // StretchRect
+        35: 3 // ColorFill
+        36: 6 // CreateOffscreenPlainSurface
+        37: 2 // SetRenderTarget
+        38: 2 // GetRenderTarget
+        39: 1 // SetDepthStencilSurface
+        40: 1 // GetDepthStencilSurface
+        41: 0 // BeginScene
+        42: 0 // EndScene
+        43: 6 // Clear
+        44: 2 // SetTransform
+        45: 2 // GetTransform
+        46: 2 // MultiplyTransform
+        47: 1 // SetViewport
+        48: 1 // GetViewport
+        49: 1 // SetMaterial
+        50: 1 // GetMaterial
+        51: 2 // SetLight
+        52: 2 // GetLight
+        53: 2 // LightEnable
+        54: 2 // GetLightEnable
+        55: 2 // SetClipPlane
+        56: 2 // GetClipPlane
+        57: 2 // SetRenderState
+        58: 2 // GetRenderState
+        59: 2 // CreateStateBlock
+        60: 0 // BeginStateBlock
+        61: 1 // EndStateBlock
+        62: 1 // SetClipStatus
+        63: 1 // GetClipStatus
+        64: 2 // GetTexture
+        65: 2 // SetTexture
+        66: 3 // GetTextureStageState
+        67: 3 // SetTextureStageState
+        68: 3 // GetSamplerState
+        69: 3 // SetSamplerState
+        70: 1 // ValidateDevice
+        71: 2 // SetPaletteEntries
+        72: 2 // GetPaletteEntries
+        73: 1 // SetCurrentTexturePalette
+        74: 1 // GetCurrentTexturePalette
+        75: 1 // SetScissorRect
+        76: 1 // GetScissorRect
+        77: 1 // SetSoftwareVertexProcessing
+        78: 0 // GetSoftwareVertexProcessing
+        79: 1 // SetNPatchMode
+        80: 0 // GetNPatchMode
+        81: 3 // DrawPrimitive
+        82: 6 // DrawIndexedPrimitive
+        83: 4 // DrawPrimitiveUP
+        84: 8 // DrawIndexedPrimitiveUP
+        85: 6 // ProcessVertices
+        86: 2 // CreateVertexDeclaration
+        87: 1 // SetVertexDeclaration
+        88: 1 // GetVertexDeclaration
+        89: 1 // SetFVF
+        90: 1 // GetFVF
+        91: 2 // CreateVertexShader
+        92: 1 // SetVertexShader
+        93: 1 // GetVertexShader
+        94: 3 // SetVertexShaderConstantF
+        95: 3 // GetVertexShaderConstantF
+        96: 3 // SetVertexShaderConstantI
+        97: 3 // GetVertexShaderConstantI
+        98: 3 // SetVertexShaderConstantB
+        99: 3 // GetVertexShaderConstantB
+        100: 4 // SetStreamSource
+        101: 4 // GetStreamSource
+        102: 2 // SetStreamSourceFreq
+        103: 2 // GetStreamSourceFreq
+        104: 1 // SetIndices
+        105: 1 // GetIndices
+        106: 2 // CreatePixelShader
+        107: 1 // SetPixelShader
+        108: 1 // GetPixelShader
+        109: 3 // SetPixelShaderConstantF
+        110: 3 // GetPixelShaderConstantF
+        111: 3 // SetPixelShaderConstantI
+        112: 3 // GetPixelShaderConstantI
+        113: 3 // SetPixelShaderConstantB
+        114: 3 // GetPixelShaderConstantB
+        115: 3 // DrawRectPatch
+        116: 3 // DrawTriPatch
+        117: 1 // DeletePatch
+        118: 2 // CreateQuery
+        119: 4 // SetConvolutionMonoKernel
+        120: 8 // ComposeRects
+        121: 5 // PresentEx
+        122: 1 // GetGPUThreadPriority
+        123: 1 // SetGPUThreadPriority
+        124: 1 // WaitForVBlank
+        125: 2 // CheckResourceResidency
+        126: 1 // SetMaximumFrameLatency
+        127: 1 // GetMaximumFrameLatency
+        128: 1 // CheckDeviceState
+        129: 9 // CreateRenderTargetEx
+        130: 7 // CreateOffscreenPlainSurfaceEx
+        131: 9 // CreateDepthStencilSurfaceEx
+        132: 2 // ResetEx
+        133: 3 // GetDisplayModeEx
+    ]
+}
+
+fn swap_chain_slots() -> [usize; SWAP_CHAIN_VTABLE_LEN] {
+    untyped_slots![
+        0: 2 // QueryInterface
+        1: 0 // AddRef
+        2: 0 // Release
+        3: 5 // Present
+        4: 1 // GetFrontBufferData
+        5: 3 // GetBackBuffer
+        6: 1 // GetRasterStatus
+        7: 1 // GetDisplayMode
+        8: 1 // GetDevice
+        9: 1 // GetPresentParameters
+    ]
+}
+
+fn state_block_slots() -> [usize; STATE_BLOCK_VTABLE_LEN] {
+    untyped_slots![
+        0: 2 // QueryInterface
+        1: 0 // AddRef
+        2: 0 // Release
+        3: 1 // GetDevice
+        4: 0 // Capture
+        5: 0 // Apply
+    ]
+}
+
+/// A vtable of `slots` with `IUnknown` wired up, so every other slot dispatches untyped.
+fn untyped_vtable(slots: &[usize]) -> Box<[usize]> {
+    let mut vtable = slots.to_vec().into_boxed_slice();
+    vtable[QUERY_INTERFACE] = query_interface as *const () as usize;
+    vtable[ADD_REF] = add_ref as *const () as usize;
+    vtable[RELEASE] = release as *const () as usize;
+    vtable
+}
@@ -0,0 +1,15 @@
+//! Test support for code built on top of the grabber.
+//!
+//! Nothing here touches a GPU: [`MockDirect3D9`] and [`MockDevice`] are heap-allocated COM
+//! objects with real `#[repr(C)]` vtables, and pair with
+//! [`FakeBackend`](crate::backend::FakeBackend) to drive the grabbing functions end to end.
+//! [`FakeClock`] lets a [`WaitPolicy`](crate::WaitPolicy) wait without sleeping.
+
+mod clock;
+mod com;
+
+pub use self::clock::FakeClock;
+pub use self::com::{
+    CreateDeviceCall, MockCall, MockDevice, MockDirect3D9, MockObject, MockStateBlock,
+    MockSwapChain,
+};
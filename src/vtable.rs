@@ -0,0 +1,313 @@
+//! Copies of COM vtables with every method named.
+//!
+//! Each interface gets a `*VTable` struct laid out exactly like the vtable in memory and a
+//! `*Method` enum giving each slot's index, for hooking by name rather than by magic number.
+
+use std::ffi::c_void;
+use std::ptr;
+use std::slice;
+
+macro_rules! vtable {
+    (
+        $(#[$method_meta:meta])*
+        pub enum $method:ident;
+        $(#[$vtable_meta:meta])*
+        pub struct $vtable:ident $({ $base_field:ident: $base:ident })?;
+        $($variant:ident => $field:ident,)*
+    ) => {
+        $(#[$method_meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum $method {
+            $($variant,)*
+        }
+
+        impl $method {
+            /// Every method, in vtable order.
+            pub const ALL: &'static [$method] = &[$($method::$variant,)*];
+
+            const FIRST: usize = 0 $(+ $base::LEN)?;
+
+            /// The method's slot in the vtable.
+            pub const fn index(self) -> usize {
+                Self::FIRST + self as usize
+            }
+
+            /// The method in slot `index`, if it is one of this enum's.
+            pub fn from_index(index: usize) -> Option<Self> {
+                index
+                    .checked_sub(Self::FIRST)
+                    .and_then(|offset| Self::ALL.get(offset).copied())
+            }
+
+            /// The method's name as in the Windows SDK headers.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $($method::$variant => stringify!($variant),)*
+                }
+            }
+        }
+
+        $(#[$vtable_meta])*
+        #[repr(C)]
+        #[derive(Debug, Clone, Copy)]
+        pub struct $vtable {
+            $(pub $base_field: $base,)?
+            $(pub $field: *const c_void,)*
+        }
+
+        // The fields are code addresses, which are the same on every thread.
+        unsafe impl Send for $vtable {}
+        unsafe impl Sync for $vtable {}
+
+        impl $vtable {
+            /// Number of slots, including those of the interfaces this one extends.
+            pub const LEN: usize = $method::FIRST + $method::ALL.len();
+
+            /// Copy the vtable starting at `vtable`.
+            ///
+            /// # Safety
+            ///
+            /// `vtable` must point to at least [`Self::LEN`] readable method pointers.
+            pub unsafe fn read(vtable: *const *const c_void) -> Self {
+                ptr::read(vtable as *const Self)
+            }
+
+            /// Every slot in order, so `as_slice()[method.index()]` is that method.
+            pub fn as_slice(&self) -> &[*const c_void] {
+                unsafe { slice::from_raw_parts(self as *const Self as *const *const c_void, Self::LEN) }
+            }
+
+            pub fn get(&self, method: $method) -> *const c_void {
+                self.as_slice()[method.index()]
+            }
+        }
+    };
+}
+
+vtable! {
+    /// The methods of `IDirect3DDevice9`, including those inherited from `IUnknown`.
+    pub enum DeviceMethod;
+    /// The `IDirect3DDevice9` vtable.
+    pub struct DeviceVTable;
+    QueryInterface => query_interface,
+    AddRef => add_ref,
+    Release => release,
+    TestCooperativeLevel => test_cooperative_level,
+    GetAvailableTextureMem => get_available_texture_mem,
+    EvictManagedResources => evict_managed_resources,
+    GetDirect3D => get_direct3d,
+    GetDeviceCaps => get_device_caps,
+    GetDisplayMode => get_display_mode,
+    GetCreationParameters => get_creation_parameters,
+    SetCursorProperties => set_cursor_properties,
+    SetCursorPosition => set_cursor_position,
+    ShowCursor => show_cursor,
+    CreateAdditionalSwapChain => create_additional_swap_chain,
+    GetSwapChain => get_swap_chain,
+    GetNumberOfSwapChains => get_number_of_swap_chains,
+    Reset => reset,
+    Present => present,
+    GetBackBuffer => get_back_buffer,
+    GetRasterStatus => get_raster_status,
+    SetDialogBoxMode => set_dialog_box_mode,
+    SetGammaRamp => set_gamma_ramp,
+    GetGammaRamp => get_gamma_ramp,
+    CreateTexture => create_texture,
+    CreateVolumeTexture => create_volume_texture,
+    CreateCubeTexture => create_cube_texture,
+    CreateVertexBuffer => create_vertex_buffer,
+    CreateIndexBuffer => create_index_buffer,
+    CreateRenderTarget => create_render_target,
+    CreateDepthStencilSurface => create_depth_stencil_surface,
+    UpdateSurface => update_surface,
+    UpdateTexture => update_texture,
+    GetRenderTargetData => get_render_target_data,
+    GetFrontBufferData => get_front_buffer_data,
+    StretchRect => stretch_rect,
+    ColorFill => color_fill,
+    CreateOffscreenPlainSurface => create_offscreen_plain_surface,
+    SetRenderTarget => set_render_target,
+    GetRenderTarget => get_render_target,
+    SetDepthStencilSurface => set_depth_stencil_surface,
+    GetDepthStencilSurface => get_depth_stencil_surface,
+    BeginScene => begin_scene,
+    EndScene => end_scene,
+    Clear => clear,
+    SetTransform => set_transform,
+    GetTransform => get_transform,
+    MultiplyTransform => multiply_transform,
+    SetViewport => set_viewport,
+    GetViewport => get_viewport,
+    SetMaterial => set_material,
+    GetMaterial => get_material,
+    SetLight => set_light,
+    GetLight => get_light,
+    LightEnable => light_enable,
+    GetLightEnable => get_light_enable,
+    SetClipPlane => set_clip_plane,
+    GetClipPlane => get_clip_plane,
+    SetRenderState => set_render_state,
+    GetRenderState => get_render_state,
+    CreateStateBlock => create_state_block,
+    BeginStateBlock => begin_state_block,
+    EndStateBlock => end_state_block,
+    SetClipStatus => set_clip_status,
+    GetClipStatus => get_clip_status,
+    GetTexture => get_texture,
+    SetTexture => set_texture,
+    GetTextureStageState => get_texture_stage_state,
+    SetTextureStageState => set_texture_stage_state,
+    GetSamplerState => get_sampler_state,
+    SetSamplerState => set_sampler_state,
+    ValidateDevice => validate_device,
+    SetPaletteEntries => set_palette_entries,
+    GetPaletteEntries => get_palette_entries,
+    SetCurrentTexturePalette => set_current_texture_palette,
+    GetCurrentTexturePalette => get_current_texture_palette,
+    SetScissorRect => set_scissor_rect,
+    GetScissorRect => get_scissor_rect,
+    SetSoftwareVertexProcessing => set_software_vertex_processing,
+    GetSoftwareVertexProcessing => get_software_vertex_processing,
+    SetNPatchMode => set_npatch_mode,
+    GetNPatchMode => get_npatch_mode,
+    DrawPrimitive => draw_primitive,
+    DrawIndexedPrimitive => draw_indexed_primitive,
+    DrawPrimitiveUP => draw_primitive_up,
+    DrawIndexedPrimitiveUP => draw_indexed_primitive_up,
+    ProcessVertices => process_vertices,
+    CreateVertexDeclaration => create_vertex_declaration,
+    SetVertexDeclaration => set_vertex_declaration,
+    GetVertexDeclaration => get_vertex_declaration,
+    SetFVF => set_fvf,
+    GetFVF => get_fvf,
+    CreateVertexShader => create_vertex_shader,
+    SetVertexShader => set_vertex_shader,
+    GetVertexShader => get_vertex_shader,
+    SetVertexShaderConstantF => set_vertex_shader_constant_f,
+    GetVertexShaderConstantF => get_vertex_shader_constant_f,
+    SetVertexShaderConstantI => set_vertex_shader_constant_i,
+    GetVertexShaderConstantI => get_vertex_shader_constant_i,
+    SetVertexShaderConstantB => set_vertex_shader_constant_b,
+    GetVertexShaderConstantB => get_vertex_shader_constant_b,
+    SetStreamSource => set_stream_source,
+    GetStreamSource => get_stream_source,
+    SetStreamSourceFreq => set_stream_source_freq,
+    GetStreamSourceFreq => get_stream_source_freq,
+    SetIndices => set_indices,
+    GetIndices => get_indices,
+    CreatePixelShader => create_pixel_shader,
+    SetPixelShader => set_pixel_shader,
+    GetPixelShader => get_pixel_shader,
+    SetPixelShaderConstantF => set_pixel_shader_constant_f,
+    GetPixelShaderConstantF => get_pixel_shader_constant_f,
+    SetPixelShaderConstantI => set_pixel_shader_constant_i,
+    GetPixelShaderConstantI => get_pixel_shader_constant_i,
+    SetPixelShaderConstantB => set_pixel_shader_constant_b,
+    GetPixelShaderConstantB => get_pixel_shader_constant_b,
+    DrawRectPatch => draw_rect_patch,
+    DrawTriPatch => draw_tri_patch,
+    DeletePatch => delete_patch,
+    CreateQuery => create_query,
+}
+
+vtable! {
+    /// The methods `IDirect3DDevice9Ex` adds to `IDirect3DDevice9`.
+    pub enum DeviceExMethod;
+    /// The `IDirect3DDevice9Ex` vtable: the `IDirect3DDevice9` slots followed by the Ex ones.
+    pub struct DeviceExVTable { device: DeviceVTable };
+    SetConvolutionMonoKernel => set_convolution_mono_kernel,
+    ComposeRects => compose_rects,
+    PresentEx => present_ex,
+    GetGPUThreadPriority => get_gpu_thread_priority,
+    SetGPUThreadPriority => set_gpu_thread_priority,
+    WaitForVBlank => wait_for_vblank,
+    CheckResourceResidency => check_resource_residency,
+    SetMaximumFrameLatency => set_maximum_frame_latency,
+    GetMaximumFrameLatency => get_maximum_frame_latency,
+    CheckDeviceState => check_device_state,
+    CreateRenderTargetEx => create_render_target_ex,
+    CreateOffscreenPlainSurfaceEx => create_offscreen_plain_surface_ex,
+    CreateDepthStencilSurfaceEx => create_depth_stencil_surface_ex,
+    ResetEx => reset_ex,
+    GetDisplayModeEx => get_display_mode_ex,
+}
+
+vtable! {
+    /// The methods of `IDirect3DSwapChain9`, including those inherited from `IUnknown`.
+    pub enum SwapChainMethod;
+    /// The `IDirect3DSwapChain9` vtable.
+    pub struct SwapChainVTable;
+    QueryInterface => query_interface,
+    AddRef => add_ref,
+    Release => release,
+    Present => present,
+    GetFrontBufferData => get_front_buffer_data,
+    GetBackBuffer => get_back_buffer,
+    GetRasterStatus => get_raster_status,
+    GetDisplayMode => get_display_mode,
+    GetDevice => get_device,
+    GetPresentParameters => get_present_parameters,
+}
+
+/// The vtables of a device and of its implicit swap chain, copied together.
+///
+/// Hooking `Present` on both catches engines that present through either interface.
+#[derive(Debug, Clone, Copy)]
+pub struct D3D9VTables {
+    pub device: DeviceVTable,
+    pub swap_chain: SwapChainVTable,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::mem;
+
+    #[test]
+    fn lengths_match_the_sdk_headers() {
+        assert_eq!(DeviceVTable::LEN, 119);
+        assert_eq!(DeviceExVTable::LEN, 134);
+        assert_eq!(SwapChainVTable::LEN, 10);
+        assert_eq!(
+            mem::size_of::<DeviceExVTable>(),
+            DeviceExVTable::LEN * mem::size_of::<usize>()
+        );
+    }
+
+    #[test]
+    fn key_methods_sit_in_their_slots() {
+        assert_eq!(DeviceMethod::Reset.index(), 16);
+        assert_eq!(DeviceMethod::Present.index(), 17);
+        assert_eq!(DeviceMethod::EndScene.index(), 42);
+        assert_eq!(DeviceMethod::CreateQuery.index(), 118);
+        assert_eq!(DeviceExMethod::ResetEx.index(), 132);
+        assert_eq!(SwapChainMethod::Present.index(), 3);
+    }
+
+    #[test]
+    fn indices_and_names_round_trip() {
+        for &method in DeviceMethod::ALL {
+            assert_eq!(DeviceMethod::from_index(method.index()), Some(method));
+        }
+        assert_eq!(DeviceMethod::from_index(DeviceVTable::LEN), None);
+        assert_eq!(
+            DeviceExMethod::from_index(DeviceMethod::EndScene.index()),
+            None
+        );
+        assert_eq!(DeviceMethod::EndScene.name(), "EndScene");
+    }
+
+    #[test]
+    fn read_copies_every_slot() {
+        let slots: Vec<*const c_void> = (0..DeviceExVTable::LEN)
+            .map(|slot| (0x1000 + slot) as *const c_void)
+            .collect();
+
+        let vtable = unsafe { DeviceExVTable::read(slots.as_ptr()) };
+
+        assert_eq!(vtable.as_slice(), &slots[..]);
+        assert_eq!(vtable.device.end_scene, slots[42]);
+        assert_eq!(vtable.get(DeviceExMethod::ResetEx), slots[132]);
+    }
+}
@@ -0,0 +1,237 @@
+//! Waiting for the game to create its window before grabbing.
+
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::window::WindowInfo;
+use crate::D3D9GrabError;
+
+/// A source of time for [`WaitPolicy`], so the waiting can be tested without sleeping.
+pub trait Clock {
+    /// Time since some fixed point in the past. It never goes backwards.
+    fn now(&self) -> Duration;
+
+    /// Block the calling thread for `duration`.
+    fn sleep(&self, duration: Duration);
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> Duration {
+        (**self).now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        (**self).sleep(duration)
+    }
+}
+
+/// The real clock: [`Instant`] and [`thread::sleep`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        static START: OnceLock<Instant> = OnceLock::new();
+        START.get_or_init(Instant::now).elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+type ReadyFn = Arc<dyn Fn(&WindowInfo) -> bool + Send + Sync>;
+
+/// How long and how often to look for the process's window when it does not exist yet, as
+/// when a DLL is injected at process start.
+///
+/// Only the window search is retried. Once a window is found, failing to create the device
+/// on it is reported straight away.
+///
+/// ```ignore
+/// let device = DeviceGrabber::new()
+///     .wait(
+///         WaitPolicy::new()
+///             .timeout(Some(Duration::from_secs(30)))
+///             .ready(|window| window.visible && window.client_area() > 0),
+///     )
+///     .device()?;
+/// ```
+#[derive(Clone)]
+pub struct WaitPolicy {
+    /// How long to keep looking before giving up with the last error, or `None` to wait
+    /// indefinitely.
+    pub timeout: Option<Duration>,
+    /// How long to wait after the first failed look.
+    pub poll_interval: Duration,
+    /// What the wait is multiplied by after every failed look. `1.0` keeps it constant.
+    pub backoff: f64,
+    /// The longest the wait can grow to through `backoff`.
+    pub max_poll_interval: Duration,
+    ready: Option<ReadyFn>,
+    clock: Arc<dyn Clock + Send + Sync>,
+}
+
+impl WaitPolicy {
+    /// Wait indefinitely, looking after 100ms at first and then twice as long each time, up
+    /// to once a second.
+    pub fn new() -> Self {
+        WaitPolicy {
+            timeout: None,
+            poll_interval: Duration::from_millis(100),
+            backoff: 2.0,
+            max_poll_interval: Duration::from_secs(1),
+            ready: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    pub fn backoff(mut self, backoff: f64) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn max_poll_interval(mut self, max_poll_interval: Duration) -> Self {
+        self.max_poll_interval = max_poll_interval;
+        self
+    }
+
+    /// Only consider windows that `ready` accepts, such as ones that are visible and sized,
+    /// and keep waiting while there are none.
+    pub fn ready<F>(mut self, ready: F) -> Self
+    where
+        F: Fn(&WindowInfo) -> bool + Send + Sync + 'static,
+    {
+        self.ready = Some(Arc::new(ready));
+        self
+    }
+
+    /// Measure time and sleep with `clock` instead of [`SystemClock`].
+    pub fn clock<C: Clock + Send + Sync + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Whether `window` passes the [`ready`](WaitPolicy::ready) predicate, if there is one.
+    pub fn is_ready(&self, window: &WindowInfo) -> bool {
+        match &self.ready {
+            Some(ready) => ready(window),
+            None => true,
+        }
+    }
+
+    /// The wait after `wait`, grown by the backoff and capped.
+    pub fn next_poll_interval(&self, wait: Duration) -> Duration {
+        let max = self.max_poll_interval.max(self.poll_interval);
+        Duration::try_from_secs_f64(wait.as_secs_f64() * self.backoff.max(1.0))
+            .map_or(max, |next| next.min(max))
+    }
+
+    /// Call `attempt` until it stops failing with
+    /// [`D3D9GrabError::GetProcessWindowFailed`], or until the timeout has passed.
+    ///
+    /// The last wait is cut short so the final attempt happens right at the timeout.
+    pub fn retry<T, F>(&self, mut attempt: F) -> Result<T, D3D9GrabError>
+    where
+        F: FnMut() -> Result<T, D3D9GrabError>,
+    {
+        let start = self.clock.now();
+        let mut wait = self.poll_interval;
+        loop {
+            let err = match attempt() {
+                Err(err @ D3D9GrabError::GetProcessWindowFailed { .. }) => err,
+                result => return result,
+            };
+
+            let elapsed = self.clock.now().saturating_sub(start);
+            let sleep = match self.timeout {
+                Some(timeout) if elapsed >= timeout => return Err(err),
+                Some(timeout) => wait.min(timeout - elapsed),
+                None => wait,
+            };
+            self.clock.sleep(sleep);
+            wait = self.next_poll_interval(wait);
+        }
+    }
+}
+
+impl Default for WaitPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for WaitPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WaitPolicy")
+            .field("timeout", &self.timeout)
+            .field("poll_interval", &self.poll_interval)
+            .field("backoff", &self.backoff)
+            .field("max_poll_interval", &self.max_poll_interval)
+            .field("ready", &self.ready.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn poll_interval_grows_by_the_backoff_up_to_the_cap() {
+        let policy = WaitPolicy::new()
+            .poll_interval(ms(10))
+            .backoff(3.0)
+            .max_poll_interval(ms(50));
+
+        assert_eq!(policy.next_poll_interval(ms(10)), ms(30));
+        assert_eq!(policy.next_poll_interval(ms(30)), ms(50));
+        assert_eq!(policy.next_poll_interval(ms(50)), ms(50));
+    }
+
+    #[test]
+    fn backoff_below_one_keeps_the_interval() {
+        assert_eq!(
+            WaitPolicy::new().backoff(0.5).next_poll_interval(ms(100)),
+            ms(100)
+        );
+        assert_eq!(
+            WaitPolicy::new()
+                .backoff(f64::NAN)
+                .next_poll_interval(ms(100)),
+            ms(100)
+        );
+    }
+
+    #[test]
+    fn huge_backoff_is_capped() {
+        let policy = WaitPolicy::new().backoff(1e300);
+        assert_eq!(policy.next_poll_interval(ms(100)), Duration::from_secs(1));
+        let policy = WaitPolicy::new().backoff(f64::INFINITY);
+        assert_eq!(policy.next_poll_interval(ms(100)), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn cap_below_the_first_interval_keeps_the_first_interval() {
+        let policy = WaitPolicy::new()
+            .poll_interval(ms(200))
+            .max_poll_interval(ms(50));
+        assert_eq!(policy.next_poll_interval(ms(200)), ms(200));
+    }
+}
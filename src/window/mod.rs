@@ -0,0 +1,164 @@
+//! Choosing which of the process's windows the device is created on.
+
+mod pattern;
+
+pub use self::pattern::TitlePattern;
+
+use std::fmt;
+use std::mem;
+use std::sync::Mutex;
+
+use crate::sys::HWND;
+
+/// What the grabber knows about a top-level window when choosing one.
+#[derive(Debug, Clone)]
+pub struct WindowInfo {
+    pub hwnd: HWND,
+    pub visible: bool,
+    pub client_width: u32,
+    pub client_height: u32,
+    /// The owner window, or null for an unowned window.
+    pub owner: HWND,
+    pub class_name: String,
+    pub title: String,
+}
+
+impl WindowInfo {
+    pub fn client_area(&self) -> u64 {
+        u64::from(self.client_width) * u64::from(self.client_height)
+    }
+}
+
+/// Destroy functions of the hidden windows still alive, by window handle, so
+/// [`shutdown`](crate::shutdown_in) can destroy the ones whose owner has not been dropped.
+static HIDDEN_WINDOWS: Mutex<Vec<(usize, DestroyFn)>> = Mutex::new(Vec::new());
+
+type DestroyFn = Box<dyn FnOnce(HWND) + Send>;
+
+/// A hidden 1x1 window created for the grabber's own use, destroyed along with its window
+/// class when dropped, unless a shutdown destroyed it already.
+pub struct HiddenWindow {
+    hwnd: HWND,
+}
+
+impl HiddenWindow {
+    /// Wrap `hwnd`, calling `destroy` with it on drop.
+    pub fn new<F>(hwnd: HWND, destroy: F) -> Self
+    where
+        F: FnOnce(HWND) + Send + 'static,
+    {
+        HIDDEN_WINDOWS
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .push((hwnd as usize, Box::new(destroy)));
+        HiddenWindow { hwnd }
+    }
+
+    pub fn hwnd(&self) -> HWND {
+        self.hwnd
+    }
+}
+
+impl fmt::Debug for HiddenWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HiddenWindow")
+            .field("hwnd", &self.hwnd)
+            .finish()
+    }
+}
+
+impl Drop for HiddenWindow {
+    fn drop(&mut self) {
+        let mut windows = HIDDEN_WINDOWS.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(index) = windows
+            .iter()
+            .rposition(|&(hwnd, _)| hwnd == self.hwnd as usize)
+        {
+            let (_, destroy) = windows.remove(index);
+            drop(windows);
+            destroy(self.hwnd);
+        }
+    }
+}
+
+/// Destroy every hidden window still alive, newest first, returning how many.
+pub(crate) fn destroy_every_hidden_window() -> usize {
+    let windows = mem::take(&mut *HIDDEN_WINDOWS.lock().unwrap_or_else(|err| err.into_inner()));
+    let count = windows.len();
+    for (hwnd, destroy) in windows.into_iter().rev() {
+        destroy(hwnd as HWND);
+    }
+    count
+}
+
+/// How to choose among the top-level windows owned by the current process.
+///
+/// Candidates are considered in `EnumWindows` order, which is z-order, so ties go to the
+/// topmost window.
+#[derive(Default)]
+pub enum WindowSelector {
+    /// The first window, visible or not. This is what the grabber has always done.
+    #[default]
+    First,
+    /// The first visible window.
+    FirstVisible,
+    /// The window with the largest client area.
+    LargestClientArea,
+    /// The first window without an owner, skipping tool windows and dialogs.
+    NoOwner,
+    /// The first window whose class name matches, ignoring ASCII case like Win32 does.
+    ClassName(String),
+    /// The first window whose title matches the pattern.
+    Title(TitlePattern),
+    /// The first window the predicate accepts.
+    Custom(Box<dyn Fn(&WindowInfo) -> bool + Send + Sync>),
+}
+
+impl WindowSelector {
+    /// Select by a custom predicate.
+    pub fn custom<F>(predicate: F) -> Self
+    where
+        F: Fn(&WindowInfo) -> bool + Send + Sync + 'static,
+    {
+        WindowSelector::Custom(Box::new(predicate))
+    }
+
+    /// Pick a window out of `windows`, which are in `EnumWindows` order.
+    pub fn select<'a>(&self, windows: &'a [WindowInfo]) -> Option<&'a WindowInfo> {
+        let mut candidates = windows.iter();
+        match self {
+            WindowSelector::First => candidates.next(),
+            WindowSelector::FirstVisible => candidates.find(|window| window.visible),
+            WindowSelector::LargestClientArea => candidates.fold(None, |best, window| match best {
+                Some(best) if best.client_area() >= window.client_area() => Some(best),
+                _ => Some(window),
+            }),
+            WindowSelector::NoOwner => candidates.find(|window| window.owner.is_null()),
+            WindowSelector::ClassName(class_name) => {
+                candidates.find(|window| window.class_name.eq_ignore_ascii_case(class_name))
+            }
+            WindowSelector::Title(pattern) => {
+                candidates.find(|window| pattern.is_match(&window.title))
+            }
+            WindowSelector::Custom(predicate) => candidates.find(|window| predicate(window)),
+        }
+    }
+}
+
+impl fmt::Debug for WindowSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WindowSelector::First => f.write_str("First"),
+            WindowSelector::FirstVisible => f.write_str("FirstVisible"),
+            WindowSelector::LargestClientArea => f.write_str("LargestClientArea"),
+            WindowSelector::NoOwner => f.write_str("NoOwner"),
+            WindowSelector::ClassName(class_name) => {
+                f.debug_tuple("ClassName").field(class_name).finish()
+            }
+            WindowSelector::Title(pattern) => {
+                f.debug_tuple("Title").field(&pattern.as_str()).finish()
+            }
+            WindowSelector::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use regex::Regex;
+
+use crate::D3D9GrabError;
+
+/// A compiled regular expression matched against window titles.
+///
+/// Uses the syntax of the [`regex`](https://docs.rs/regex) crate, which matches in time linear
+/// in the length of the title whatever the pattern. The pattern matches if it matches anywhere
+/// in the title, so anchor it with `^` and `$` to match the whole title.
+#[derive(Debug, Clone)]
+pub struct TitlePattern {
+    regex: Regex,
+}
+
+impl TitlePattern {
+    pub fn new(pattern: &str) -> Result<Self, D3D9GrabError> {
+        let regex = Regex::new(pattern).map_err(|err| D3D9GrabError::InvalidTitlePattern {
+            pattern: pattern.to_owned(),
+            reason: err.to_string(),
+        })?;
+        Ok(TitlePattern { regex })
+    }
+
+    /// The pattern this was compiled from.
+    pub fn as_str(&self) -> &str {
+        self.regex.as_str()
+    }
+
+    pub fn is_match(&self, title: &str) -> bool {
+        self.regex.is_match(title)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(pattern: &str, title: &str) -> bool {
+        TitlePattern::new(pattern).unwrap().is_match(title)
+    }
+
+    #[test]
+    fn matches_anywhere_unless_anchored() {
+        assert!(is_match("Game", "My Game v1"));
+        assert!(!is_match("^Game", "My Game v1"));
+        assert!(is_match("v1$", "My Game v1"));
+        assert!(!is_match("^Game$", "My Game"));
+        assert!(is_match("", ""));
+    }
+
+    #[test]
+    fn matches_groups_classes_and_repetition() {
+        assert!(is_match(r"^My (Game|App) v\d+$", "My App v12"));
+        assert!(!is_match(r"^My (Game|App) v\d+$", "My App v12x"));
+        assert!(is_match("[a-c]+x?$", "zzabca"));
+        assert!(is_match("^[^ ]*$", "NoSpaces"));
+        assert!(!is_match("^[^ ]*$", "Two words"));
+        assert!(is_match("^a(b*)*c$", "abbbc"));
+        assert!(is_match(r"^\w{3,5} \d{2}$", "Game 42"));
+        assert!(!is_match(r"^\w{3,5} \d{2}$", "Ga 42"));
+        assert!(is_match("(?i)^the game$", "The Game"));
+    }
+
+    #[test]
+    fn pathological_patterns_match_in_linear_time() {
+        let title = "a".repeat(10_000);
+        assert!(!is_match("^(a|a)*b$", &title));
+        assert!(!is_match("^(a*)*b$", &title));
+        assert!(is_match("^(a|aa)+$", &title));
+    }
+
+    #[test]
+    fn keeps_its_source() {
+        let pattern = TitlePattern::new(r"^Game \d+$").unwrap();
+        assert_eq!(pattern.as_str(), r"^Game \d+$");
+    }
+
+    #[test]
+    fn invalid_patterns_are_errors() {
+        for &pattern in &["(ab", "ab)", "*a", "[ab", "[b-a]", r"\", "a{2,1}"] {
+            match TitlePattern::new(pattern) {
+                Err(D3D9GrabError::InvalidTitlePattern {
+                    pattern: source,
+                    reason,
+                }) => {
+                    assert_eq!(source, pattern);
+                    assert!(!reason.is_empty());
+                }
+                other => panic!("`{}` gave {:?}", pattern, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! `capture_live_device_in` picking up the device that renders on another thread.
+
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use d3d9_device_grabber::backend::FakeBackend;
+use d3d9_device_grabber::hook::{capture_live_device_in, D3D9Hooks};
+use d3d9_device_grabber::sys::*;
+use d3d9_device_grabber::testing::{MockDevice, MockDirect3D9};
+use d3d9_device_grabber::{DeviceMethod, HookError};
+
+mod common;
+
+use common::{hidden_window_backend, serial, slot};
+
+type EndSceneFn = unsafe extern "system" fn(*mut IDirect3DDevice9) -> HRESULT;
+type PresentFn = unsafe extern "system" fn(
+    *mut IDirect3DDevice9,
+    *const RECT,
+    *const RECT,
+    HWND,
+    *const RGNDATA,
+) -> HRESULT;
+
+/// A game's render loop, calling `method` of `game` through the shared vtable until stopped.
+/// Returns how many frames it rendered.
+struct RenderLoop {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<usize>,
+}
+
+impl RenderLoop {
+    fn start(game: &MockDevice, method: DeviceMethod) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (vtable, game) = (game.vtable() as usize, game.as_ptr() as usize);
+        let stopped = stop.clone();
+        let thread = thread::spawn(move || {
+            let mut frames = 0;
+            while !stopped.load(Ordering::SeqCst) {
+                unsafe {
+                    let function = *(vtable as *const usize).add(method.index());
+                    let game = game as *mut IDirect3DDevice9;
+                    if method == DeviceMethod::Present {
+                        let present: PresentFn = mem::transmute(function);
+                        present(game, ptr::null(), ptr::null(), ptr::null_mut(), ptr::null());
+                    } else {
+                        let end_scene: EndSceneFn = mem::transmute(function);
+                        end_scene(game);
+                    }
+                }
+                frames += 1;
+                thread::sleep(Duration::from_millis(1));
+            }
+            frames
+        });
+        RenderLoop { stop, thread }
+    }
+
+    fn stop(self) -> usize {
+        self.stop.store(true, Ordering::SeqCst);
+        self.thread.join().unwrap()
+    }
+}
+
+fn captures_through(method: DeviceMethod) {
+    let dummy = MockDevice::new();
+    let game = MockDevice::new().with_shared_vtable(&dummy);
+    let direct3d9 = MockDirect3D9::new().with_device(&dummy);
+    let (end_scene, present) = (
+        slot(&dummy, DeviceMethod::EndScene),
+        slot(&dummy, DeviceMethod::Present),
+    );
+
+    let render_loop = RenderLoop::start(&game, method);
+    let live = unsafe {
+        capture_live_device_in(hidden_window_backend(&direct3d9), Duration::from_secs(5))
+    };
+    let frames = render_loop.stop();
+    let live = live.unwrap();
+
+    assert!(frames > 0);
+    assert_eq!(live.as_ptr(), game.as_ptr());
+    assert_eq!(slot(&dummy, DeviceMethod::EndScene), end_scene);
+    assert_eq!(slot(&dummy, DeviceMethod::Present), present);
+    // Every frame reached the real method, and the dummy never rendered.
+    assert_eq!(game.call_count(method.index()), frames);
+    assert_eq!(dummy.call_count(method.index()), 0);
+    assert_eq!(game.ref_count(), 2);
+    assert_eq!(dummy.ref_count(), 1);
+    assert_eq!(direct3d9.ref_count(), 0);
+
+    drop(live);
+    assert_eq!(game.ref_count(), 1);
+}
+
+#[test]
+fn captures_the_device_calling_end_scene() {
+    let _serial = serial();
+    captures_through(DeviceMethod::EndScene);
+}
+
+#[test]
+fn captures_the_device_calling_present() {
+    let _serial = serial();
+    captures_through(DeviceMethod::Present);
+}
+
+#[test]
+fn times_out_when_nothing_renders() {
+    let _serial = serial();
+    let dummy = MockDevice::new();
+    let direct3d9 = MockDirect3D9::new().with_device(&dummy);
+    let end_scene = slot(&dummy, DeviceMethod::EndScene);
+
+    let err = unsafe {
+        capture_live_device_in(hidden_window_backend(&direct3d9), Duration::from_millis(20))
+    }
+    .err()
+    .unwrap();
+
+    assert!(
+        matches!(err, HookError::CaptureTimedOut { timeout } if timeout == Duration::from_millis(20))
+    );
+    assert_eq!(slot(&dummy, DeviceMethod::EndScene), end_scene);
+    assert_eq!(dummy.ref_count(), 1);
+    assert_eq!(direct3d9.ref_count(), 0);
+}
+
+#[test]
+fn refuses_to_capture_under_d3d9_hooks() {
+    let _serial = serial();
+    let dummy = MockDevice::new();
+    let direct3d9 = MockDirect3D9::new().with_device(&dummy);
+    let (end_scene, present) = (
+        slot(&dummy, DeviceMethod::EndScene),
+        slot(&dummy, DeviceMethod::Present),
+    );
+    let hooks = unsafe {
+        D3D9Hooks::new()
+            .on_present(|_, _, _, _, _| ())
+            .install_for_device_in(FakeBackend::new(7), dummy.as_ptr())
+    }
+    .unwrap();
+
+    let err = unsafe {
+        capture_live_device_in(hidden_window_backend(&direct3d9), Duration::from_millis(20))
+    }
+    .err()
+    .unwrap();
+
+    assert!(
+        matches!(err, HookError::AlreadyHooked { slot } if slot == DeviceMethod::Present.index()),
+        "{}",
+        err
+    );
+    // The EndScene hook made before the conflict was found is gone again.
+    assert_eq!(slot(&dummy, DeviceMethod::EndScene), end_scene);
+    assert_eq!(dummy.ref_count(), 1);
+    drop(hooks);
+    assert_eq!(slot(&dummy, DeviceMethod::Present), present);
+}
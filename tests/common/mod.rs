@@ -0,0 +1,48 @@
+//! Helpers shared by the integration tests.
+
+// Every test crate builds this module, and none of them uses all of it.
+#![allow(dead_code)]
+
+use std::mem;
+use std::sync::{Mutex, MutexGuard};
+
+use d3d9_device_grabber::backend::{fake_hwnd, FakeBackend};
+use d3d9_device_grabber::testing::{MockDevice, MockDirect3D9};
+use d3d9_device_grabber::DeviceMethod;
+
+/// Hooks, captures and shutdowns affect the whole process, so the tests of a crate that
+/// make them take turns.
+pub fn serial() -> MutexGuard<'static, ()> {
+    static SERIAL: Mutex<()> = Mutex::new(());
+    SERIAL.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// A backend for process 7, owning the window `fake_hwnd(1)`, whose `Direct3DCreate9`
+/// returns `direct3d9`.
+pub fn backend(direct3d9: &MockDirect3D9) -> FakeBackend {
+    FakeBackend::new(7)
+        .with_window(fake_hwnd(1), 7)
+        .with_direct3d9(direct3d9.as_ptr())
+}
+
+/// A backend for process 7 without windows of its own, which creates the hidden window
+/// `fake_hwnd(99)` and whose `Direct3DCreate9` returns `direct3d9`.
+pub fn hidden_window_backend(direct3d9: &MockDirect3D9) -> FakeBackend {
+    FakeBackend::new(7)
+        .with_hidden_window(fake_hwnd(99))
+        .with_direct3d9(direct3d9.as_ptr())
+}
+
+/// What the vtable of `device` holds for `method`.
+pub fn slot(device: &MockDevice, method: DeviceMethod) -> usize {
+    unsafe { *device.vtable().add(method.index()) }
+}
+
+/// What the vtable of `device` holds for `method`, as the function pointer type `F`.
+///
+/// # Safety
+///
+/// `F` must be the signature of `method`.
+pub unsafe fn method<F>(device: &MockDevice, method: DeviceMethod) -> F {
+    mem::transmute_copy(&slot(device, method))
+}
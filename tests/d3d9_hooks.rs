@@ -0,0 +1,279 @@
+//! `D3D9Hooks` routing calls on a mock device into closures.
+
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use d3d9_device_grabber::backend::{fake_hwnd, FakeBackend};
+use d3d9_device_grabber::hook::D3D9Hooks;
+use d3d9_device_grabber::sys::*;
+use d3d9_device_grabber::testing::{MockDevice, MockDirect3D9};
+use d3d9_device_grabber::{D3dResult, DeviceMethod, HookError};
+
+mod common;
+
+use common::{hidden_window_backend, method, serial};
+
+type EndSceneFn = unsafe extern "system" fn(*mut IDirect3DDevice9) -> HRESULT;
+type PresentFn = unsafe extern "system" fn(
+    *mut IDirect3DDevice9,
+    *const RECT,
+    *const RECT,
+    HWND,
+    *const RGNDATA,
+) -> HRESULT;
+type ResetFn =
+    unsafe extern "system" fn(*mut IDirect3DDevice9, *mut D3DPRESENT_PARAMETERS) -> HRESULT;
+type DrawIndexedPrimitiveFn = unsafe extern "system" fn(
+    *mut IDirect3DDevice9,
+    D3DPRIMITIVETYPE,
+    INT,
+    UINT,
+    UINT,
+    UINT,
+    UINT,
+) -> HRESULT;
+
+fn counter() -> (Arc<AtomicUsize>, Arc<AtomicUsize>) {
+    let count = Arc::new(AtomicUsize::new(0));
+    (count.clone(), count)
+}
+
+#[test]
+fn end_scene_callbacks_run_in_order_before_the_method() {
+    let _serial = serial();
+    let device = MockDevice::new();
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let (first, second) = (order.clone(), order.clone());
+    let hooks = unsafe {
+        D3D9Hooks::new()
+            .on_end_scene(move |_| first.lock().unwrap().push("first"))
+            .on_end_scene(move |_| second.lock().unwrap().push("second"))
+            .install_for_device_in(FakeBackend::new(7), device.as_ptr())
+    }
+    .unwrap();
+    let seen = order.clone();
+    device.on(DeviceMethod::EndScene.index(), move |_| {
+        seen.lock().unwrap().push("EndScene");
+        D3dResult::InvalidCall.code()
+    });
+
+    let result = unsafe { method::<EndSceneFn>(&device, DeviceMethod::EndScene)(device.as_ptr()) };
+
+    assert_eq!(result, D3dResult::InvalidCall.code());
+    assert_eq!(*order.lock().unwrap(), vec!["first", "second", "EndScene"]);
+    drop(hooks);
+}
+
+#[test]
+fn only_methods_with_callbacks_are_hooked() {
+    let _serial = serial();
+    let device = MockDevice::new();
+    let before: Vec<usize> = (0..device.slot_count())
+        .map(|slot| unsafe { *device.vtable().add(slot) })
+        .collect();
+
+    let hooks = unsafe {
+        D3D9Hooks::new()
+            .on_present(|_, _, _, _, _| ())
+            .install_for_device_in(FakeBackend::new(7), device.as_ptr())
+    }
+    .unwrap();
+
+    assert!(hooks.is_hooked(DeviceMethod::Present));
+    assert!(!hooks.is_hooked(DeviceMethod::EndScene));
+    assert!(!hooks.is_hooked(DeviceMethod::Reset));
+    let changed: Vec<usize> = (0..device.slot_count())
+        .filter(|&slot| unsafe { *device.vtable().add(slot) } != before[slot])
+        .collect();
+    assert_eq!(changed, vec![DeviceMethod::Present.index()]);
+
+    drop(hooks);
+    let after: Vec<usize> = (0..device.slot_count())
+        .map(|slot| unsafe { *device.vtable().add(slot) })
+        .collect();
+    assert_eq!(after, before);
+}
+
+#[test]
+fn present_callbacks_see_the_arguments() {
+    let _serial = serial();
+    let device = MockDevice::new();
+    let seen = Arc::new(Mutex::new(None));
+    let record = seen.clone();
+    let hooks = unsafe {
+        D3D9Hooks::new()
+            .on_present(move |device, source, dest, window, region| {
+                *record.lock().unwrap() = Some((
+                    device as usize,
+                    source as usize,
+                    dest as usize,
+                    window as usize,
+                    region as usize,
+                ));
+            })
+            .install_for_device_in(FakeBackend::new(7), device.as_ptr())
+    }
+    .unwrap();
+
+    let source: RECT = unsafe { mem::zeroed() };
+    let result = unsafe {
+        method::<PresentFn>(&device, DeviceMethod::Present)(
+            device.as_ptr(),
+            &source,
+            ptr::null(),
+            fake_hwnd(3),
+            ptr::null(),
+        )
+    };
+
+    assert_eq!(result, 0);
+    assert_eq!(
+        *seen.lock().unwrap(),
+        Some((
+            device.as_ptr() as usize,
+            &source as *const RECT as usize,
+            0,
+            fake_hwnd(3) as usize,
+            0
+        ))
+    );
+    assert_eq!(device.call_count(DeviceMethod::Present.index()), 1);
+    drop(hooks);
+}
+
+#[test]
+fn reset_callbacks_run_around_the_method() {
+    let _serial = serial();
+    let device = MockDevice::new();
+    device.on(DeviceMethod::Reset.index(), |_| {
+        D3dResult::DeviceLost.code()
+    });
+    let (after, after_count) = counter();
+    let hooks = unsafe {
+        D3D9Hooks::new()
+            .on_reset(
+                |_, params| params.BackBufferWidth = 640,
+                move |_, params, result| {
+                    assert_eq!(params.BackBufferWidth, 640);
+                    assert_eq!(result, D3dResult::DeviceLost);
+                    after.fetch_add(1, Ordering::SeqCst);
+                },
+            )
+            .install_for_device_in(FakeBackend::new(7), device.as_ptr())
+    }
+    .unwrap();
+
+    let mut params: D3DPRESENT_PARAMETERS = unsafe { mem::zeroed() };
+    let result =
+        unsafe { method::<ResetFn>(&device, DeviceMethod::Reset)(device.as_ptr(), &mut params) };
+
+    assert_eq!(result, D3dResult::DeviceLost.code());
+    assert_eq!(params.BackBufferWidth, 640);
+    assert_eq!(after_count.load(Ordering::SeqCst), 1);
+
+    // Without parameters there is nothing to show the callbacks, but the method still runs.
+    let result = unsafe {
+        method::<ResetFn>(&device, DeviceMethod::Reset)(device.as_ptr(), ptr::null_mut())
+    };
+    assert_eq!(result, D3dResult::DeviceLost.code());
+    assert_eq!(after_count.load(Ordering::SeqCst), 1);
+    assert_eq!(device.call_count(DeviceMethod::Reset.index()), 2);
+    drop(hooks);
+}
+
+#[test]
+fn draw_indexed_primitive_callbacks_see_the_arguments() {
+    let _serial = serial();
+    let device = MockDevice::new();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let record = seen.clone();
+    let hooks = unsafe {
+        D3D9Hooks::new()
+            .on_draw_indexed_primitive(move |_, kind, base, min, vertices, start, count| {
+                record
+                    .lock()
+                    .unwrap()
+                    .push((kind, base, min, vertices, start, count));
+            })
+            .install_for_device_in(FakeBackend::new(7), device.as_ptr())
+    }
+    .unwrap();
+
+    unsafe {
+        method::<DrawIndexedPrimitiveFn>(&device, DeviceMethod::DrawIndexedPrimitive)(
+            device.as_ptr(),
+            D3DPT_TRIANGLELIST,
+            -4,
+            1,
+            300,
+            12,
+            100,
+        )
+    };
+
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec![(D3DPT_TRIANGLELIST, -4, 1, 300, 12, 100)]
+    );
+    drop(hooks);
+}
+
+#[test]
+fn one_set_of_hooks_at_a_time() {
+    let _serial = serial();
+    let device = MockDevice::new();
+    let hooks = unsafe {
+        D3D9Hooks::new()
+            .on_end_scene(|_| ())
+            .install_for_device_in(FakeBackend::new(7), device.as_ptr())
+    }
+    .unwrap();
+
+    let other = MockDevice::new();
+    let err = unsafe {
+        D3D9Hooks::new()
+            .on_end_scene(|_| ())
+            .install_for_device_in(FakeBackend::new(7), other.as_ptr())
+    }
+    .err()
+    .unwrap();
+    assert!(matches!(err, HookError::AlreadyInstalled));
+
+    drop(hooks);
+    let hooks = unsafe {
+        D3D9Hooks::new()
+            .on_end_scene(|_| ())
+            .install_for_device_in(FakeBackend::new(7), other.as_ptr())
+    };
+    assert!(hooks.is_ok());
+}
+
+#[test]
+fn install_finds_the_vtable_through_a_dummy_device() {
+    let _serial = serial();
+    let device = MockDevice::new();
+    let direct3d9 = MockDirect3D9::new().with_device(&device);
+    let backend = hidden_window_backend(&direct3d9);
+    let (count, calls) = counter();
+
+    let hooks = unsafe {
+        D3D9Hooks::new()
+            .on_end_scene(move |_| {
+                count.fetch_add(1, Ordering::SeqCst);
+            })
+            .install_in(backend)
+    }
+    .unwrap();
+
+    // The dummy device and its window are gone, but its vtable stays hooked.
+    assert_eq!(device.ref_count(), 1);
+    assert_eq!(direct3d9.ref_count(), 0);
+    unsafe { method::<EndSceneFn>(&device, DeviceMethod::EndScene)(device.as_ptr()) };
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    drop(hooks);
+    unsafe { method::<EndSceneFn>(&device, DeviceMethod::EndScene)(device.as_ptr()) };
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,123 @@
+//! `Detour` patching functions copied into heap buffers.
+
+use std::ffi::c_void;
+use std::slice;
+
+use d3d9_device_grabber::backend::FakeBackend;
+use d3d9_device_grabber::hook::detour::{build_patch, encode_jump, jump_len, Arch, Detour};
+use d3d9_device_grabber::HookError;
+
+type FunctionFn = unsafe extern "system" fn();
+
+unsafe extern "system" fn detour() {}
+
+/// `push ebp; mov ebp, esp; sub esp, 0x20` or `push rbp; mov rbp, rsp; sub rsp, 0x20`,
+/// followed by `nop`s.
+fn function() -> Vec<u8> {
+    let mut function = match Arch::HOST {
+        Arch::X86 => vec![0x55, 0x8B, 0xEC, 0x83, 0xEC, 0x20],
+        Arch::X64 => vec![0x55, 0x48, 0x89, 0xE5, 0x48, 0x83, 0xEC, 0x20],
+    };
+    function.resize(40, 0x90);
+    function
+}
+
+/// Where the first instruction of [`function`] ending at or after `len` ends.
+fn instruction_boundary(len: usize) -> usize {
+    let prologue: &[usize] = match Arch::HOST {
+        Arch::X86 => &[1, 3, 6],
+        Arch::X64 => &[1, 4, 8],
+    };
+    prologue
+        .iter()
+        .copied()
+        .find(|&end| end >= len)
+        .unwrap_or(len)
+}
+
+#[test]
+fn patches_the_function_and_restores_it_on_drop() {
+    let mut function = function();
+    let before = function.clone();
+    let target = function.as_mut_ptr() as *const c_void;
+
+    let hook =
+        unsafe { Detour::new_in(FakeBackend::new(7), target, detour as FunctionFn) }.unwrap();
+
+    let target_address = target as usize;
+    let trampoline = hook.trampoline() as usize;
+    let detour_address = detour as *const () as usize;
+    let stolen_len = instruction_boundary(jump_len(Arch::HOST, target_address, detour_address));
+    let patch = build_patch(Arch::HOST, target_address, detour_address, stolen_len);
+    assert_eq!(&function[..stolen_len], &patch[..]);
+    assert_eq!(&function[stolen_len..], &before[stolen_len..]);
+
+    let mut expected = before[..stolen_len].to_vec();
+    expected.extend(encode_jump(
+        Arch::HOST,
+        trampoline + stolen_len,
+        target_address + stolen_len,
+    ));
+    let code = unsafe { slice::from_raw_parts(trampoline as *const u8, expected.len()) };
+    assert_eq!(code, &expected[..]);
+    assert_eq!(hook.original().get() as usize, trampoline);
+    assert_eq!(hook.backend().live_allocations(), 1);
+    assert_eq!(
+        hook.backend().flushed_ranges(),
+        vec![(trampoline, expected.len()), (target_address, stolen_len)]
+    );
+    assert!(hook.backend().unprotected_ranges().is_empty());
+
+    drop(hook);
+    assert_eq!(function, before);
+}
+
+#[test]
+fn dropping_keeps_the_trampoline_for_threads_still_in_the_detour() {
+    let mut function = function();
+    let before = function.clone();
+    let target = function.as_mut_ptr() as *const c_void;
+    let backend = FakeBackend::new(7);
+
+    let hook = unsafe { Detour::new_in(&backend, target, detour as FunctionFn) }.unwrap();
+    assert_eq!(backend.live_allocations(), 1);
+    drop(hook);
+
+    assert_eq!(function, before);
+    assert_eq!(backend.live_allocations(), 1);
+    // The function is no longer detoured, so it can be again.
+    let hook = unsafe { Detour::new_in(FakeBackend::new(7), target, detour as FunctionFn) };
+    assert!(hook.is_ok());
+}
+
+#[test]
+fn a_function_is_detoured_at_most_once() {
+    let mut function = function();
+    let target = function.as_mut_ptr() as *const c_void;
+    let _hook =
+        unsafe { Detour::new_in(FakeBackend::new(7), target, detour as FunctionFn) }.unwrap();
+
+    let err = unsafe { Detour::new_in(FakeBackend::new(7), target, detour as FunctionFn) }
+        .err()
+        .unwrap();
+
+    assert!(matches!(err, HookError::AlreadyDetoured { address } if address == target as usize));
+}
+
+#[test]
+fn read_only_functions_are_left_alone() {
+    let mut function = function();
+    let before = function.clone();
+    let target = function.as_mut_ptr() as *const c_void;
+    let backend = FakeBackend::new(7).with_read_only_memory();
+
+    let err = unsafe { Detour::new_in(backend, target, detour as FunctionFn) }
+        .err()
+        .unwrap();
+
+    assert!(matches!(err, HookError::ProtectFailed { address, .. } if address == target as usize));
+    assert_eq!(function, before);
+    // Nothing is recorded, so it can be detoured once it is writable.
+    let hook = unsafe { Detour::new_in(FakeBackend::new(7), target, detour as FunctionFn) };
+    assert!(hook.is_ok());
+}
@@ -0,0 +1,68 @@
+//! Grabbing an `IDirect3DDevice9Ex`, and the default chain it is created with.
+
+use d3d9_device_grabber::backend::{fake_hwnd, FakeBackend};
+use d3d9_device_grabber::sys::*;
+use d3d9_device_grabber::testing::{MockDevice, MockDirect3D9};
+use d3d9_device_grabber::{D3D9GrabError, D3dResult, DeviceGrabber};
+
+const NOT_AVAILABLE: HRESULT = D3dResult::NotAvailable.code();
+
+#[test]
+fn process_window_tries_fullscreen_then_windowed() {
+    let device = MockDevice::new_ex();
+    let direct3d9 = MockDirect3D9::new_ex()
+        .with_device(&device)
+        .with_create_device_results(vec![NOT_AVAILABLE]);
+    let backend = FakeBackend::new(7)
+        .with_window(fake_hwnd(1), 7)
+        .with_direct3d9_ex(direct3d9.as_ex_ptr());
+
+    let (grabbed, window) =
+        unsafe { DeviceGrabber::new().device_ex_with_hwnd_in(&backend) }.unwrap();
+
+    assert_eq!(window, fake_hwnd(1));
+    assert_eq!(grabbed.as_ptr(), device.as_ex_ptr());
+    let calls = direct3d9.create_device_calls();
+    assert_eq!(calls.len(), 2);
+    assert_eq!(calls[0].present_params.Windowed, FALSE);
+    assert_eq!(calls[1].present_params.Windowed, TRUE);
+}
+
+#[test]
+fn hidden_window_is_only_tried_windowed() {
+    let device = MockDevice::new_ex();
+    let direct3d9 = MockDirect3D9::new_ex()
+        .with_device(&device)
+        .with_create_device_results(vec![NOT_AVAILABLE]);
+    let backend = FakeBackend::new(7)
+        .with_hidden_window(fake_hwnd(99))
+        .with_direct3d9_ex(direct3d9.as_ex_ptr());
+
+    let err = unsafe {
+        DeviceGrabber::new()
+            .hidden_window(true)
+            .device_ex_in(&backend)
+    }
+    .err()
+    .unwrap();
+
+    match err {
+        D3D9GrabError::CreateDeviceError { attempts } => {
+            assert_eq!(attempts.len(), 1);
+            assert!(attempts[0].windowed());
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+    assert_eq!(direct3d9.ref_count(), 0);
+    assert_eq!(backend.hidden_windows_destroyed(), 1);
+}
+
+#[test]
+fn missing_direct3d_create9_ex_is_an_error() {
+    let backend = FakeBackend::new(7).with_window(fake_hwnd(1), 7);
+
+    let err = unsafe { DeviceGrabber::new().device_ex_in(&backend) }
+        .err()
+        .unwrap();
+    assert!(matches!(err, D3D9GrabError::Direct3DCreate9ExUnavailable));
+}
@@ -0,0 +1,248 @@
+//! `DeviceRegistry` filled in by the hooked `Present`, `IDirect3DSwapChain9::Present`,
+//! `Reset` and `Release`, and the `DeviceFilter` it answers.
+
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use d3d9_device_grabber::backend::{fake_hwnd, FakeBackend};
+use d3d9_device_grabber::hook::{D3D9Hooks, DeviceFilter, DeviceRegistry};
+use d3d9_device_grabber::sys::*;
+use d3d9_device_grabber::testing::{MockDevice, MockSwapChain};
+use d3d9_device_grabber::{DeviceMethod, SwapChainMethod};
+
+mod common;
+
+use common::{method, serial};
+
+type EndSceneFn = unsafe extern "system" fn(*mut IDirect3DDevice9) -> HRESULT;
+type PresentFn = unsafe extern "system" fn(
+    *mut IDirect3DDevice9,
+    *const RECT,
+    *const RECT,
+    HWND,
+    *const RGNDATA,
+) -> HRESULT;
+type SwapChainPresentFn = unsafe extern "system" fn(
+    *mut IDirect3DSwapChain9,
+    *const RECT,
+    *const RECT,
+    HWND,
+    *const RGNDATA,
+    DWORD,
+) -> HRESULT;
+type ResetFn =
+    unsafe extern "system" fn(*mut IDirect3DDevice9, *mut D3DPRESENT_PARAMETERS) -> HRESULT;
+type ReleaseFn = unsafe extern "system" fn(*mut IDirect3DDevice9) -> u32;
+
+unsafe fn present(device: &MockDevice, window_override: HWND) {
+    method::<PresentFn>(device, DeviceMethod::Present)(
+        device.as_ptr(),
+        ptr::null(),
+        ptr::null(),
+        window_override,
+        ptr::null(),
+    );
+}
+
+unsafe fn swap_chain_present(swap_chain: &MockSwapChain, window_override: HWND) {
+    let present: SwapChainPresentFn =
+        mem::transmute(*swap_chain.vtable().add(SwapChainMethod::Present.index()));
+    present(
+        swap_chain.as_ptr(),
+        ptr::null(),
+        ptr::null(),
+        window_override,
+        ptr::null(),
+        0,
+    );
+}
+
+#[test]
+fn records_devices_and_filters_callbacks() {
+    let _serial = serial();
+    let first_swap_chain = MockSwapChain::new().with_device_window(fake_hwnd(11));
+    let second_swap_chain = MockSwapChain::new().with_device_window(fake_hwnd(22));
+    let first = MockDevice::new().with_swap_chain(&first_swap_chain);
+    let second = MockDevice::new()
+        .with_swap_chain(&second_swap_chain)
+        .with_shared_vtable(&first);
+    let devices = DeviceRegistry::new();
+    let presented = Arc::new(AtomicUsize::new(0));
+    let ended = Arc::new(AtomicUsize::new(0));
+    let (last_presented, end_scenes) = (presented.clone(), ended.clone());
+    let hooks = unsafe {
+        D3D9Hooks::new()
+            .with_devices(devices.clone())
+            .only(DeviceFilter::window(fake_hwnd(22)))
+            .on_present(move |device, _, _, _, _| {
+                last_presented.store(device as usize, Ordering::SeqCst);
+            })
+            .on_end_scene(move |_| {
+                end_scenes.fetch_add(1, Ordering::SeqCst);
+            })
+            .install_for_device_in(FakeBackend::new(7), first.as_ptr())
+    }
+    .unwrap();
+    let end_scene = unsafe { method::<EndSceneFn>(&first, DeviceMethod::EndScene) };
+
+    unsafe {
+        end_scene(first.as_ptr());
+        present(&first, ptr::null_mut());
+    }
+    assert_eq!(presented.load(Ordering::SeqCst), 0);
+    assert_eq!(ended.load(Ordering::SeqCst), 0);
+
+    unsafe {
+        present(&second, ptr::null_mut());
+        end_scene(second.as_ptr());
+    }
+    assert_eq!(presented.load(Ordering::SeqCst), second.as_ptr() as usize);
+    assert_eq!(ended.load(Ordering::SeqCst), 1);
+
+    // Presenting to the filtered window through an override lets the first device through.
+    unsafe {
+        present(&first, fake_hwnd(33));
+        present(&first, fake_hwnd(22));
+    }
+    assert_eq!(presented.load(Ordering::SeqCst), first.as_ptr() as usize);
+
+    let infos = devices.devices();
+    assert_eq!(infos.len(), 2);
+    assert_eq!(infos[0].device, first.as_ptr());
+    assert_eq!(infos[0].window, fake_hwnd(11));
+    assert_eq!(
+        infos[0].presented_to,
+        vec![fake_hwnd(11), fake_hwnd(33), fake_hwnd(22)]
+    );
+    assert_eq!(infos[0].presents, 3);
+    assert_eq!(infos[1].presents, 1);
+    assert_eq!(
+        devices.windows(),
+        vec![fake_hwnd(11), fake_hwnd(33), fake_hwnd(22)]
+    );
+    assert_eq!(
+        devices.device_for_window(fake_hwnd(22)),
+        Some(first.as_ptr())
+    );
+    assert!(devices.matches(first.as_ptr(), &DeviceFilter::first()));
+    assert!(!devices.matches(second.as_ptr(), &DeviceFilter::first()));
+    assert!(devices.matches(second.as_ptr(), &DeviceFilter::device(second.as_ptr())));
+    // Every swap chain asked for its window was released again.
+    assert_eq!(first_swap_chain.ref_count(), 1);
+    assert_eq!(second_swap_chain.ref_count(), 1);
+    assert_eq!(first.call_count(DeviceMethod::Present.index()), 3);
+
+    drop(hooks);
+    devices.clear();
+    assert!(devices.is_empty());
+}
+
+#[test]
+fn additional_swap_chains_register_their_windows() {
+    let _serial = serial();
+    let implicit = MockSwapChain::new().with_device_window(fake_hwnd(11));
+    let device = MockDevice::new().with_swap_chain(&implicit);
+    let additional = MockSwapChain::new()
+        .with_device_window(fake_hwnd(44))
+        .with_device(&device)
+        .with_shared_vtable(&implicit);
+    let devices = DeviceRegistry::new();
+    let present_slot = || unsafe { *implicit.vtable().add(SwapChainMethod::Present.index()) };
+    let unhooked = present_slot();
+    let hooks = unsafe {
+        D3D9Hooks::new()
+            .with_devices(devices.clone())
+            .install_for_device_in(FakeBackend::new(7), device.as_ptr())
+    }
+    .unwrap();
+    assert_ne!(present_slot(), unhooked);
+    assert!(hooks.is_swap_chain_hooked(SwapChainMethod::Present));
+
+    unsafe {
+        swap_chain_present(&additional, ptr::null_mut());
+        swap_chain_present(&additional, fake_hwnd(55));
+    }
+
+    let info = devices.device(device.as_ptr()).unwrap();
+    assert_eq!(info.window, fake_hwnd(11));
+    assert_eq!(info.presented_to, vec![fake_hwnd(44), fake_hwnd(55)]);
+    assert_eq!(info.presents, 2);
+    assert_eq!(
+        devices.device_for_window(fake_hwnd(44)),
+        Some(device.as_ptr())
+    );
+    // The original ran, and the reference `GetDevice` added was given back.
+    assert_eq!(additional.call_count(SwapChainMethod::Present.index()), 2);
+    assert_eq!(device.ref_count(), 1);
+
+    drop(hooks);
+    assert_eq!(present_slot(), unhooked);
+}
+
+#[test]
+fn final_release_forgets_the_device() {
+    let _serial = serial();
+    let swap_chain = MockSwapChain::new().with_device_window(fake_hwnd(11));
+    let device = MockDevice::new().with_swap_chain(&swap_chain);
+    let devices = DeviceRegistry::new();
+    let hooks = unsafe {
+        D3D9Hooks::new()
+            .with_devices(devices.clone())
+            .install_for_device_in(FakeBackend::new(7), device.as_ptr())
+    }
+    .unwrap();
+    assert!(hooks.is_hooked(DeviceMethod::Release));
+    let release = unsafe { method::<ReleaseFn>(&device, DeviceMethod::Release) };
+
+    unsafe {
+        present(&device, ptr::null_mut());
+        com_add_ref(&device);
+        assert_eq!(release(device.as_ptr()), 1);
+    }
+    assert!(devices.device(device.as_ptr()).is_some());
+
+    assert_eq!(unsafe { release(device.as_ptr()) }, 0);
+    assert!(devices.is_empty());
+    drop(hooks);
+}
+
+#[test]
+fn reset_asks_for_the_window_again() {
+    let _serial = serial();
+    let swap_chain = MockSwapChain::new().with_device_window(fake_hwnd(11));
+    let device = MockDevice::new().with_swap_chain(&swap_chain);
+    let devices = DeviceRegistry::new();
+    let hooks = unsafe {
+        D3D9Hooks::new()
+            .with_devices(devices.clone())
+            .install_for_device_in(FakeBackend::new(7), device.as_ptr())
+    }
+    .unwrap();
+    assert!(hooks.is_hooked(DeviceMethod::Reset));
+    let reset = unsafe { method::<ResetFn>(&device, DeviceMethod::Reset) };
+    let mut params: D3DPRESENT_PARAMETERS = unsafe { mem::zeroed() };
+
+    unsafe {
+        present(&device, ptr::null_mut());
+        present(&device, fake_hwnd(33));
+    }
+    swap_chain.set_device_window(fake_hwnd(22));
+    assert_eq!(unsafe { reset(device.as_ptr(), &mut params) }, 0);
+
+    let info = devices.device(device.as_ptr()).unwrap();
+    assert_eq!(info.window, fake_hwnd(22));
+    assert!(info.presented_to.is_empty());
+    assert_eq!(devices.device_for_window(fake_hwnd(11)), None);
+
+    unsafe { present(&device, ptr::null_mut()) };
+    assert_eq!(devices.windows(), vec![fake_hwnd(22)]);
+    drop(hooks);
+}
+
+/// Take another reference to `device` through its unhooked `AddRef`.
+unsafe fn com_add_ref(device: &MockDevice) {
+    let add_ref: ReleaseFn = method(device, DeviceMethod::AddRef);
+    add_ref(device.as_ptr());
+}
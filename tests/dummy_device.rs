@@ -0,0 +1,129 @@
+//! Ownership of the COM objects behind a `DummyDevice`.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use d3d9_device_grabber::backend::{fake_hwnd, FakeBackend};
+use d3d9_device_grabber::com::{ADD_REF_SLOT, RELEASE_SLOT};
+use d3d9_device_grabber::testing::{MockDevice, MockDirect3D9, MockSwapChain};
+use d3d9_device_grabber::{D3D9GrabError, DeviceGrabber, DeviceMethod};
+
+mod common;
+
+use common::{backend, hidden_window_backend};
+
+#[test]
+fn holds_one_reference_to_each_interface() {
+    let device = MockDevice::new();
+    let direct3d9 = MockDirect3D9::new().with_device(&device);
+
+    let grabbed = unsafe { DeviceGrabber::new().device_in(&backend(&direct3d9)) }.unwrap();
+
+    // The mocks start at one, standing in for the references of whoever created them.
+    assert_eq!(device.ref_count(), 2);
+    assert_eq!(direct3d9.ref_count(), 1);
+    assert_eq!(grabbed.as_ptr(), device.as_ptr());
+    assert_eq!(grabbed.direct3d9(), direct3d9.as_ptr());
+
+    drop(grabbed);
+    assert_eq!(device.ref_count(), 1);
+    assert_eq!(direct3d9.ref_count(), 0);
+    let slots: Vec<usize> = device.calls().iter().map(|call| call.slot).collect();
+    assert_eq!(slots, vec![ADD_REF_SLOT, RELEASE_SLOT]);
+}
+
+#[test]
+fn releases_the_device_before_direct3d9() {
+    let released = Rc::new(RefCell::new(Vec::new()));
+    let device = MockDevice::new();
+    let direct3d9 = MockDirect3D9::new().with_device(&device);
+    let log = released.clone();
+    device.on(RELEASE_SLOT, move |_| {
+        log.borrow_mut().push("device");
+        0
+    });
+    let log = released.clone();
+    direct3d9.on(RELEASE_SLOT, move |_| {
+        log.borrow_mut().push("direct3d9");
+        0
+    });
+
+    let grabbed = unsafe { DeviceGrabber::new().device_in(&backend(&direct3d9)) }.unwrap();
+    assert!(released.borrow().is_empty());
+    drop(grabbed);
+
+    assert_eq!(*released.borrow(), vec!["device", "direct3d9"]);
+}
+
+#[test]
+fn destroys_the_hidden_window_after_the_device() {
+    let device = MockDevice::new();
+    let direct3d9 = MockDirect3D9::new().with_device(&device);
+    let backend = hidden_window_backend(&direct3d9);
+
+    let grabbed = unsafe { DeviceGrabber::new().hidden_window(true).device_in(&backend) }.unwrap();
+    assert_eq!(backend.hidden_windows_destroyed(), 0);
+    drop(grabbed);
+
+    assert_eq!(device.ref_count(), 1);
+    assert_eq!(backend.hidden_windows_destroyed(), 1);
+}
+
+#[test]
+fn swap_chain_is_released_on_drop() {
+    let swap_chain = MockSwapChain::new();
+    let device = MockDevice::new().with_swap_chain(&swap_chain);
+    let direct3d9 = MockDirect3D9::new().with_device(&device);
+    let grabbed = unsafe { DeviceGrabber::new().device_in(&backend(&direct3d9)) }.unwrap();
+
+    let implicit = grabbed.swap_chain(0).unwrap();
+    assert_eq!(implicit.as_ptr(), swap_chain.as_ptr());
+    assert_eq!(swap_chain.ref_count(), 2);
+    drop(implicit);
+    assert_eq!(swap_chain.ref_count(), 1);
+
+    let call = device
+        .calls()
+        .into_iter()
+        .find(|call| call.slot == DeviceMethod::GetSwapChain.index())
+        .unwrap();
+    assert_eq!(call.args[0], 0);
+}
+
+#[test]
+fn missing_swap_chain_is_an_error() {
+    let device = MockDevice::new();
+    let direct3d9 = MockDirect3D9::new().with_device(&device);
+    let grabbed = unsafe { DeviceGrabber::new().device_in(&backend(&direct3d9)) }.unwrap();
+
+    assert!(matches!(
+        grabbed.swap_chain(0),
+        Err(D3D9GrabError::GetSwapChainFailed(_))
+    ));
+}
+
+#[test]
+fn hidden_window_leaves_the_game_window_alone() {
+    let device = MockDevice::new();
+    let direct3d9 = MockDirect3D9::new().with_device(&device);
+    let backend = FakeBackend::new(7)
+        .with_window(fake_hwnd(1), 7)
+        .with_hidden_window(fake_hwnd(99))
+        .with_direct3d9(direct3d9.as_ptr());
+
+    let (grabbed, window) = unsafe {
+        DeviceGrabber::new()
+            .hidden_window(true)
+            .device_with_hwnd_in(&backend)
+    }
+    .unwrap();
+
+    assert_eq!(window, fake_hwnd(99));
+    // The process window is never looked up, nor given to d3d9.
+    assert!(backend.queried_windows().is_empty());
+    let calls = direct3d9.create_device_calls();
+    assert_eq!(calls.len(), 1);
+    assert!(calls.iter().all(|call| call.focus_window == fake_hwnd(99)
+        && call.present_params.hDeviceWindow == fake_hwnd(99)));
+    drop(grabbed);
+}
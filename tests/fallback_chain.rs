@@ -0,0 +1,227 @@
+//! `FallbackChain` setups tried in order against a mock `IDirect3D9`.
+
+use d3d9_device_grabber::backend::fake_hwnd;
+use d3d9_device_grabber::sys::*;
+use d3d9_device_grabber::testing::{MockDevice, MockDirect3D9};
+use d3d9_device_grabber::{
+    D3D9GrabError, D3dResult, DeviceGrabber, DeviceSetup, FallbackChain, PresentParamsBuilder,
+    PresentParamsError,
+};
+
+mod common;
+
+use common::backend;
+
+const NOT_AVAILABLE: HRESULT = D3dResult::NotAvailable.code();
+const INVALID_CALL: HRESULT = D3dResult::InvalidCall.code();
+const OUT_OF_VIDEO_MEMORY: HRESULT = D3dResult::OutOfVideoMemory.code();
+
+fn three_setups() -> FallbackChain {
+    FallbackChain::new()
+        .then(
+            DeviceSetup::new()
+                .behavior_flags(D3DCREATE_HARDWARE_VERTEXPROCESSING)
+                .windowed(false)
+                .back_buffer_size(800, 600)
+                .back_buffer_format(D3DFMT_X8R8G8B8),
+        )
+        .then(
+            DeviceSetup::new()
+                .device_type(D3DDEVTYPE_REF)
+                .swap_effect(D3DSWAPEFFECT_COPY),
+        )
+        .then(DeviceSetup::new().device_type(D3DDEVTYPE_NULLREF))
+}
+
+#[test]
+fn tries_every_setup_in_order() {
+    let direct3d9 = MockDirect3D9::new().with_create_device_results(vec![
+        NOT_AVAILABLE,
+        OUT_OF_VIDEO_MEMORY,
+        INVALID_CALL,
+    ]);
+
+    let err = unsafe {
+        DeviceGrabber::new()
+            .fallback(three_setups())
+            .device_in(&backend(&direct3d9))
+    }
+    .err()
+    .unwrap();
+
+    let calls = direct3d9.create_device_calls();
+    assert_eq!(calls.len(), 3);
+    assert_eq!(calls[0].device_type, D3DDEVTYPE_HAL);
+    assert_eq!(calls[0].behavior_flags, D3DCREATE_HARDWARE_VERTEXPROCESSING);
+    assert_eq!(calls[0].present_params.Windowed, FALSE);
+    assert_eq!(calls[0].present_params.BackBufferWidth, 800);
+    assert_eq!(calls[0].present_params.BackBufferHeight, 600);
+    assert_eq!(calls[0].present_params.BackBufferFormat, D3DFMT_X8R8G8B8);
+    assert_eq!(calls[1].device_type, D3DDEVTYPE_REF);
+    assert_eq!(calls[1].present_params.SwapEffect, D3DSWAPEFFECT_COPY);
+    assert_eq!(calls[2].device_type, D3DDEVTYPE_NULLREF);
+    assert!(calls.iter().all(|call| call.focus_window == fake_hwnd(1)
+        && call.present_params.hDeviceWindow == fake_hwnd(1)));
+
+    let message = err.to_string();
+    assert!(message.starts_with(
+        "d3d9.CreateDevice call failed: HAL fullscreen mode returned D3DERR_NOTAVAILABLE"
+    ));
+    assert!(message.contains(", then REF windowed mode returned D3DERR_OUTOFVIDEOMEMORY"));
+    assert!(message.contains(", then NULLREF windowed mode returned D3DERR_INVALIDCALL"));
+    match err {
+        D3D9GrabError::CreateDeviceError { attempts } => {
+            let results: Vec<D3dResult> = attempts.iter().map(|attempt| attempt.result).collect();
+            assert_eq!(
+                results,
+                vec![
+                    D3dResult::NotAvailable,
+                    D3dResult::OutOfVideoMemory,
+                    D3dResult::InvalidCall
+                ]
+            );
+            assert_eq!(attempts[1].device_type, D3DDEVTYPE_REF);
+            assert_eq!(
+                attempts[0].behavior_flags,
+                D3DCREATE_HARDWARE_VERTEXPROCESSING
+            );
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+    assert_eq!(direct3d9.ref_count(), 0);
+}
+
+#[test]
+fn stops_at_the_first_setup_that_works() {
+    let device = MockDevice::new();
+    let direct3d9 = MockDirect3D9::new()
+        .with_device(&device)
+        .with_create_device_results(vec![NOT_AVAILABLE]);
+
+    let grabbed = unsafe {
+        DeviceGrabber::new()
+            .fallback(three_setups())
+            .device_in(&backend(&direct3d9))
+    }
+    .unwrap();
+
+    assert_eq!(grabbed.as_ptr(), device.as_ptr());
+    let device_types: Vec<D3DDEVTYPE> = direct3d9
+        .create_device_calls()
+        .iter()
+        .map(|call| call.device_type)
+        .collect();
+    assert_eq!(device_types, vec![D3DDEVTYPE_HAL, D3DDEVTYPE_REF]);
+}
+
+#[test]
+fn default_chain_is_fullscreen_then_windowed() {
+    let direct3d9 =
+        MockDirect3D9::new().with_create_device_results(vec![NOT_AVAILABLE, INVALID_CALL]);
+
+    let err = unsafe { DeviceGrabber::new().device_in(&backend(&direct3d9)) }
+        .err()
+        .unwrap();
+
+    assert!(matches!(err, D3D9GrabError::CreateDeviceError { .. }));
+    let calls = direct3d9.create_device_calls();
+    assert_eq!(calls.len(), 2);
+    assert_eq!(calls[0].present_params.Windowed, FALSE);
+    assert_eq!(calls[1].present_params.Windowed, TRUE);
+    assert!(calls.iter().all(|call| call.device_type == D3DDEVTYPE_HAL
+        && call.behavior_flags == D3DCREATE_SOFTWARE_VERTEXPROCESSING));
+}
+
+#[test]
+fn empty_chain_fails_without_creating_a_device() {
+    let direct3d9 = MockDirect3D9::new();
+
+    let err = unsafe {
+        DeviceGrabber::new()
+            .fallback(FallbackChain::new())
+            .device_in(&backend(&direct3d9))
+    }
+    .err()
+    .unwrap();
+
+    assert_eq!(
+        err.to_string(),
+        "d3d9.CreateDevice call failed: the fallback chain is empty"
+    );
+    assert!(direct3d9.create_device_calls().is_empty());
+    assert_eq!(direct3d9.ref_count(), 0);
+}
+
+#[test]
+fn chain_collects_from_an_iterator() {
+    let chain: FallbackChain = [D3DDEVTYPE_HAL, D3DDEVTYPE_REF]
+        .iter()
+        .map(|&device_type| DeviceSetup::new().device_type(device_type))
+        .collect();
+
+    assert_eq!(
+        chain,
+        FallbackChain::new()
+            .then(DeviceSetup::new())
+            .then(DeviceSetup::new().device_type(D3DDEVTYPE_REF))
+    );
+    assert_eq!(
+        FallbackChain::for_hidden_window().setups(),
+        &[DeviceSetup::new()]
+    );
+    assert_eq!(FallbackChain::for_process_window().setups().len(), 2);
+}
+
+#[test]
+fn invalid_present_params_fail_before_creating_a_device() {
+    let direct3d9 = MockDirect3D9::new();
+    let chain = FallbackChain::new().then(DeviceSetup::new()).then(
+        DeviceSetup::new().present_params(PresentParamsBuilder::dummy_windowed().refresh_rate(75)),
+    );
+
+    let err = unsafe {
+        DeviceGrabber::new()
+            .fallback(chain)
+            .device_in(&backend(&direct3d9))
+    }
+    .err()
+    .unwrap();
+
+    assert!(matches!(
+        err,
+        D3D9GrabError::InvalidPresentParams {
+            index: 1,
+            source: PresentParamsError::RefreshRateWhenWindowed { refresh_rate: 75 },
+        }
+    ));
+    assert!(direct3d9.create_device_calls().is_empty());
+    assert_eq!(direct3d9.ref_count(), 0);
+}
+
+#[test]
+fn present_params_replace_the_chain_with_one_attempt() {
+    let device = MockDevice::new();
+    let direct3d9 = MockDirect3D9::new()
+        .with_device(&device)
+        .with_create_device_results(vec![NOT_AVAILABLE]);
+    let backend = backend(&direct3d9);
+    let grabber = DeviceGrabber::new()
+        .fallback(three_setups())
+        .present_params(PresentParamsBuilder::dummy_1x1());
+
+    let err = unsafe { grabber.device_in(&backend) }.err().unwrap();
+    assert!(
+        matches!(&err, D3D9GrabError::CreateDeviceError { attempts } if attempts.len() == 1),
+        "{}",
+        err
+    );
+    let (grabbed, window) = unsafe { grabber.device_with_hwnd_in(&backend) }.unwrap();
+
+    assert_eq!(window, fake_hwnd(1));
+    assert_eq!(grabbed.as_ptr(), device.as_ptr());
+    let calls = direct3d9.create_device_calls();
+    assert_eq!(calls.len(), 2);
+    assert!(calls.iter().all(|call| call.device_type == D3DDEVTYPE_HAL
+        && call.present_params.BackBufferWidth == 1
+        && call.present_params.Windowed == TRUE));
+}
@@ -0,0 +1,118 @@
+//! The mock COM objects, and the fullscreen-then-windowed retry driven through them.
+
+use std::ffi::c_void;
+
+use d3d9_device_grabber::backend::{fake_hwnd, FakeBackend};
+use d3d9_device_grabber::sys::*;
+use d3d9_device_grabber::testing::{MockCall, MockDevice, MockDirect3D9};
+use d3d9_device_grabber::{D3D9GrabError, D3dResult, DeviceGrabber, DeviceMethod};
+
+mod common;
+
+use common::{method, slot};
+
+const NOT_AVAILABLE: HRESULT = D3dResult::NotAvailable.code();
+const INVALID_CALL: HRESULT = D3dResult::InvalidCall.code();
+
+fn process_window_backend(direct3d9: &MockDirect3D9) -> FakeBackend {
+    FakeBackend::new(7)
+        .with_window(fake_hwnd(1), 3)
+        .with_window(fake_hwnd(2), 7)
+        .with_direct3d9(direct3d9.as_ptr())
+}
+
+#[test]
+fn mock_slots_record_calls_and_run_handlers() {
+    let device = MockDevice::new();
+    device.on(DeviceMethod::EndScene.index(), |_| NOT_AVAILABLE);
+    type EndSceneFn = unsafe extern "system" fn(*mut IDirect3DDevice9) -> HRESULT;
+
+    let end_scene: EndSceneFn = unsafe { method(&device, DeviceMethod::EndScene) };
+    let begin_scene: EndSceneFn = unsafe { method(&device, DeviceMethod::BeginScene) };
+    assert_eq!(unsafe { end_scene(device.as_ptr()) }, NOT_AVAILABLE);
+    assert_eq!(unsafe { begin_scene(device.as_ptr()) }, 0);
+
+    assert_eq!(device.slot_count(), 119);
+    assert_eq!(
+        device.calls(),
+        vec![
+            MockCall {
+                slot: DeviceMethod::EndScene.index(),
+                args: Vec::new(),
+            },
+            MockCall {
+                slot: DeviceMethod::BeginScene.index(),
+                args: Vec::new(),
+            },
+        ]
+    );
+    assert_eq!(device.call_count(DeviceMethod::EndScene.index()), 1);
+}
+
+#[test]
+fn retries_windowed_after_fullscreen_fails() {
+    let device = MockDevice::new();
+    let direct3d9 = MockDirect3D9::new()
+        .with_device(&device)
+        .with_create_device_results(vec![NOT_AVAILABLE]);
+    let backend = process_window_backend(&direct3d9);
+
+    let (grabbed, window) = unsafe { DeviceGrabber::new().device_with_hwnd_in(&backend) }.unwrap();
+
+    assert_eq!(window, fake_hwnd(2));
+    assert_eq!(grabbed.as_ptr(), device.as_ptr());
+    let calls = direct3d9.create_device_calls();
+    assert_eq!(calls.len(), 2);
+    assert_eq!(calls[0].present_params.Windowed, FALSE);
+    assert_eq!(calls[1].present_params.Windowed, TRUE);
+    assert!(calls
+        .iter()
+        .all(|call| call.focus_window == fake_hwnd(2) && call.device_type == D3DDEVTYPE_HAL));
+    assert_eq!(
+        grabbed.vtable().end_scene,
+        slot(&device, DeviceMethod::EndScene) as *const c_void
+    );
+}
+
+#[test]
+fn stops_at_fullscreen_when_it_succeeds() {
+    let device = MockDevice::new();
+    let direct3d9 = MockDirect3D9::new().with_device(&device);
+    let backend = process_window_backend(&direct3d9);
+
+    let grabbed = unsafe { DeviceGrabber::new().device_in(&backend) }.unwrap();
+
+    let calls = direct3d9.create_device_calls();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].present_params.Windowed, FALSE);
+    drop(grabbed);
+}
+
+#[test]
+fn reports_both_attempts_when_every_mode_fails() {
+    let direct3d9 =
+        MockDirect3D9::new().with_create_device_results(vec![NOT_AVAILABLE, INVALID_CALL]);
+    let backend = process_window_backend(&direct3d9);
+
+    let err = unsafe { DeviceGrabber::new().device_in(&backend) }
+        .err()
+        .unwrap();
+
+    match &err {
+        D3D9GrabError::CreateDeviceError { attempts } => {
+            assert_eq!(attempts.len(), 2);
+            assert_eq!(attempts[0].result, D3dResult::NotAvailable);
+            assert!(!attempts[0].windowed());
+            assert_eq!(attempts[1].result, D3dResult::InvalidCall);
+            assert!(attempts[1].windowed());
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+    let message = err.to_string();
+    assert!(message.starts_with(
+        "d3d9.CreateDevice call failed: HAL fullscreen mode returned D3DERR_NOTAVAILABLE"
+    ));
+    assert!(message.contains(", then HAL windowed mode returned D3DERR_INVALIDCALL"));
+    // The IDirect3D9 from Direct3DCreate9 is released on failure too.
+    assert_eq!(direct3d9.ref_count(), 0);
+}
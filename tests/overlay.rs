@@ -0,0 +1,202 @@
+//! `Batch::flush` drawing on a mock device, inside the game's scene or in one of its own.
+
+use d3d9_device_grabber::com::STATE_BLOCK_VTABLE_LEN;
+use d3d9_device_grabber::overlay::{Batch, Color, Rect};
+use d3d9_device_grabber::sys::*;
+use d3d9_device_grabber::testing::{MockDevice, MockStateBlock};
+use d3d9_device_grabber::{D3dResult, DeviceMethod, OverlayError};
+
+const APPLY_SLOT: usize = STATE_BLOCK_VTABLE_LEN - 1;
+
+fn batch() -> Batch {
+    let mut batch = Batch::new();
+    batch.fill_rect(Rect::new(0.0, 0.0, 5.0, 5.0), Color::WHITE);
+    batch.circle((10.0, 10.0), 5.0, 1.0, Color::BLACK);
+    batch
+}
+
+/// A device whose `BeginScene` fails, as it does between the game's `BeginScene` and
+/// `EndScene`.
+fn device_in_scene(state_block: &MockStateBlock) -> MockDevice {
+    let device = MockDevice::new().with_state_block(state_block);
+    device.on(DeviceMethod::BeginScene.index(), |_| {
+        D3dResult::InvalidCall.code()
+    });
+    device
+}
+
+fn position(device: &MockDevice, method: DeviceMethod) -> usize {
+    device
+        .calls()
+        .iter()
+        .position(|call| call.slot == method.index())
+        .unwrap()
+}
+
+#[test]
+fn flush_in_a_scene_draws_between_saving_and_restoring_state() {
+    let state_block = MockStateBlock::new();
+    let device = device_in_scene(&state_block);
+    let mut batch = batch();
+
+    unsafe { batch.flush(device.as_ptr()) }.unwrap();
+
+    assert!(batch.is_empty());
+    assert_eq!(device.call_count(DeviceMethod::DrawPrimitiveUP.index()), 1);
+    assert_eq!(device.call_count(DeviceMethod::SetFVF.index()), 1);
+    assert_eq!(device.call_count(DeviceMethod::EndScene.index()), 0);
+    let create = position(&device, DeviceMethod::CreateStateBlock);
+    assert!(create < position(&device, DeviceMethod::DrawPrimitiveUP));
+    assert_eq!(device.calls()[create].args[0], D3DSBT_ALL as usize);
+    assert_eq!(state_block.call_count(APPLY_SLOT), 1);
+    assert_eq!(state_block.ref_count(), 1);
+
+    // Nothing is left to draw, so the device is not touched again.
+    let calls = device.calls().len();
+    unsafe { batch.flush(device.as_ptr()) }.unwrap();
+    assert_eq!(device.calls().len(), calls);
+}
+
+#[test]
+fn flush_outside_a_scene_opens_one() {
+    let state_block = MockStateBlock::new();
+    let device = MockDevice::new().with_state_block(&state_block);
+    let mut batch = batch();
+
+    unsafe { batch.flush(device.as_ptr()) }.unwrap();
+
+    assert_eq!(device.call_count(DeviceMethod::BeginScene.index()), 1);
+    assert_eq!(device.call_count(DeviceMethod::EndScene.index()), 1);
+    let draw = position(&device, DeviceMethod::DrawPrimitiveUP);
+    assert!(position(&device, DeviceMethod::BeginScene) < draw);
+    assert!(draw < position(&device, DeviceMethod::EndScene));
+    assert_eq!(state_block.call_count(APPLY_SLOT), 1);
+}
+
+#[test]
+fn scene_is_closed_after_a_failed_draw() {
+    let state_block = MockStateBlock::new();
+    let device = MockDevice::new().with_state_block(&state_block);
+    device.on(DeviceMethod::DrawPrimitiveUP.index(), |_| {
+        D3dResult::InvalidCall.code()
+    });
+    let mut batch = batch();
+
+    let err = unsafe { batch.flush(device.as_ptr()) }.unwrap_err();
+
+    assert_eq!(
+        err,
+        OverlayError::DeviceCallFailed {
+            method: DeviceMethod::DrawPrimitiveUP,
+            result: D3dResult::InvalidCall,
+        }
+    );
+    assert!(err
+        .to_string()
+        .starts_with("IDirect3DDevice9.DrawPrimitiveUP call failed"));
+    assert!(batch.is_empty());
+    assert_eq!(device.call_count(DeviceMethod::EndScene.index()), 1);
+    assert_eq!(state_block.call_count(APPLY_SLOT), 1);
+    assert_eq!(state_block.ref_count(), 1);
+}
+
+#[test]
+fn failing_begin_scene_draws_nothing() {
+    let state_block = MockStateBlock::new();
+    let device = MockDevice::new().with_state_block(&state_block);
+    device.on(DeviceMethod::BeginScene.index(), |_| {
+        D3dResult::DeviceLost.code()
+    });
+    let mut batch = batch();
+
+    let err = unsafe { batch.flush(device.as_ptr()) }.unwrap_err();
+
+    assert_eq!(
+        err,
+        OverlayError::DeviceCallFailed {
+            method: DeviceMethod::BeginScene,
+            result: D3dResult::DeviceLost,
+        }
+    );
+    assert!(batch.is_empty());
+    assert_eq!(device.call_count(DeviceMethod::CreateStateBlock.index()), 0);
+    assert_eq!(device.call_count(DeviceMethod::EndScene.index()), 0);
+}
+
+#[test]
+fn pure_devices_cannot_save_their_state() {
+    // Without a state block, `CreateStateBlock` fails as it does on a pure device.
+    let device = MockDevice::new();
+    device.on(DeviceMethod::BeginScene.index(), |_| {
+        D3dResult::InvalidCall.code()
+    });
+    let mut batch = batch();
+
+    let err = unsafe { batch.flush(device.as_ptr()) }.unwrap_err();
+
+    assert!(matches!(
+        err,
+        OverlayError::DeviceCallFailed {
+            method: DeviceMethod::CreateStateBlock,
+            ..
+        }
+    ));
+    assert_eq!(device.call_count(DeviceMethod::DrawPrimitiveUP.index()), 0);
+}
+
+#[test]
+fn each_texture_gets_its_own_draw_call() {
+    let state_block = MockStateBlock::new();
+    let device = device_in_scene(&state_block);
+    let mut batch = Batch::new();
+    batch.fill_rect(Rect::new(0.0, 0.0, 5.0, 5.0), Color::WHITE);
+    batch.textured_quad(
+        0x1000 as *mut IDirect3DTexture9,
+        Rect::new(0.0, 0.0, 5.0, 5.0),
+        Rect::FULL_TEXTURE,
+        Color::WHITE,
+    );
+
+    unsafe { batch.flush(device.as_ptr()) }.unwrap();
+
+    assert_eq!(device.call_count(DeviceMethod::DrawPrimitiveUP.index()), 2);
+    assert_eq!(device.call_count(DeviceMethod::SetTexture.index()), 2);
+}
+
+#[test]
+fn flush_sets_up_blending_and_clamped_sampling() {
+    let state_block = MockStateBlock::new();
+    let device = device_in_scene(&state_block);
+
+    unsafe { batch().flush(device.as_ptr()) }.unwrap();
+
+    let calls = device.calls();
+    let set = |method: DeviceMethod, args: &[usize]| {
+        calls
+            .iter()
+            .any(|call| call.slot == method.index() && call.args.starts_with(args))
+    };
+    for &(state, value) in &[
+        (D3DRS_FILLMODE, D3DFILL_SOLID),
+        (D3DRS_BLENDOP, D3DBLENDOP_ADD),
+        (D3DRS_SEPARATEALPHABLENDENABLE, FALSE as DWORD),
+        (D3DRS_SRGBWRITEENABLE, FALSE as DWORD),
+        (D3DRS_ALPHABLENDENABLE, TRUE as DWORD),
+    ] {
+        assert!(
+            set(
+                DeviceMethod::SetRenderState,
+                &[state as usize, value as usize]
+            ),
+            "render state {} was not set to {}",
+            state,
+            value
+        );
+    }
+    for &state in &[D3DSAMP_ADDRESSU, D3DSAMP_ADDRESSV] {
+        assert!(set(
+            DeviceMethod::SetSamplerState,
+            &[0, state as usize, D3DTADDRESS_CLAMP as usize]
+        ));
+    }
+}
@@ -0,0 +1,111 @@
+//! Panicking `D3D9Hooks` callbacks on a mock device.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use d3d9_device_grabber::backend::FakeBackend;
+use d3d9_device_grabber::hook::{D3D9Hooks, InstalledHooks, PanicPolicy};
+use d3d9_device_grabber::sys::*;
+use d3d9_device_grabber::testing::MockDevice;
+use d3d9_device_grabber::DeviceMethod;
+
+mod common;
+
+use common::{method, serial};
+
+type EndSceneFn = unsafe extern "system" fn(*mut IDirect3DDevice9) -> HRESULT;
+
+/// Hook `EndScene` of `device` with one callback that always panics and one that panics on
+/// every other call, returning the hooks and how often each callback ran.
+fn install(
+    device: &MockDevice,
+    policy: PanicPolicy,
+) -> (
+    InstalledHooks<FakeBackend>,
+    Arc<AtomicUsize>,
+    Arc<AtomicUsize>,
+) {
+    let (always, every_other) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+    let (always_count, every_other_count) = (always.clone(), every_other.clone());
+    let hooks = unsafe {
+        D3D9Hooks::new()
+            .panic_policy(policy)
+            .on_end_scene(move |_| {
+                always_count.fetch_add(1, Ordering::SeqCst);
+                panic!("always");
+            })
+            .on_end_scene(move |_| {
+                if every_other_count.fetch_add(1, Ordering::SeqCst) % 2 == 0 {
+                    panic!("every other");
+                }
+            })
+            .install_for_device_in(FakeBackend::new(7), device.as_ptr())
+    }
+    .unwrap();
+    (hooks, always, every_other)
+}
+
+fn end_scene(device: &MockDevice) -> HRESULT {
+    unsafe { method::<EndSceneFn>(device, DeviceMethod::EndScene)(device.as_ptr()) }
+}
+
+#[test]
+fn panicking_callbacks_still_reach_the_method() {
+    let _serial = serial();
+    let device = MockDevice::new();
+    device.on(DeviceMethod::EndScene.index(), |_| 9);
+    let (hooks, always, every_other) = install(&device, PanicPolicy::new().disable_after(2));
+
+    for _ in 0..5 {
+        assert_eq!(end_scene(&device), 9);
+    }
+
+    assert_eq!(device.call_count(DeviceMethod::EndScene.index()), 5);
+    assert_eq!(always.load(Ordering::SeqCst), 2);
+    assert_eq!(every_other.load(Ordering::SeqCst), 5);
+    assert_eq!(hooks.disabled_callbacks(), 1);
+}
+
+#[test]
+fn never_disable_keeps_calling() {
+    let _serial = serial();
+    let device = MockDevice::new();
+    let (hooks, always, _) = install(&device, PanicPolicy::new().never_disable());
+
+    for _ in 0..5 {
+        end_scene(&device);
+    }
+
+    assert_eq!(always.load(Ordering::SeqCst), 5);
+    assert_eq!(hooks.disabled_callbacks(), 0);
+}
+
+#[test]
+fn reports_go_to_the_sink() {
+    let _serial = serial();
+    let device = MockDevice::new();
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let sink = reports.clone();
+    let policy = PanicPolicy::new()
+        .disable_after(1)
+        .report_to(move |report| {
+            sink.lock().unwrap().push((
+                report.method.to_owned(),
+                report.message.to_owned(),
+                report.disabled,
+            ))
+        });
+    let (hooks, _, _) = install(&device, policy);
+
+    end_scene(&device);
+    end_scene(&device);
+
+    assert_eq!(
+        *reports.lock().unwrap(),
+        vec![
+            ("EndScene".to_owned(), "always".to_owned(), true),
+            ("EndScene".to_owned(), "every other".to_owned(), true),
+        ]
+    );
+    assert_eq!(hooks.disabled_callbacks(), 2);
+}
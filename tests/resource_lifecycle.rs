@@ -0,0 +1,217 @@
+//! `ResourceRegistry` driven by the hooked `TestCooperativeLevel`, `Reset` and `ResetEx`
+//! of a mock device returning scripted codes.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ffi::c_void;
+use std::mem;
+use std::ptr;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use d3d9_device_grabber::backend::FakeBackend;
+use d3d9_device_grabber::hook::{D3D9Hooks, DeviceState, PanicPolicy, ResourceRegistry};
+use d3d9_device_grabber::sys::*;
+use d3d9_device_grabber::testing::MockDevice;
+use d3d9_device_grabber::{D3dResult, DeviceExMethod, DeviceMethod};
+
+mod common;
+
+use common::{method, serial};
+
+type TestCooperativeLevelFn = unsafe extern "system" fn(*mut IDirect3DDevice9) -> HRESULT;
+type ResetFn =
+    unsafe extern "system" fn(*mut IDirect3DDevice9, *mut D3DPRESENT_PARAMETERS) -> HRESULT;
+type ResetExFn = unsafe extern "system" fn(
+    *mut IDirect3DDevice9Ex,
+    *mut D3DPRESENT_PARAMETERS,
+    *mut c_void,
+) -> HRESULT;
+
+/// Make `slot` of `device` return `results` in turn, then `D3D_OK`.
+fn script(device: &MockDevice, slot: usize, results: &[D3dResult]) {
+    let results = Rc::new(RefCell::new(
+        results.iter().copied().collect::<VecDeque<_>>(),
+    ));
+    device.on(slot, move |_| {
+        results
+            .borrow_mut()
+            .pop_front()
+            .unwrap_or(D3dResult::Ok)
+            .code()
+    });
+}
+
+/// A registry with one resource on `device`, logging its callbacks.
+fn logged(device: &MockDevice) -> (ResourceRegistry, Arc<Mutex<Vec<&'static str>>>) {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let resources = ResourceRegistry::new();
+    let (lost, reset) = (log.clone(), log.clone());
+    resources.register(
+        device.as_ptr(),
+        move |_| lost.lock().unwrap().push("lost"),
+        move |_| reset.lock().unwrap().push("reset"),
+    );
+    (resources, log)
+}
+
+unsafe fn test_cooperative_level(device: &MockDevice) -> D3dResult {
+    let test_cooperative_level: TestCooperativeLevelFn =
+        method(device, DeviceMethod::TestCooperativeLevel);
+    test_cooperative_level(device.as_ptr()).into()
+}
+
+unsafe fn reset(device: &MockDevice) -> D3dResult {
+    let reset: ResetFn = method(device, DeviceMethod::Reset);
+    let mut params: D3DPRESENT_PARAMETERS = mem::zeroed();
+    reset(device.as_ptr(), &mut params).into()
+}
+
+#[test]
+fn alt_tab_releases_and_recreates_the_resources() {
+    let _serial = serial();
+    let device = MockDevice::new();
+    let (resources, log) = logged(&device);
+    let hooks = unsafe {
+        D3D9Hooks::new()
+            .with_resources(resources.clone())
+            .install_for_device_in(FakeBackend::new(7), device.as_ptr())
+    }
+    .unwrap();
+    assert!(hooks.is_hooked(DeviceMethod::TestCooperativeLevel));
+    assert!(hooks.is_hooked(DeviceMethod::Reset));
+
+    // What a game polls while it is in the background, then a failed and a good reset.
+    script(
+        &device,
+        DeviceMethod::TestCooperativeLevel.index(),
+        &[
+            D3dResult::Ok,
+            D3dResult::DeviceLost,
+            D3dResult::DeviceLost,
+            D3dResult::DeviceNotReset,
+        ],
+    );
+    script(
+        &device,
+        DeviceMethod::Reset.index(),
+        &[D3dResult::DeviceLost, D3dResult::Ok],
+    );
+    let expected = [
+        (D3dResult::Ok, DeviceState::Operational),
+        (D3dResult::DeviceLost, DeviceState::Lost),
+        (D3dResult::DeviceLost, DeviceState::Lost),
+        (D3dResult::DeviceNotReset, DeviceState::NotReset),
+    ];
+    for &(result, state) in &expected {
+        assert_eq!(unsafe { test_cooperative_level(&device) }, result);
+        assert_eq!(resources.state(device.as_ptr()), state);
+    }
+    assert_eq!(*log.lock().unwrap(), vec!["lost"]);
+
+    assert_eq!(unsafe { reset(&device) }, D3dResult::DeviceLost);
+    assert_eq!(resources.state(device.as_ptr()), DeviceState::Lost);
+    assert_eq!(unsafe { reset(&device) }, D3dResult::Ok);
+    assert_eq!(resources.state(device.as_ptr()), DeviceState::Operational);
+    assert_eq!(*log.lock().unwrap(), vec!["lost", "reset"]);
+    drop(hooks);
+}
+
+#[test]
+fn reset_ex_is_hooked_on_ex_devices() {
+    let _serial = serial();
+    let device = MockDevice::new_ex();
+    let (resources, log) = logged(&device);
+    let hooks = unsafe {
+        D3D9Hooks::new()
+            .with_resources(resources.clone())
+            .install_for_device_in(FakeBackend::new(7), device.as_ptr())
+    }
+    .unwrap();
+
+    let reset_ex: ResetExFn =
+        unsafe { mem::transmute(*device.vtable().add(DeviceExMethod::ResetEx.index())) };
+    let mut params: D3DPRESENT_PARAMETERS = unsafe { mem::zeroed() };
+    let result = unsafe { reset_ex(device.as_ex_ptr(), &mut params, ptr::null_mut()) };
+
+    assert_eq!(result, 0);
+    assert_eq!(device.call_count(DeviceExMethod::ResetEx.index()), 1);
+    assert_eq!(*log.lock().unwrap(), vec!["lost", "reset"]);
+    drop(hooks);
+}
+
+#[test]
+fn callbacks_can_register_resources_from_the_hooks() {
+    let _serial = serial();
+    let device = MockDevice::new();
+    let resources = ResourceRegistry::new();
+    let registry = resources.clone();
+    resources.register(
+        device.as_ptr(),
+        |_| (),
+        move |device| {
+            registry.register(device, |_| (), |_| ());
+        },
+    );
+    let hooks = unsafe {
+        D3D9Hooks::new()
+            .with_resources(resources.clone())
+            .install_for_device_in(FakeBackend::new(7), device.as_ptr())
+    }
+    .unwrap();
+
+    assert_eq!(unsafe { reset(&device) }, D3dResult::Ok);
+
+    assert_eq!(resources.len(), 2);
+    assert_eq!(hooks.disabled_callbacks(), 0);
+    drop(hooks);
+}
+
+#[test]
+fn other_devices_keep_their_resources() {
+    let _serial = serial();
+    let device = MockDevice::new();
+    let other = MockDevice::new().with_shared_vtable(&device);
+    let (resources, log) = logged(&device);
+    let hooks = unsafe {
+        D3D9Hooks::new()
+            .with_resources(resources.clone())
+            .install_for_device_in(FakeBackend::new(7), device.as_ptr())
+    }
+    .unwrap();
+
+    assert_eq!(unsafe { reset(&other) }, D3dResult::Ok);
+
+    assert!(log.lock().unwrap().is_empty());
+    assert_eq!(resources.state(device.as_ptr()), DeviceState::Operational);
+    drop(hooks);
+}
+
+#[test]
+fn a_panicking_resource_is_disabled_on_its_own() {
+    let _serial = serial();
+    let device = MockDevice::new();
+    let (resources, log) = logged(&device);
+    resources.register(device.as_ptr(), |_| panic!("release failed"), |_| ());
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let sink = reports.clone();
+    let policy = PanicPolicy::new()
+        .disable_after(1)
+        .report_to(move |report| sink.lock().unwrap().push(report.method.to_owned()));
+    let hooks = unsafe {
+        D3D9Hooks::new()
+            .panic_policy(policy)
+            .with_resources(resources.clone())
+            .install_for_device_in(FakeBackend::new(7), device.as_ptr())
+    }
+    .unwrap();
+
+    assert_eq!(unsafe { reset(&device) }, D3dResult::Ok);
+    assert_eq!(unsafe { reset(&device) }, D3dResult::Ok);
+
+    assert_eq!(*log.lock().unwrap(), vec!["lost", "reset", "lost", "reset"]);
+    assert_eq!(*reports.lock().unwrap(), vec!["Reset"]);
+    assert_eq!(hooks.disabled_callbacks(), 1);
+    assert!(!resources.is_released(device.as_ptr()));
+    drop(hooks);
+}
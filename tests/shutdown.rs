@@ -0,0 +1,230 @@
+//! `shutdown_in` undoing hooks, releasing dummies and waiting for calls in flight.
+
+use std::ffi::c_void;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use d3d9_device_grabber::backend::FakeBackend;
+use d3d9_device_grabber::hook::{D3D9Hooks, Detour, VmtHook};
+use d3d9_device_grabber::sys::*;
+use d3d9_device_grabber::testing::{MockDevice, MockDirect3D9};
+use d3d9_device_grabber::{
+    shutdown_in, CallGuard, DeviceGrabber, DeviceMethod, HookError, LiveDevice,
+};
+
+mod common;
+
+use common::{hidden_window_backend, method, serial, slot};
+
+type PresentFn = unsafe extern "system" fn(
+    *mut IDirect3DDevice9,
+    *const RECT,
+    *const RECT,
+    HWND,
+    *const RGNDATA,
+) -> HRESULT;
+type FunctionFn = unsafe extern "system" fn();
+type AddRefFn = unsafe extern "system" fn(*mut IDirect3DDevice9) -> u32;
+
+unsafe extern "system" fn present(
+    _: *mut IDirect3DDevice9,
+    _: *const RECT,
+    _: *const RECT,
+    _: HWND,
+    _: *const RGNDATA,
+) -> HRESULT {
+    42
+}
+
+unsafe extern "system" fn detour() {}
+
+fn shutdown(timeout: Duration) -> Result<(), HookError> {
+    unsafe { shutdown_in(&FakeBackend::new(7), timeout) }
+}
+
+/// A 64-bit `push rbp; mov rbp, rsp; sub rsp, 0x20`, or its 32-bit equivalent, and `nop`s.
+fn function() -> Vec<u8> {
+    let mut function = if cfg!(target_pointer_width = "64") {
+        vec![0x55, 0x48, 0x89, 0xE5, 0x48, 0x83, 0xEC, 0x20]
+    } else {
+        vec![0x55, 0x8B, 0xEC, 0x83, 0xEC, 0x20]
+    };
+    function.resize(40, 0x90);
+    function
+}
+
+/// Hold a `CallGuard` on another thread until the returned barrier is waited on.
+fn call_in_flight() -> (thread::JoinHandle<()>, Arc<Barrier>) {
+    let (entered, done) = (Arc::new(Barrier::new(2)), Arc::new(Barrier::new(2)));
+    let (entered_here, done_here) = (entered.clone(), done.clone());
+    let worker = thread::spawn(move || {
+        let _call = CallGuard::enter();
+        entered_here.wait();
+        done_here.wait();
+    });
+    entered.wait();
+    (worker, done)
+}
+
+#[test]
+fn restores_hooks_and_releases_dummies() {
+    let _serial = serial();
+    let device = MockDevice::new();
+    let direct3d9 = MockDirect3D9::new().with_device(&device);
+    let backend = hidden_window_backend(&direct3d9);
+    let dummy = unsafe { DeviceGrabber::new().hidden_window(true).device_in(&backend) }.unwrap();
+    let end_scene = slot(&device, DeviceMethod::EndScene);
+
+    let hooks = unsafe {
+        D3D9Hooks::new()
+            .on_end_scene(|_| ())
+            .install_for_device_in(FakeBackend::new(7), device.as_ptr())
+    }
+    .unwrap();
+    let other = MockDevice::new();
+    let other_present = slot(&other, DeviceMethod::Present);
+    let mut vmt = unsafe { VmtHook::for_device_in(FakeBackend::new(7), other.as_ptr()) };
+    unsafe { vmt.hook(DeviceMethod::Present.index(), present as PresentFn) }.unwrap();
+    let mut function = function();
+    let before = function.clone();
+    let patched = unsafe {
+        Detour::new_in(
+            FakeBackend::new(7),
+            function.as_mut_ptr() as *const c_void,
+            detour as FunctionFn,
+        )
+    }
+    .unwrap();
+    assert_ne!(function, before);
+
+    shutdown(Duration::from_secs(5)).unwrap();
+
+    assert_eq!(slot(&device, DeviceMethod::EndScene), end_scene);
+    assert_eq!(slot(&other, DeviceMethod::Present), other_present);
+    assert_eq!(function, before);
+    assert_eq!(device.ref_count(), 1);
+    assert_eq!(direct3d9.ref_count(), 0);
+    assert_eq!(backend.hidden_windows_destroyed(), 1);
+
+    // Dropping afterwards leaves the process alone.
+    function[0] = 0xCC;
+    drop(patched);
+    assert_eq!(function[0], 0xCC);
+    drop(vmt);
+    drop(hooks);
+    drop(dummy);
+    assert_eq!(device.ref_count(), 1);
+    assert_eq!(direct3d9.ref_count(), 0);
+    assert_eq!(backend.hidden_windows_destroyed(), 1);
+}
+
+#[test]
+fn releases_nothing_while_calls_are_in_flight() {
+    let _serial = serial();
+    let device = MockDevice::new();
+    let direct3d9 = MockDirect3D9::new().with_device(&device);
+    let backend = hidden_window_backend(&direct3d9);
+    let dummy = unsafe { DeviceGrabber::new().hidden_window(true).device_in(&backend) }.unwrap();
+
+    let (worker, done) = call_in_flight();
+    let err = shutdown(Duration::from_millis(10)).unwrap_err();
+    assert!(matches!(err, HookError::DrainTimedOut { in_flight: 1 }));
+    // The call may still be using the dummy.
+    assert_eq!(device.ref_count(), 2);
+    assert_eq!(backend.hidden_windows_destroyed(), 0);
+
+    done.wait();
+    worker.join().unwrap();
+    shutdown(Duration::from_secs(5)).unwrap();
+    assert_eq!(device.ref_count(), 1);
+    assert_eq!(backend.hidden_windows_destroyed(), 1);
+    drop(dummy);
+}
+
+#[test]
+fn waits_for_calls_on_other_threads_only() {
+    let _serial = serial();
+    let (entered, done) = (Arc::new(Barrier::new(2)), Arc::new(AtomicUsize::new(0)));
+    let (entered_here, done_here) = (entered.clone(), done.clone());
+    let worker = thread::spawn(move || {
+        let _call = CallGuard::enter();
+        entered_here.wait();
+        thread::sleep(Duration::from_millis(50));
+        done_here.store(1, Ordering::SeqCst);
+    });
+    entered.wait();
+    let _mine = CallGuard::enter();
+
+    let start = Instant::now();
+    shutdown(Duration::from_secs(5)).unwrap();
+
+    assert!(start.elapsed() >= Duration::from_millis(30));
+    assert_eq!(done.load(Ordering::SeqCst), 1);
+    worker.join().unwrap();
+    assert_eq!(CallGuard::in_flight(), 1);
+}
+
+#[test]
+fn stale_vmt_hook_leaves_a_newer_hook_alone() {
+    let _serial = serial();
+    let device = MockDevice::new();
+    let original = slot(&device, DeviceMethod::Present);
+    let mut stale = unsafe { VmtHook::for_device_in(FakeBackend::new(7), device.as_ptr()) };
+    unsafe { stale.hook(DeviceMethod::Present.index(), present as PresentFn) }.unwrap();
+    shutdown(Duration::from_secs(5)).unwrap();
+
+    let mut newer = unsafe { VmtHook::for_device_in(FakeBackend::new(7), device.as_ptr()) };
+    unsafe { newer.hook(DeviceMethod::Present.index(), present as PresentFn) }.unwrap();
+    stale.unhook(DeviceMethod::Present.index()).unwrap();
+    drop(stale);
+    assert_eq!(
+        slot(&device, DeviceMethod::Present),
+        present as PresentFn as usize
+    );
+
+    drop(newer);
+    assert_eq!(slot(&device, DeviceMethod::Present), original);
+}
+
+#[test]
+fn stale_detour_leaves_a_newer_detour_alone() {
+    let _serial = serial();
+    let mut function = function();
+    let before = function.clone();
+    let target = function.as_mut_ptr() as *const c_void;
+    let stale =
+        unsafe { Detour::new_in(FakeBackend::new(7), target, detour as FunctionFn) }.unwrap();
+    shutdown(Duration::from_secs(5)).unwrap();
+
+    let newer =
+        unsafe { Detour::new_in(FakeBackend::new(7), target, detour as FunctionFn) }.unwrap();
+    let patched = function.clone();
+    assert_ne!(patched, before);
+    drop(stale);
+    assert_eq!(function, patched);
+    assert_eq!(newer.backend().live_allocations(), 1);
+
+    drop(newer);
+    assert_eq!(function, before);
+}
+
+#[test]
+fn stale_reference_leaves_a_newer_one_at_the_same_address_alone() {
+    let _serial = serial();
+    let device = MockDevice::new();
+    let stale = unsafe { LiveDevice::from_raw(NonNull::new(device.as_ptr()).unwrap()) };
+    shutdown(Duration::from_secs(5)).unwrap();
+    assert_eq!(device.ref_count(), 0);
+
+    // The same address, owned anew.
+    unsafe { method::<AddRefFn>(&device, DeviceMethod::AddRef)(device.as_ptr()) };
+    let newer = unsafe { LiveDevice::from_raw(NonNull::new(device.as_ptr()).unwrap()) };
+    drop(stale);
+    assert_eq!(device.ref_count(), 1);
+
+    drop(newer);
+    assert_eq!(device.ref_count(), 0);
+}
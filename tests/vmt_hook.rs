@@ -0,0 +1,161 @@
+//! `VmtHook` swapping methods in vtables on the heap.
+
+use std::ffi::c_void;
+use std::mem;
+
+use d3d9_device_grabber::backend::FakeBackend;
+use d3d9_device_grabber::hook::VmtHook;
+use d3d9_device_grabber::sys::*;
+use d3d9_device_grabber::testing::MockDevice;
+use d3d9_device_grabber::{DeviceMethod, HookError};
+
+type EndSceneFn = unsafe extern "system" fn(*mut IDirect3DDevice9) -> HRESULT;
+
+const END_SCENE: usize = DeviceMethod::EndScene.index();
+
+unsafe extern "system" fn end_scene(_device: *mut IDirect3DDevice9) -> HRESULT {
+    42
+}
+
+unsafe fn slot(device: &MockDevice, slot: usize) -> usize {
+    *device.vtable().add(slot)
+}
+
+unsafe fn call_end_scene(device: &MockDevice) -> HRESULT {
+    let end_scene: EndSceneFn = mem::transmute(slot(device, END_SCENE));
+    end_scene(device.as_ptr())
+}
+
+#[test]
+fn hook_replaces_the_slot_and_returns_the_original() {
+    let device = MockDevice::new();
+    let before = unsafe { slot(&device, END_SCENE) };
+    let mut hook = unsafe { VmtHook::for_device_in(FakeBackend::new(7), device.as_ptr()) };
+
+    let original = unsafe { hook.hook(END_SCENE, end_scene as EndSceneFn) }.unwrap();
+
+    assert!(hook.is_hooked(END_SCENE));
+    assert_eq!(original.get() as usize, before);
+    assert_eq!(unsafe { call_end_scene(&device) }, 42);
+    assert_eq!(unsafe { original.get()(device.as_ptr()) }, 0);
+    assert_eq!(device.call_count(END_SCENE), 1);
+    // Protection is put back as soon as the slot is written.
+    assert!(hook.backend().unprotected_ranges().is_empty());
+}
+
+#[test]
+fn drop_puts_every_slot_back() {
+    let device = MockDevice::new();
+    let before: Vec<usize> = (0..device.slot_count())
+        .map(|index| unsafe { slot(&device, index) })
+        .collect();
+    let mut hook = unsafe { VmtHook::for_device_in(FakeBackend::new(7), device.as_ptr()) };
+    unsafe {
+        hook.hook(END_SCENE, end_scene as EndSceneFn).unwrap();
+        hook.hook(DeviceMethod::BeginScene.index(), end_scene as EndSceneFn)
+            .unwrap();
+    }
+
+    drop(hook);
+
+    let after: Vec<usize> = (0..device.slot_count())
+        .map(|index| unsafe { slot(&device, index) })
+        .collect();
+    assert_eq!(after, before);
+}
+
+#[test]
+fn unhook_restores_one_slot() {
+    let device = MockDevice::new();
+    let before = unsafe { slot(&device, END_SCENE) };
+    let mut hook = unsafe { VmtHook::for_device_in(FakeBackend::new(7), device.as_ptr()) };
+    unsafe { hook.hook(END_SCENE, end_scene as EndSceneFn) }.unwrap();
+
+    hook.unhook(END_SCENE).unwrap();
+
+    assert!(!hook.is_hooked(END_SCENE));
+    assert_eq!(unsafe { slot(&device, END_SCENE) }, before);
+    assert!(matches!(
+        hook.unhook(END_SCENE),
+        Err(HookError::NotHooked { slot: END_SCENE })
+    ));
+    // The slot is free to be hooked again.
+    unsafe { hook.hook(END_SCENE, end_scene as EndSceneFn) }.unwrap();
+}
+
+#[test]
+fn a_slot_is_hooked_at_most_once() {
+    let device = MockDevice::new();
+    let mut hook = unsafe { VmtHook::for_device_in(FakeBackend::new(7), device.as_ptr()) };
+    let mut other = unsafe { VmtHook::for_device_in(FakeBackend::new(7), device.as_ptr()) };
+    unsafe { hook.hook(END_SCENE, end_scene as EndSceneFn) }.unwrap();
+
+    assert!(matches!(
+        unsafe { hook.hook(END_SCENE, end_scene as EndSceneFn) },
+        Err(HookError::AlreadyHooked { slot: END_SCENE })
+    ));
+    assert!(matches!(
+        unsafe { other.hook(END_SCENE, end_scene as EndSceneFn) },
+        Err(HookError::AlreadyHooked { slot: END_SCENE })
+    ));
+    assert!(!other.is_hooked(END_SCENE));
+}
+
+#[test]
+fn slots_past_the_end_are_rejected() {
+    let device = MockDevice::new();
+    let mut hook = unsafe { VmtHook::for_device_in(FakeBackend::new(7), device.as_ptr()) };
+
+    assert!(matches!(
+        unsafe { hook.hook(500, end_scene as EndSceneFn) },
+        Err(HookError::SlotOutOfRange {
+            slot: 500,
+            len: 119
+        })
+    ));
+}
+
+#[test]
+fn read_only_vtables_are_left_alone() {
+    let device = MockDevice::new();
+    let before = unsafe { slot(&device, END_SCENE) };
+    let backend = FakeBackend::new(7).with_read_only_memory();
+    let mut hook = unsafe { VmtHook::for_device_in(backend, device.as_ptr()) };
+
+    let err = unsafe { hook.hook(END_SCENE, end_scene as EndSceneFn) }
+        .err()
+        .unwrap();
+
+    let address = unsafe { device.vtable().add(END_SCENE) } as usize;
+    assert!(matches!(
+        err,
+        HookError::ProtectFailed { address: failed, len } if failed == address && len == mem::size_of::<usize>()
+    ));
+    assert!(!hook.is_hooked(END_SCENE));
+    assert_eq!(unsafe { slot(&device, END_SCENE) }, before);
+}
+
+#[test]
+fn any_heap_vtable_can_be_hooked() {
+    unsafe extern "system" fn zero(_this: *mut c_void) -> HRESULT {
+        0
+    }
+    unsafe extern "system" fn one(_this: *mut c_void) -> HRESULT {
+        1
+    }
+    type MethodFn = unsafe extern "system" fn(*mut c_void) -> HRESULT;
+
+    let mut vtable: Vec<*const c_void> = vec![zero as *const c_void; 3];
+    let mut hook = unsafe { VmtHook::new_in(FakeBackend::new(7), vtable.as_mut_ptr(), 3) };
+
+    let original = unsafe { hook.hook(2, one as MethodFn) }.unwrap();
+    assert_eq!(vtable[2], one as *const c_void);
+    assert_eq!(unsafe { original.get()(std::ptr::null_mut()) }, 0);
+    assert!(matches!(
+        unsafe { hook.hook(3, one as MethodFn) },
+        Err(HookError::SlotOutOfRange { slot: 3, len: 3 })
+    ));
+
+    drop(hook);
+    assert_eq!(vtable[2], zero as *const c_void);
+}
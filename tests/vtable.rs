@@ -0,0 +1,59 @@
+//! Copying vtables from a dummy device that is released before the copy is returned.
+
+use std::ffi::c_void;
+
+use d3d9_device_grabber::testing::{MockDevice, MockDirect3D9, MockSwapChain};
+use d3d9_device_grabber::{
+    get_d3d9_vtable_in, get_d3d9_vtables_in, DeviceMethod, DeviceVTable, SwapChainMethod,
+    SwapChainVTable,
+};
+
+mod common;
+
+use common::hidden_window_backend;
+
+fn slots(vtable: *mut usize, len: usize) -> Vec<*const c_void> {
+    (0..len)
+        .map(|slot| unsafe { *vtable.add(slot) } as *const c_void)
+        .collect()
+}
+
+#[test]
+fn device_vtable_is_copied_and_nothing_stays_alive() {
+    let device = MockDevice::new();
+    let direct3d9 = MockDirect3D9::new().with_device(&device);
+    let backend = hidden_window_backend(&direct3d9);
+
+    let vtable = unsafe { get_d3d9_vtable_in(&backend) }.unwrap();
+
+    assert_eq!(
+        vtable.as_slice(),
+        &slots(device.vtable(), DeviceVTable::LEN)[..]
+    );
+    assert_eq!(
+        vtable.get(DeviceMethod::EndScene),
+        slots(device.vtable(), DeviceVTable::LEN)[DeviceMethod::EndScene.index()]
+    );
+    // Every reference taken was given back: the IDirect3D9 is released for good, and the
+    // device is left with only the reference the mock itself starts with.
+    assert_eq!(direct3d9.ref_count(), 0);
+    assert_eq!(device.ref_count(), 1);
+    assert_eq!(backend.hidden_windows_destroyed(), 1);
+}
+
+#[test]
+fn swap_chain_vtable_is_copied_too() {
+    let swap_chain = MockSwapChain::new();
+    let device = MockDevice::new().with_swap_chain(&swap_chain);
+    let direct3d9 = MockDirect3D9::new().with_device(&device);
+
+    let vtables = unsafe { get_d3d9_vtables_in(&hidden_window_backend(&direct3d9)) }.unwrap();
+
+    assert_eq!(
+        vtables.swap_chain.get(SwapChainMethod::Present),
+        slots(swap_chain.vtable(), SwapChainVTable::LEN)[SwapChainMethod::Present.index()]
+    );
+    assert_eq!(direct3d9.ref_count(), 0);
+    assert_eq!(device.ref_count(), 1);
+    assert_eq!(swap_chain.ref_count(), 1);
+}
@@ -0,0 +1,116 @@
+//! `WaitPolicy` retrying the window search on a fake clock.
+
+use std::time::Duration;
+
+use d3d9_device_grabber::backend::{fake_hwnd, FakeBackend, FakeWindow};
+use d3d9_device_grabber::testing::{FakeClock, MockDevice, MockDirect3D9};
+use d3d9_device_grabber::{Clock, D3D9GrabError, DeviceGrabber, WaitPolicy};
+
+fn ms(millis: u64) -> Duration {
+    Duration::from_millis(millis)
+}
+
+#[test]
+fn backs_off_until_the_timeout() {
+    let clock = FakeClock::new();
+    let backend = FakeBackend::new(7);
+    let policy = WaitPolicy::new()
+        .poll_interval(ms(10))
+        .backoff(2.0)
+        .max_poll_interval(ms(50))
+        .timeout(Some(ms(200)))
+        .clock(clock.clone());
+
+    let err = unsafe { DeviceGrabber::new().wait(policy).device_in(&backend) }
+        .err()
+        .unwrap();
+
+    assert!(matches!(err, D3D9GrabError::GetProcessWindowFailed { .. }));
+    // The last wait is cut short to end right at the timeout.
+    assert_eq!(
+        clock.sleeps(),
+        vec![ms(10), ms(20), ms(40), ms(50), ms(50), ms(30)]
+    );
+    assert_eq!(clock.now(), ms(200));
+}
+
+#[test]
+fn zero_timeout_looks_once() {
+    let clock = FakeClock::new();
+    let policy = WaitPolicy::new()
+        .timeout(Some(Duration::ZERO))
+        .clock(clock.clone());
+
+    let mut attempts = 0;
+    let result: Result<(), _> = policy.retry(|| {
+        attempts += 1;
+        Err(D3D9GrabError::GetProcessWindowFailed {
+            enumerated: 0,
+            owned: 0,
+        })
+    });
+
+    assert!(result.is_err());
+    assert_eq!(attempts, 1);
+    assert!(clock.sleeps().is_empty());
+}
+
+#[test]
+fn other_errors_are_not_retried() {
+    let clock = FakeClock::new();
+    let backend = FakeBackend::new(7).with_window(fake_hwnd(1), 7);
+    let policy = WaitPolicy::new().clock(clock.clone());
+
+    let err = unsafe { DeviceGrabber::new().wait(policy).device_in(&backend) }
+        .err()
+        .unwrap();
+
+    assert!(matches!(err, D3D9GrabError::D3DCreate9Null), "{}", err);
+    assert!(clock.sleeps().is_empty());
+}
+
+#[test]
+fn waits_until_a_window_is_ready() {
+    let clock = FakeClock::new();
+    let ready_clock = clock.clone();
+    let device = MockDevice::new();
+    let direct3d9 = MockDirect3D9::new().with_device(&device);
+    let backend = FakeBackend::new(7)
+        .with_fake_window(FakeWindow::new(fake_hwnd(1), 7).title("splash"))
+        .with_direct3d9(direct3d9.as_ptr());
+    let policy = WaitPolicy::new()
+        .poll_interval(ms(100))
+        .backoff(1.0)
+        .ready(move |_| ready_clock.now() >= ms(250))
+        .clock(clock.clone());
+
+    let grabbed = unsafe { DeviceGrabber::new().wait(policy).device_in(&backend) }.unwrap();
+
+    assert_eq!(grabbed.as_ptr(), device.as_ptr());
+    assert_eq!(clock.sleeps(), vec![ms(100); 3]);
+}
+
+#[test]
+fn advancing_the_clock_counts_towards_the_timeout() {
+    let clock = FakeClock::new();
+    let policy = WaitPolicy::new()
+        .poll_interval(ms(10))
+        .backoff(1.0)
+        .timeout(Some(ms(100)))
+        .clock(clock.clone());
+
+    let mut attempts = 0;
+    let result: Result<(), _> = policy.retry(|| {
+        attempts += 1;
+        clock.advance(ms(40));
+        Err(D3D9GrabError::GetProcessWindowFailed {
+            enumerated: 0,
+            owned: 0,
+        })
+    });
+
+    assert!(result.is_err());
+    // 40ms looking and 10ms waiting per attempt, until the third look ends past 100ms.
+    assert_eq!(attempts, 3);
+    assert_eq!(clock.sleeps(), vec![ms(10), ms(10)]);
+}
@@ -0,0 +1,146 @@
+//! Choosing the process window the device is created on, from a fake window list.
+
+use d3d9_device_grabber::backend::{fake_hwnd, FakeBackend, FakeWindow};
+use d3d9_device_grabber::testing::{MockDevice, MockDirect3D9};
+use d3d9_device_grabber::window::{TitlePattern, WindowSelector};
+use d3d9_device_grabber::{D3D9GrabError, DeviceGrabber};
+
+/// An invisible splash screen, a window of another process, the main window and a larger
+/// window owned by it, in that z-order.
+fn backend(direct3d9: &MockDirect3D9) -> FakeBackend {
+    FakeBackend::new(7)
+        .with_fake_window(
+            FakeWindow::new(fake_hwnd(1), 7)
+                .visible(false)
+                .client_size(10, 10)
+                .title("Splash"),
+        )
+        .with_fake_window(FakeWindow::new(fake_hwnd(2), 8).client_size(1000, 1000))
+        .with_fake_window(
+            FakeWindow::new(fake_hwnd(3), 7)
+                .client_size(800, 600)
+                .class_name("GameWnd")
+                .title("The Game"),
+        )
+        .with_fake_window(
+            FakeWindow::new(fake_hwnd(4), 7)
+                .client_size(900, 600)
+                .owner(fake_hwnd(3)),
+        )
+        .with_direct3d9(direct3d9.as_ptr())
+}
+
+fn selected(selector: WindowSelector) -> Result<usize, D3D9GrabError> {
+    let device = MockDevice::new();
+    let direct3d9 = MockDirect3D9::new().with_device(&device);
+    let (_grabbed, window) = unsafe {
+        DeviceGrabber::new()
+            .window(selector)
+            .device_with_hwnd_in(&backend(&direct3d9))
+    }?;
+    Ok((1..=4)
+        .find(|&n| fake_hwnd(n) == window)
+        .expect("picked a window that is not in the list"))
+}
+
+#[test]
+fn first_takes_the_topmost_window_of_the_process() {
+    assert_eq!(selected(WindowSelector::First).unwrap(), 1);
+}
+
+#[test]
+fn first_visible_skips_hidden_windows() {
+    assert_eq!(selected(WindowSelector::FirstVisible).unwrap(), 3);
+}
+
+#[test]
+fn largest_client_area_ignores_other_processes() {
+    assert_eq!(selected(WindowSelector::LargestClientArea).unwrap(), 4);
+}
+
+#[test]
+fn no_owner_skips_owned_windows() {
+    let device = MockDevice::new();
+    let direct3d9 = MockDirect3D9::new().with_device(&device);
+    let backend = FakeBackend::new(7)
+        .with_fake_window(FakeWindow::new(fake_hwnd(4), 7).owner(fake_hwnd(3)))
+        .with_fake_window(FakeWindow::new(fake_hwnd(3), 7))
+        .with_direct3d9(direct3d9.as_ptr());
+
+    let (_grabbed, window) = unsafe {
+        DeviceGrabber::new()
+            .window(WindowSelector::NoOwner)
+            .device_with_hwnd_in(&backend)
+    }
+    .unwrap();
+    assert_eq!(window, fake_hwnd(3));
+}
+
+#[test]
+fn class_name_ignores_ascii_case() {
+    let selector = WindowSelector::ClassName("gamewnd".to_owned());
+    assert_eq!(selected(selector).unwrap(), 3);
+}
+
+#[test]
+fn title_matches_the_pattern() {
+    let selector = WindowSelector::Title(TitlePattern::new("Game$").unwrap());
+    assert_eq!(selected(selector).unwrap(), 3);
+}
+
+#[test]
+fn custom_predicate_sees_the_window_info() {
+    let selector = WindowSelector::custom(|window| window.client_width == 900);
+    assert_eq!(selected(selector).unwrap(), 4);
+}
+
+#[test]
+fn no_match_reports_what_was_enumerated() {
+    let selector = WindowSelector::ClassName("Missing".to_owned());
+    match selected(selector) {
+        Err(D3D9GrabError::GetProcessWindowFailed { enumerated, owned }) => {
+            assert_eq!((enumerated, owned), (4, 3));
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+#[allow(deprecated)]
+fn get_d3d9_device_in_uses_the_first_window_of_the_process() {
+    let device = MockDevice::new();
+    let direct3d9 = MockDirect3D9::new().with_device(&device);
+    let backend = FakeBackend::new(7)
+        .with_window(fake_hwnd(2), 8)
+        .with_window(fake_hwnd(5), 7)
+        .with_direct3d9(direct3d9.as_ptr());
+
+    let grabbed = unsafe { d3d9_device_grabber::get_d3d9_device_in(&backend) }.unwrap();
+
+    assert_eq!(grabbed.as_ptr(), device.as_ptr());
+    assert!(backend
+        .queried_windows()
+        .starts_with(&[fake_hwnd(2), fake_hwnd(5)]));
+    let calls = direct3d9.create_device_calls();
+    assert!(!calls.is_empty());
+    assert!(calls.iter().all(|call| call.focus_window == fake_hwnd(5)
+        && call.present_params.hDeviceWindow == fake_hwnd(5)));
+}
+
+#[test]
+#[allow(deprecated)]
+fn get_d3d9_device_in_fails_without_a_window_of_the_process() {
+    let direct3d9 = MockDirect3D9::new();
+    let backend = FakeBackend::new(7)
+        .with_window(fake_hwnd(2), 8)
+        .with_direct3d9(direct3d9.as_ptr());
+
+    match unsafe { d3d9_device_grabber::get_d3d9_device_in(&backend) } {
+        Err(D3D9GrabError::GetProcessWindowFailed { enumerated, owned }) => {
+            assert_eq!((enumerated, owned), (1, 0));
+        }
+        Err(other) => panic!("unexpected error {:?}", other),
+        Ok(_) => panic!("grabbed a device without a window"),
+    }
+    assert!(direct3d9.create_device_calls().is_empty());
+}